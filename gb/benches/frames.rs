@@ -0,0 +1,73 @@
+//! Times whole frames of emulation, as `cargo bench` runs it.
+//!
+//! There's no benchmark harness among the dependencies, so this is a plain
+//! binary timing a couple of hand-assembled workloads: one keeps the CPU
+//! busy writing VRAM and scrolling, the other halts between timer
+//! interrupts, leaving the scheduled components to do most of the work.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use liam_gb::GameBoy;
+
+/// The frames each run of a workload takes.
+const FRAMES: u32 = 300;
+
+/// The runs of each workload, of which the fastest is reported to keep
+/// noise from the rest of the host down.
+const RUNS: usize = 7;
+
+/// Return a ROM that enables the timer interrupt at 262 kHz, then runs
+/// `program` from `0x0109`.
+fn rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // RETI from the timer interrupt.
+    rom[0x0050] = 0xD9;
+    rom[0x0100..0x0109].copy_from_slice(&[
+        0x3E, 0x05, // LD A,$05
+        0xE0, 0x07, // LDH ($07),A
+        0x3E, 0x04, // LD A,$04
+        0xE0, 0xFF, // LDH ($FF),A
+        0xFB, // EI
+    ]);
+    rom[0x0109..0x0109 + program.len()].copy_from_slice(program);
+    rom
+}
+
+/// Run `program` for [`FRAMES`] frames [`RUNS`] times and print the time
+/// the fastest run took.
+fn bench(name: &str, program: &[u8]) {
+    let mut gb = GameBoy::from_rom(rom(program)).unwrap();
+    // Warm up the caches before timing.
+    gb.run_frame();
+
+    let elapsed = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..FRAMES {
+                black_box(gb.run_frame());
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap();
+
+    let per_frame = elapsed / FRAMES;
+    let fps = f64::from(FRAMES) / elapsed.as_secs_f64();
+    println!("{name:<8} {per_frame:>10.2?}/frame {fps:>8.0} frames/s");
+}
+
+fn main() {
+    bench("busy", &[
+        0x21, 0x00, 0x80, // LD HL,$8000
+        0x22, // LD (HL+),A
+        0x3C, // INC A
+        0xE0, 0x43, // LDH ($43),A
+        0xCB, 0xAC, // RES 5,H
+        0x18, 0xF8, // JR -8
+    ]);
+    bench("halting", &[
+        0x76, // HALT
+        0x18, 0xFD, // JR -3
+    ]);
+}
@@ -0,0 +1,822 @@
+//! The audio processing unit, mixing four sound channels.
+
+mod noise;
+mod resampler;
+mod square;
+mod units;
+mod wave;
+
+use alloc::vec::Vec;
+
+use self::noise::Noise;
+use self::resampler::Resampler;
+use self::square::Square;
+use self::wave::Wave;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The address of the channel 1 sweep register.
+pub const NR10: u16 = 0xFF10;
+/// The address of the channel 1 duty and length register.
+pub const NR11: u16 = 0xFF11;
+/// The address of the channel 1 envelope register.
+pub const NR12: u16 = 0xFF12;
+/// The address of the channel 1 frequency low byte.
+pub const NR13: u16 = 0xFF13;
+/// The address of the channel 1 frequency high bits and control register.
+pub const NR14: u16 = 0xFF14;
+/// The address of the channel 2 duty and length register.
+pub const NR21: u16 = 0xFF16;
+/// The address of the channel 2 envelope register.
+pub const NR22: u16 = 0xFF17;
+/// The address of the channel 2 frequency low byte.
+pub const NR23: u16 = 0xFF18;
+/// The address of the channel 2 frequency high bits and control register.
+pub const NR24: u16 = 0xFF19;
+/// The address of the channel 3 DAC enable register.
+pub const NR30: u16 = 0xFF1A;
+/// The address of the channel 3 length register.
+pub const NR31: u16 = 0xFF1B;
+/// The address of the channel 3 output level register.
+pub const NR32: u16 = 0xFF1C;
+/// The address of the channel 3 frequency low byte.
+pub const NR33: u16 = 0xFF1D;
+/// The address of the channel 3 frequency high bits and control register.
+pub const NR34: u16 = 0xFF1E;
+/// The address of the channel 4 length register.
+pub const NR41: u16 = 0xFF20;
+/// The address of the channel 4 envelope register.
+pub const NR42: u16 = 0xFF21;
+/// The address of the channel 4 frequency and randomness register.
+pub const NR43: u16 = 0xFF22;
+/// The address of the channel 4 control register.
+pub const NR44: u16 = 0xFF23;
+/// The address of the master volume register.
+pub const NR50: u16 = 0xFF24;
+/// The address of the channel panning register.
+pub const NR51: u16 = 0xFF25;
+/// The address of the sound on/off register.
+pub const NR52: u16 = 0xFF26;
+/// The address of the first byte of wave RAM, which runs to `0xFF3F`.
+pub const WAVE_RAM: u16 = 0xFF30;
+
+/// The rate of the samples produced, one per M-cycle.
+pub const SAMPLE_RATE: u32 = 1 << 20;
+
+/// The output rate samples are drained at until it's changed.
+const DEFAULT_OUTPUT_RATE: u32 = 48_000;
+
+/// The bits of each register from `NR10` to `NR51` that read back as 1,
+/// including every bit of the unused addresses in between.
+const READ_MASKS: [u8; 0x16] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR41-NR44
+    0x00, 0x00, // NR50-NR51
+];
+
+/// The `NR52` bit that powers the APU.
+const POWER: u8 = 0x80;
+
+/// The T-cycles per frame sequencer step, clocking it at 512 Hz.
+const SEQUENCER_PERIOD: u16 = 8192;
+
+/// The most stereo samples buffered, one second's worth. Samples past this
+/// are dropped until the buffer is taken.
+const MAX_SAMPLES: usize = SAMPLE_RATE as usize;
+
+/// A sound channel, which can be muted with [`Apu::set_channel_enabled`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// Channel 1, the square wave with a frequency sweep.
+    Square1,
+    /// Channel 2, the square wave.
+    Square2,
+    /// Channel 3, the wave channel.
+    Wave,
+    /// Channel 4, the noise channel.
+    Noise,
+}
+
+impl Channel {
+    /// Every channel, in the order of their `NR52` status bits.
+    pub const ALL: [Self; 4] = [Self::Square1, Self::Square2, Self::Wave, Self::Noise];
+
+    /// Return the bit of the channel in a channel mask.
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The audio processing unit.
+///
+/// Two square channels, channel 1 with a frequency sweep, a wave channel
+/// and a noise channel are each turned into an analog level by their DAC,
+/// then mixed into left and right outputs through the panning in `NR51`
+/// and the master volume in `NR50`.
+///
+/// A frame sequencer clocked at 512 Hz drives the length counters at
+/// 256 Hz, the sweep at 128 Hz and the envelopes at 64 Hz.
+///
+/// Clearing bit 7 of `NR52` powers the APU down, clearing every register
+/// but wave RAM and ignoring writes to them until it's powered up again.
+/// On DMG the length counters stay powered: they keep their counts, and
+/// writes to `NR11`, `NR21`, `NR31` and `NR41` still load them.
+///
+/// A stereo sample is produced every M-cycle, at [`SAMPLE_RATE`]. These can
+/// be taken as they are, or drained resampled down to a host output rate.
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Apu {
+    ch1: Square,
+    ch2: Square,
+    ch3: Wave,
+    ch4: Noise,
+    /// Whether wave RAM conflicts behave as on CGB.
+    cgb: bool,
+    powered: bool,
+    /// Whether the length counters stay powered with the APU off, as on
+    /// the models without CGB hardware.
+    lengths_powered: bool,
+    /// The last values written from `NR10` to `NR51`.
+    regs: [u8; 0x16],
+    /// The next frame sequencer step, from 0 to 7.
+    sequencer_step: u8,
+    /// The T-cycles towards the next frame sequencer step.
+    sequencer_cycles: u16,
+    /// The T-cycles towards the next M-cycle.
+    cycles: u8,
+    /// The samples produced since they were last taken, interleaved left
+    /// then right.
+    samples: Vec<f32>,
+    resampler: Resampler,
+    /// Whether samples are skipped, for fast-forwarding.
+    fast_forward: bool,
+    /// The channels left out of the mix, as a mask of [`Channel::bit`]s.
+    muted: u8,
+}
+
+impl Apu {
+    /// Create an APU as the boot ROM leaves it, powered with every channel
+    /// silent and panned to both sides at full volume.
+    #[must_use]
+    pub fn new() -> Self {
+        let mut apu = Self {
+            ch1: Square::new(true),
+            ch2: Square::new(false),
+            ch3: Wave::new(false),
+            ch4: Noise::new(),
+            cgb: false,
+            powered: true,
+            lengths_powered: true,
+            regs: [0; 0x16],
+            sequencer_step: 0,
+            sequencer_cycles: 0,
+            cycles: 0,
+            samples: Vec::new(),
+            resampler: Resampler::new(DEFAULT_OUTPUT_RATE),
+            fast_forward: false,
+            muted: 0,
+        };
+
+        apu.write(NR11, 0x80);
+        apu.write(NR12, 0xF3);
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0xF3);
+        apu
+    }
+
+    /// Create an APU with the wave RAM conflicts of a CGB, as the CGB boot
+    /// ROM leaves it.
+    #[must_use]
+    pub fn new_cgb() -> Self {
+        let mut apu = Self::new();
+        apu.cgb = true;
+        apu.lengths_powered = false;
+        apu.ch3 = Wave::new(true);
+        apu
+    }
+
+    /// Set whether the length counters stay powered with the APU off, see
+    /// [`crate::Model::has_powered_lengths`]. An APU created by [`Apu::new`]
+    /// keeps them powered, and one created by [`Apu::new_cgb`] doesn't.
+    pub const fn set_lengths_powered(&mut self, enabled: bool) {
+        self.lengths_powered = enabled;
+    }
+
+    /// Return and clear the samples produced since the last call, as
+    /// interleaved left and right levels at [`SAMPLE_RATE`], without any
+    /// resampling or filtering.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        core::mem::take(&mut self.samples)
+    }
+
+    /// Return the output rate of [`Apu::drain_samples`] in Hz, 48000 by
+    /// default.
+    #[must_use]
+    pub const fn sample_rate(&self) -> u32 {
+        self.resampler.rate()
+    }
+
+    /// Set the output rate of [`Apu::drain_samples`] to `hz`, clamped to at
+    /// most [`SAMPLE_RATE`].
+    pub fn set_sample_rate(&mut self, hz: u32) {
+        self.resampler.set_rate(hz);
+    }
+
+    /// Resample the samples produced since the last call to the output
+    /// rate, appending them to `out` as interleaved left and right levels
+    /// from -1.0 to 1.0.
+    ///
+    /// The DC offset of the DACs is filtered out, so silence drains as 0.0.
+    pub fn drain_samples(&mut self, out: &mut Vec<f32>) {
+        self.resampler.resample(&self.samples, out);
+        self.samples.clear();
+    }
+
+    /// Check if `channel` is mixed into the output, see
+    /// [`Apu::set_channel_enabled`].
+    #[must_use]
+    pub const fn is_channel_enabled(&self, channel: Channel) -> bool {
+        self.muted & channel.bit() == 0
+    }
+
+    /// Mix `channel` into the output or mute it, for isolating channels.
+    ///
+    /// A muted channel still runs as before, with its length counter, its
+    /// envelope and its `NR52` status bit, it only adds nothing to the mix.
+    pub const fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        if enabled {
+            self.muted &= !channel.bit();
+        } else {
+            self.muted |= channel.bit();
+        }
+    }
+
+    /// Return the level of each channel from -1.0 to 1.0, before panning
+    /// and master volume, in the order of [`Channel::ALL`].
+    ///
+    /// Each DAC maps its channel's output from 0 to 15 onto 1.0 to -1.0,
+    /// while a DAC that's off outputs 0.0. Muted channels are reported too.
+    #[must_use]
+    pub fn channel_output(&self) -> [f32; 4] {
+        let level = |output: u8, dac: bool| {
+            if dac { 1.0 - f32::from(output) / 7.5 } else { 0.0 }
+        };
+        [
+            level(self.ch1.output(), self.ch1.dac_enabled()),
+            level(self.ch2.output(), self.ch2.dac_enabled()),
+            level(self.ch3.output(), self.ch3.dac_enabled()),
+            level(self.ch4.output(), self.ch4.dac_enabled()),
+        ]
+    }
+
+    /// Check if samples are skipped, see [`Apu::set_fast_forward`].
+    #[must_use]
+    pub const fn is_fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Skip producing samples while `enabled`, still running the channels
+    /// and frame sequencer so their registers read back exactly.
+    ///
+    /// The samples already buffered are dropped, so audio resumes from
+    /// silence rather than from before the skip.
+    pub fn set_fast_forward(&mut self, enabled: bool) {
+        if enabled && !self.fast_forward {
+            self.samples.clear();
+            self.resampler.clear();
+        }
+        self.fast_forward = enabled;
+    }
+
+    /// Write the registers and channel state to a save state.
+    ///
+    /// The buffered samples are left out.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.powered);
+        state.write_bytes(&self.regs);
+        state.write_u8(self.sequencer_step);
+        state.write_u16(self.sequencer_cycles);
+        state.write_u8(self.cycles);
+        self.ch1.save_state(state);
+        self.ch2.save_state(state);
+        self.ch3.save_state(state);
+        self.ch4.save_state(state);
+    }
+
+    /// Restore the state written by [`Apu::save_state`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is truncated or corrupt.
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.powered = state.read_bool()?;
+        state.read_bytes(&mut self.regs)?;
+        self.sequencer_step = state.read_u8()? & 0x07;
+        self.sequencer_cycles = state.read_u16()?;
+        self.cycles = state.read_u8()? & 0x03;
+        if self.sequencer_cycles >= SEQUENCER_PERIOD || !self.sequencer_cycles.is_multiple_of(4) {
+            return Err(StateError::Corrupt);
+        }
+
+        self.ch1.load_state(state)?;
+        self.ch2.load_state(state)?;
+        self.ch3.load_state(state)?;
+        self.ch4.load_state(state)?;
+        self.samples.clear();
+        self.resampler.clear();
+        Ok(())
+    }
+
+    /// Advance the APU by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles;
+        while self.cycles >= 4 {
+            self.cycles -= 4;
+            self.step();
+        }
+    }
+
+    /// Advance the APU by `cycles` T-cycles like [`Apu::tick`], but hold a
+    /// frame sequencer step due on the last M-cycle for
+    /// [`Apu::step_sequencer`].
+    pub(crate) fn advance(&mut self, cycles: u8) {
+        self.cycles += cycles;
+        while self.cycles >= 8 || (self.cycles >= 4 && !self.is_sequencer_due()) {
+            self.cycles -= 4;
+            self.step();
+        }
+    }
+
+    /// Run the M-cycle [`Apu::advance`] held, stepping the frame sequencer.
+    pub(crate) fn step_sequencer(&mut self) {
+        self.tick(0);
+    }
+
+    /// Return the T-cycles until the M-cycle that next steps the frame
+    /// sequencer, or `None` while the APU is off.
+    pub(crate) fn cycles_until_sequencer(&self) -> Option<u32> {
+        let steps = u32::from(SEQUENCER_PERIOD - self.sequencer_cycles) / 4;
+        self.powered.then(|| (4 * steps).saturating_sub(u32::from(self.cycles)))
+    }
+
+    /// Check if the next M-cycle steps the frame sequencer.
+    const fn is_sequencer_due(&self) -> bool {
+        self.powered && self.sequencer_cycles + 4 == SEQUENCER_PERIOD
+    }
+
+    /// Advance the APU by one M-cycle, producing a sample.
+    fn step(&mut self) {
+        if self.powered {
+            self.ch1.tick(4);
+            self.ch2.tick(4);
+            self.ch3.tick(4);
+            self.ch4.tick(4);
+
+            self.sequencer_cycles += 4;
+            if self.sequencer_cycles == SEQUENCER_PERIOD {
+                self.sequencer_cycles = 0;
+                self.clock_sequencer();
+            }
+        }
+
+        if !self.fast_forward && self.samples.len() < MAX_SAMPLES * 2 {
+            self.samples.extend(self.mix());
+        }
+    }
+
+    /// Run the next frame sequencer step.
+    fn clock_sequencer(&mut self) {
+        if self.sequencer_step.is_multiple_of(2) {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if self.sequencer_step % 4 == 2 {
+            self.ch1.clock_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.ch1.clock_envelope();
+            self.ch2.clock_envelope();
+            self.ch4.clock_envelope();
+        }
+
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+
+    /// Return the left and right levels, each from -1.0 to 1.0, of the
+    /// channels that aren't muted.
+    fn mix(&self) -> [f32; 2] {
+        let nr50 = self.regs[usize::from(NR50 - NR10)];
+        let nr51 = self.regs[usize::from(NR51 - NR10)];
+        let (mut left, mut right) = (0.0, 0.0);
+        let channels = Channel::ALL.into_iter().zip(self.channel_output());
+        for (i, (channel, level)) in channels.enumerate() {
+            if !self.is_channel_enabled(channel) {
+                continue;
+            }
+            if nr51 & 0x10 << i != 0 {
+                left += level;
+            }
+            if nr51 & 1 << i != 0 {
+                right += level;
+            }
+        }
+
+        let volume = |bits: u8| f32::from((bits & 0x07) + 1) / 8.0;
+        [left / 4.0 * volume(nr50 >> 4), right / 4.0 * volume(nr50)]
+    }
+
+    /// Return the `NR52` status bits of the channels that are playing.
+    fn status(&self) -> u8 {
+        [self.ch1.enabled, self.ch2.enabled, self.ch3.enabled, self.ch4.enabled]
+            .into_iter()
+            .enumerate()
+            .fold(0, |status, (i, enabled)| status | u8::from(enabled) << i)
+    }
+
+    /// Read an APU register or wave RAM.
+    #[must_use]
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            NR10..=NR51 => {
+                let index = usize::from(addr - NR10);
+                self.regs[index] | READ_MASKS[index]
+            }
+            NR52 => 0x70 | if self.powered { POWER } else { 0 } | self.status(),
+            WAVE_RAM..=0xFF3F => self.ch3.read_ram(usize::from(addr - WAVE_RAM)),
+            _ => 0xFF,
+        }
+    }
+
+    /// Set an APU register without triggering its channel, for tooling.
+    ///
+    /// Wave RAM is written directly, even while the wave channel plays.
+    /// Other writes go through as normal, so poking `NR52` still powers the
+    /// APU up or down.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            NR14 | NR24 | NR34 | NR44 => self.write(addr, value & !0x80),
+            WAVE_RAM..=0xFF3F => self.ch3.ram[usize::from(addr - WAVE_RAM)] = value,
+            _ => self.write(addr, value),
+        }
+    }
+
+    /// Write an APU register or wave RAM.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            NR52 => self.set_power(value & POWER != 0),
+            WAVE_RAM..=0xFF3F => self.ch3.write_ram(usize::from(addr - WAVE_RAM), value),
+            NR10..=NR51 if self.powered => {
+                self.regs[usize::from(addr - NR10)] = value;
+                // Length is clocked on even steps, so an odd next step
+                // means an extra clock on enabling it.
+                let extra_clock = self.sequencer_step % 2 == 1;
+                match addr {
+                    NR14 => self.ch1.write_control(value, extra_clock),
+                    NR24 => self.ch2.write_control(value, extra_clock),
+                    NR34 => self.ch3.write_control(value, extra_clock),
+                    NR44 => self.ch4.write_control(value, extra_clock),
+                    NR10..=NR13 => self.ch1.write(addr - NR10, value),
+                    NR21..=NR23 => self.ch2.write(addr - NR21 + 1, value),
+                    NR30..=NR33 => self.ch3.write(addr - NR30, value),
+                    NR41..=NR43 => self.ch4.write(addr - NR41 + 1, value),
+                    _ => {}
+                }
+            }
+            // Powered length counters still load while the APU is off, but
+            // the duty bits of `NR11` and `NR21` stay cleared.
+            NR11 if self.lengths_powered => self.ch1.write(1, value & 0x3F),
+            NR21 if self.lengths_powered => self.ch2.write(1, value & 0x3F),
+            NR31 if self.lengths_powered => self.ch3.write(1, value),
+            NR41 if self.lengths_powered => self.ch4.write(1, value),
+            _ => {}
+        }
+    }
+
+    /// Power the APU up or down.
+    ///
+    /// Powering down clears every register but wave RAM, and the length
+    /// counters if they aren't kept powered. Powering up restarts the frame
+    /// sequencer from step 0.
+    fn set_power(&mut self, powered: bool) {
+        if self.powered && !powered {
+            self.ch1.power_off(self.lengths_powered);
+            self.ch2.power_off(self.lengths_powered);
+            self.ch3.power_off(self.lengths_powered);
+            self.ch4.power_off(self.lengths_powered);
+            self.regs = [0; 0x16];
+        } else if !self.powered && powered {
+            self.sequencer_step = 0;
+            self.sequencer_cycles = 0;
+        }
+
+        self.powered = powered;
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameboy::FRAME_CYCLES;
+
+    /// Run the APU for `n` M-cycles.
+    fn run(apu: &mut Apu, n: usize) {
+        for _ in 0..n {
+            apu.tick(4);
+        }
+    }
+
+    /// Return an APU playing channel 2 at full volume with a 50% duty.
+    fn playing() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(NR21, 0x80);
+        apu.write(NR22, 0xF0);
+        apu.write(NR23, 0x00);
+        apu.write(NR24, 0x87);
+        apu
+    }
+
+    #[test]
+    fn unused_bits_read_high() {
+        let mut apu = Apu::new();
+        apu.write(NR11, 0x41);
+        apu.write(NR13, 0x12);
+        apu.write(NR50, 0x35);
+
+        assert_eq!(apu.read(NR10), 0x80);
+        assert_eq!(apu.read(NR11), 0x7F);
+        assert_eq!(apu.read(NR13), 0xFF);
+        assert_eq!(apu.read(NR50), 0x35);
+        assert_eq!(apu.read(0xFF15), 0xFF);
+        assert_eq!(apu.read(0xFF27), 0xFF);
+    }
+
+    #[test]
+    fn muted_channels_leave_the_mix() {
+        let mut apu = playing();
+        apu.set_channel_enabled(Channel::Square2, false);
+        assert!(!apu.is_channel_enabled(Channel::Square2));
+        // The same mix with channel 2's DAC off instead.
+        let mut silent = Apu::new();
+        silent.write(NR22, 0x00);
+
+        let mut swings = false;
+        for _ in 0..1000 {
+            run(&mut apu, 1);
+            run(&mut silent, 1);
+            let [left, right] = apu.mix();
+            let [silent_left, silent_right] = silent.mix();
+            assert!((left - silent_left).abs() < f32::EPSILON);
+            assert!((right - silent_right).abs() < f32::EPSILON);
+            swings |= apu.channel_output()[1] < 0.0;
+        }
+        assert!(swings);
+        assert_eq!(apu.read(NR52), 0xF2);
+
+        // The length counter keeps running while muted.
+        apu.write(NR21, 0xBF);
+        apu.write(NR24, 0xC7);
+        run(&mut apu, usize::from(SEQUENCER_PERIOD) / 4 * 2);
+        assert_eq!(apu.read(NR52), 0xF0);
+    }
+
+    #[test]
+    fn nr52_reports_playing_channels() {
+        let mut apu = playing();
+        assert_eq!(apu.read(NR52), 0xF2);
+
+        apu.write(NR22, 0x00);
+        assert_eq!(apu.read(NR52), 0xF0);
+    }
+
+    #[test]
+    fn power_off_clears_registers_but_wave_ram() {
+        let mut apu = playing();
+        apu.write(WAVE_RAM, 0x5A);
+
+        apu.write(NR52, 0x00);
+        assert_eq!(apu.read(NR52), 0x70);
+        assert_eq!(apu.read(NR22), 0x00);
+        assert_eq!(apu.read(NR50), 0x00);
+        assert_eq!(apu.read(WAVE_RAM), 0x5A);
+
+        // Writes are ignored until powered up again.
+        apu.write(NR50, 0x77);
+        assert_eq!(apu.read(NR50), 0x00);
+        apu.write(NR52, 0x80);
+        apu.write(NR50, 0x77);
+        assert_eq!(apu.read(NR50), 0x77);
+    }
+
+    #[test]
+    fn power_off_lengths_follow_model() {
+        for (mut apu, lengths_powered) in [(Apu::new(), true), (Apu::new_cgb(), false)] {
+            apu.write(NR52, 0x00);
+            apu.write(NR10, 0x7F);
+            assert_eq!(apu.read(NR10), 0x80);
+
+            // Only DMG loads the length counter, to play a single step.
+            apu.write(NR21, 0xFF);
+            assert_eq!(apu.read(NR21), 0x3F);
+            apu.write(NR52, 0x80);
+            apu.write(NR22, 0xF0);
+            apu.write(NR24, 0xC0);
+            run(&mut apu, usize::from(SEQUENCER_PERIOD / 4));
+            assert_eq!(apu.read(NR52) & 0x02 == 0, lengths_powered);
+        }
+    }
+
+    #[test]
+    fn wave_ram_conflicts_follow_model() {
+        for (mut apu, playing_read) in [(Apu::new(), 0xFF), (Apu::new_cgb(), 0xA0)] {
+            for (addr, value) in (WAVE_RAM..).zip(0xA0..0xB0) {
+                apu.write(addr, value);
+            }
+
+            // Play at the lowest frequency, so no sample is read for a while.
+            apu.write(NR30, 0x80);
+            apu.write(NR33, 0x00);
+            apu.write(NR34, 0x80);
+            run(&mut apu, 4);
+
+            // DMG blocks the access, while CGB reaches the byte being played.
+            assert_eq!(apu.read(WAVE_RAM + 5), playing_read);
+            apu.write(WAVE_RAM + 5, 0x99);
+            apu.write(NR30, 0x00);
+            assert_eq!(apu.read(WAVE_RAM + 5), 0xA5);
+        }
+    }
+
+    #[test]
+    fn powered_off_apu_is_silent() {
+        let mut apu = playing();
+        apu.write(NR52, 0x00);
+        apu.take_samples();
+
+        run(&mut apu, 100);
+        assert!(apu.take_samples().iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn produces_a_sample_per_m_cycle() {
+        let mut apu = playing();
+        run(&mut apu, 1000);
+        apu.tick(2);
+
+        assert_eq!(apu.take_samples().len(), 2000);
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn sequencer_clocks_length_at_256_hz() {
+        let mut apu = playing();
+        apu.write(NR21, 0x80 | 0x3E);
+        apu.write(NR24, 0xC7);
+
+        // Only every other step clocks the length.
+        run(&mut apu, 2048);
+        assert_eq!(apu.read(NR52) & 0x02, 0x02);
+        run(&mut apu, 2 * 2048 - 1);
+        assert_eq!(apu.read(NR52) & 0x02, 0x02);
+        run(&mut apu, 1);
+        assert_eq!(apu.read(NR52) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn advance_holds_the_sequencer_step_for_last() {
+        let mut apu = playing();
+        apu.tick(2);
+        assert_eq!(apu.cycles_until_sequencer(), Some(8190));
+
+        for _ in 0..2047 {
+            apu.advance(4);
+        }
+        assert_eq!(apu.cycles_until_sequencer(), Some(2));
+        apu.advance(2);
+        assert_eq!(apu.cycles_until_sequencer(), Some(0));
+        assert_eq!(apu.sequencer_step, 0);
+
+        apu.step_sequencer();
+        assert_eq!(apu.sequencer_step, 1);
+        assert_eq!(apu.cycles_until_sequencer(), Some(8192));
+
+        apu.write(NR52, 0x00);
+        assert_eq!(apu.cycles_until_sequencer(), None);
+    }
+
+    #[test]
+    fn enabling_length_clocks_it_early() {
+        // The next step clocks length, so enabling it waits for that step.
+        let mut apu = playing();
+        apu.write(NR21, 0x3F);
+        apu.write(NR24, 0x40);
+        assert_eq!(apu.read(NR52) & 0x02, 0x02);
+        run(&mut apu, 2048);
+        assert_eq!(apu.read(NR52) & 0x02, 0x00);
+
+        // With the next step skipping length, it's clocked straight away.
+        let mut apu = playing();
+        run(&mut apu, 2048);
+        apu.write(NR21, 0x3F);
+        apu.write(NR24, 0x40);
+        assert_eq!(apu.read(NR52) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn trigger_reloads_length_one_short() {
+        let mut apu = playing();
+        run(&mut apu, 2048);
+
+        // The extra clock expires the counter, then the trigger reloads it
+        // with 63 rather than 64.
+        apu.write(NR21, 0x3F);
+        apu.write(NR24, 0xC0);
+        assert_eq!(apu.read(NR52) & 0x02, 0x02);
+        run(&mut apu, 125 * 2048);
+        assert_eq!(apu.read(NR52) & 0x02, 0x02);
+        run(&mut apu, 2048);
+        assert_eq!(apu.read(NR52) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn nr51_pans_channels() {
+        let mut apu = playing();
+        apu.write(NR51, 0x20);
+        run(&mut apu, 2048);
+
+        let samples = apu.take_samples();
+        assert!(samples.chunks(2).any(|frame| frame[0] != 0.0));
+        assert!(samples.chunks(2).all(|frame| frame[1] == 0.0));
+    }
+
+    #[test]
+    fn nr50_scales_master_volume() {
+        let peak = |nr50| {
+            let mut apu = playing();
+            apu.write(NR50, nr50);
+            run(&mut apu, 2048);
+            apu.take_samples()
+                .chunks(2)
+                .map(|frame| frame[0].abs())
+                .fold(0.0, f32::max)
+        };
+
+        assert!((peak(0x70) / 8.0 - peak(0x00)).abs() < f32::EPSILON);
+        // Channel 1's DAC is on while it's silent, adding a steady level.
+        assert!((peak(0x70) - 0.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn drains_a_frame_at_48_khz() {
+        let mut apu = playing();
+        let mut out = Vec::new();
+        let mut total = 0;
+        for _ in 0..10 {
+            run(&mut apu, FRAME_CYCLES as usize / 4);
+            apu.drain_samples(&mut out);
+
+            // 17556 M-cycles at 48000 Hz is 803.65 stereo samples.
+            let frames = out.len() / 2;
+            assert!(frames == 803 || frames == 804, "{frames} samples");
+            total += frames;
+            out.clear();
+        }
+
+        assert_eq!(total, 8036);
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn silence_drains_as_zero() {
+        let mut apu = Apu::new();
+        apu.write(NR12, 0x00);
+        let mut out = Vec::new();
+
+        run(&mut apu, 1000);
+        apu.drain_samples(&mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn sample_rate_is_configurable() {
+        let mut apu = playing();
+        assert_eq!(apu.sample_rate(), 48_000);
+
+        apu.set_sample_rate(44_100);
+        assert_eq!(apu.sample_rate(), 44_100);
+        run(&mut apu, SAMPLE_RATE as usize);
+
+        let mut out = Vec::new();
+        apu.drain_samples(&mut out);
+        assert_eq!(out.len(), 2 * 44_100);
+    }
+}
@@ -0,0 +1,237 @@
+//! The noise channel 4.
+
+use super::units::{Envelope, Length};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The T-cycle divisors selected by the lower bits of `NR43`.
+const DIVISORS: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// The noise channel, playing the output of a linear-feedback shift register.
+#[derive(Debug, Clone)]
+pub(super) struct Noise {
+    pub enabled: bool,
+    /// The clock shift, the upper nibble of `NR43`.
+    shift: u8,
+    /// The shift register is 7 bits wide instead of 15, from bit 3 of `NR43`.
+    narrow: bool,
+    divisor: u8,
+    /// The 15-bit shift register.
+    lfsr: u16,
+    /// The T-cycles until the next shift.
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    /// Create a silent noise channel.
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            shift: 0,
+            narrow: false,
+            divisor: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::default(),
+        }
+    }
+
+    /// Return to the state powering the APU down leaves the channel in,
+    /// keeping the length counter if `keep_length` is set.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = self.length.clone();
+        length.power_off(keep_length);
+        *self = Self { length, ..Self::new() };
+    }
+
+    /// Return the T-cycles per shift.
+    fn period(&self) -> u32 {
+        u32::from(DIVISORS[usize::from(self.divisor)]) << self.shift
+    }
+
+    /// Check if the DAC is powered.
+    pub const fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    /// Write register `NR41` to `NR44`, by `reg` from 1 to 4, writing `NR44`
+    /// as [`Noise::write_control`] does without an extra length clock.
+    pub fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write(value);
+                self.enabled &= self.dac_enabled();
+            }
+            3 => {
+                self.shift = value >> 4;
+                self.narrow = value & 0x08 != 0;
+                self.divisor = value & 0x07;
+            }
+            _ => self.write_control(value, false),
+        }
+    }
+
+    /// Write `NR44`, restarting the channel if bit 7 is set. `extra_clock`
+    /// is set when the frame sequencer's next step doesn't clock length,
+    /// see [`Length::set_enabled`].
+    pub fn write_control(&mut self, value: u8, extra_clock: bool) {
+        let trigger = value & 0x80 != 0;
+        if self.length.set_enabled(value & 0x40 != 0, extra_clock) && !trigger {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger(extra_clock);
+        }
+    }
+
+    /// Restart the channel with a full shift register.
+    fn trigger(&mut self, extra_clock: bool) {
+        self.enabled = self.dac_enabled();
+        self.length.trigger(extra_clock);
+        self.envelope.trigger();
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+    }
+
+    /// Advance the channel by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u16) {
+        let mut cycles = u32::from(cycles);
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            let feedback = (self.lfsr ^ self.lfsr >> 1) & 1;
+            self.lfsr = self.lfsr >> 1 | feedback << 14;
+            if self.narrow {
+                self.lfsr = self.lfsr & !0x40 | feedback << 6;
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    /// Clock the length counter.
+    pub const fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Clock the volume envelope.
+    pub const fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Return the digital output, from 0 to 15.
+    pub const fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 != 0 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+
+    /// Write the channel to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.shift);
+        state.write_bool(self.narrow);
+        state.write_u8(self.divisor);
+        state.write_u16(self.lfsr);
+        state.write_u32(self.timer);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+    }
+
+    /// Restore the channel written by [`Noise::save_state`].
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.shift = state.read_u8()? & 0x0F;
+        self.narrow = state.read_bool()?;
+        self.divisor = state.read_u8()? & 0x07;
+        self.lfsr = state.read_u16()? & 0x7FFF;
+        self.timer = state.read_u32()?;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Return a triggered noise channel at full volume, shifting every
+    /// 8 T-cycles.
+    fn noise(narrow: bool) -> Noise {
+        let mut noise = Noise::new();
+        noise.write(2, 0xF0);
+        noise.write(3, u8::from(narrow) << 3);
+        noise.write(4, 0x80);
+        noise
+    }
+
+    /// Return how many shifts it takes the register to repeat.
+    fn cycle_length(noise: &mut Noise) -> usize {
+        let start = noise.lfsr;
+        (1..=0x7FFF)
+            .find(|_| {
+                noise.tick(8);
+                noise.lfsr == start
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn first_shifts_feed_in_zeroes() {
+        let mut noise = noise(false);
+        assert_eq!(noise.output(), 0);
+
+        noise.tick(8);
+        assert_eq!(noise.lfsr, 0x3FFF);
+        assert_eq!(noise.output(), 0);
+    }
+
+    #[test]
+    fn wide_register_has_full_period() {
+        assert_eq!(cycle_length(&mut noise(false)), 0x7FFF);
+    }
+
+    #[test]
+    fn narrow_register_has_short_period() {
+        let mut noise = noise(true);
+        // Settle into the 7-bit loop first.
+        noise.tick(8 * 16);
+
+        assert_eq!(cycle_length(&mut noise), 0x7F);
+    }
+
+    #[test]
+    fn plays_both_levels() {
+        let mut noise = noise(false);
+        let outputs: Vec<_> = (0..64)
+            .map(|_| {
+                noise.tick(8);
+                noise.output()
+            })
+            .collect();
+
+        assert!(outputs.contains(&0));
+        assert!(outputs.contains(&15));
+    }
+
+    #[test]
+    fn clock_shift_divides_rate() {
+        let mut noise = noise(false);
+        noise.write(3, 0x23);
+        noise.write(4, 0x80);
+
+        // A divisor of 48 shifted by 2 steps every 192 T-cycles.
+        noise.tick(191);
+        assert_eq!(noise.lfsr, 0x7FFF);
+        noise.tick(1);
+        assert_eq!(noise.lfsr, 0x3FFF);
+    }
+}
@@ -0,0 +1,166 @@
+//! Resampling the APU output down to a host rate.
+
+use alloc::vec::Vec;
+
+use super::SAMPLE_RATE;
+
+/// The fraction of the high-pass capacitor's charge kept every T-cycle.
+const CHARGE_PER_CYCLE: f64 = 0.999_958;
+
+/// A resampler from [`SAMPLE_RATE`] down to an output rate.
+///
+/// Each output sample averages the input samples since the last one, which
+/// filters out what the output rate can't represent. A high-pass filter
+/// then removes the DC offset, like the capacitors on the real outputs, so
+/// silence settles at 0.0.
+#[derive(Debug, Clone)]
+pub(super) struct Resampler {
+    rate: u32,
+    /// The input samples towards the next output sample, in units of
+    /// `1 / SAMPLE_RATE` of an output sample.
+    phase: u32,
+    /// The sums of the left and right input samples since the last output.
+    sum: [f32; 2],
+    count: f32,
+    /// The charge kept by the high-pass filter per output sample.
+    charge: f32,
+    capacitor: [f32; 2],
+}
+
+impl Resampler {
+    /// Create a resampler to `rate` Hz, clamped to at most [`SAMPLE_RATE`].
+    pub fn new(rate: u32) -> Self {
+        let mut resampler = Self {
+            rate: 0,
+            phase: 0,
+            sum: [0.0; 2],
+            count: 0.0,
+            charge: 0.0,
+            capacitor: [0.0; 2],
+        };
+        resampler.set_rate(rate);
+        resampler
+    }
+
+    /// Return the output rate in Hz.
+    pub const fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Change the output rate to `rate` Hz, clamped to at most
+    /// [`SAMPLE_RATE`].
+    #[allow(clippy::cast_possible_truncation)] // The charge is within 0.0 to 1.0.
+    pub fn set_rate(&mut self, rate: u32) {
+        self.rate = rate.clamp(1, SAMPLE_RATE);
+
+        let cycles = f64::from(SAMPLE_RATE) * 4.0 / f64::from(self.rate);
+        self.charge = charge_after(cycles) as f32;
+    }
+
+    /// Drop a partly accumulated output sample.
+    pub const fn clear(&mut self) {
+        self.phase = 0;
+        self.sum = [0.0; 2];
+        self.count = 0.0;
+    }
+
+    /// Resample interleaved stereo `input`, appending the output to `out`.
+    // `mul_add` needs `std`.
+    #[allow(clippy::suboptimal_flops)]
+    pub fn resample(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        for frame in input.chunks_exact(2) {
+            self.sum[0] += frame[0];
+            self.sum[1] += frame[1];
+            self.count += 1.0;
+
+            self.phase += self.rate;
+            if self.phase < SAMPLE_RATE {
+                continue;
+            }
+            self.phase -= SAMPLE_RATE;
+
+            for (side, sum) in self.sum.iter().enumerate() {
+                let level = sum / self.count;
+                let filtered = level - self.capacitor[side];
+                self.capacitor[side] = level - filtered * self.charge;
+                out.push(filtered.clamp(-1.0, 1.0));
+            }
+            self.sum = [0.0; 2];
+            self.count = 0.0;
+        }
+    }
+}
+
+/// Return the charge kept by the high-pass capacitor over `cycles` T-cycles,
+/// without the float functions of `std`.
+///
+/// Whole cycles are multiplied out by squaring. What's left of a cycle comes
+/// from the first terms of the series for `ln` and `exp`, which are exact to
+/// well within an `f32` this close to 1.0.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss,
+    clippy::suboptimal_flops
+)]
+fn charge_after(cycles: f64) -> f64 {
+    let whole = cycles as u64;
+    let (mut charge, mut square, mut bits) = (1.0, CHARGE_PER_CYCLE, whole);
+    while bits > 0 {
+        if bits & 1 != 0 {
+            charge *= square;
+        }
+        square *= square;
+        bits >>= 1;
+    }
+
+    let drain = 1.0 - CHARGE_PER_CYCLE;
+    let exponent = -(cycles - whole as f64) * (drain + drain * drain / 2.0);
+    charge * (1.0 + exponent + exponent * exponent / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn rate_is_clamped() {
+        assert_eq!(Resampler::new(0).rate(), 1);
+        assert_eq!(Resampler::new(96_000).rate(), 96_000);
+        assert_eq!(Resampler::new(u32::MAX).rate(), SAMPLE_RATE);
+    }
+
+    #[test]
+    fn full_rate_passes_every_sample() {
+        let mut resampler = Resampler::new(SAMPLE_RATE);
+        let mut out = Vec::new();
+        resampler.resample(&[0.5, -0.5, 0.25, 0.0], &mut out);
+
+        assert_eq!(out.len(), 4);
+    }
+
+    #[test]
+    fn dc_offset_decays_to_silence() {
+        let mut resampler = Resampler::new(48_000);
+        let input = vec![-1.0; SAMPLE_RATE as usize * 2];
+        let mut out = Vec::new();
+        resampler.resample(&input, &mut out);
+
+        assert!((out[0] + 1.0).abs() < 0.01);
+        assert!(out[out.len() - 2..].iter().all(|sample| sample.abs() < 0.001));
+    }
+
+    #[test]
+    fn output_stays_in_range() {
+        let mut resampler = Resampler::new(48_000);
+        let mut input = vec![-1.0; SAMPLE_RATE as usize];
+        input.extend(vec![1.0; SAMPLE_RATE as usize]);
+        let mut out = Vec::new();
+        resampler.resample(&input, &mut out);
+
+        assert!(out.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+        assert!(out.iter().any(|&sample| sample > 0.99));
+    }
+}
@@ -0,0 +1,297 @@
+//! The square channels 1 and 2.
+
+use super::units::{Envelope, Length, Sweep};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The waveforms selected by the duty bits of `NRx1`, played from the most
+/// significant bit.
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// A square channel, with a frequency sweep on channel 1.
+#[derive(Debug, Clone)]
+pub(super) struct Square {
+    pub enabled: bool,
+    duty: u8,
+    /// The step within the duty waveform.
+    position: u8,
+    /// The 11-bit frequency, from `NRx3` and `NRx4`.
+    frequency: u16,
+    /// The T-cycles until the next waveform step.
+    timer: u16,
+    length: Length,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl Square {
+    /// Create a silent square channel, with a sweep unit if `sweep` is set.
+    pub fn new(sweep: bool) -> Self {
+        Self {
+            enabled: false,
+            duty: 0,
+            position: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::default(),
+            sweep: sweep.then(Sweep::default),
+        }
+    }
+
+    /// Return to the state powering the APU down leaves the channel in,
+    /// keeping the length counter if `keep_length` is set.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = self.length.clone();
+        length.power_off(keep_length);
+        *self = Self { length, ..Self::new(self.sweep.is_some()) };
+    }
+
+    /// Return the T-cycles per waveform step.
+    const fn period(&self) -> u16 {
+        (2048 - self.frequency) * 4
+    }
+
+    /// Check if the DAC is powered.
+    pub const fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    /// Write register `NRx0` to `NRx4`, by `reg` from 0 to 4, writing `NRx4`
+    /// as [`Square::write_control`] does without an extra length clock.
+    pub fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    sweep.write(value);
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value);
+                self.enabled &= self.dac_enabled();
+            }
+            3 => self.frequency = self.frequency & 0x0700 | u16::from(value),
+            _ => self.write_control(value, false),
+        }
+    }
+
+    /// Write `NRx4`, restarting the channel if bit 7 is set. `extra_clock`
+    /// is set when the frame sequencer's next step doesn't clock length,
+    /// see [`Length::set_enabled`].
+    pub fn write_control(&mut self, value: u8, extra_clock: bool) {
+        self.frequency = u16::from(value & 0x07) << 8 | self.frequency & 0xFF;
+        let trigger = value & 0x80 != 0;
+        if self.length.set_enabled(value & 0x40 != 0, extra_clock) && !trigger {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger(extra_clock);
+        }
+    }
+
+    /// Restart the channel.
+    fn trigger(&mut self, extra_clock: bool) {
+        self.enabled = self.dac_enabled();
+        self.length.trigger(extra_clock);
+        self.envelope.trigger();
+        self.timer = self.period();
+
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.reload();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            if sweep.shift != 0 && sweep.calculate() > 0x07FF {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Advance the waveform by `cycles` T-cycles.
+    pub fn tick(&mut self, mut cycles: u16) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    /// Clock the length counter.
+    pub const fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Clock the volume envelope.
+    pub const fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clock the frequency sweep, which silences the channel on an overflow.
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+
+        sweep.reload();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+
+        let frequency = sweep.calculate();
+        if frequency > 0x07FF {
+            self.enabled = false;
+        } else if sweep.shift != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+
+            // The new frequency is checked again, without being applied.
+            if sweep.calculate() > 0x07FF {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Return the digital output, from 0 to 15.
+    pub const fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let high = DUTY[self.duty as usize] >> (7 - self.position) & 1;
+        high * self.envelope.volume
+    }
+
+    /// Write the channel to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.duty);
+        state.write_u8(self.position);
+        state.write_u16(self.frequency);
+        state.write_u16(self.timer);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+        if let Some(sweep) = &self.sweep {
+            sweep.save_state(state);
+        }
+    }
+
+    /// Restore the channel written by [`Square::save_state`].
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.duty = state.read_u8()? & 0x03;
+        self.position = state.read_u8()? & 0x07;
+        self.frequency = state.read_u16()? & 0x07FF;
+        self.timer = state.read_u16()?;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load_state(state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Return a triggered channel 2 at full volume, with `duty`.
+    fn square(duty: u8) -> Square {
+        let mut square = Square::new(false);
+        square.write(1, duty << 6);
+        square.write(2, 0xF0);
+        square.write(3, 0xFF);
+        square.write(4, 0x87);
+        square
+    }
+
+    /// Return the next 8 waveform steps, at the channel's highest frequency.
+    fn waveform(square: &mut Square) -> Vec<u8> {
+        (0..8)
+            .map(|_| {
+                square.tick(4);
+                square.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_duty_waveforms() {
+        assert_eq!(waveform(&mut square(0)), [0, 0, 0, 0, 0, 0, 15, 0]);
+        assert_eq!(waveform(&mut square(2)), [0, 0, 0, 0, 15, 15, 15, 15]);
+        assert_eq!(waveform(&mut square(3)), [15, 15, 15, 15, 15, 15, 0, 0]);
+    }
+
+    #[test]
+    fn period_follows_frequency() {
+        let mut square = square(2);
+        square.write(3, 0x00);
+        square.write(4, 0x80);
+
+        // A frequency of 0 steps every 8192 T-cycles.
+        square.tick(8191);
+        assert_eq!(square.position, 0);
+        square.tick(1);
+        assert_eq!(square.position, 1);
+    }
+
+    #[test]
+    fn length_silences_channel() {
+        let mut square = square(2);
+        square.write(1, 0x80 | 0x3E);
+        square.write(4, 0xC7);
+
+        square.clock_length();
+        assert!(square.enabled);
+        square.clock_length();
+        assert!(!square.enabled);
+    }
+
+    #[test]
+    fn disabling_dac_silences_channel() {
+        let mut square = square(2);
+        square.write(2, 0x00);
+        assert!(!square.enabled);
+
+        square.write(4, 0x80);
+        assert!(!square.enabled);
+    }
+
+    #[test]
+    fn sweep_overflow_silences_channel() {
+        let mut square = Square::new(true);
+        square.write(0, 0x11);
+        square.write(2, 0xF0);
+        square.write(3, 0x00);
+        square.write(4, 0x84);
+        assert!(square.enabled);
+
+        // 0x400 sweeps up to 0x600, which then fails the overflow check.
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x600);
+        assert!(!square.enabled);
+    }
+
+    #[test]
+    fn sweep_overflow_on_trigger() {
+        let mut square = Square::new(true);
+        square.write(0, 0x01);
+        square.write(2, 0xF0);
+        square.write(3, 0xFF);
+        square.write(4, 0x87);
+
+        assert!(!square.enabled);
+    }
+}
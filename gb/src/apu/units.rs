@@ -0,0 +1,315 @@
+//! The length, envelope and sweep units shared between channels.
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// A length counter, silencing its channel once it runs out.
+#[derive(Debug, Clone)]
+pub(super) struct Length {
+    /// The length loaded by a trigger with an expired counter.
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    /// Create an expired, disabled counter of up to `max` steps.
+    pub const fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// Load the counter from the length field of `NRx1`.
+    pub const fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    /// Enable or disable counting, from bit 6 of `NRx4`, returning whether
+    /// the counter just expired.
+    ///
+    /// `extra_clock` is set when the frame sequencer's next step doesn't
+    /// clock length. Enabling the counter then clocks it once straight away.
+    pub const fn set_enabled(&mut self, enabled: bool, extra_clock: bool) -> bool {
+        let clock = extra_clock && enabled && !self.enabled;
+        self.enabled = enabled;
+        clock && self.clock()
+    }
+
+    /// Reload an expired counter on a trigger. With `extra_clock`, as for
+    /// [`Length::set_enabled`], an enabled counter is clocked once as well.
+    pub const fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+    }
+
+    /// Disable the counter as powering the APU down does, clearing it too
+    /// unless `keep_counter` is set.
+    pub const fn power_off(&mut self, keep_counter: bool) {
+        self.enabled = false;
+        if !keep_counter {
+            self.counter = 0;
+        }
+    }
+
+    /// Clock the counter at 256 Hz, returning whether it just expired.
+    pub const fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    /// Write the counter to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_bool(self.enabled);
+    }
+
+    /// Restore the counter written by [`Length::save_state`].
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.counter = state.read_u16()?;
+        self.enabled = state.read_bool()?;
+        if self.counter > self.max {
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
+    }
+}
+
+/// A volume envelope, stepping the volume up or down at 64 Hz.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Envelope {
+    /// The volume loaded on a trigger.
+    initial: u8,
+    increase: bool,
+    /// The envelope steps between volume changes, where 0 stops it.
+    period: u8,
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    /// Write `NRx2`.
+    pub const fn write(&mut self, value: u8) {
+        self.initial = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    /// Check if the DAC is powered, which the upper 5 bits of `NRx2` control.
+    pub const fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    /// Restart the envelope on a trigger.
+    pub const fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    /// Clock the envelope at 64 Hz.
+    pub const fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+
+        self.timer = self.period;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+
+    /// Write the envelope to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.initial, self.period, self.volume, self.timer]);
+        state.write_bool(self.increase);
+    }
+
+    /// Restore the envelope written by [`Envelope::save_state`].
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        let mut bytes = [0; 4];
+        state.read_bytes(&mut bytes)?;
+        let [initial, period, volume, timer] = bytes;
+        if initial > 15 || period > 7 || volume > 15 {
+            return Err(StateError::Corrupt);
+        }
+
+        (self.initial, self.period, self.volume, self.timer) = (initial, period, volume, timer);
+        self.increase = state.read_bool()?;
+        Ok(())
+    }
+}
+
+/// The frequency sweep of channel 1.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Sweep {
+    /// The sweep steps between frequency changes, where 0 stops it.
+    pub period: u8,
+    pub negate: bool,
+    pub shift: u8,
+    pub timer: u8,
+    /// The frequency the sweep works from, copied on a trigger.
+    pub shadow: u16,
+    pub enabled: bool,
+}
+
+impl Sweep {
+    /// Write `NR10`.
+    pub const fn write(&mut self, value: u8) {
+        self.period = value >> 4 & 0x07;
+        self.negate = value & 0x08 != 0;
+        self.shift = value & 0x07;
+    }
+
+    /// Reload the timer, where a period of 0 acts as 8.
+    pub const fn reload(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    /// Return the next frequency, which silences the channel above 2047.
+    pub const fn calculate(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    /// Write the sweep to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.period, self.shift, self.timer]);
+        state.write_bool(self.negate);
+        state.write_u16(self.shadow);
+        state.write_bool(self.enabled);
+    }
+
+    /// Restore the sweep written by [`Sweep::save_state`].
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        let mut bytes = [0; 3];
+        state.read_bytes(&mut bytes)?;
+        let [period, shift, timer] = bytes;
+        if period > 7 || shift > 7 {
+            return Err(StateError::Corrupt);
+        }
+
+        (self.period, self.shift, self.timer) = (period, shift, timer);
+        self.negate = state.read_bool()?;
+        self.shadow = state.read_u16()? & 0x07FF;
+        self.enabled = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_expires_once() {
+        let mut length = Length::new(64);
+        length.load(62);
+        length.set_enabled(true, false);
+
+        assert!(!length.clock());
+        assert!(length.clock());
+        assert!(!length.clock());
+
+        length.trigger(false);
+        assert_eq!(length.counter, 64);
+    }
+
+    #[test]
+    fn extra_clock_on_enable_and_trigger() {
+        let mut length = Length::new(64);
+        length.load(62);
+
+        // Enabling clocks the counter early, but only when newly enabled.
+        assert!(!length.set_enabled(true, true));
+        assert_eq!(length.counter, 1);
+        assert!(!length.set_enabled(true, true));
+        assert_eq!(length.counter, 1);
+        length.set_enabled(false, true);
+        assert!(length.set_enabled(true, true));
+
+        // A counter reloaded while enabled loses a step, but not otherwise.
+        length.trigger(true);
+        assert_eq!(length.counter, 63);
+        length.counter = 0;
+        length.set_enabled(false, true);
+        length.trigger(true);
+        assert_eq!(length.counter, 64);
+    }
+
+    #[test]
+    fn disabled_length_holds() {
+        let mut length = Length::new(256);
+        length.load(255);
+
+        assert!(!length.clock());
+        assert_eq!(length.counter, 1);
+    }
+
+    #[test]
+    fn envelope_steps_and_saturates() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x22);
+        envelope.trigger();
+
+        envelope.clock();
+        assert_eq!(envelope.volume, 2);
+        envelope.clock();
+        assert_eq!(envelope.volume, 1);
+        for _ in 0..4 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume, 0);
+
+        envelope.write(0xE9);
+        envelope.trigger();
+        for _ in 0..4 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume, 15);
+    }
+
+    #[test]
+    fn envelope_dac_follows_upper_bits() {
+        let mut envelope = Envelope::default();
+
+        envelope.write(0x07);
+        assert!(!envelope.dac_enabled());
+        envelope.write(0x08);
+        assert!(envelope.dac_enabled());
+        envelope.write(0x10);
+        assert!(envelope.dac_enabled());
+    }
+
+    #[test]
+    fn sweep_calculates_both_ways() {
+        let mut sweep = Sweep {
+            shadow: 0x400,
+            ..Sweep::default()
+        };
+
+        sweep.write(0x11);
+        assert_eq!(sweep.calculate(), 0x600);
+        sweep.write(0x19);
+        assert_eq!(sweep.calculate(), 0x200);
+    }
+}
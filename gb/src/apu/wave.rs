@@ -0,0 +1,310 @@
+//! The wave channel 3.
+
+use super::units::Length;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The wave channel, playing 32 4-bit samples from wave RAM.
+///
+/// While the channel plays, the CPU can't reach wave RAM freely. On CGB, any
+/// access reaches the byte being played instead. On DMG, that only works in
+/// the M-cycle the channel read the byte, and other accesses read `0xFF` and
+/// drop writes.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub(super) struct Wave {
+    pub enabled: bool,
+    /// The DAC power, bit 7 of `NR30`.
+    dac: bool,
+    /// The output level bits of `NR32`.
+    level: u8,
+    /// The 11-bit frequency, from `NR33` and `NR34`.
+    frequency: u16,
+    /// The T-cycles until the next sample.
+    timer: u16,
+    /// The sample being played, from 0 to 31.
+    position: u8,
+    /// The last sample read from wave RAM.
+    sample: u8,
+    /// Whether a sample was read from wave RAM in the last tick.
+    fetched: bool,
+    /// Whether wave RAM conflicts behave as on CGB.
+    cgb: bool,
+    length: Length,
+    /// The samples, two per byte with the high nibble first.
+    pub ram: [u8; 16],
+}
+
+impl Wave {
+    /// Create a silent wave channel with cleared wave RAM, with the wave RAM
+    /// conflicts of a CGB if `cgb` is set.
+    pub const fn new(cgb: bool) -> Self {
+        Self {
+            enabled: false,
+            dac: false,
+            level: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            fetched: false,
+            cgb,
+            length: Length::new(256),
+            ram: [0; 16],
+        }
+    }
+
+    /// Return to the state powering the APU down leaves the channel in,
+    /// keeping wave RAM, and the length counter if `keep_length` is set.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = self.length.clone();
+        length.power_off(keep_length);
+        *self = Self { length, ram: self.ram, ..Self::new(self.cgb) };
+    }
+
+    /// Return the T-cycles per sample.
+    const fn period(&self) -> u16 {
+        (2048 - self.frequency) * 2
+    }
+
+    /// Check if the DAC is powered.
+    pub const fn dac_enabled(&self) -> bool {
+        self.dac
+    }
+
+    /// Return the index into wave RAM that a CPU access to `index` reaches,
+    /// if any.
+    fn ram_index(&self, index: usize) -> Option<usize> {
+        if !self.enabled {
+            return Some(index);
+        }
+
+        (self.cgb || self.fetched).then_some(usize::from(self.position / 2))
+    }
+
+    /// Read wave RAM at `index` from 0 to 15, as the CPU sees it.
+    pub fn read_ram(&self, index: usize) -> u8 {
+        self.ram_index(index).map_or(0xFF, |index| self.ram[index])
+    }
+
+    /// Write wave RAM at `index` from 0 to 15, as the CPU sees it.
+    pub fn write_ram(&mut self, index: usize, value: u8) {
+        if let Some(index) = self.ram_index(index) {
+            self.ram[index] = value;
+        }
+    }
+
+    /// Write register `NR30` to `NR34`, by `reg` from 0 to 4, writing `NR34`
+    /// as [`Wave::write_control`] does without an extra length clock.
+    pub fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.dac = value & 0x80 != 0;
+                self.enabled &= self.dac;
+            }
+            1 => self.length.load(value),
+            2 => self.level = value >> 5 & 0x03,
+            3 => self.frequency = self.frequency & 0x0700 | u16::from(value),
+            _ => self.write_control(value, false),
+        }
+    }
+
+    /// Write `NR34`, restarting the channel if bit 7 is set. `extra_clock`
+    /// is set when the frame sequencer's next step doesn't clock length,
+    /// see [`Length::set_enabled`].
+    pub fn write_control(&mut self, value: u8, extra_clock: bool) {
+        self.frequency = u16::from(value & 0x07) << 8 | self.frequency & 0xFF;
+        let trigger = value & 0x80 != 0;
+        if self.length.set_enabled(value & 0x40 != 0, extra_clock) && !trigger {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger(extra_clock);
+        }
+    }
+
+    /// Restart the channel from the first sample.
+    ///
+    /// On DMG, retriggering the channel just as it reads a sample corrupts
+    /// the start of wave RAM. The byte about to be read is copied over the
+    /// first byte if it's one of the first four, or else the four-byte block
+    /// holding it is copied over the first four.
+    fn trigger(&mut self, extra_clock: bool) {
+        if !self.cgb && self.enabled && self.timer <= 2 {
+            let next = usize::from((self.position + 1) % 32 / 2);
+            if next < 4 {
+                self.ram[0] = self.ram[next];
+            } else {
+                let block = next & !0x03;
+                self.ram.copy_within(block..block + 4, 0);
+            }
+        }
+
+        self.enabled = self.dac;
+        self.length.trigger(extra_clock);
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    /// Advance the channel by `cycles` T-cycles.
+    pub fn tick(&mut self, mut cycles: u16) {
+        self.fetched = false;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.fetched = true;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+
+            let byte = self.ram[usize::from(self.position / 2)];
+            self.sample = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0x0F };
+        }
+        self.timer -= cycles;
+    }
+
+    /// Clock the length counter.
+    pub const fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Return the digital output, from 0 to 15.
+    pub const fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        match self.level {
+            0 => 0,
+            level => self.sample >> (level - 1),
+        }
+    }
+
+    /// Write the channel to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.dac);
+        state.write_u8(self.level);
+        state.write_u16(self.frequency);
+        state.write_u16(self.timer);
+        state.write_u8(self.position);
+        state.write_u8(self.sample);
+        state.write_bool(self.fetched);
+        self.length.save_state(state);
+        state.write_bytes(&self.ram);
+    }
+
+    /// Restore the channel written by [`Wave::save_state`].
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.dac = state.read_bool()?;
+        self.level = state.read_u8()? & 0x03;
+        self.frequency = state.read_u16()? & 0x07FF;
+        self.timer = state.read_u16()?;
+        self.position = state.read_u8()? & 0x1F;
+        self.sample = state.read_u8()? & 0x0F;
+        self.fetched = state.read_bool()?;
+        self.length.load_state(state)?;
+        state.read_bytes(&mut self.ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Return a triggered wave channel at the highest frequency and `level`,
+    /// playing a ramp from 0 to 15.
+    fn wave(level: u8) -> Wave {
+        let mut wave = Wave::new(false);
+        for (i, byte) in (0..).zip(&mut wave.ram) {
+            *byte = i % 8 * 0x22 + 0x01;
+        }
+        wave.write(0, 0x80);
+        wave.write(2, level << 5);
+        wave.write(3, 0xFF);
+        wave.write(4, 0x87);
+        wave
+    }
+
+    /// Return the next `n` samples.
+    fn samples(wave: &mut Wave, n: usize) -> Vec<u8> {
+        (0..n)
+            .map(|_| {
+                wave.tick(2);
+                wave.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_wave_ram_from_second_sample() {
+        let mut wave = wave(1);
+
+        assert_eq!(samples(&mut wave, 4), [1, 2, 3, 4]);
+        samples(&mut wave, 27);
+        assert_eq!(samples(&mut wave, 1), [0]);
+    }
+
+    #[test]
+    fn level_shifts_samples() {
+        assert_eq!(samples(&mut wave(0), 3), [0, 0, 0]);
+        assert_eq!(samples(&mut wave(2), 3), [0, 1, 1]);
+        assert_eq!(samples(&mut wave(3), 7), [0, 0, 0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn dac_off_silences_channel() {
+        let mut wave = wave(1);
+        wave.write(0, 0x00);
+        assert!(!wave.enabled);
+
+        wave.write(4, 0x80);
+        assert!(!wave.enabled);
+    }
+
+    #[test]
+    fn length_counts_256_steps() {
+        let mut wave = wave(1);
+        wave.write(1, 0x00);
+        wave.write(4, 0xC7);
+
+        for _ in 0..255 {
+            wave.clock_length();
+        }
+        assert!(wave.enabled);
+        wave.clock_length();
+        assert!(!wave.enabled);
+    }
+
+    #[test]
+    fn dmg_retrigger_while_reading_corrupts_ram() {
+        let mut wave = wave(1);
+        samples(&mut wave, 9);
+        wave.tick(1);
+
+        // About to read sample 10, in byte 5 of the block from byte 4.
+        wave.write(4, 0x87);
+        assert_eq!(wave.ram[..8], [0x89, 0xAB, 0xCD, 0xEF, 0x89, 0xAB, 0xCD, 0xEF]);
+    }
+
+    #[test]
+    fn dmg_retrigger_in_first_block_copies_one_byte() {
+        let mut wave = wave(1);
+        wave.tick(3);
+        wave.write(4, 0x87);
+        assert_eq!(wave.ram[..3], [0x23, 0x23, 0x45]);
+    }
+
+    #[test]
+    fn cgb_retrigger_leaves_ram_alone() {
+        let mut wave = wave(1);
+        wave.cgb = true;
+        samples(&mut wave, 9);
+        wave.tick(1);
+
+        wave.write(4, 0x87);
+        assert_eq!(wave.ram[..2], [0x01, 0x23]);
+    }
+}
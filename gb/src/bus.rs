@@ -0,0 +1,144 @@
+//! The memory bus between the CPU and the rest of the system.
+
+use crate::interrupt::{IF, Interrupt};
+
+/// A memory bus the CPU can access.
+///
+/// Reads take `&mut self` as well as writes, since many memory-mapped I/O
+/// registers have side effects when read.
+pub trait Bus {
+    /// Read a byte from `addr`.
+    fn read(&mut self, addr: u16) -> u8;
+
+    /// Write a byte to `addr`.
+    fn write(&mut self, addr: u16, value: u8);
+
+    /// Read a byte from `addr` without any side effects, for debugging.
+    fn peek(&self, addr: u16) -> u8;
+
+    /// Read an opcode or operand byte of an instruction from `addr`.
+    ///
+    /// This is a plain read by default. It's told apart for tracing with the
+    /// `trace` feature, as an `AccessKind::Fetch`.
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// Note that the CPU put `addr` on the address bus to increment or
+    /// decrement it, without a read or write, as `INC rr` and `DEC rr` do.
+    ///
+    /// This does nothing by default. On DMG it corrupts OAM when `addr` is
+    /// in `0xFE00-0xFEFF`, see [`crate::ppu::Ppu::corrupt_oam`].
+    fn increment(&mut self, addr: u16) {
+        let _ = addr;
+    }
+
+    /// Read a byte from `addr` while the CPU increments or decrements it, as
+    /// `POP rr` and `LD A,(HL+)` do.
+    ///
+    /// This is a plain read by default, see [`Bus::increment`].
+    fn read_increment(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// Read a little-endian word from `addr`, as two byte reads from `addr`
+    /// then the address after it, wrapping at the top of memory.
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr);
+        let hi = self.read(addr.wrapping_add(1));
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// Write `value` to `addr` as a little-endian word, as two byte writes
+    /// to `addr` then the address after it, wrapping at the top of memory.
+    fn write_u16(&mut self, addr: u16, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.write(addr, lo);
+        self.write(addr.wrapping_add(1), hi);
+    }
+
+    /// Request an interrupt by setting its bit in `IF`.
+    fn request_interrupt(&mut self, kind: Interrupt) {
+        let flags = self.read(IF);
+        self.write(IF, flags | kind.bit());
+    }
+}
+
+/// A flat 64 KiB address space without any memory-mapped hardware.
+#[cfg(test)]
+pub(crate) struct FlatMemory(pub alloc::boxed::Box<[u8; 0x10000]>);
+
+#[cfg(test)]
+impl FlatMemory {
+    /// Create a zeroed memory with `program` placed at `addr`.
+    pub fn with_program(addr: u16, program: &[u8]) -> Self {
+        let mut memory = Self(alloc::vec![0; 0x10000].try_into().unwrap());
+        let start = usize::from(addr);
+        memory.0[start..start + program.len()].copy_from_slice(program);
+        memory
+    }
+}
+
+#[cfg(test)]
+impl Bus for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.0[usize::from(addr)]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.0[usize::from(addr)] = value;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.0[usize::from(addr)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// A memory recording every access made to it.
+    struct Recorder {
+        memory: FlatMemory,
+        accesses: Vec<(u16, Option<u8>)>,
+    }
+
+    impl Bus for Recorder {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.accesses.push((addr, None));
+            self.memory.read(addr)
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.accesses.push((addr, Some(value)));
+            self.memory.write(addr, value);
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.memory.peek(addr)
+        }
+    }
+
+    #[test]
+    fn words_are_little_endian_byte_accesses() {
+        let mut bus = Recorder {
+            memory: FlatMemory::with_program(0, &[]),
+            accesses: Vec::new(),
+        };
+
+        bus.write_u16(0xC000, 0x1234);
+        assert_eq!(bus.accesses, [(0xC000, Some(0x34)), (0xC001, Some(0x12))]);
+        bus.accesses.clear();
+        assert_eq!(bus.read_u16(0xC000), 0x1234);
+        assert_eq!(bus.accesses, [(0xC000, None), (0xC001, None)]);
+
+        // The second byte wraps around to the bottom of memory.
+        bus.write_u16(0xFFFF, 0xBEEF);
+        assert_eq!(bus.peek(0xFFFF), 0xEF);
+        assert_eq!(bus.peek(0x0000), 0xBE);
+        assert_eq!(bus.read_u16(0xFFFF), 0xBEEF);
+    }
+}
@@ -0,0 +1,240 @@
+//! The memory bank controller of the Game Boy Camera.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{CartridgeHeader, Mbc};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The bit of the RAM bank register that maps the camera registers.
+const SELECT_REGISTERS: u8 = 0x10;
+
+/// The number of camera registers, repeated across the RAM region.
+const REGISTER_COUNT: usize = 0x36;
+
+/// The bit of the first register that starts a capture, and reads set while
+/// one runs.
+const CAPTURE: u8 = 0x01;
+
+/// The offset into RAM bank 0 of the captured image.
+const IMAGE_RAM: usize = 0x0100;
+
+/// The Game Boy Camera, supporting up to 1 MiB of ROM, 128 KiB of RAM and
+/// its image sensor.
+///
+/// Setting bit 4 of the RAM bank register maps the sensor registers into
+/// `0xA000-0xBFFF`. No sensor is emulated: a capture completes at once,
+/// copying the image set with [`Mbc::set_camera_image`] into RAM bank 0 as
+/// tiles, without the exposure and dithering settings applied.
+#[derive(Debug, Clone)]
+pub struct PocketCamera {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    ram_enabled: bool,
+    /// The 6-bit ROM bank number.
+    rom_bank: u8,
+    /// The RAM bank register, a 4-bit bank number or the register select.
+    ram_bank: u8,
+    registers: [u8; REGISTER_COUNT],
+    /// The image captured, as shades from 0 to 3, row by row.
+    image: Box<[u8; Self::PIXELS]>,
+}
+
+impl PocketCamera {
+    /// The width of the captured image in pixels.
+    pub const WIDTH: usize = 128;
+
+    /// The height of the captured image in pixels.
+    pub const HEIGHT: usize = 112;
+
+    /// The number of pixels in the captured image.
+    pub const PIXELS: usize = Self::WIDTH * Self::HEIGHT;
+
+    /// Create a controller around `rom`, sizing RAM from its `header`.
+    ///
+    /// The image starts out blank.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, header: &CartridgeHeader) -> Self {
+        Self {
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; header.ram_size].into_boxed_slice(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: [0; REGISTER_COUNT],
+            image: Box::new([0; Self::PIXELS]),
+        }
+    }
+
+    /// Return the offset into RAM of `addr` in the selected bank.
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+
+        let offset = usize::from(self.ram_bank & 0x0F) << 13 | usize::from(addr & 0x1FFF);
+        Some(offset % self.ram.len())
+    }
+
+    /// Copy the image into RAM as 2-bit tiles, 16 across and 14 down.
+    fn capture(&mut self) {
+        let Some(tiles) = self.ram.get_mut(IMAGE_RAM..IMAGE_RAM + Self::PIXELS / 4) else {
+            return;
+        };
+
+        tiles.fill(0);
+        for (index, &shade) in self.image.iter().enumerate() {
+            let (x, y) = (index % Self::WIDTH, index / Self::WIDTH);
+            let tile = (y / 8 * (Self::WIDTH / 8) + x / 8) * 16;
+            let row = tile + y % 8 * 2;
+            let bit = 0x80 >> (x % 8);
+            if shade & 1 != 0 {
+                tiles[row] |= bit;
+            }
+            if shade & 2 != 0 {
+                tiles[row + 1] |= bit;
+            }
+        }
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        let offset = usize::from(bank) << 14 | usize::from(addr & 0x3FFF);
+
+        if self.rom.is_empty() {
+            0xFF
+        } else {
+            self.rom[offset % self.rom.len()]
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Like the MBC5, bank 0 can be mapped here.
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & (SELECT_REGISTERS | 0x0F),
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ram_bank & SELECT_REGISTERS != 0 {
+            // Only the first register can be read back.
+            return match addr & 0x7F {
+                0 => self.registers[0],
+                _ => 0x00,
+            };
+        }
+
+        // RAM can be read even while writes to it are disabled.
+        self.ram_offset(addr).map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_bank & SELECT_REGISTERS != 0 {
+            let index = usize::from(addr & 0x7F);
+            if let Some(register) = self.registers.get_mut(index) {
+                *register = value;
+            }
+            if index == 0 && value & CAPTURE != 0 {
+                self.capture();
+                self.registers[0] &= !CAPTURE;
+            }
+            return;
+        }
+
+        if self.ram_enabled
+            && let Some(offset) = self.ram_offset(addr)
+        {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn set_camera_image(&mut self, image: &[u8; Self::PIXELS]) {
+        for (pixel, &shade) in self.image.iter_mut().zip(image) {
+            *pixel = shade & 0x03;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.rom_bank);
+        state.write_u8(self.ram_bank);
+        state.write_bytes(&self.registers);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.rom_bank = state.read_u8()? & 0x3F;
+        self.ram_bank = state.read_u8()? & (SELECT_REGISTERS | 0x0F);
+        state.read_bytes(&mut self.registers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    fn camera() -> PocketCamera {
+        // 1 MiB, 64 banks, with 128 KiB of RAM.
+        let rom = test_rom(0xFC, [0x05, 0x04]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        PocketCamera::new(rom, &header)
+    }
+
+    #[test]
+    fn capture_copies_image_as_tiles() {
+        let mut mbc = camera();
+        let mut image = [0; PocketCamera::PIXELS];
+        // Shades 1, 2 and 3 at the start of the first row, and shade 3 in the
+        // last pixel.
+        image[..3].copy_from_slice(&[1, 2, 3]);
+        image[PocketCamera::PIXELS - 1] = 3;
+        mbc.set_camera_image(&image);
+
+        mbc.write_rom(0x4000, 0x10);
+        mbc.write_ram(0xA000, 0x03);
+        assert_eq!(mbc.read_ram(0xA000), 0x02);
+        assert_eq!(mbc.read_ram(0xA001), 0x00);
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA100), 0b1010_0000);
+        assert_eq!(mbc.read_ram(0xA101), 0b0110_0000);
+        assert_eq!(mbc.read_ram(0xAEFE), 0x01);
+        assert_eq!(mbc.read_ram(0xAEFF), 0x01);
+    }
+
+    #[test]
+    fn ram_writes_need_enable() {
+        let mut mbc = camera();
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(0xA000, 0x42);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x42);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+        assert_eq!(mbc.ram()[3 << 13], 0x42);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x00);
+    }
+}
@@ -0,0 +1,447 @@
+//! The cartridge header at `0x0100-0x014F`.
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use core::error::Error;
+use core::fmt;
+
+/// The offset of the Nintendo logo in the ROM.
+pub(super) const LOGO_START: usize = 0x0104;
+/// The Nintendo logo the boot ROM checks before starting a cartridge.
+pub(super) const LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+/// The offset of the title in the ROM.
+const TITLE: usize = 0x0134;
+/// The offset of the CGB flag in the ROM.
+const CGB_FLAG: usize = 0x0143;
+/// The offset of the SGB flag in the ROM.
+const SGB_FLAG: usize = 0x0146;
+/// The offset of the cartridge type in the ROM.
+const CARTRIDGE_TYPE: usize = 0x0147;
+/// The offset of the ROM size code in the ROM.
+const ROM_SIZE: usize = 0x0148;
+/// The offset of the RAM size code in the ROM.
+const RAM_SIZE: usize = 0x0149;
+/// The offset of the destination code in the ROM.
+const DESTINATION: usize = 0x014A;
+/// The offset of the first byte covered by the header checksum.
+const HEADER_CHECKSUM_START: usize = 0x0134;
+/// The offset of the header checksum in the ROM.
+const HEADER_CHECKSUM: usize = 0x014D;
+/// The offset of the big-endian global checksum in the ROM.
+const GLOBAL_CHECKSUM: usize = 0x014E;
+
+/// The length of a ROM just large enough to hold a header.
+pub(super) const HEADER_END: usize = 0x0150;
+
+/// An error encountered while parsing a cartridge header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The ROM is too short to contain a header.
+    TooShort {
+        /// The length of the ROM in bytes.
+        len: usize,
+    },
+    /// The ROM size code at `0x0148` is not a known size.
+    UnknownRomSize(u8),
+    /// The RAM size code at `0x0149` is not a known size.
+    UnknownRamSize(u8),
+    /// The cartridge type names a controller that is not emulated.
+    UnsupportedMapper(MapperKind),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { len } => {
+                write!(f, "ROM is {len} bytes, too short to contain a header")
+            }
+            Self::UnknownRomSize(code) => write!(f, "unknown ROM size code {code:#04X}"),
+            Self::UnknownRamSize(code) => write!(f, "unknown RAM size code {code:#04X}"),
+            Self::UnsupportedMapper(kind) => write!(f, "unsupported mapper {kind:?}"),
+        }
+    }
+}
+
+impl Error for HeaderError {}
+
+/// The level of CGB support a cartridge declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CgbSupport {
+    /// A DMG cartridge, run in compatibility mode on a CGB.
+    None,
+    /// A cartridge that uses CGB features but also runs on a DMG.
+    Enhanced,
+    /// A cartridge that only runs on a CGB.
+    Required,
+}
+
+/// The memory bank controller a cartridge declares, decoded from its type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapperKind {
+    /// No controller, for ROMs of at most 32 KiB.
+    None,
+    /// The MBC1.
+    Mbc1,
+    /// The MBC2, with its built-in 512 nibbles of RAM.
+    Mbc2,
+    /// The MMM01 multicart controller.
+    Mmm01,
+    /// The MBC3, optionally with a real-time clock.
+    Mbc3,
+    /// The MBC5, optionally with a rumble motor.
+    Mbc5,
+    /// The MBC6.
+    Mbc6,
+    /// The MBC7, with its accelerometer and EEPROM.
+    Mbc7,
+    /// The Game Boy Camera.
+    PocketCamera,
+    /// Bandai's TAMA5.
+    Tama5,
+    /// Hudson's `HuC3`.
+    HuC3,
+    /// Hudson's `HuC1`, with its infrared port.
+    HuC1,
+    /// A cartridge type byte that matches no known controller.
+    Unknown(u8),
+}
+
+impl MapperKind {
+    /// Decode the cartridge type byte at `0x0147`.
+    #[must_use]
+    pub const fn from_cartridge_type(kind: u8) -> Self {
+        match kind {
+            0x00 | 0x08 | 0x09 => Self::None,
+            0x01..=0x03 => Self::Mbc1,
+            0x05 | 0x06 => Self::Mbc2,
+            0x0B..=0x0D => Self::Mmm01,
+            0x0F..=0x13 => Self::Mbc3,
+            0x19..=0x1E => Self::Mbc5,
+            0x20 => Self::Mbc6,
+            0x22 => Self::Mbc7,
+            0xFC => Self::PocketCamera,
+            0xFD => Self::Tama5,
+            0xFE => Self::HuC3,
+            0xFF => Self::HuC1,
+            kind => Self::Unknown(kind),
+        }
+    }
+}
+
+/// The result of verifying both cartridge checksums.
+///
+/// The boot ROM refuses to run a cartridge with a bad header checksum, while
+/// the global checksum is never verified by hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChecksumStatus {
+    /// Whether the header checksum matches.
+    pub header: bool,
+    /// Whether the global checksum matches.
+    pub global: bool,
+}
+
+impl ChecksumStatus {
+    /// Check if both checksums match.
+    #[must_use]
+    pub const fn is_valid(self) -> bool {
+        self.header && self.global
+    }
+}
+
+/// The parsed cartridge header.
+///
+/// See the "The Cartridge Header" section of Pan Docs for the layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    /// The game title, in upper case ASCII without trailing nulls.
+    pub title: String,
+    /// The declared CGB support.
+    pub cgb: CgbSupport,
+    /// Whether the cartridge supports SGB functions.
+    pub sgb: bool,
+    /// The raw cartridge type byte.
+    pub cartridge_type: u8,
+    /// The size of the ROM in bytes.
+    pub rom_size: usize,
+    /// The size of the external RAM in bytes.
+    pub ram_size: usize,
+    /// The destination code, 0 for Japan and 1 for everywhere else.
+    pub destination: u8,
+    /// The header checksum at `0x014D`.
+    pub header_checksum: u8,
+    /// The global checksum at `0x014E-0x014F`.
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    /// Parse the header of `rom`.
+    pub fn parse(rom: &[u8]) -> Result<Self, HeaderError> {
+        if rom.len() < HEADER_END {
+            return Err(HeaderError::TooShort { len: rom.len() });
+        }
+
+        let cgb = match rom[CGB_FLAG] {
+            0xC0 => CgbSupport::Required,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        // CGB-era headers repurpose the end of the title for the manufacturer
+        // code and the CGB flag.
+        let title_len = if cgb == CgbSupport::None { 16 } else { 11 };
+        let title = rom[TITLE..TITLE + title_len]
+            .iter()
+            .map(|&byte| char::from(byte))
+            .collect::<String>()
+            .trim_end_matches('\0')
+            .to_owned();
+
+        Ok(Self {
+            title,
+            cgb,
+            sgb: rom[SGB_FLAG] == 0x03,
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size: rom_size(rom[ROM_SIZE])?,
+            ram_size: ram_size(rom[RAM_SIZE])?,
+            destination: rom[DESTINATION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: u16::from_be_bytes([rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]]),
+        })
+    }
+
+    /// Return the memory bank controller declared by the cartridge type.
+    #[must_use]
+    pub const fn mapper_kind(&self) -> MapperKind {
+        MapperKind::from_cartridge_type(self.cartridge_type)
+    }
+
+    /// Check if the cartridge type declares a battery to back its RAM.
+    #[must_use]
+    pub const fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFC..=0xFF
+        )
+    }
+
+    /// Verify the header checksum against the header bytes of `rom`.
+    ///
+    /// The checksum is computed as `x = x - rom[i] - 1` over `0x0134-0x014C`.
+    #[must_use]
+    pub fn verify_header_checksum(&self, rom: &[u8]) -> bool {
+        let Some(bytes) = rom.get(HEADER_CHECKSUM_START..HEADER_CHECKSUM) else {
+            return false;
+        };
+
+        let checksum = bytes
+            .iter()
+            .fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1));
+        checksum == self.header_checksum
+    }
+
+    /// Verify the global checksum against the whole of `rom`.
+    ///
+    /// The checksum is the wrapping sum of every byte in the ROM, excluding
+    /// the two checksum bytes themselves.
+    #[must_use]
+    pub fn verify_global_checksum(&self, rom: &[u8]) -> bool {
+        let checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != GLOBAL_CHECKSUM && i != GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(u16::from(byte)));
+        checksum == self.global_checksum
+    }
+
+    /// Verify both checksums against `rom`.
+    #[must_use]
+    pub fn checksums(&self, rom: &[u8]) -> ChecksumStatus {
+        ChecksumStatus {
+            header: self.verify_header_checksum(rom),
+            global: self.verify_global_checksum(rom),
+        }
+    }
+}
+
+/// Decode a ROM size code into a size in bytes.
+const fn rom_size(code: u8) -> Result<usize, HeaderError> {
+    match code {
+        0x00..=0x08 => Ok(0x8000 << code),
+        code => Err(HeaderError::UnknownRomSize(code)),
+    }
+}
+
+/// Decode a RAM size code into a size in bytes.
+const fn ram_size(code: u8) -> Result<usize, HeaderError> {
+    match code {
+        0x00 => Ok(0),
+        0x01 => Ok(0x800),
+        0x02 => Ok(0x2000),
+        0x03 => Ok(0x8000),
+        0x04 => Ok(0x2_0000),
+        0x05 => Ok(0x1_0000),
+        code => Err(HeaderError::UnknownRamSize(code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use alloc::vec;
+
+    use super::*;
+
+    /// Build a 32 KiB ROM with `title` and the given header bytes.
+    fn build_rom(title: &[u8], cgb: u8, kind: u8, sizes: [u8; 2]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[TITLE..TITLE + title.len()].copy_from_slice(title);
+        rom[CGB_FLAG] = cgb;
+        rom[CARTRIDGE_TYPE] = kind;
+        [rom[ROM_SIZE], rom[RAM_SIZE]] = sizes;
+        rom
+    }
+
+    #[test]
+    fn parses_fields() {
+        let mut rom = build_rom(b"TETRIS", 0x00, 0x00, [0x00, 0x00]);
+        rom[SGB_FLAG] = 0x03;
+        rom[DESTINATION] = 0x01;
+        rom[HEADER_CHECKSUM] = 0x0A;
+        rom[GLOBAL_CHECKSUM] = 0x16;
+        rom[GLOBAL_CHECKSUM + 1] = 0xBF;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert!(header.sgb);
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.ram_size, 0);
+        assert_eq!(header.destination, 0x01);
+        assert_eq!(header.header_checksum, 0x0A);
+        assert_eq!(header.global_checksum, 0x16BF);
+        assert_eq!(header.mapper_kind(), MapperKind::None);
+    }
+
+    #[test]
+    fn title_layouts() {
+        // A 16 byte title runs into the CGB flag on DMG cartridges.
+        let rom = build_rom(b"ABCDEFGHIJKLMNO", 0x50, 0x00, [0x00, 0x00]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "ABCDEFGHIJKLMNOP");
+
+        // CGB cartridges end the title before the manufacturer code.
+        let rom = build_rom(b"POKEMON_SLVAAXE", 0x80, 0x10, [0x06, 0x03]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.cgb, CgbSupport::Enhanced);
+        assert_eq!(header.rom_size, 0x20_0000);
+        assert_eq!(header.ram_size, 0x8000);
+        assert_eq!(header.mapper_kind(), MapperKind::Mbc3);
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(
+            CartridgeHeader::parse(&[0; 0x014F]),
+            Err(HeaderError::TooShort { len: 0x014F })
+        );
+    }
+
+    #[test]
+    fn unknown_sizes() {
+        let rom = build_rom(b"", 0x00, 0x00, [0x52, 0x00]);
+        assert_eq!(CartridgeHeader::parse(&rom), Err(HeaderError::UnknownRomSize(0x52)));
+
+        let rom = build_rom(b"", 0x00, 0x00, [0x00, 0x06]);
+        assert_eq!(CartridgeHeader::parse(&rom), Err(HeaderError::UnknownRamSize(0x06)));
+    }
+
+    /// The header bytes `0x0134-0x014F` of Tetris (World) (Rev 1).
+    const TETRIS: [u8; 28] = [
+        b'T', b'E', b'T', b'R', b'I', b'S', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x0A, 0x16, 0xBF,
+    ];
+
+    /// The header bytes `0x0134-0x014F` of Pokemon Red (USA, Europe).
+    const POKEMON_RED: [u8; 28] = [
+        b'P', b'O', b'K', b'E', b'M', b'O', b'N', b' ', b'R', b'E', b'D', 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x30, 0x31, 0x03, 0x13, 0x05, 0x03, 0x01, 0x33, 0x00, 0x20, 0x91, 0xE6,
+    ];
+
+    /// Place commercial header bytes into an otherwise empty ROM.
+    fn with_header(bytes: &[u8; 28]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[TITLE..HEADER_END].copy_from_slice(bytes);
+        rom
+    }
+
+    #[test]
+    fn commercial_header_checksums() {
+        for bytes in [TETRIS, POKEMON_RED] {
+            let rom = with_header(&bytes);
+            let header = CartridgeHeader::parse(&rom).unwrap();
+            assert!(header.verify_header_checksum(&rom), "{}", header.title);
+        }
+
+        let header = CartridgeHeader::parse(&with_header(&POKEMON_RED)).unwrap();
+        assert_eq!(header.title, "POKEMON RED");
+        assert_eq!(header.mapper_kind(), MapperKind::Mbc3);
+        assert_eq!(header.global_checksum, 0x91E6);
+    }
+
+    #[test]
+    fn corrupt_header_checksum() {
+        let mut rom = with_header(&TETRIS);
+        rom[TITLE] = b'S';
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        let status = header.checksums(&rom);
+
+        assert!(!status.header);
+        assert!(!status.is_valid());
+    }
+
+    #[test]
+    fn global_checksum_skips_itself() {
+        let mut rom = with_header(&TETRIS);
+        rom[0x4000] = 0x80;
+
+        let sum = rom.iter().map(|&byte| u16::from(byte)).sum::<u16>() - 0x16 - 0xBF;
+        [rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]] = sum.to_be_bytes();
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(
+            header.checksums(&rom),
+            ChecksumStatus { header: true, global: true }
+        );
+
+        rom[0x7FFF] = 0x01;
+        assert!(!header.verify_global_checksum(&rom));
+    }
+
+    #[test]
+    fn mapper_kinds() {
+        let kinds = [0x00, 0x03, 0x06, 0x13, 0x1E, 0x22, 0xFC, 0xFF, 0x42]
+            .map(MapperKind::from_cartridge_type);
+
+        assert_eq!(
+            kinds,
+            [
+                MapperKind::None,
+                MapperKind::Mbc1,
+                MapperKind::Mbc2,
+                MapperKind::Mbc3,
+                MapperKind::Mbc5,
+                MapperKind::Mbc7,
+                MapperKind::PocketCamera,
+                MapperKind::HuC1,
+                MapperKind::Unknown(0x42),
+            ]
+        );
+    }
+}
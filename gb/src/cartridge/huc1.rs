@@ -0,0 +1,173 @@
+//! Hudson's `HuC1` memory bank controller.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{CartridgeHeader, IR_NO_LIGHT, Mbc};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The value of the select register that maps the infrared port.
+const SELECT_IR: u8 = 0x0E;
+
+/// The `HuC1`, supporting up to 1 MiB of ROM, 32 KiB of RAM and an infrared
+/// port.
+///
+/// It banks ROM like an MBC1 in its ROM banking mode, but has no mode select.
+/// The register at `0x0000-0x1FFF` instead maps either RAM or the infrared
+/// port into `0xA000-0xBFFF`.
+#[derive(Debug, Clone)]
+pub struct Huc1 {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    /// Whether the infrared port is mapped in place of RAM.
+    ir_selected: bool,
+    /// The 6-bit ROM bank number.
+    rom_bank: u8,
+    /// The 2-bit RAM bank number.
+    ram_bank: u8,
+}
+
+impl Huc1 {
+    /// Create a controller around `rom`, sizing RAM from its `header`.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, header: &CartridgeHeader) -> Self {
+        Self {
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; header.ram_size.min(0x8000)].into_boxed_slice(),
+            ir_selected: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    /// Return the offset into RAM of `addr` in the selected bank.
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+
+        let offset = usize::from(self.ram_bank) << 13 | usize::from(addr & 0x1FFF);
+        Some(offset % self.ram.len())
+    }
+}
+
+impl Mbc for Huc1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        let offset = usize::from(bank) << 14 | usize::from(addr & 0x3FFF);
+
+        if self.rom.is_empty() {
+            0xFF
+        } else {
+            self.rom[offset % self.rom.len()]
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ir_selected = value & 0x0F == SELECT_IR,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x3F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            // There is no banking mode to select.
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ir_selected {
+            return IR_NO_LIGHT;
+        }
+
+        self.ram_offset(addr).map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        // Writes to the infrared port would switch its LED, which nothing
+        // can see.
+        if self.ir_selected {
+            return;
+        }
+
+        if let Some(offset) = self.ram_offset(addr) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ir_selected);
+        state.write_u8(self.rom_bank);
+        state.write_u8(self.ram_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.ir_selected = state.read_bool()?;
+        self.rom_bank = (state.read_u8()? & 0x3F).max(1);
+        self.ram_bank = state.read_u8()? & 0x03;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    fn huc1() -> Huc1 {
+        // 1 MiB, 64 banks, with 32 KiB of RAM.
+        let rom = test_rom(0xFF, [0x05, 0x03]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        Huc1::new(rom, &header)
+    }
+
+    #[test]
+    fn banks_rom_like_mbc1() {
+        let mut mbc = huc1();
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        mbc.write_rom(0x2000, 0x25);
+        assert_eq!(mbc.read_rom(0x4000), 0x25);
+        assert_eq!(mbc.read_rom(0x7FFF), 0x25);
+        mbc.write_rom(0x3FFF, 0x7F);
+        assert_eq!(mbc.read_rom(0x4000), 0x3F);
+
+        // The mode select region does nothing, and bank 0 stays fixed.
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x3F);
+    }
+
+    #[test]
+    fn ir_select_maps_port_over_ram() {
+        let mut mbc = huc1();
+        mbc.write_ram(0xA000, 0x11);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xA000, 0x22);
+        assert_eq!(mbc.read_ram(0xA000), 0x22);
+
+        mbc.write_rom(0x0000, 0x0E);
+        assert_eq!(mbc.read_ram(0xA000), IR_NO_LIGHT);
+        mbc.write_ram(0xA000, 0x01);
+
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0xA000), 0x22);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+    }
+}
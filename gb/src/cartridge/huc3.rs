@@ -0,0 +1,347 @@
+//! Hudson's `HuC3` memory bank controller and its clock.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::mbc3::now;
+use super::{CartridgeHeader, IR_NO_LIGHT, Mbc};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The minutes in a day, after which the minute counter wraps.
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// What the select register at `0x0000-0x1FFF` maps into `0xA000-0xBFFF`.
+mod select {
+    /// RAM, for reading only.
+    pub const RAM_READ: u8 = 0x00;
+    /// RAM, for reading and writing.
+    pub const RAM: u8 = 0x0A;
+    /// The clock command register, for writing.
+    pub const COMMAND: u8 = 0x0B;
+    /// The clock response register, for reading.
+    pub const RESPONSE: u8 = 0x0C;
+    /// The clock semaphore, which reads 1 once a command is done.
+    pub const SEMAPHORE: u8 = 0x0D;
+    /// The infrared port.
+    pub const IR: u8 = 0x0E;
+}
+
+/// The clock of a `HuC3` cartridge, reached through nibble commands.
+///
+/// The chip has 256 nibbles of memory. The time is copied into the first six
+/// on request, as a 12-bit minute of the day and a 12-bit day counter, both
+/// least significant nibble first, and can be written back the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Clock {
+    /// The minute of the day, 0-1439.
+    minutes: u16,
+    /// The 12-bit day counter.
+    days: u16,
+    /// The seconds into the current minute.
+    seconds: u8,
+    /// The host time in seconds since the Unix epoch at which the counters
+    /// were last brought up to date.
+    timestamp: u64,
+    memory: [u8; 0x100],
+    /// The memory address read and written by commands.
+    address: u8,
+    /// The last command, echoed in the upper nibble of the response.
+    command: u8,
+    /// The nibble produced by the last command.
+    response: u8,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Self {
+            minutes: 0,
+            days: 0,
+            seconds: 0,
+            timestamp: now(),
+            memory: [0; 0x100],
+            address: 0,
+            command: 0,
+            response: 0,
+        }
+    }
+
+    /// Advance the counters to the host time `now`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn update(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.timestamp);
+        self.timestamp = now;
+
+        let seconds = u64::from(self.seconds) + elapsed;
+        let minutes = u64::from(self.minutes) + seconds / 60;
+        let days = u64::from(self.days) + minutes / u64::from(MINUTES_PER_DAY);
+
+        // Each value was reduced below its modulus, so the casts are lossless.
+        self.seconds = (seconds % 60) as u8;
+        self.minutes = (minutes % u64::from(MINUTES_PER_DAY)) as u16;
+        self.days = (days % 0x1000) as u16;
+    }
+
+    /// Run the command in the upper nibble of `value`, with the lower nibble
+    /// as its argument.
+    fn execute(&mut self, value: u8, now: u64) {
+        let arg = value & 0x0F;
+        self.command = value >> 4;
+
+        match self.command {
+            0x1 => {
+                self.response = self.memory[usize::from(self.address)];
+                self.address = self.address.wrapping_add(1);
+            }
+            0x3 => {
+                self.memory[usize::from(self.address)] = arg;
+                self.address = self.address.wrapping_add(1);
+            }
+            0x4 => self.address = self.address & 0xF0 | arg,
+            0x5 => self.address = self.address & 0x0F | arg << 4,
+            0x6 => match arg {
+                0x0 => self.store_time(now),
+                0x1 => self.load_time(now),
+                // The status check always reports the clock as ready.
+                0x2 => self.response = 0x1,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Copy the time into the first six nibbles of memory.
+    #[allow(clippy::cast_possible_truncation)]
+    fn store_time(&mut self, now: u64) {
+        self.update(now);
+        let time = u32::from(self.minutes) | u32::from(self.days) << 12;
+        for (index, nibble) in self.memory[..6].iter_mut().enumerate() {
+            *nibble = (time >> (index * 4)) as u8 & 0x0F;
+        }
+    }
+
+    /// Set the time from the first six nibbles of memory.
+    #[allow(clippy::cast_possible_truncation)]
+    fn load_time(&mut self, now: u64) {
+        self.update(now);
+        let time = self.memory[..6]
+            .iter()
+            .rev()
+            .fold(0, |time, &nibble| time << 4 | u32::from(nibble & 0x0F));
+        self.minutes = (time & 0xFFF) as u16 % MINUTES_PER_DAY;
+        self.days = (time >> 12) as u16;
+        self.seconds = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.minutes);
+        state.write_u16(self.days);
+        state.write_u8(self.seconds);
+        state.write_u64(self.timestamp);
+        state.write_bytes(&self.memory);
+        state.write_u8(self.address);
+        state.write_u8(self.command);
+        state.write_u8(self.response);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.minutes = state.read_u16()? % MINUTES_PER_DAY;
+        self.days = state.read_u16()? & 0xFFF;
+        self.seconds = state.read_u8()? % 60;
+        self.timestamp = state.read_u64()?;
+        state.read_bytes(&mut self.memory)?;
+        self.address = state.read_u8()?;
+        self.command = state.read_u8()? & 0x0F;
+        self.response = state.read_u8()? & 0x0F;
+        Ok(())
+    }
+}
+
+/// The `HuC3`, supporting up to 2 MiB of ROM, 32 KiB of RAM, a clock and an
+/// infrared port.
+///
+/// The register at `0x0000-0x1FFF` picks what `0xA000-0xBFFF` maps: RAM, one
+/// of the clock registers, or the infrared port. The clock is kept in save
+/// states, but not in save files.
+#[derive(Debug, Clone)]
+pub struct Huc3 {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    clock: Clock,
+    /// The value of the select register.
+    select: u8,
+    /// The 7-bit ROM bank number.
+    rom_bank: u8,
+    /// The 2-bit RAM bank number.
+    ram_bank: u8,
+}
+
+impl Huc3 {
+    /// Create a controller around `rom`, sizing RAM from its `header`.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, header: &CartridgeHeader) -> Self {
+        Self {
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; header.ram_size.min(0x8000)].into_boxed_slice(),
+            clock: Clock::new(),
+            select: select::RAM_READ,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    /// Return the offset into RAM of `addr` in the selected bank.
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+
+        let offset = usize::from(self.ram_bank) << 13 | usize::from(addr & 0x1FFF);
+        Some(offset % self.ram.len())
+    }
+}
+
+impl Mbc for Huc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        let offset = usize::from(bank) << 14 | usize::from(addr & 0x3FFF);
+
+        if self.rom.is_empty() {
+            0xFF
+        } else {
+            self.rom[offset % self.rom.len()]
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.select = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match self.select {
+            select::RAM_READ | select::RAM => {
+                self.ram_offset(addr).map_or(0xFF, |offset| self.ram[offset])
+            }
+            select::RESPONSE => self.clock.command << 4 | self.clock.response,
+            // Commands finish as soon as they are written, so the semaphore
+            // always reads as ready.
+            select::SEMAPHORE => 0x01,
+            select::IR => IR_NO_LIGHT,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        match self.select {
+            select::RAM => {
+                if let Some(offset) = self.ram_offset(addr) {
+                    self.ram[offset] = value;
+                }
+            }
+            select::COMMAND => self.clock.execute(value, now()),
+            // The semaphore, the infrared LED and read-only RAM ignore writes.
+            _ => {}
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.select);
+        state.write_u8(self.rom_bank);
+        state.write_u8(self.ram_bank);
+        self.clock.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.select = state.read_u8()? & 0x0F;
+        self.rom_bank = (state.read_u8()? & 0x7F).max(1);
+        self.ram_bank = state.read_u8()? & 0x03;
+        self.clock.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    fn huc3() -> Huc3 {
+        // 1 MiB, 64 banks, with 32 KiB of RAM.
+        let rom = test_rom(0xFE, [0x05, 0x03]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        Huc3::new(rom, &header)
+    }
+
+    /// Read the six time nibbles back through the response register.
+    fn read_time(mbc: &mut Huc3) -> [u8; 6] {
+        mbc.write_rom(0x0000, select::COMMAND);
+        mbc.write_ram(0xA000, 0x40);
+        mbc.write_ram(0xA000, 0x50);
+        [0; 6].map(|_| {
+            mbc.write_rom(0x0000, select::COMMAND);
+            mbc.write_ram(0xA000, 0x10);
+            mbc.write_rom(0x0000, select::RESPONSE);
+            mbc.read_ram(0xA000) & 0x0F
+        })
+    }
+
+    #[test]
+    fn select_register_maps_ram() {
+        let mut mbc = huc3();
+        mbc.write_rom(0x2000, 0x21);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+
+        mbc.write_rom(0x0000, select::RAM);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0xA000, 0x42);
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+
+        // Select 0 maps RAM for reading only.
+        mbc.write_rom(0x0000, select::RAM_READ);
+        mbc.write_ram(0xA000, 0x99);
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+
+        mbc.write_rom(0x0000, select::IR);
+        assert_eq!(mbc.read_ram(0xA000), IR_NO_LIGHT);
+        mbc.write_rom(0x0000, select::SEMAPHORE);
+        assert_eq!(mbc.read_ram(0xA000) & 1, 1);
+    }
+
+    #[test]
+    fn clock_time_is_read_and_written_through_memory() {
+        let mut mbc = huc3();
+        mbc.clock.timestamp = 1_000;
+        // 1:02 on day 3.
+        mbc.clock.minutes = 62;
+        mbc.clock.days = 3;
+
+        mbc.clock.execute(0x60, 1_000);
+        assert_eq!(read_time(&mut mbc), [0xE, 0x3, 0x0, 0x3, 0x0, 0x0]);
+
+        // Write 23:59 on day 0x123 and let two minutes pass.
+        mbc.clock.execute(0x40, 1_000);
+        for nibble in [0xF, 0x9, 0x5, 0x3, 0x2, 0x1] {
+            mbc.clock.execute(0x30 | nibble, 1_000);
+        }
+        mbc.clock.execute(0x61, 1_000);
+        mbc.clock.execute(0x60, 1_120);
+        assert_eq!(mbc.clock.minutes, 1);
+        assert_eq!(mbc.clock.days, 0x124);
+    }
+}
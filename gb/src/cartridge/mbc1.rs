@@ -0,0 +1,309 @@
+//! The MBC1 memory bank controller.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::header::{LOGO, LOGO_START};
+use super::{CartridgeHeader, Mbc};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The size of each game in an MBC1M multicart.
+const MULTICART_GAME: usize = 0x40000;
+
+/// The MBC1, supporting up to 2 MiB of ROM and 32 KiB of RAM.
+///
+/// Bank numbers are masked to the size of the ROM and RAM, so smaller
+/// cartridges mirror their banks across the unused register bits.
+///
+/// Multicart compilations wire the MBC1 differently, as the MBC1M: bit 4 of
+/// the ROM bank register is left unconnected and the 2-bit register drives
+/// bits 4-5 of the bank instead of 5-6, splitting 1 MiB into four 256 KiB
+/// games that the menu picks between in mode 1.
+#[derive(Debug, Clone)]
+pub struct Mbc1 {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    ram_enabled: bool,
+    /// The 5-bit ROM bank register at `0x2000-0x3FFF`.
+    bank_lo: u8,
+    /// The 2-bit register at `0x4000-0x5FFF`.
+    bank_hi: u8,
+    /// The banking mode at `0x6000-0x7FFF`, which routes `bank_hi` to RAM
+    /// and the `0x0000-0x3FFF` region.
+    advanced: bool,
+    /// Whether the banks are wired as the MBC1M.
+    multicart: bool,
+}
+
+impl Mbc1 {
+    /// Create a controller around `rom`, sizing RAM from its `header`.
+    ///
+    /// The MBC1M wiring is picked for ROMs detected by [`Mbc1::detect_multicart`].
+    #[must_use]
+    pub fn new(rom: Vec<u8>, header: &CartridgeHeader) -> Self {
+        let multicart = Self::detect_multicart(&rom);
+        Self::with_wiring(rom, header, multicart)
+    }
+
+    /// Create a controller around `rom` like [`Mbc1::new`], wired as the MBC1M
+    /// if `multicart` is set and as the plain MBC1 otherwise.
+    #[must_use]
+    pub fn with_wiring(rom: Vec<u8>, header: &CartridgeHeader, multicart: bool) -> Self {
+        Self {
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; header.ram_size.min(0x8000)].into_boxed_slice(),
+            ram_enabled: false,
+            bank_lo: 1,
+            bank_hi: 0,
+            advanced: false,
+            multicart,
+        }
+    }
+
+    /// Check if `rom` looks like an MBC1M multicart: a 1 MiB ROM with the
+    /// Nintendo logo of another game's header after the first 256 KiB.
+    ///
+    /// The header doesn't tell the wirings apart, but the boot ROM needs the
+    /// logo in every game the menu can boot.
+    #[must_use]
+    pub fn detect_multicart(rom: &[u8]) -> bool {
+        rom.len() == 4 * MULTICART_GAME
+            && (1..4)
+                .map(|game| game * MULTICART_GAME + LOGO_START)
+                .any(|offset| rom[offset..offset + LOGO.len()] == LOGO)
+    }
+
+    /// Check if the banks are wired as the MBC1M.
+    #[must_use]
+    pub const fn is_multicart(&self) -> bool {
+        self.multicart
+    }
+
+    /// Return the bank selected by the 2-bit register in `0x0000-0x3FFF`.
+    const fn high_bank(&self) -> u8 {
+        if self.multicart { self.bank_hi << 4 } else { self.bank_hi << 5 }
+    }
+
+    /// Read a byte from a 16 KiB ROM bank, wrapping to the size of the ROM.
+    fn rom_byte(&self, bank: u8, addr: u16) -> u8 {
+        let offset = usize::from(bank) << 14 | usize::from(addr & 0x3FFF);
+        if self.rom.is_empty() {
+            0xFF
+        } else {
+            self.rom[offset % self.rom.len()]
+        }
+    }
+
+    /// Return the offset into RAM of `addr`.
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = if self.advanced { self.bank_hi } else { 0 };
+        (usize::from(bank) << 13 | usize::from(addr & 0x1FFF)) % self.ram.len()
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        if addr < 0x4000 {
+            let bank = if self.advanced { self.high_bank() } else { 0 };
+            self.rom_byte(bank, addr)
+        } else {
+            // The MBC1M still compares all five bits against zero.
+            let bank_lo = if self.multicart { self.bank_lo & 0x0F } else { self.bank_lo };
+            self.rom_byte(self.high_bank() | bank_lo, addr)
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Bank 0 cannot be selected here, so 0x00/0x20/0x40/0x60 map to
+            // the bank after them.
+            0x2000..=0x3FFF => self.bank_lo = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.bank_hi = value & 0x03,
+            _ => self.advanced = value & 1 != 0,
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = value;
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.bank_lo);
+        state.write_u8(self.bank_hi);
+        state.write_bool(self.advanced);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.bank_lo = (state.read_u8()? & 0x1F).max(1);
+        self.bank_hi = state.read_u8()? & 0x03;
+        self.advanced = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    fn mbc1(rom_code: u8) -> Mbc1 {
+        let rom = test_rom(0x03, [rom_code, 0x03]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        Mbc1::new(rom, &header)
+    }
+
+    /// Build a 1 MiB MBC1M multicart, with a header and logo on each game.
+    fn multicart_rom() -> Vec<u8> {
+        let mut rom = test_rom(0x01, [0x05, 0x00]);
+        for game in rom.chunks_mut(MULTICART_GAME) {
+            game[LOGO_START..LOGO_START + LOGO.len()].copy_from_slice(&LOGO);
+        }
+        rom
+    }
+
+    #[test]
+    fn multicart_menu_selects_each_game() {
+        let rom = multicart_rom();
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(Mbc1::detect_multicart(&rom));
+        let mut mbc = Mbc1::new(rom, &header);
+        assert!(mbc.is_multicart());
+
+        // The second game's bank 0, then its bank 1.
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x10);
+        assert_eq!(mbc.read_rom(0x4000), 0x11);
+
+        // Bit 4 of the bank register isn't wired, but still counts as non-zero.
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 0x1F);
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(mbc.read_rom(0x4000), 0x10);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x11);
+
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_rom(0x0000), 0x30);
+    }
+
+    #[test]
+    fn plain_wiring_ignores_multicart_logos() {
+        let rom = multicart_rom();
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        let mut mbc = Mbc1::with_wiring(rom, &header, false);
+
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+
+        // A single game of the same size isn't detected.
+        assert!(!Mbc1::detect_multicart(&test_rom(0x01, [0x05, 0x00])));
+    }
+
+    #[test]
+    fn bank_zero_remaps_to_next_bank() {
+        // 1 MiB, 64 banks.
+        let mut mbc = mbc1(0x05);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        for (hi, bank) in [(0, 0x01), (1, 0x21)] {
+            mbc.write_rom(0x4000, hi);
+            mbc.write_rom(0x2000, 0x00);
+            assert_eq!(mbc.read_rom(0x4000), bank);
+
+            // Only the lower five bits are compared against zero.
+            mbc.write_rom(0x2000, 0x20);
+            assert_eq!(mbc.read_rom(0x4000), bank);
+        }
+
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x7FFF), 0x3F);
+    }
+
+    #[test]
+    fn bank_numbers_wrap_to_rom_size() {
+        // 256 KiB, 16 banks.
+        let mut mbc = mbc1(0x03);
+
+        mbc.write_rom(0x2000, 0x11);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+    }
+
+    #[test]
+    fn mode_1_switches_ram_bank() {
+        let mut mbc = mbc1(0x06);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_ram(0xA000, 0x11);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xA000, 0x22);
+        assert_eq!(mbc.read_ram(0xA000), 0x22);
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+
+        // Mode 0 always maps RAM bank 0.
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+    }
+
+    #[test]
+    fn mode_1_banks_low_region_on_large_roms() {
+        // 2 MiB, 128 banks.
+        let mut mbc = mbc1(0x06);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+    }
+
+    #[test]
+    fn ram_disabled_by_default() {
+        let mut mbc = mbc1(0x00);
+
+        mbc.write_ram(0xA000, 0x42);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x42);
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+}
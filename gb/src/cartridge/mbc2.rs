@@ -0,0 +1,172 @@
+//! The MBC2 memory bank controller.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{CartridgeHeader, Mbc};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The size of the built-in RAM, in 4-bit cells.
+const RAM_SIZE: usize = 0x200;
+
+/// The address bit that selects between the two registers.
+const REGISTER_SELECT: u16 = 0x0100;
+
+/// The MBC2, supporting up to 256 KiB of ROM, with 512 half-bytes of RAM
+/// built into the controller.
+///
+/// Its header declares no RAM, as the RAM is always there. Only the low
+/// nibble of each byte is stored, and the upper nibble reads back as 1s.
+#[derive(Debug, Clone)]
+pub struct Mbc2 {
+    rom: Box<[u8]>,
+    /// The RAM, a nibble in the low bits of each byte.
+    ram: Box<[u8]>,
+    ram_enabled: bool,
+    /// The 4-bit ROM bank number.
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    /// Create a controller around `rom`, with its built-in RAM cleared.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, _header: &CartridgeHeader) -> Self {
+        Self {
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; RAM_SIZE].into_boxed_slice(),
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        let offset = usize::from(bank) << 14 | usize::from(addr & 0x3FFF);
+
+        if self.rom.is_empty() {
+            0xFF
+        } else {
+            self.rom[offset % self.rom.len()]
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        // Both registers sit in `0x0000-0x3FFF`, told apart by address bit 8
+        // rather than by region.
+        if addr >= 0x4000 {
+            return;
+        }
+
+        if addr & REGISTER_SELECT == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = (value & 0x0F).max(1);
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        // The RAM repeats every 512 bytes across the whole region.
+        0xF0 | self.ram[usize::from(addr) % RAM_SIZE]
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[usize::from(addr) % RAM_SIZE] = value & 0x0F;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.rom_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.rom_bank = (state.read_u8()? & 0x0F).max(1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    fn mbc2() -> Mbc2 {
+        // 256 KiB, 16 banks.
+        let rom = test_rom(0x06, [0x03, 0x00]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        Mbc2::new(rom, &header)
+    }
+
+    #[test]
+    fn address_bit_8_selects_register() {
+        let mut mbc = mbc2();
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+
+        // With bit 8 clear, a bank number is taken as the RAM enable.
+        mbc.write_rom(0x2000, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        mbc.write_ram(0xA000, 0x05);
+        assert_eq!(mbc.read_ram(0xA000), 0xF5);
+
+        // With bit 8 set, even a RAM enable value selects a bank.
+        mbc.write_rom(0x0100, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 0x0A);
+        assert_eq!(mbc.read_ram(0xA000), 0xF5);
+
+        mbc.write_rom(0x3FFF, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        mbc.write_rom(0x21FF, 0x1F);
+        assert_eq!(mbc.read_rom(0x7FFF), 0x0F);
+
+        mbc.write_rom(0x3EFF, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn ram_stores_low_nibbles() {
+        let mut mbc = mbc2();
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_ram(0xA000, 0xAB);
+        mbc.write_ram(0xA1FF, 0x30);
+        assert_eq!(mbc.read_ram(0xA000), 0xFB);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xF0);
+        assert_eq!(mbc.ram()[..2], [0x0B, 0x00]);
+        assert_eq!(mbc.ram().len(), 512);
+    }
+
+    #[test]
+    fn ram_repeats_every_512_bytes() {
+        let mut mbc = mbc2();
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_ram(0xA003, 0x07);
+        for addr in [0xA203, 0xA403, 0xBE03] {
+            assert_eq!(mbc.read_ram(addr), 0xF7);
+        }
+
+        mbc.write_ram(0xBFFF, 0x0C);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xFC);
+    }
+}
@@ -0,0 +1,572 @@
+//! The MBC3 memory bank controller and its real-time clock.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{CartridgeHeader, Mbc};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The bit of the day-high register that stops the clock.
+const HALT: u8 = 0x40;
+/// The bit of the day-high register set when the day counter overflows.
+const DAY_CARRY: u8 = 0x80;
+
+/// The real-time clock of an MBC3 cartridge.
+///
+/// The registers are brought up to date lazily from the cartridge's
+/// [`RtcSource`] whenever they are latched or written, using `timestamp` as
+/// the point in time they were last valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rtc {
+    /// The seconds counter, 0-59.
+    pub seconds: u8,
+    /// The minutes counter, 0-59.
+    pub minutes: u8,
+    /// The hours counter, 0-23.
+    pub hours: u8,
+    /// The 9-bit day counter.
+    pub days: u16,
+    /// Whether the clock is stopped.
+    pub halted: bool,
+    /// Whether the day counter has overflowed since this was last cleared.
+    pub day_carry: bool,
+    /// The registers as of the last latch, in register select order.
+    pub latched: [u8; 5],
+    /// The time in seconds since the Unix epoch, as told by the
+    /// [`RtcSource`], at which the counters were last brought up to date.
+    pub timestamp: u64,
+}
+
+impl Rtc {
+    /// The length of the serialized clock.
+    pub const SAVE_SIZE: usize = 48;
+
+    /// Create a clock at day zero, starting now.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            timestamp: now(),
+        }
+    }
+
+    /// Serialize the live and latched registers along with the timestamp.
+    ///
+    /// This uses the 48 byte layout appended to save files by BGB and VBA:
+    /// the five live registers and the five latched registers as
+    /// little-endian 32-bit words, followed by the 64-bit timestamp.
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SAVE_SIZE] {
+        let mut bytes = [0; Self::SAVE_SIZE];
+        let registers = (0x08..=0x0C).map(|select| self.register(select));
+
+        for (chunk, register) in bytes.chunks_exact_mut(4).zip(registers.chain(self.latched)) {
+            chunk.copy_from_slice(&u32::from(register).to_le_bytes());
+        }
+
+        bytes[40..].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize a clock written by [`Rtc::to_bytes`].
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8; Self::SAVE_SIZE]) -> Self {
+        // Every register fits in the low byte of its word.
+        let word = |index: usize| bytes[index * 4];
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&bytes[40..]);
+
+        let mut rtc = Self {
+            latched: [5, 6, 7, 8, 9].map(word),
+            timestamp: u64::from_le_bytes(timestamp),
+            ..Self::new()
+        };
+
+        for (select, index) in (0x08..=0x0C).zip(0..) {
+            rtc.set_register(select, word(index));
+        }
+
+        rtc
+    }
+
+    /// Advance the counters to the time `now`.
+    fn update(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.timestamp);
+        self.timestamp = now;
+
+        if !self.halted {
+            self.advance(elapsed);
+        }
+    }
+
+    /// Advance the counters by `elapsed` seconds.
+    #[allow(clippy::cast_possible_truncation)]
+    fn advance(&mut self, elapsed: u64) {
+        let seconds = u64::from(self.seconds) + elapsed;
+        let minutes = u64::from(self.minutes) + seconds / 60;
+        let hours = u64::from(self.hours) + minutes / 60;
+        let days = u64::from(self.days) + hours / 24;
+
+        // Each value was reduced below its modulus, so the casts are lossless.
+        self.seconds = (seconds % 60) as u8;
+        self.minutes = (minutes % 60) as u8;
+        self.hours = (hours % 24) as u8;
+        self.days = (days % 512) as u16;
+        self.day_carry |= days >= 512;
+    }
+
+    /// Return the live value of the register selected by `select`.
+    const fn register(&self, select: u8) -> u8 {
+        let [lo, hi] = self.days.to_le_bytes();
+
+        match select {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => lo,
+            _ => {
+                let mut value = hi & 1;
+                if self.halted {
+                    value |= HALT;
+                }
+                if self.day_carry {
+                    value |= DAY_CARRY;
+                }
+                value
+            }
+        }
+    }
+
+    /// Copy the live registers into the latched registers.
+    fn latch(&mut self, now: u64) {
+        self.update(now);
+        self.latched = [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|select| self.register(select));
+    }
+
+    /// Write the register selected by `select`.
+    fn write(&mut self, select: u8, value: u8, now: u64) {
+        self.update(now);
+        self.set_register(select, value);
+        self.latched[usize::from(select - 0x08)] = self.register(select);
+    }
+
+    /// Set the live value of the register selected by `select`.
+    fn set_register(&mut self, select: u8, value: u8) {
+        match select {
+            0x08 => self.seconds = value & 0x3F,
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = self.days & 0x100 | u16::from(value),
+            _ => {
+                self.days = self.days & 0xFF | u16::from(value & 1) << 8;
+                self.halted = value & HALT != 0;
+                self.day_carry = value & DAY_CARRY != 0;
+            }
+        }
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Return the host time in seconds since the Unix epoch.
+#[cfg(feature = "std")]
+pub(super) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Return the host time, which is always the epoch without `std`.
+///
+/// The clocks then stand still, except when written by the game.
+#[cfg(not(feature = "std"))]
+pub(super) const fn now() -> u64 {
+    0
+}
+
+/// A source of the time a real-time clock keeps, in seconds since the Unix
+/// epoch.
+///
+/// The clock only advances by however much the source does between latches,
+/// so it keeps to the wall clock however fast the emulator runs. A frontend
+/// can stop the source while paused, and tests can drive it by hand.
+pub trait RtcSource: fmt::Debug {
+    /// Return the current time in seconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+/// The host clock, the [`RtcSource`] cartridges start with.
+///
+/// Without `std` this always reads the epoch, so the clock stands still
+/// unless the game writes it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostClock;
+
+impl RtcSource for HostClock {
+    fn now(&self) -> u64 {
+        now()
+    }
+}
+
+/// The MBC3, supporting up to 2 MiB of ROM, 32 KiB of RAM and an optional
+/// real-time clock.
+#[derive(Debug, Clone)]
+pub struct Mbc3 {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    rtc: Option<Rtc>,
+    /// Where the clock reads the time from.
+    source: Rc<dyn RtcSource>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    /// The RAM bank, or an RTC register from `0x08` to `0x0C`.
+    select: u8,
+    /// Whether `0x00` was the last value written to the latch register.
+    latch_armed: bool,
+}
+
+impl Mbc3 {
+    /// Create a controller around `rom`, sizing RAM from its `header`.
+    ///
+    /// Cartridge types `0x0F` and `0x10` carry a real-time clock.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, header: &CartridgeHeader) -> Self {
+        let rtc = matches!(header.cartridge_type, 0x0F | 0x10).then(Rtc::new);
+
+        Self {
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; header.ram_size.min(0x8000)].into_boxed_slice(),
+            rtc,
+            source: Rc::new(HostClock),
+            ram_enabled: false,
+            rom_bank: 1,
+            select: 0,
+            latch_armed: false,
+        }
+    }
+
+    /// Read the time from `source` from now on, in place of the host clock.
+    ///
+    /// The clock carries on from where it stands, counting only the time
+    /// that passes on the new source.
+    ///
+    /// Clocks restored from save states or `.sav` files carry a timestamp
+    /// on the host clock. Against a source keeping other time, such as
+    /// seconds of emulation, the clock then stands still until the source
+    /// passes the timestamp, or leaps ahead if it's already past. A custom
+    /// source should keep to Unix time, say by falling behind the host clock
+    /// while paused, or have the timestamp set from it through
+    /// [`Mbc::rtc_mut`] after every restore.
+    pub fn set_rtc_source(&mut self, source: impl RtcSource + 'static) {
+        self.set_source(Rc::new(source));
+    }
+
+    /// Switch to `source`, rebasing the clock on its current time.
+    fn set_source(&mut self, source: Rc<dyn RtcSource>) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.update(self.source.now());
+            rtc.timestamp = source.now();
+        }
+        self.source = source;
+    }
+
+    /// Return the offset into RAM of `addr` in the selected bank.
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() || self.select > 0x03 {
+            return None;
+        }
+
+        let offset = usize::from(self.select) << 13 | usize::from(addr & 0x1FFF);
+        Some(offset % self.ram.len())
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        let offset = usize::from(bank) << 14 | usize::from(addr & 0x3FFF);
+
+        if self.rom.is_empty() {
+            0xFF
+        } else {
+            self.rom[offset % self.rom.len()]
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.select = value & 0x0F,
+            _ => {
+                if self.latch_armed
+                    && value == 0x01
+                    && let Some(rtc) = &mut self.rtc
+                {
+                    rtc.latch(self.source.now());
+                }
+                self.latch_armed = value == 0x00;
+            }
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        match (self.select, &self.rtc) {
+            (0x08..=0x0C, Some(rtc)) => rtc.latched[usize::from(self.select - 0x08)],
+            _ => self.ram_offset(addr).map_or(0xFF, |offset| self.ram[offset]),
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+
+        match (self.select, &mut self.rtc) {
+            (0x08..=0x0C, Some(rtc)) => rtc.write(self.select, value, self.source.now()),
+            _ => {
+                if let Some(offset) = self.ram_offset(addr) {
+                    self.ram[offset] = value;
+                }
+            }
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+
+    fn set_rtc_source(&mut self, source: Rc<dyn RtcSource>) {
+        self.set_source(source);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.rom_bank);
+        state.write_u8(self.select);
+        state.write_bool(self.latch_armed);
+        if let Some(rtc) = &self.rtc {
+            state.write_bytes(&rtc.to_bytes());
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.rom_bank = (state.read_u8()? & 0x7F).max(1);
+        self.select = state.read_u8()? & 0x0F;
+        self.latch_armed = state.read_bool()?;
+        if let Some(rtc) = &mut self.rtc {
+            let mut bytes = [0; Rtc::SAVE_SIZE];
+            state.read_bytes(&mut bytes)?;
+            *rtc = Rtc::from_bytes(&bytes);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    /// A clock the tests move by hand.
+    #[derive(Debug)]
+    struct FixedClock(Rc<Cell<u64>>);
+
+    impl RtcSource for FixedClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn mbc3(kind: u8) -> Mbc3 {
+        let rom = test_rom(kind, [0x06, 0x03]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        let mut mbc = Mbc3::new(rom, &header);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc
+    }
+
+    fn latch(mbc: &mut Mbc3) {
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+    }
+
+    fn read_rtc(mbc: &mut Mbc3, select: u8) -> u8 {
+        mbc.write_rom(0x4000, select);
+        mbc.read_ram(0xA000)
+    }
+
+    #[test]
+    fn seven_bit_rom_bank() {
+        let mut mbc = mbc3(0x13);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+
+        mbc.write_rom(0x2000, 0x7F);
+        assert_eq!(mbc.read_rom(0x4000), 0x7F);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut mbc = mbc3(0x13);
+
+        for bank in 0..4 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0xA000, bank + 0x10);
+        }
+
+        assert_eq!(mbc.read_ram(0xA000), 0x13);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+    }
+
+    #[test]
+    fn rtc_requires_timer_type() {
+        let mut mbc = mbc3(0x13);
+
+        assert!(mbc.rtc().is_none());
+        assert_eq!(read_rtc(&mut mbc, 0x08), 0xFF);
+    }
+
+    #[test]
+    fn latch_sequence() {
+        let mut mbc = mbc3(0x10);
+        mbc.write_rom(0x4000, 0x09);
+        mbc.write_ram(0xA000, 42);
+
+        // Latching needs 0x00 immediately followed by 0x01.
+        let rtc = mbc.rtc_mut().unwrap();
+        rtc.minutes = 7;
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, 0x09), 42);
+
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x02);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, 0x09), 42);
+
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x09), 7);
+    }
+
+    #[test]
+    fn clock_advances() {
+        let mut rtc = Rtc::new();
+        let start = rtc.timestamp;
+
+        rtc.update(start + 86_400 + 3_600 + 61);
+        rtc.latch(start + 86_400 + 3_600 + 61);
+
+        assert_eq!(rtc.latched, [1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn halt_stops_clock() {
+        let mut rtc = Rtc::new();
+        let start = rtc.timestamp;
+
+        rtc.write(0x0C, HALT, start);
+        rtc.update(start + 100);
+        assert_eq!(rtc.seconds, 0);
+
+        rtc.write(0x0C, 0x00, start + 100);
+        rtc.update(start + 130);
+        assert_eq!(rtc.seconds, 30);
+    }
+
+    #[test]
+    fn latch_reads_the_source() {
+        let time = Rc::new(Cell::new(1_000));
+        let mut mbc = mbc3(0x10);
+        mbc.set_rtc_source(FixedClock(Rc::clone(&time)));
+
+        time.set(1_090);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 30);
+        assert_eq!(read_rtc(&mut mbc, 0x09), 1);
+
+        // Halting stops the clock however far the source moves.
+        mbc.write_rom(0x4000, 0x0C);
+        mbc.write_ram(0xA000, HALT);
+        time.set(5_000);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0x08), 30);
+    }
+
+    #[test]
+    fn rtc_bytes_round_trip() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 12, rtc.timestamp);
+        rtc.write(0x0C, HALT | 1, rtc.timestamp);
+        rtc.latched[2] = 5;
+
+        let bytes = rtc.to_bytes();
+        assert_eq!(bytes[0..4], [12, 0, 0, 0]);
+        assert_eq!(bytes[16..20], [HALT | 1, 0, 0, 0]);
+        assert_eq!(bytes[28..32], [5, 0, 0, 0]);
+        assert_eq!(Rtc::from_bytes(&bytes), rtc);
+    }
+
+    #[test]
+    fn day_carry_latches_on_overflow() {
+        let mut rtc = Rtc::new();
+        let start = rtc.timestamp;
+        rtc.days = 511;
+        rtc.hours = 23;
+        rtc.minutes = 59;
+        rtc.seconds = 59;
+
+        rtc.latch(start + 1);
+
+        assert_eq!(rtc.days, 0);
+        assert!(rtc.day_carry);
+        assert_eq!(rtc.latched, [0, 0, 0, 0, DAY_CARRY]);
+
+        // The carry stays set until cleared by a write.
+        rtc.latch(start + 86_400);
+        assert!(rtc.day_carry);
+        rtc.write(0x0C, 0x00, start + 86_400);
+        assert!(!rtc.day_carry);
+    }
+}
@@ -0,0 +1,197 @@
+//! The MBC5 memory bank controller.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{CartridgeHeader, Mbc};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The bit of the RAM bank register that drives the motor on rumble carts.
+const RUMBLE: u8 = 0x08;
+
+/// The MBC5, supporting up to 8 MiB of ROM, 128 KiB of RAM and an optional
+/// rumble motor.
+#[derive(Debug, Clone)]
+pub struct Mbc5 {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    ram_enabled: bool,
+    /// The 9-bit ROM bank number.
+    rom_bank: u16,
+    ram_bank: u8,
+    /// Whether the RAM bank register's bit 3 drives a motor rather than RAM.
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    /// Create a controller around `rom`, sizing RAM from its `header`.
+    ///
+    /// Cartridge types `0x1C` to `0x1E` carry a rumble motor.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, header: &CartridgeHeader) -> Self {
+        Self {
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; header.ram_size].into_boxed_slice(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble: matches!(header.cartridge_type, 0x1C..=0x1E),
+            rumble: false,
+        }
+    }
+
+    /// Return the offset into RAM of `addr` in the selected bank.
+    fn ram_offset(&self, addr: u16) -> usize {
+        (usize::from(self.ram_bank) << 13 | usize::from(addr & 0x1FFF)) % self.ram.len()
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        let offset = usize::from(bank) << 14 | usize::from(addr & 0x3FFF);
+
+        if self.rom.is_empty() {
+            0xFF
+        } else {
+            self.rom[offset % self.rom.len()]
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            // Unlike the MBC1 and MBC3, bank 0 can be mapped here.
+            0x2000..=0x2FFF => self.rom_bank = self.rom_bank & 0x100 | u16::from(value),
+            0x3000..=0x3FFF => self.rom_bank = self.rom_bank & 0xFF | u16::from(value & 1) << 8,
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = value & RUMBLE != 0;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_offset(addr)]
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+
+        let offset = self.ram_offset(addr);
+        self.ram[offset] = value;
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_u16(self.rom_bank);
+        state.write_u8(self.ram_bank);
+        state.write_bool(self.rumble);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.rom_bank = state.read_u16()? & 0x1FF;
+        self.ram_bank = state.read_u8()? & 0x0F;
+        self.rumble = state.read_bool()? && self.has_rumble;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    fn mbc5(kind: u8, rom_code: u8) -> Mbc5 {
+        let rom = test_rom(kind, [rom_code, 0x04]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        Mbc5::new(rom, &header)
+    }
+
+    #[test]
+    fn bank_zero_is_selectable() {
+        let mut mbc = mbc5(0x19, 0x02);
+
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x00);
+    }
+
+    #[test]
+    fn nine_bit_bank() {
+        // 8 MiB, 512 banks.
+        let mut mbc = mbc5(0x19, 0x08);
+
+        mbc.write_rom(0x2000, 0x23);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!([mbc.read_rom(0x4000), mbc.read_rom(0x4001)], [0x23, 0x01]);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!([mbc.read_rom(0x4000), mbc.read_rom(0x4001)], [0x00, 0x01]);
+
+        mbc.write_rom(0x3000, 0x00);
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!([mbc.read_rom(0x7FFF), mbc.read_rom(0x4001)], [0xFF, 0x00]);
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut mbc = mbc5(0x1B, 0x02);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(0xA000, 0x42);
+        mbc.write_rom(0x4000, 0x07);
+        mbc.write_ram(0xA000, 0x24);
+
+        mbc.write_rom(0x4000, 0x0F);
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+        assert!(!mbc.rumble());
+    }
+
+    #[test]
+    fn rumble_bit_is_not_ram_banking() {
+        let mut mbc = mbc5(0x1E, 0x02);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0xA000, 0x42);
+
+        mbc.write_rom(0x4000, 0x09);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+
+        mbc.write_rom(0x4000, 0x01);
+        assert!(!mbc.rumble());
+    }
+}
@@ -0,0 +1,458 @@
+//! The MBC7 memory bank controller, with its accelerometer and EEPROM.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{CartridgeHeader, Mbc};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The size of the 93LC56 EEPROM, in bytes.
+const EEPROM_SIZE: usize = 0x100;
+
+/// The reading of an accelerometer axis while level.
+const AXIS_CENTER: u16 = 0x81D0;
+
+/// The change in an axis reading per g of acceleration.
+const AXIS_PER_G: f32 = 112.0;
+
+/// The value the latched axes are erased to.
+const AXIS_ERASED: u16 = 0x8000;
+
+/// The bits of the EEPROM register.
+mod pin {
+    /// Chip select.
+    pub const CS: u8 = 0x80;
+    /// The serial clock.
+    pub const CLK: u8 = 0x40;
+    /// The data into the EEPROM.
+    pub const DI: u8 = 0x02;
+    /// The data out of the EEPROM.
+    pub const DO: u8 = 0x01;
+}
+
+/// What the EEPROM expects on the next rising clock edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Phase {
+    /// Waiting for a start bit.
+    Idle,
+    /// Shifting in the opcode and address.
+    Command,
+    /// Shifting out a word.
+    Read,
+    /// Shifting in a word for one address.
+    Write,
+    /// Shifting in a word for every address.
+    WriteAll,
+}
+
+impl Phase {
+    /// Every phase, in the order of their discriminants.
+    const ALL: [Self; 5] = [
+        Self::Idle,
+        Self::Command,
+        Self::Read,
+        Self::Write,
+        Self::WriteAll,
+    ];
+}
+
+/// A 93LC56 serial EEPROM of 128 16-bit words, driven a pin at a time.
+///
+/// Commands are a start bit, two opcode bits and eight address bits, of
+/// which the top one is ignored. Writes only land after an `EWEN` command,
+/// and complete at once.
+#[derive(Debug, Clone)]
+struct Eeprom {
+    /// The words, low byte first.
+    data: Box<[u8]>,
+    /// The pins as last written, and `DO` as last driven.
+    pins: u8,
+    phase: Phase,
+    /// The bits shifted in or out so far.
+    shift: u16,
+    /// The number of bits in `shift`.
+    bits: u8,
+    /// The word address of the command running.
+    addr: u8,
+    /// Whether writes are enabled.
+    write_enabled: bool,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Self {
+            data: vec![0xFF; EEPROM_SIZE].into_boxed_slice(),
+            pins: pin::DO,
+            phase: Phase::Idle,
+            shift: 0,
+            bits: 0,
+            addr: 0,
+            write_enabled: false,
+        }
+    }
+
+    fn word(&self, addr: u8) -> u16 {
+        let offset = usize::from(addr & 0x7F) * 2;
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+
+    fn set_word(&mut self, addr: u8, word: u16) {
+        if self.write_enabled {
+            let offset = usize::from(addr & 0x7F) * 2;
+            self.data[offset..offset + 2].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    /// Set `DO`, the only pin the EEPROM drives.
+    const fn drive(&mut self, high: bool) {
+        self.pins = self.pins & !pin::DO | if high { pin::DO } else { 0 };
+    }
+
+    /// Set the input pins from `value`, clocking in `DI` on a rising edge
+    /// of `CLK`.
+    fn write(&mut self, value: u8) {
+        let rising = self.pins & pin::CLK == 0 && value & pin::CLK != 0;
+        self.pins = self.pins & pin::DO | value & (pin::CS | pin::CLK | pin::DI);
+
+        if value & pin::CS == 0 {
+            // Deselecting aborts a command, and the chip reports ready.
+            self.phase = Phase::Idle;
+            self.drive(true);
+        } else if rising {
+            self.clock(value & pin::DI != 0);
+        }
+    }
+
+    /// Handle a rising clock edge with `di` on the input.
+    fn clock(&mut self, di: bool) {
+        match self.phase {
+            Phase::Idle => {
+                if di {
+                    self.phase = Phase::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+            Phase::Command => {
+                self.shift = self.shift << 1 | u16::from(di);
+                self.bits += 1;
+                if self.bits == 10 {
+                    self.execute();
+                }
+            }
+            Phase::Read => {
+                self.drive(self.shift & 0x8000 != 0);
+                self.shift <<= 1;
+                self.bits += 1;
+                if self.bits == 16 {
+                    self.phase = Phase::Idle;
+                }
+            }
+            Phase::Write | Phase::WriteAll => {
+                self.shift = self.shift << 1 | u16::from(di);
+                self.bits += 1;
+                if self.bits == 16 {
+                    if self.phase == Phase::Write {
+                        self.set_word(self.addr, self.shift);
+                    } else {
+                        (0..0x80).for_each(|addr| self.set_word(addr, self.shift));
+                    }
+                    self.phase = Phase::Idle;
+                    self.drive(true);
+                }
+            }
+        }
+    }
+
+    /// Run the command just shifted in.
+    #[allow(clippy::cast_possible_truncation)]
+    fn execute(&mut self) {
+        // The low byte of the ten bits shifted in is the address.
+        self.addr = self.shift as u8 & 0x7F;
+        let opcode = self.shift >> 8;
+        let extended = self.shift >> 6 & 0x03;
+        self.phase = Phase::Idle;
+        self.bits = 0;
+
+        match (opcode, extended) {
+            (0b10, _) => {
+                // A dummy zero comes out before the word.
+                self.shift = self.word(self.addr);
+                self.phase = Phase::Read;
+                self.drive(false);
+            }
+            (0b01, _) => self.phase = Phase::Write,
+            (0b11, _) => self.set_word(self.addr, 0xFFFF),
+            (_, 0b11) => self.write_enabled = true,
+            (_, 0b00) => self.write_enabled = false,
+            (_, 0b10) => (0..0x80).for_each(|addr| self.set_word(addr, 0xFFFF)),
+            _ => self.phase = Phase::WriteAll,
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.pins);
+        state.write_u8(self.phase as u8);
+        state.write_u16(self.shift);
+        state.write_u8(self.bits);
+        state.write_u8(self.addr);
+        state.write_bool(self.write_enabled);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.pins = state.read_u8()? & (pin::CS | pin::CLK | pin::DI | pin::DO);
+        self.phase = *Phase::ALL
+            .get(usize::from(state.read_u8()?))
+            .ok_or(StateError::Corrupt)?;
+        self.shift = state.read_u16()?;
+        self.bits = state.read_u8()?.min(15);
+        self.addr = state.read_u8()? & 0x7F;
+        self.write_enabled = state.read_bool()?;
+        Ok(())
+    }
+}
+
+/// The MBC7, supporting up to 2 MiB of ROM, with a two-axis accelerometer
+/// and a 256-byte EEPROM in place of RAM.
+///
+/// Both are reached through registers in `0xA000-0xAFFF`, once enabled by
+/// `0x0A` at `0x0000-0x1FFF` and `0x40` at `0x4000-0x5FFF`. The accelerometer
+/// is set by the frontend with [`Mbc::set_accelerometer`] and latched by the
+/// game writing `0x55` then `0xAA`. The EEPROM is the save data.
+#[derive(Debug, Clone)]
+pub struct Mbc7 {
+    rom: Box<[u8]>,
+    eeprom: Eeprom,
+    /// Whether each of the two enable registers holds its value.
+    ram_enabled: [bool; 2],
+    /// The 7-bit ROM bank number.
+    rom_bank: u8,
+    /// The axis readings the frontend last set.
+    axes: [u16; 2],
+    /// The axis readings the game last latched.
+    latched: [u16; 2],
+    /// Whether the latch was erased, readying it for a new reading.
+    erased: bool,
+}
+
+impl Mbc7 {
+    /// Create a controller around `rom`, with an erased EEPROM.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, _header: &CartridgeHeader) -> Self {
+        Self {
+            rom: rom.into_boxed_slice(),
+            eeprom: Eeprom::new(),
+            ram_enabled: [false; 2],
+            rom_bank: 1,
+            axes: [AXIS_CENTER; 2],
+            latched: [AXIS_ERASED; 2],
+            erased: false,
+        }
+    }
+
+    /// Check if both enable registers are set.
+    fn is_enabled(&self) -> bool {
+        self.ram_enabled == [true; 2]
+    }
+}
+
+/// Convert `g` of acceleration to an axis reading.
+#[allow(clippy::cast_possible_truncation)]
+fn axis(g: f32) -> u16 {
+    // The cast saturates, and takes NaN to 0.
+    AXIS_CENTER.saturating_add_signed((g * AXIS_PER_G) as i16)
+}
+
+impl Mbc for Mbc7 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+        let offset = usize::from(bank) << 14 | usize::from(addr & 0x3FFF);
+
+        if self.rom.is_empty() {
+            0xFF
+        } else {
+            self.rom[offset % self.rom.len()]
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled[0] = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_enabled[1] = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.is_enabled() || addr >= 0xB000 {
+            return 0xFF;
+        }
+
+        let [x, y] = self.latched.map(u16::to_le_bytes);
+        match addr >> 4 & 0x0F {
+            0x2 => x[0],
+            0x3 => x[1],
+            0x4 => y[0],
+            0x5 => y[1],
+            0x6 => 0x00,
+            0x8 => self.eeprom.pins,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.is_enabled() || addr >= 0xB000 {
+            return;
+        }
+
+        match addr >> 4 & 0x0F {
+            0x0 if value == 0x55 => {
+                self.latched = [AXIS_ERASED; 2];
+                self.erased = true;
+            }
+            0x1 if value == 0xAA && self.erased => {
+                self.latched = self.axes;
+                self.erased = false;
+            }
+            0x8 => self.eeprom.write(value),
+            _ => {}
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.eeprom.data
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.eeprom.data
+    }
+
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.axes = [axis(x), axis(y)];
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled[0]);
+        state.write_bool(self.ram_enabled[1]);
+        state.write_u8(self.rom_bank);
+        for value in self.axes.into_iter().chain(self.latched) {
+            state.write_u16(value);
+        }
+        state.write_bool(self.erased);
+        self.eeprom.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.ram_enabled = [state.read_bool()?, state.read_bool()?];
+        self.rom_bank = state.read_u8()? & 0x7F;
+        for value in self.axes.iter_mut().chain(&mut self.latched) {
+            *value = state.read_u16()?;
+        }
+        self.erased = state.read_bool()?;
+        self.eeprom.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    fn mbc7() -> Mbc7 {
+        // 1 MiB, 64 banks.
+        let rom = test_rom(0x22, [0x05, 0x00]);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        let mut mbc = Mbc7::new(rom, &header);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x40);
+        mbc
+    }
+
+    /// Latch the accelerometer and return the X and Y readings.
+    fn latch(mbc: &mut Mbc7) -> [u16; 2] {
+        mbc.write_ram(0xA000, 0x55);
+        mbc.write_ram(0xA010, 0xAA);
+        [0xA020, 0xA040].map(|addr| {
+            u16::from_le_bytes([mbc.read_ram(addr), mbc.read_ram(addr + 0x10)])
+        })
+    }
+
+    /// Clock the bits of `value`, most significant first, into the EEPROM.
+    fn send(mbc: &mut Mbc7, value: u32, bits: u32) {
+        for bit in (0..bits).rev() {
+            let di = if value >> bit & 1 != 0 { pin::DI } else { 0 };
+            mbc.write_ram(0xA080, pin::CS | di);
+            mbc.write_ram(0xA080, pin::CS | pin::CLK | di);
+        }
+    }
+
+    /// Clock a word out of the EEPROM.
+    fn receive(mbc: &mut Mbc7) -> u16 {
+        (0..16).fold(0, |word, _| {
+            mbc.write_ram(0xA080, pin::CS);
+            mbc.write_ram(0xA080, pin::CS | pin::CLK);
+            word << 1 | u16::from(mbc.read_ram(0xA080) & pin::DO)
+        })
+    }
+
+    #[test]
+    fn accelerometer_is_latched_into_axis_registers() {
+        let mut mbc = mbc7();
+        assert_eq!(latch(&mut mbc), [0x81D0; 2]);
+
+        mbc.set_accelerometer(1.0, -0.5);
+        assert_eq!(latch(&mut mbc), [0x81D0 + 0x70, 0x81D0 - 0x38]);
+
+        // The reading holds until latched again, and latching needs an erase.
+        mbc.set_accelerometer(0.0, 0.0);
+        mbc.write_ram(0xA010, 0xAA);
+        assert_eq!(mbc.read_ram(0xA020), 0x40);
+        mbc.write_ram(0xA000, 0x55);
+        assert_eq!(mbc.read_ram(0xA030), 0x80);
+    }
+
+    #[test]
+    fn registers_need_both_enables() {
+        let mut mbc = mbc7();
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA020), 0xFF);
+
+        mbc.write_rom(0x4000, 0x40);
+        assert_eq!(mbc.read_ram(0xA060), 0x00);
+        assert_eq!(mbc.read_ram(0xB000), 0xFF);
+
+        mbc.write_rom(0x2000, 0x21);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+    }
+
+    #[test]
+    fn eeprom_reads_and_writes_words() {
+        let mut mbc = mbc7();
+
+        // Writes are ignored until enabled. Commands are a start bit, the
+        // opcode and the address.
+        send(&mut mbc, 0b101_0000_0011 << 16 | 0xBEEF, 27);
+        mbc.write_ram(0xA080, 0x00);
+        assert_eq!(mbc.ram()[6..8], [0xFF, 0xFF]);
+
+        // EWEN, then WRITE $03.
+        send(&mut mbc, 0b100_1100_0000, 11);
+        mbc.write_ram(0xA080, 0x00);
+        send(&mut mbc, 0b101_0000_0011 << 16 | 0xBEEF, 27);
+        mbc.write_ram(0xA080, 0x00);
+        assert_eq!(mbc.ram()[6..8], [0xEF, 0xBE]);
+        assert_eq!(mbc.read_ram(0xA080) & pin::DO, pin::DO);
+
+        // READ $03, after its dummy zero.
+        send(&mut mbc, 0b110_0000_0011, 11);
+        assert_eq!(mbc.read_ram(0xA080) & pin::DO, 0);
+        assert_eq!(receive(&mut mbc), 0xBEEF);
+    }
+}
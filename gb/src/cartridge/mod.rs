@@ -0,0 +1,829 @@
+//! Game Boy cartridges and their memory bank controllers.
+
+mod camera;
+mod header;
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc7;
+mod no_mbc;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+#[cfg(feature = "std")]
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+
+pub use camera::PocketCamera;
+pub use header::{CartridgeHeader, CgbSupport, ChecksumStatus, HeaderError, MapperKind};
+pub use huc1::Huc1;
+pub use huc3::Huc3;
+pub use mbc1::Mbc1;
+pub use mbc2::Mbc2;
+pub use mbc3::{HostClock, Mbc3, Rtc, RtcSource};
+pub use mbc5::Mbc5;
+pub use mbc7::Mbc7;
+pub use no_mbc::NoMbc;
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[cfg(feature = "std")]
+use self::header::HEADER_END;
+
+/// The size of a ROM bank.
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+/// The size of an external RAM bank.
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// A function wrapping a ROM in the controller its header declares.
+type MbcConstructor = fn(Vec<u8>, &CartridgeHeader) -> Box<dyn Mbc>;
+
+/// What an infrared port reads while it sees no light.
+///
+/// No IR peripherals are emulated, so the ports of the Hudson controllers
+/// always read this.
+const IR_NO_LIGHT: u8 = 0xC0;
+
+/// A memory bank controller, mapping CPU addresses into the cartridge ROM and
+/// RAM.
+///
+/// ROM accesses cover `0x0000-0x7FFF`, where writes drive the controller
+/// registers. RAM accesses cover `0xA000-0xBFFF`. Addresses are passed through
+/// unchanged, so implementations decode them from the start of the region.
+pub trait Mbc: fmt::Debug {
+    /// Read a byte from the ROM region.
+    fn read_rom(&self, addr: u16) -> u8;
+
+    /// Write a byte to the ROM region.
+    fn write_rom(&mut self, addr: u16, value: u8);
+
+    /// Read a byte from the RAM region.
+    fn read_ram(&self, addr: u16) -> u8;
+
+    /// Write a byte to the RAM region.
+    fn write_ram(&mut self, addr: u16, value: u8);
+
+    /// Return the whole of the ROM.
+    fn rom(&self) -> &[u8];
+
+    /// Return the whole of the external RAM.
+    fn ram(&self) -> &[u8];
+
+    /// Return the whole of the external RAM mutably.
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    /// Return the real-time clock, if the cartridge has one.
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+
+    /// Return the real-time clock mutably, if the cartridge has one.
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    /// Read the real-time clock's time from `source`, if the cartridge has
+    /// an MBC3 clock.
+    fn set_rtc_source(&mut self, _source: Rc<dyn RtcSource>) {}
+
+    /// Check if the rumble motor is running, if the cartridge has one.
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Set the acceleration the cartridge feels along its X and Y axes, in
+    /// g, if it has an accelerometer.
+    fn set_accelerometer(&mut self, _x: f32, _y: f32) {}
+
+    /// Set the image the camera captures from now on, if the cartridge has
+    /// one, as shades from 0 to 3 row by row.
+    fn set_camera_image(&mut self, _image: &[u8; PocketCamera::PIXELS]) {}
+
+    /// Write the controller registers to a save state.
+    ///
+    /// The ROM and RAM are left out, as the [`Cartridge`] saves the RAM
+    /// itself. Controllers without registers can keep the default, which
+    /// writes nothing.
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    /// Restore the controller registers written by [`Mbc::save_state`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is truncated or corrupt.
+    fn load_state(&mut self, _state: &mut StateReader<'_>) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+/// An error encountered while loading save data into a cartridge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveError {
+    /// The cartridge has no external RAM.
+    NoRam,
+    /// The cartridge has no real-time clock.
+    NoRtc,
+    /// The save data does not match the size of the cartridge RAM or clock.
+    SizeMismatch {
+        /// The size the cartridge expects in bytes.
+        expected: usize,
+        /// The size of the save data in bytes.
+        actual: usize,
+    },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoRam => f.write_str("cartridge has no external RAM"),
+            Self::NoRtc => f.write_str("cartridge has no real-time clock"),
+            Self::SizeMismatch { expected, actual } => {
+                write!(f, "save data is {actual} bytes, expected {expected}")
+            }
+        }
+    }
+}
+
+impl Error for SaveError {}
+
+/// An error encountered while reading a cartridge from a stream.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum LoadError {
+    /// The header is invalid or unsupported.
+    Header(HeaderError),
+    /// The stream ended before the size declared by the header.
+    Truncated {
+        /// The declared size of the ROM in bytes.
+        expected: usize,
+        /// The bytes read before the stream ended.
+        actual: usize,
+    },
+    /// The stream goes on past the size declared by the header.
+    TooLong {
+        /// The declared size of the ROM in bytes.
+        expected: usize,
+        /// The length of the stream in bytes.
+        actual: usize,
+    },
+    /// Reading the stream failed.
+    Io(io::Error),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header(err) => write!(f, "invalid cartridge: {err}"),
+            Self::Truncated { expected, actual } => {
+                write!(f, "ROM ends after {actual} bytes, expected {expected}")
+            }
+            Self::TooLong { expected, actual } => {
+                write!(f, "ROM is {actual} bytes, expected {expected}")
+            }
+            Self::Io(err) => write!(f, "failed to read ROM: {err}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Header(err) => Some(err),
+            Self::Io(err) => Some(err),
+            Self::Truncated { .. } | Self::TooLong { .. } => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<HeaderError> for LoadError {
+    fn from(err: HeaderError) -> Self {
+        Self::Header(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// How to wire a controller whose variants the header can't tell apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MapperHint {
+    /// Guess the wiring from the ROM contents, as [`Mbc1::detect_multicart`]
+    /// does.
+    #[default]
+    Detect,
+    /// Wire the controller the usual way.
+    Standard,
+    /// Wire an MBC1 as the MBC1M found in multicart compilations.
+    Multicart,
+}
+
+/// A cartridge, its parsed header and the controller it declares.
+#[derive(Debug)]
+pub struct Cartridge {
+    header: CartridgeHeader,
+    mbc: Box<dyn Mbc>,
+    ram_dirty: bool,
+}
+
+impl Cartridge {
+    /// Load a cartridge from a ROM image, constructing the controller named
+    /// by the cartridge type in its header.
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, HeaderError> {
+        Self::from_bytes_with_hint(rom, MapperHint::Detect)
+    }
+
+    /// Load a cartridge like [`Cartridge::from_bytes`], wiring the controller
+    /// as `hint` says.
+    ///
+    /// The hint only matters to the MBC1, and is ignored for the rest.
+    pub fn from_bytes_with_hint(rom: Vec<u8>, hint: MapperHint) -> Result<Self, HeaderError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let build = controller(&header, hint)?;
+        Ok(Self::new(header, rom, build))
+    }
+
+    /// Load a cartridge from a stream, like [`Cartridge::from_bytes`].
+    ///
+    /// The header is read and validated first, and only then the rest of
+    /// the ROM, which must be exactly the size the header declares.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, LoadError> {
+        let mut rom = vec![0; HEADER_END];
+        let len = read_fully(&mut reader, &mut rom)?;
+        if len < HEADER_END {
+            return Err(HeaderError::TooShort { len }.into());
+        }
+
+        let header = CartridgeHeader::parse(&rom)?;
+        let build = controller(&header, MapperHint::Detect)?;
+
+        let expected = header.rom_size;
+        rom.resize(expected, 0);
+        let len = HEADER_END + read_fully(&mut reader, &mut rom[HEADER_END..])?;
+        if len < expected {
+            return Err(LoadError::Truncated {
+                expected,
+                actual: len,
+            });
+        }
+
+        let extra = io::copy(&mut reader, &mut io::sink())?;
+        if extra > 0 {
+            let actual = usize::try_from(extra).map_or(usize::MAX, |extra| expected + extra);
+            return Err(LoadError::TooLong { expected, actual });
+        }
+
+        Ok(Self::new(header, rom, build))
+    }
+
+    /// Wrap `rom` in the controller made by `build`.
+    fn new(header: CartridgeHeader, rom: Vec<u8>, build: MbcConstructor) -> Self {
+        Self {
+            mbc: build(rom, &header),
+            header,
+            ram_dirty: false,
+        }
+    }
+
+    /// Return the parsed header.
+    #[must_use]
+    pub const fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    /// Return the game title from the header.
+    #[must_use]
+    pub fn title(&self) -> &str {
+        &self.header.title
+    }
+
+    /// Read a byte from the ROM region.
+    #[inline]
+    #[must_use]
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.mbc.read_rom(addr)
+    }
+
+    /// Write a byte to the ROM region.
+    #[inline]
+    pub fn write_rom(&mut self, addr: u16, value: u8) {
+        self.mbc.write_rom(addr, value);
+    }
+
+    /// Read a byte from the RAM region.
+    #[inline]
+    #[must_use]
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.mbc.read_ram(addr)
+    }
+
+    /// Write a byte to the RAM region.
+    #[inline]
+    pub fn write_ram(&mut self, addr: u16, value: u8) {
+        // Comparing against the old value skips writes the controller drops,
+        // such as those while RAM is disabled.
+        let old = self.mbc.read_ram(addr);
+        self.mbc.write_ram(addr, value);
+        self.ram_dirty |= self.mbc.read_ram(addr) != old;
+    }
+
+    /// Write `value` to `addr` in external RAM bank `bank`, rather than the
+    /// bank the controller maps.
+    ///
+    /// This goes around the controller, so it works with RAM disabled, and
+    /// banks past the end of RAM wrap around. Without RAM it does nothing.
+    pub fn poke_ram_bank(&mut self, bank: u8, addr: u16, value: u8) {
+        let ram = self.mbc.ram_mut();
+        if ram.is_empty() {
+            return;
+        }
+
+        let offset = (usize::from(bank) * RAM_BANK_SIZE + usize::from(addr & 0x1FFF)) % ram.len();
+        self.ram_dirty |= ram[offset] != value;
+        ram[offset] = value;
+    }
+
+    /// Return the 16 KiB banks of the ROM in order, regardless of which
+    /// the controller maps.
+    ///
+    /// A ROM that isn't a whole number of banks leaves out its last,
+    /// partial bank.
+    pub fn rom_banks(&self) -> impl Iterator<Item = &[u8; ROM_BANK_SIZE]> {
+        self.mbc.rom().as_chunks().0.iter()
+    }
+
+    /// Return ROM bank `bank`, if the ROM is large enough to hold it.
+    #[must_use]
+    pub fn rom_bank(&self, bank: usize) -> Option<&[u8; ROM_BANK_SIZE]> {
+        self.mbc.rom().as_chunks().0.get(bank)
+    }
+
+    /// Return the 8 KiB banks of the external RAM in order, regardless of
+    /// which the controller maps.
+    ///
+    /// RAM smaller than a bank, such as the 512 nibbles of the MBC2, is
+    /// returned as a single shorter bank.
+    pub fn ram_banks(&self) -> impl Iterator<Item = &[u8]> {
+        self.mbc.ram().chunks(RAM_BANK_SIZE)
+    }
+
+    /// Return the battery-backed RAM to persist, if the cartridge has any.
+    #[must_use]
+    pub fn save_ram(&self) -> Option<&[u8]> {
+        let ram = self.mbc.ram();
+        (self.header.has_battery() && !ram.is_empty()).then_some(ram)
+    }
+
+    /// Restore the external RAM from a save file.
+    ///
+    /// The length of `data` must match the size of the RAM declared by the
+    /// header exactly.
+    pub fn load_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        let ram = self.mbc.ram_mut();
+        if ram.is_empty() {
+            return Err(SaveError::NoRam);
+        }
+
+        if ram.len() != data.len() {
+            return Err(SaveError::SizeMismatch {
+                expected: ram.len(),
+                actual: data.len(),
+            });
+        }
+
+        ram.copy_from_slice(data);
+        self.ram_dirty = false;
+        Ok(())
+    }
+
+    /// Check if the RAM was written since it was last loaded or marked clean.
+    #[must_use]
+    pub const fn is_ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    /// Mark the RAM as clean, after a frontend has flushed it to disk.
+    pub const fn clear_ram_dirty(&mut self) {
+        self.ram_dirty = false;
+    }
+
+    /// Write the RAM and controller registers to a save state, along with
+    /// the header checksums identifying the cartridge.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.header.header_checksum);
+        state.write_u16(self.header.global_checksum);
+        state.write_bytes(self.mbc.ram());
+        self.mbc.save_state(state);
+    }
+
+    /// Restore the state written by [`Cartridge::save_state`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state was saved with another cartridge, or
+    /// is truncated or corrupt.
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        if state.read_u8()? != self.header.header_checksum
+            || state.read_u16()? != self.header.global_checksum
+        {
+            return Err(StateError::CartridgeMismatch);
+        }
+
+        state.read_bytes(self.mbc.ram_mut())?;
+        self.mbc.load_state(state)
+    }
+
+    /// Serialize the real-time clock, if the cartridge has one.
+    ///
+    /// See [`Rtc::to_bytes`] for the layout.
+    #[must_use]
+    pub fn save_rtc(&self) -> Option<[u8; Rtc::SAVE_SIZE]> {
+        self.mbc.rtc().map(Rtc::to_bytes)
+    }
+
+    /// Restore the real-time clock from data written by
+    /// [`Cartridge::save_rtc`].
+    ///
+    /// The clock catches up on the time elapsed since it was saved when
+    /// it is next latched.
+    pub fn load_rtc(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        let rtc = self.mbc.rtc_mut().ok_or(SaveError::NoRtc)?;
+        let bytes = data.try_into().map_err(|_| SaveError::SizeMismatch {
+            expected: Rtc::SAVE_SIZE,
+            actual: data.len(),
+        })?;
+
+        *rtc = Rtc::from_bytes(bytes);
+        Ok(())
+    }
+
+    /// Return the real-time clock, if the cartridge has one.
+    #[must_use]
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.mbc.rtc()
+    }
+
+    /// Return the real-time clock mutably, to restore a saved clock.
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.mbc.rtc_mut()
+    }
+
+    /// Read the real-time clock's time from `source` from now on, in place
+    /// of the host clock.
+    ///
+    /// Only MBC3 clocks read a source; other cartridges ignore this. See
+    /// [`Mbc3::set_rtc_source`] for how it meets restored clocks.
+    pub fn set_rtc_source(&mut self, source: impl RtcSource + 'static) {
+        self.mbc.set_rtc_source(Rc::new(source));
+    }
+
+    /// Check if the rumble motor is running.
+    ///
+    /// Frontends can poll this once per frame to drive controller feedback.
+    #[must_use]
+    pub fn is_rumbling(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    /// Tilt the cartridge, if it has an accelerometer like the MBC7.
+    ///
+    /// `x` and `y` are the acceleration in g along each axis, with 0 when
+    /// held level. The game sees them the next time it latches the sensor.
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.mbc.set_accelerometer(x, y);
+    }
+
+    /// Set the image a Game Boy Camera sees, as [`PocketCamera::WIDTH`] by
+    /// [`PocketCamera::HEIGHT`] shades from 0 to 3, row by row.
+    ///
+    /// Other cartridges ignore this.
+    pub fn set_camera_image(&mut self, image: &[u8; PocketCamera::PIXELS]) {
+        self.mbc.set_camera_image(image);
+    }
+}
+
+/// Return the constructor of the controller declared by `header`, wired as
+/// `hint` says.
+fn controller(header: &CartridgeHeader, hint: MapperHint) -> Result<MbcConstructor, HeaderError> {
+    let build: MbcConstructor = match header.mapper_kind() {
+        MapperKind::None => |rom, header| Box::new(NoMbc::new(rom, header)),
+        MapperKind::Mbc1 => match hint {
+            MapperHint::Detect => |rom, header| Box::new(Mbc1::new(rom, header)),
+            MapperHint::Standard => |rom, header| Box::new(Mbc1::with_wiring(rom, header, false)),
+            MapperHint::Multicart => |rom, header| Box::new(Mbc1::with_wiring(rom, header, true)),
+        },
+        MapperKind::Mbc2 => |rom, header| Box::new(Mbc2::new(rom, header)),
+        MapperKind::Mbc3 => |rom, header| Box::new(Mbc3::new(rom, header)),
+        MapperKind::Mbc5 => |rom, header| Box::new(Mbc5::new(rom, header)),
+        MapperKind::Mbc7 => |rom, header| Box::new(Mbc7::new(rom, header)),
+        MapperKind::PocketCamera => |rom, header| Box::new(PocketCamera::new(rom, header)),
+        MapperKind::HuC1 => |rom, header| Box::new(Huc1::new(rom, header)),
+        MapperKind::HuC3 => |rom, header| Box::new(Huc3::new(rom, header)),
+        kind => return Err(HeaderError::UnsupportedMapper(kind)),
+    };
+    Ok(build)
+}
+
+/// Read from `reader` until `buf` is full or the stream ends, returning the
+/// bytes read.
+#[cfg(feature = "std")]
+fn read_fully(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+/// Build a ROM with the ROM and RAM size codes in `sizes`, where each 16 KiB
+/// bank starts with its little-endian bank number and ends with its low byte.
+#[cfg(test)]
+pub(crate) fn test_rom(kind: u8, sizes: [u8; 2]) -> Vec<u8> {
+    let mut rom = alloc::vec![0; 0x8000 << sizes[0]];
+    for (bank, chunk) in rom.chunks_mut(0x4000).enumerate() {
+        let [lo, hi] = u16::try_from(bank).unwrap().to_le_bytes();
+        [chunk[0], chunk[1]] = [lo, hi];
+        chunk[0x3FFF] = lo;
+    }
+
+    rom[0x0147] = kind;
+    [rom[0x0148], rom[0x0149]] = sizes;
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "std")]
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn from_bytes_selects_mapper() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x08;
+        rom[0x0149] = 0x02;
+        rom[0x0200] = 0x42;
+
+        let mut cartridge = Cartridge::from_bytes(rom).unwrap();
+        cartridge.write_ram(0xA000, 0x99);
+
+        assert_eq!(cartridge.header().mapper_kind(), MapperKind::None);
+        assert_eq!(cartridge.read_rom(0x0200), 0x42);
+        assert_eq!(cartridge.read_ram(0xA000), 0x99);
+    }
+
+    #[test]
+    fn save_ram_round_trip() {
+        let rom = test_rom(0x03, [0x00, 0x02]);
+        let mut cartridge = Cartridge::from_bytes(rom).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+
+        assert!(!cartridge.is_ram_dirty());
+        cartridge.write_ram(0xA123, 0x42);
+        assert!(cartridge.is_ram_dirty());
+
+        let save = cartridge.save_ram().unwrap().to_vec();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0x123], 0x42);
+
+        let rom = test_rom(0x03, [0x00, 0x02]);
+        let mut cartridge = Cartridge::from_bytes(rom).unwrap();
+        cartridge.load_ram(&save).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+
+        assert_eq!(cartridge.read_ram(0xA123), 0x42);
+        assert!(!cartridge.is_ram_dirty());
+    }
+
+    #[test]
+    fn load_ram_validates_size() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x03, [0x00, 0x03])).unwrap();
+        assert_eq!(
+            cartridge.load_ram(&[0; 0x2000]),
+            Err(SaveError::SizeMismatch {
+                expected: 0x8000,
+                actual: 0x2000
+            })
+        );
+
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x01, [0x00, 0x00])).unwrap();
+        assert_eq!(cartridge.load_ram(&[0; 0x2000]), Err(SaveError::NoRam));
+    }
+
+    #[test]
+    fn disabled_ram_writes_are_not_dirty() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x03, [0x00, 0x02])).unwrap();
+
+        cartridge.write_ram(0xA000, 0x42);
+        assert!(!cartridge.is_ram_dirty());
+    }
+
+    #[test]
+    fn save_ram_requires_battery() {
+        let cartridge = Cartridge::from_bytes(test_rom(0x02, [0x00, 0x02])).unwrap();
+
+        assert_eq!(cartridge.save_ram(), None);
+    }
+
+    #[test]
+    fn rtc_round_trip() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x10, [0x00, 0x03])).unwrap();
+        cartridge.rtc_mut().unwrap().hours = 13;
+
+        let save = cartridge.save_rtc().unwrap();
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x10, [0x00, 0x03])).unwrap();
+        cartridge.load_rtc(&save).unwrap();
+        assert_eq!(cartridge.rtc().unwrap().hours, 13);
+
+        assert_eq!(
+            cartridge.load_rtc(&save[..44]),
+            Err(SaveError::SizeMismatch {
+                expected: 48,
+                actual: 44
+            })
+        );
+
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x13, [0x00, 0x03])).unwrap();
+        assert_eq!(cartridge.load_rtc(&save), Err(SaveError::NoRtc));
+    }
+
+    #[test]
+    fn state_restores_banks_and_ram() {
+        for kind in [0x03, 0x13, 0x1B] {
+            let rom = test_rom(kind, [0x04, 0x03]);
+            let mut cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+            cartridge.write_rom(0x0000, 0x0A);
+            cartridge.write_rom(0x2000, 0x05);
+            cartridge.write_rom(0x4000, 0x02);
+            cartridge.write_ram(0xA123, 0x42);
+
+            let mut state = StateWriter::new();
+            cartridge.save_state(&mut state);
+            let state = state.finish();
+
+            let mut restored = Cartridge::from_bytes(rom).unwrap();
+            let mut reader = StateReader::new(&state).unwrap();
+            restored.load_state(&mut reader).unwrap();
+            reader.finish().unwrap();
+
+            assert_eq!(restored.read_rom(0x4000), 0x05, "{kind:#04X}");
+            assert_eq!(restored.read_ram(0xA123), 0x42, "{kind:#04X}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn from_reader_matches_from_bytes() {
+        let rom = test_rom(0x01, [0x02, 0x00]);
+        let mut cartridge = Cartridge::from_reader(rom.as_slice()).unwrap();
+        cartridge.write_rom(0x2000, 0x05);
+
+        assert_eq!(cartridge.header().rom_size, rom.len());
+        assert_eq!(cartridge.read_rom(0x4000), 0x05);
+        assert_eq!(cartridge.read_rom(0x7FFF), 0x05);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn from_reader_rejects_truncated_stream() {
+        let rom = test_rom(0x01, [0x02, 0x00]);
+
+        let err = Cartridge::from_reader(&rom[..0x1_2345]).unwrap_err();
+        assert!(matches!(
+            err,
+            LoadError::Truncated {
+                expected: 0x2_0000,
+                actual: 0x1_2345
+            }
+        ));
+
+        let err = Cartridge::from_reader(&rom[..0x0100]).unwrap_err();
+        assert!(matches!(err, LoadError::Header(HeaderError::TooShort { len: 0x0100 })));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn from_reader_rejects_trailing_data() {
+        let mut rom = test_rom(0x01, [0x00, 0x00]);
+        rom.extend([0; 0x10]);
+
+        let err = Cartridge::from_reader(rom.as_slice()).unwrap_err();
+        assert!(matches!(
+            err,
+            LoadError::TooLong {
+                expected: 0x8000,
+                actual: 0x8010
+            }
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn from_reader_surfaces_read_errors() {
+        /// A stream that fails after its first few bytes.
+        struct Failing<'a>(&'a [u8]);
+
+        impl Read for Failing<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::other("disk on fire"));
+                }
+                let len = self.0.len().min(buf.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let rom = test_rom(0x01, [0x00, 0x00]);
+        let err = Cartridge::from_reader(Failing(&rom[..0x4000])).unwrap_err();
+        assert!(matches!(err, LoadError::Io(_)));
+        assert_eq!(err.to_string(), "failed to read ROM: disk on fire");
+    }
+
+    #[test]
+    fn mbc2_ram_is_saved_without_header_size() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x06, [0x00, 0x00])).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA010, 0x3C);
+
+        assert!(cartridge.is_ram_dirty());
+        let save = cartridge.save_ram().unwrap();
+        assert_eq!((save.len(), save[0x10]), (512, 0x0C));
+    }
+
+    #[test]
+    fn banks_cover_rom_and_ram() {
+        use self::header::{LOGO, LOGO_START};
+
+        // 64 KiB, 4 banks, with 32 KiB of RAM.
+        let mut rom = test_rom(0x03, [0x01, 0x03]);
+        rom[LOGO_START..LOGO_START + LOGO.len()].copy_from_slice(&LOGO);
+        let mut cartridge = Cartridge::from_bytes(rom).unwrap();
+        cartridge.write_rom(0x2000, 0x02);
+
+        let banks: Vec<_> = cartridge.rom_banks().collect();
+        assert_eq!(banks.len(), 4);
+        assert_eq!(banks[0][LOGO_START..LOGO_START + LOGO.len()], LOGO);
+        for (index, bank) in banks.iter().enumerate() {
+            assert_eq!(usize::from(bank[0]), index);
+        }
+        assert_eq!(cartridge.rom_bank(3).map(|bank| bank[0x3FFF]), Some(3));
+        assert!(cartridge.rom_bank(4).is_none());
+
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_rom(0x6000, 0x01);
+        cartridge.write_rom(0x4000, 0x02);
+        cartridge.write_ram(0xA000, 0x42);
+        let banks: Vec<_> = cartridge.ram_banks().collect();
+        assert_eq!(banks.len(), 4);
+        assert_eq!((banks[0][0], banks[2][0]), (0x00, 0x42));
+    }
+
+    #[test]
+    fn hint_forces_multicart_wiring() {
+        let rom = test_rom(0x01, [0x05, 0x00]);
+        let mut plain = Cartridge::from_bytes(rom.clone()).unwrap();
+        let mut multicart = Cartridge::from_bytes_with_hint(rom, MapperHint::Multicart).unwrap();
+
+        for cartridge in [&mut plain, &mut multicart] {
+            cartridge.write_rom(0x6000, 0x01);
+            cartridge.write_rom(0x4000, 0x01);
+        }
+        assert_eq!(plain.read_rom(0x0000), 0x20);
+        assert_eq!(multicart.read_rom(0x0000), 0x10);
+    }
+
+    #[test]
+    fn from_bytes_rejects_unknown_mapper() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x42;
+
+        assert_eq!(
+            Cartridge::from_bytes(rom).unwrap_err(),
+            HeaderError::UnsupportedMapper(MapperKind::Unknown(0x42))
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Cartridges without a memory bank controller.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{CartridgeHeader, Mbc};
+
+/// A ROM-only cartridge of at most 32 KiB, with up to 8 KiB of optional RAM.
+///
+/// This covers cartridge types `0x00`, `0x08` and `0x09`.
+#[derive(Debug, Clone)]
+pub struct NoMbc {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+}
+
+impl NoMbc {
+    /// Create a controller around `rom`, sizing RAM from its `header`.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, header: &CartridgeHeader) -> Self {
+        // Types 0x08 and 0x09 declare RAM even when the size code is missing.
+        let ram_size = match header.cartridge_type {
+            0x08 | 0x09 => 0x2000,
+            _ => header.ram_size.min(0x2000),
+        };
+
+        Self {
+            rom: rom.into_boxed_slice(),
+            ram: vec![0; ram_size].into_boxed_slice(),
+        }
+    }
+}
+
+impl Mbc for NoMbc {
+    fn read_rom(&self, addr: u16) -> u8 {
+        // Reads past the end of a small ROM see an open bus.
+        self.rom.get(usize::from(addr)).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _value: u8) {}
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.ram
+            .get(usize::from(addr - 0xA000))
+            .copied()
+            .unwrap_or(0xFF)
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if let Some(byte) = self.ram.get_mut(usize::from(addr - 0xA000)) {
+            *byte = value;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_mbc(kind: u8) -> NoMbc {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = kind;
+        rom[0x1234] = 0x42;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        NoMbc::new(rom, &header)
+    }
+
+    #[test]
+    fn rom_writes_are_ignored() {
+        let mut mbc = no_mbc(0x00);
+
+        mbc.write_rom(0x1234, 0x00);
+        mbc.write_rom(0x2000, 0x01);
+
+        assert_eq!(mbc.read_rom(0x1234), 0x42);
+        assert_eq!(mbc.read_rom(0x4000), 0x00);
+    }
+
+    #[test]
+    fn optional_ram() {
+        let mut mbc = no_mbc(0x00);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        let mut mbc = no_mbc(0x09);
+        mbc.write_ram(0xBFFF, 0x12);
+        assert_eq!(mbc.read_ram(0xBFFF), 0x12);
+    }
+}
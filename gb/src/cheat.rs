@@ -0,0 +1,252 @@
+//! Game Genie and `GameShark` cheat codes.
+//!
+//! A Game Genie sits between the cartridge and the console and patches the
+//! bytes read from ROM, while a `GameShark` writes values into RAM once a
+//! frame. Both are applied by [`GameBoy::add_cheat`](crate::GameBoy::add_cheat).
+
+use core::error::Error;
+use core::fmt;
+use core::str::FromStr;
+
+/// The most digits in a code, that of a Game Genie code with a compare byte.
+const MAX_DIGITS: usize = 9;
+
+/// An error parsing a cheat code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheatError {
+    /// The code has a character other than a hex digit or a dash.
+    InvalidDigit(char),
+    /// The code has a number of digits no format uses.
+    InvalidLength {
+        /// The number of hex digits in the code.
+        len: usize,
+    },
+    /// The code is a Game Genie code outside ROM, or a `GameShark` code
+    /// inside it.
+    InvalidAddress(u16),
+    /// The code is a `GameShark` code of a type that names no RAM bank.
+    InvalidKind(u8),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidDigit(c) => write!(f, "invalid character {c:?} in cheat code"),
+            Self::InvalidLength { len } => {
+                write!(f, "cheat code has {len} digits, expected 6, 8 or 9")
+            }
+            Self::InvalidAddress(addr) => {
+                write!(f, "cheat code address {addr:#06X} is outside its memory")
+            }
+            Self::InvalidKind(kind) => write!(f, "cheat code type {kind:#04X} is unknown"),
+        }
+    }
+}
+
+impl Error for CheatError {}
+
+/// A parsed cheat code.
+///
+/// Game Genie codes are written `ABC-DEF` or `ABC-DEF-GHI`, and `GameShark`
+/// codes `ABCDEFGH`, all in hex. Dashes are optional, so the number of
+/// digits tells the formats apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cheat {
+    /// A Game Genie code, substituting `value` for the ROM byte at `addr`.
+    GameGenie {
+        /// The ROM address patched.
+        addr: u16,
+        /// The byte read instead.
+        value: u8,
+        /// The byte the ROM must hold for the patch to apply, which keeps it
+        /// to one bank when the address is banked.
+        compare: Option<u8>,
+    },
+    /// A `GameShark` code, writing `value` to the RAM at `addr` every frame.
+    GameShark {
+        /// The code type, naming the RAM bank written, see [`Cheat::bank`].
+        kind: u8,
+        /// The RAM address written.
+        addr: u16,
+        /// The byte written.
+        value: u8,
+    },
+}
+
+impl Cheat {
+    /// Decode a Game Genie code from its digits.
+    ///
+    /// The digits `AB` are the value. The address is `FCDE`, with `F`
+    /// inverted. `GI` is the compare byte exclusive-ored with `0xBA`, then
+    /// rotated left by two, and `H` is unused.
+    fn game_genie(digits: &[u8]) -> Result<Self, CheatError> {
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+        let addr = u16::from(digits[5] ^ 0x0F) << 12
+            | u16::from(digits[2]) << 8
+            | u16::from(digits[3]) << 4
+            | u16::from(digits[4]);
+        if addr >= 0x8000 {
+            return Err(CheatError::InvalidAddress(addr));
+        }
+
+        let compare = (digits.len() == MAX_DIGITS)
+            .then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA);
+        Ok(Self::GameGenie {
+            addr,
+            value: byte(0),
+            compare,
+        })
+    }
+
+    /// Decode a `GameShark` code from its digits.
+    ///
+    /// The digits are the type, the value, then the address low byte first.
+    fn game_shark(digits: &[u8]) -> Result<Self, CheatError> {
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+        let addr = u16::from_le_bytes([byte(4), byte(6)]);
+        if addr < 0x8000 {
+            return Err(CheatError::InvalidAddress(addr));
+        }
+
+        let kind = byte(0);
+        if !matches!(kind, 0x00..=0x0F | 0x90..=0x97) {
+            return Err(CheatError::InvalidKind(kind));
+        }
+
+        Ok(Self::GameShark {
+            kind,
+            addr,
+            value: byte(2),
+        })
+    }
+
+    /// Return the RAM bank a `GameShark` code writes, if it's in banked RAM.
+    ///
+    /// Type `0x01`, the usual one, writes to whichever bank is mapped. Other
+    /// types `0x00-0x0F` name the external RAM bank of codes at
+    /// `0xA000-0xBFFF`, and types `0x90-0x97` the CGB WRAM bank of codes at
+    /// `0xD000-0xDFFF`. Codes outside banked RAM write to the memory mapped.
+    #[must_use]
+    pub const fn bank(self) -> Option<u8> {
+        match self {
+            Self::GameShark {
+                kind: kind @ (0x00 | 0x02..=0x0F),
+                addr: 0xA000..=0xBFFF,
+                ..
+            } => Some(kind),
+            Self::GameShark {
+                kind: kind @ 0x90..=0x97,
+                addr: 0xD000..=0xDFFF,
+                ..
+            } => Some(kind & 0x07),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Cheat {
+    type Err = CheatError;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let mut digits = [0; MAX_DIGITS];
+        let mut len = 0;
+        for c in code.trim().chars().filter(|&c| c != '-') {
+            let digit = c.to_digit(16).ok_or(CheatError::InvalidDigit(c))?;
+            if let Some(slot) = digits.get_mut(len) {
+                // A hex digit is below 16, so the cast is lossless.
+                *slot = digit as u8;
+            }
+            len += 1;
+        }
+
+        match len {
+            6 | 9 => Self::game_genie(&digits[..len]),
+            8 => Self::game_shark(&digits[..len]),
+            len => Err(CheatError::InvalidLength { len }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_game_genie_codes() {
+        assert_eq!(
+            "010-EE9".parse(),
+            Ok(Cheat::GameGenie {
+                addr: 0x60EE,
+                value: 0x01,
+                compare: None,
+            })
+        );
+        assert_eq!(
+            "3C1-50F-EA3".parse(),
+            Ok(Cheat::GameGenie {
+                addr: 0x0150,
+                value: 0x3C,
+                compare: Some(0x42),
+            })
+        );
+        assert_eq!("3c150fea3".parse::<Cheat>(), "3C1-50F-EA3".parse());
+        // The eighth digit plays no part.
+        assert_eq!("3C1-50F-E03".parse::<Cheat>(), "3C1-50F-EA3".parse());
+
+        // A Game Genie only patches ROM.
+        assert_eq!(
+            "010-EE7".parse::<Cheat>(),
+            Err(CheatError::InvalidAddress(0x80EE))
+        );
+    }
+
+    #[test]
+    fn parses_game_shark_codes() {
+        assert_eq!(
+            "010078E8".parse(),
+            Ok(Cheat::GameShark {
+                kind: 0x01,
+                addr: 0xE878,
+                value: 0x00,
+            })
+        );
+        assert_eq!(
+            "01FF0040".parse::<Cheat>(),
+            Err(CheatError::InvalidAddress(0x4000))
+        );
+        assert_eq!(
+            "A1FF00C0".parse::<Cheat>(),
+            Err(CheatError::InvalidKind(0xA1))
+        );
+    }
+
+    #[test]
+    fn game_shark_banks() {
+        let bank = |code: &str| code.parse::<Cheat>().unwrap().bank();
+        assert_eq!(bank("03FF00A0"), Some(3));
+        assert_eq!(bank("00FF00A0"), Some(0));
+        assert_eq!(bank("01FF00A0"), None);
+        assert_eq!(bank("93FF10D0"), Some(3));
+        // The type only names a bank of the RAM it's for.
+        assert_eq!(bank("03FF10D0"), None);
+        assert_eq!(bank("93FF00A0"), None);
+        assert_eq!(bank("010078E8"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        assert_eq!(
+            "010-EEX".parse::<Cheat>(),
+            Err(CheatError::InvalidDigit('X'))
+        );
+        assert_eq!(
+            "010-EE".parse::<Cheat>(),
+            Err(CheatError::InvalidLength { len: 5 })
+        );
+        assert_eq!(
+            "0100-78E8-00".parse::<Cheat>(),
+            Err(CheatError::InvalidLength { len: 10 })
+        );
+    }
+}
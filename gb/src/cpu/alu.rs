@@ -0,0 +1,281 @@
+//! The SM83 arithmetic logic unit.
+//!
+//! Every operation returns its result along with the exact flags the
+//! instruction leaves behind.
+
+use super::Flags;
+
+/// Add `b` and an optional carry to `a`.
+pub const fn add(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+    let carry = carry as u8;
+    let result = a.wrapping_add(b).wrapping_add(carry);
+
+    let mut flags = Flags::EMPTY;
+    flags.set(Flags::Z, result == 0);
+    flags.set(Flags::H, (a & 0xF) + (b & 0xF) + carry > 0xF);
+    flags.set(Flags::C, a as u16 + b as u16 + carry as u16 > 0xFF);
+    (result, flags)
+}
+
+/// Subtract `b` and an optional borrow from `a`.
+pub const fn sub(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+    let carry = carry as u8;
+    let result = a.wrapping_sub(b).wrapping_sub(carry);
+
+    let mut flags = Flags::N;
+    flags.set(Flags::Z, result == 0);
+    flags.set(Flags::H, (a & 0xF) < (b & 0xF) + carry);
+    flags.set(Flags::C, (a as u16) < b as u16 + carry as u16);
+    (result, flags)
+}
+
+/// Add `value` to `hl`, preserving the zero flag from `flags`.
+///
+/// The half carry and carry come out of bits 11 and 15, as the addition runs
+/// on the upper bytes after the lower ones.
+pub const fn add_hl(hl: u16, value: u16, flags: Flags) -> (u16, Flags) {
+    let (result, carry) = hl.overflowing_add(value);
+
+    let mut flags = Flags::from_bits(flags.into_bits() & Flags::Z.into_bits());
+    flags.set(Flags::H, (hl & 0xFFF) + (value & 0xFFF) > 0xFFF);
+    flags.set(Flags::C, carry);
+    (result, flags)
+}
+
+/// Add the signed `offset` to `sp`, for `ADD SP, e8` and `LD HL, SP+e8`.
+///
+/// Unlike [`add_hl`], the flags come from an unsigned addition of the offset
+/// byte to the lower byte of `sp`, out of bits 3 and 7, even for negative
+/// offsets. Z and N are always cleared.
+pub const fn add_sp(sp: u16, offset: i8) -> (u16, Flags) {
+    let byte = offset.cast_unsigned();
+
+    let mut flags = Flags::EMPTY;
+    flags.set(Flags::H, (sp & 0xF) + (byte & 0xF) as u16 > 0xF);
+    flags.set(Flags::C, (sp & 0xFF) + byte as u16 > 0xFF);
+    (sp.wrapping_add_signed(offset as i16), flags)
+}
+
+/// Bitwise AND `a` with `b`.
+pub const fn and(a: u8, b: u8) -> (u8, Flags) {
+    let result = a & b;
+
+    let mut flags = Flags::H;
+    flags.set(Flags::Z, result == 0);
+    (result, flags)
+}
+
+/// Bitwise XOR `a` with `b`.
+pub const fn xor(a: u8, b: u8) -> (u8, Flags) {
+    let result = a ^ b;
+
+    let mut flags = Flags::EMPTY;
+    flags.set(Flags::Z, result == 0);
+    (result, flags)
+}
+
+/// Bitwise OR `a` with `b`.
+pub const fn or(a: u8, b: u8) -> (u8, Flags) {
+    let result = a | b;
+
+    let mut flags = Flags::EMPTY;
+    flags.set(Flags::Z, result == 0);
+    (result, flags)
+}
+
+/// Increment `value`, preserving the carry flag from `flags`.
+pub const fn inc(value: u8, flags: Flags) -> (u8, Flags) {
+    let result = value.wrapping_add(1);
+
+    let mut flags = Flags::from_bits(flags.into_bits() & Flags::C.into_bits());
+    flags.set(Flags::Z, result == 0);
+    flags.set(Flags::H, value & 0xF == 0xF);
+    (result, flags)
+}
+
+/// Decrement `value`, preserving the carry flag from `flags`.
+#[allow(clippy::verbose_bit_mask)]
+pub const fn dec(value: u8, flags: Flags) -> (u8, Flags) {
+    let result = value.wrapping_sub(1);
+
+    let mut flags = Flags::from_bits(flags.into_bits() & Flags::C.into_bits());
+    flags.insert(Flags::N);
+    flags.set(Flags::Z, result == 0);
+    flags.set(Flags::H, value & 0xF == 0);
+    (result, flags)
+}
+
+/// Adjust `a` back into BCD after an addition or subtraction.
+///
+/// The correction is derived from the N, H and C flags left by the previous
+/// operation rather than from the operands.
+pub const fn daa(a: u8, flags: Flags) -> (u8, Flags) {
+    let mut result = a;
+    let mut carry = flags.contains(Flags::C);
+
+    if flags.contains(Flags::N) {
+        if flags.contains(Flags::H) {
+            result = result.wrapping_sub(0x06);
+        }
+        if carry {
+            result = result.wrapping_sub(0x60);
+        }
+    } else {
+        if carry || a > 0x99 {
+            result = result.wrapping_add(0x60);
+            carry = true;
+        }
+        if flags.contains(Flags::H) || a & 0xF > 0x9 {
+            result = result.wrapping_add(0x06);
+        }
+    }
+
+    let mut flags = Flags::from_bits(flags.into_bits() & Flags::N.into_bits());
+    flags.set(Flags::Z, result == 0);
+    flags.set(Flags::C, carry);
+    (result, flags)
+}
+
+/// Build the flags left by a CB shift or rotate.
+const fn shift_flags(result: u8, carry: bool) -> Flags {
+    let mut flags = Flags::EMPTY;
+    flags.set(Flags::Z, result == 0);
+    flags.set(Flags::C, carry);
+    flags
+}
+
+/// Rotate `value` left, copying bit 7 into the carry.
+pub const fn rlc(value: u8) -> (u8, Flags) {
+    let result = value.rotate_left(1);
+    (result, shift_flags(result, value & 0x80 != 0))
+}
+
+/// Rotate `value` right, copying bit 0 into the carry.
+pub const fn rrc(value: u8) -> (u8, Flags) {
+    let result = value.rotate_right(1);
+    (result, shift_flags(result, value & 1 != 0))
+}
+
+/// Rotate `value` left through the carry.
+pub const fn rl(value: u8, carry: bool) -> (u8, Flags) {
+    let result = value << 1 | carry as u8;
+    (result, shift_flags(result, value & 0x80 != 0))
+}
+
+/// Rotate `value` right through the carry.
+pub const fn rr(value: u8, carry: bool) -> (u8, Flags) {
+    let result = value >> 1 | (carry as u8) << 7;
+    (result, shift_flags(result, value & 1 != 0))
+}
+
+/// Shift `value` left arithmetically.
+pub const fn sla(value: u8) -> (u8, Flags) {
+    let result = value << 1;
+    (result, shift_flags(result, value & 0x80 != 0))
+}
+
+/// Shift `value` right arithmetically, preserving bit 7.
+pub const fn sra(value: u8) -> (u8, Flags) {
+    let result = value >> 1 | value & 0x80;
+    (result, shift_flags(result, value & 1 != 0))
+}
+
+/// Swap the nibbles of `value`.
+pub const fn swap(value: u8) -> (u8, Flags) {
+    let result = value.rotate_left(4);
+    (result, shift_flags(result, false))
+}
+
+/// Shift `value` right logically.
+pub const fn srl(value: u8) -> (u8, Flags) {
+    let result = value >> 1;
+    (result, shift_flags(result, value & 1 != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_half_carry_and_carry() {
+        assert_eq!(add(0x0F, 0x01, false), (0x10, Flags::H));
+        assert_eq!(add(0xFF, 0x01, false), (0x00, Flags::Z | Flags::H | Flags::C));
+        assert_eq!(add(0x0E, 0x01, true), (0x10, Flags::H));
+        assert_eq!(add(0xF0, 0x10, false), (0x00, Flags::Z | Flags::C));
+    }
+
+    #[test]
+    fn sub_half_borrow_and_borrow() {
+        assert_eq!(sub(0x10, 0x01, false), (0x0F, Flags::N | Flags::H));
+        assert_eq!(sub(0x00, 0x01, false), (0xFF, Flags::N | Flags::H | Flags::C));
+        assert_eq!(sub(0x10, 0x0F, true), (0x00, Flags::Z | Flags::N | Flags::H));
+        assert_eq!(sub(0x42, 0x42, false), (0x00, Flags::Z | Flags::N));
+    }
+
+    /// Encode `value` as packed BCD.
+    const fn bcd(value: u8) -> u8 {
+        ((value / 10) << 4) | (value % 10)
+    }
+
+    #[test]
+    fn add_hl_carries_out_of_bits_11_and_15() {
+        assert_eq!(add_hl(0x0FFF, 0x0001, Flags::EMPTY), (0x1000, Flags::H));
+        assert_eq!(add_hl(0x0FFF, 0x0001, Flags::ALL), (0x1000, Flags::Z | Flags::H));
+        // A carry out of bit 7 alone sets nothing.
+        assert_eq!(add_hl(0x00FF, 0x0001, Flags::N), (0x0100, Flags::EMPTY));
+        assert_eq!(add_hl(0xFFFF, 0x0001, Flags::EMPTY), (0x0000, Flags::H | Flags::C));
+        assert_eq!(add_hl(0x8000, 0x8000, Flags::EMPTY), (0x0000, Flags::C));
+    }
+
+    #[test]
+    fn add_sp_carries_out_of_bits_3_and_7() {
+        // Adding -1 adds 0xFF to the lower byte, carrying out of both, and
+        // a zero result leaves Z clear.
+        assert_eq!(add_sp(0x0001, -1), (0x0000, Flags::H | Flags::C));
+        assert_eq!(add_sp(0x0000, -1), (0xFFFF, Flags::EMPTY));
+        assert_eq!(add_sp(0xFFF8, 8), (0x0000, Flags::H | Flags::C));
+        assert_eq!(add_sp(0x00FF, 1), (0x0100, Flags::H | Flags::C));
+        // Carries out of the upper byte set nothing.
+        assert_eq!(add_sp(0x0FF0, 0x10), (0x1000, Flags::C));
+        assert_eq!(add_sp(0x1000, -128), (0x0F80, Flags::EMPTY));
+    }
+
+    #[test]
+    fn daa_representative_cases() {
+        // 0x15 + 0x27 = 0x3C, adjusted to 0x42.
+        assert_eq!(daa(0x3C, Flags::EMPTY), (0x42, Flags::EMPTY));
+        // 0x99 + 0x01 = 0x9A, adjusted to 0x00 with a carry out.
+        assert_eq!(daa(0x9A, Flags::EMPTY), (0x00, Flags::Z | Flags::C));
+        // 0x09 + 0x09 = 0x12 with a half-carry, adjusted to 0x18.
+        assert_eq!(daa(0x12, Flags::H), (0x18, Flags::EMPTY));
+        // 0x42 - 0x15 = 0x2D with a half-borrow, adjusted to 0x27.
+        assert_eq!(daa(0x2D, Flags::N | Flags::H), (0x27, Flags::N));
+        // 0x10 - 0x20 = 0xF0 with a borrow, adjusted to 0x90.
+        assert_eq!(daa(0xF0, Flags::N | Flags::C), (0x90, Flags::N | Flags::C));
+    }
+
+    #[test]
+    fn daa_matches_decimal_arithmetic() {
+        for x in 0..100 {
+            for y in 0..100 {
+                let (sum, flags) = add(bcd(x), bcd(y), false);
+                let (sum, flags) = daa(sum, flags);
+                assert_eq!(sum, bcd((x + y) % 100), "{x} + {y}");
+                assert_eq!(flags.contains(Flags::C), x + y >= 100, "{x} + {y}");
+
+                let (diff, flags) = sub(bcd(x), bcd(y), false);
+                let (diff, flags) = daa(diff, flags);
+                assert_eq!(diff, bcd((100 + x - y) % 100), "{x} - {y}");
+                assert_eq!(flags.contains(Flags::C), x < y, "{x} - {y}");
+                assert!(!flags.contains(Flags::H));
+            }
+        }
+    }
+
+    #[test]
+    fn inc_dec_preserve_carry() {
+        assert_eq!(inc(0xFF, Flags::C), (0x00, Flags::Z | Flags::H | Flags::C));
+        assert_eq!(dec(0x01, Flags::ALL), (0x00, Flags::Z | Flags::N | Flags::C));
+        assert_eq!(dec(0x10, Flags::EMPTY), (0x0F, Flags::N | Flags::H));
+    }
+}
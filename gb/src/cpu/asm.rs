@@ -0,0 +1,119 @@
+//! Assembling instructions into bytes, for writing test programs.
+
+use alloc::vec::Vec;
+
+use super::Instruction;
+
+/// Assemble `program` into its bytes, one instruction after another.
+///
+/// Tests can bring the variants into scope to read like assembly:
+/// `assemble(&[LdImm(Reg(A), 0x42), Inc(Reg(A)), Halt])`.
+///
+/// # Panics
+///
+/// Panics if an instruction has no encoding, see [`Instruction::encode`].
+pub fn assemble(program: &[Instruction]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for instruction in program {
+        let Some((encoded, len)) = instruction.encode() else {
+            panic!("{instruction} has no encoding");
+        };
+        bytes.extend_from_slice(&encoded[..usize::from(len)]);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{
+        AluOp, Condition, Indirect, Instruction::*, Operand::*, Reg8::*, Reg16, ShiftOp, decode,
+    };
+
+    #[test]
+    fn assembles_a_program() {
+        assert_eq!(
+            assemble(&[
+                LdImm(Reg(A), 0x42),
+                Inc(Reg(A)),
+                Jp(Some(Condition::NZ), 0x0150),
+                Halt
+            ]),
+            [0x3E, 0x42, 0x3C, 0xC2, 0x50, 0x01, 0x76]
+        );
+        assert_eq!(
+            assemble(&[Bit(7, Reg(H)), Stop, Jr(None, -2)]),
+            [0xCB, 0x7C, 0x10, 0x00, 0x18, 0xFE]
+        );
+    }
+
+    #[test]
+    fn encoding_inverts_decoding() {
+        for opcode in 0..=0xFF {
+            // STOP is followed by 0x00, and the CB opcodes are covered below.
+            let bytes = [opcode, if opcode == 0x10 { 0x00 } else { 0xA5 }, 0x12];
+            let (instruction, len) = decode(&bytes);
+            if opcode != 0xCB {
+                let (encoded, encoded_len) = instruction.encode().unwrap();
+                assert_eq!(encoded_len, len, "{instruction}");
+                assert_eq!(
+                    encoded[..usize::from(len)],
+                    bytes[..usize::from(len)],
+                    "{instruction}"
+                );
+            }
+        }
+
+        for opcode in 0..=0xFF {
+            let (instruction, _) = decode(&[0xCB, opcode]);
+            assert_eq!(
+                instruction.encode(),
+                Some(([0xCB, opcode, 0], 2)),
+                "{instruction}"
+            );
+        }
+    }
+
+    #[test]
+    fn assembled_program_decodes_back() {
+        let program = [
+            Ld16(Reg16::HL, 0xC000),
+            LdImm(Hl, 0x99),
+            LoadIndirect(Indirect::HlInc),
+            Alu(AluOp::Xor, Reg(A)),
+            AluImm(AluOp::Cp, 0x90),
+            Push(Reg16::AF),
+            LdHlSp(-3),
+            Call(Some(Condition::C), 0x4000),
+            Shift(ShiftOp::Swap, Hl),
+            Set(3, Reg(E)),
+            Rst(0x38),
+            Ret(None),
+        ];
+
+        let bytes = assemble(&program);
+        let mut offset = 0;
+        let mut decoded = Vec::new();
+        while offset < bytes.len() {
+            let (instruction, len) = decode(&bytes[offset..]);
+            decoded.push(instruction);
+            offset += usize::from(len);
+        }
+        assert_eq!(decoded, program);
+    }
+
+    #[test]
+    fn unencodable_operands_are_rejected() {
+        assert_eq!(Inc16(Reg16::AF).encode(), None);
+        assert_eq!(Ld16(Reg16::AF, 0x1234).encode(), None);
+        assert_eq!(Push(Reg16::SP).encode(), None);
+        assert_eq!(Bit(8, Reg(A)).encode(), None);
+        assert_eq!(Rst(0x09).encode(), None);
+    }
+
+    #[test]
+    #[should_panic = "INC AF has no encoding"]
+    fn assembling_unencodable_operands_panics() {
+        let _ = assemble(&[Inc16(Reg16::AF)]);
+    }
+}
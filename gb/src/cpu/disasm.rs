@@ -0,0 +1,118 @@
+//! A disassembler over the decoded instructions.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use crate::bus::Bus;
+
+use super::{Instruction, decode, length};
+
+/// Decode `count` instructions from `addr` onwards, returning each along with
+/// its address and its assembly text.
+///
+/// Memory is read through [`Bus::peek`], so disassembling has no side
+/// effects. Relative jumps show their absolute target, followed by the raw
+/// offset as a comment, and illegal opcodes show up as `DB` bytes.
+pub fn disassemble(bus: &impl Bus, addr: u16, count: usize) -> Vec<(u16, Instruction, String)> {
+    let mut lines = Vec::with_capacity(count);
+    let mut pc = addr;
+
+    for _ in 0..count {
+        let mut bytes = [bus.peek(pc), 0, 0];
+        let len = length(bytes[0]);
+        for (byte, offset) in bytes[1..usize::from(len)].iter_mut().zip(1..) {
+            *byte = bus.peek(pc.wrapping_add(offset));
+        }
+
+        let (instruction, len) = decode(&bytes);
+        let next = pc.wrapping_add(len.into());
+        let text = match instruction {
+            Instruction::Jr(cc, e) => {
+                let target = next.wrapping_add_signed(e.into());
+                let cc = cc.map(|cc| format!("{cc}, ")).unwrap_or_default();
+                format!("{} {cc}${target:04X} ; {e:+}", instruction.mnemonic())
+            }
+            _ => instruction.to_string(),
+        };
+
+        lines.push((pc, instruction, text));
+        pc = next;
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::borrow::ToOwned;
+
+    use super::*;
+    use crate::bus::FlatMemory;
+    use crate::cpu::{Condition, Operand, Reg8};
+
+    /// Disassemble `count` instructions of `program` placed at `addr`, keeping
+    /// only the addresses and text.
+    fn listing(addr: u16, program: &[u8], count: usize) -> Vec<(u16, String)> {
+        let memory = FlatMemory::with_program(addr, program);
+        disassemble(&memory, addr, count)
+            .into_iter()
+            .map(|(addr, _, text)| (addr, text))
+            .collect()
+    }
+
+    #[test]
+    fn walks_multi_byte_instructions() {
+        // NOP ; LD BC,$1234 ; LD A,$42 ; BIT 7,H ; JP $0150
+        let program = [0x00, 0x01, 0x34, 0x12, 0x3E, 0x42, 0xCB, 0x7C, 0xC3, 0x50, 0x01];
+
+        assert_eq!(
+            listing(0x0100, &program, 5),
+            [
+                (0x0100, "NOP".to_owned()),
+                (0x0101, "LD BC, $1234".to_owned()),
+                (0x0104, "LD A, $42".to_owned()),
+                (0x0106, "BIT 7, H".to_owned()),
+                (0x0108, "JP $0150".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_relative_jumps() {
+        // JR NZ,-6 ; JR +2
+        let lines = listing(0x0154, &[0x20, 0xFA, 0x18, 0x02], 2);
+
+        assert_eq!(lines[0], (0x0154, "JR NZ, $0150 ; -6".to_owned()));
+        assert_eq!(lines[1], (0x0156, "JR $015A ; +2".to_owned()));
+    }
+
+    #[test]
+    fn returns_decoded_instructions() {
+        let memory = FlatMemory::with_program(0xC000, &[0x28, 0x10, 0x70]);
+        let lines = disassemble(&memory, 0xC000, 2);
+
+        assert_eq!(lines[0].1, Instruction::Jr(Some(Condition::Z), 0x10));
+        assert_eq!(lines[1].1, Instruction::Ld(Operand::Hl, Operand::Reg(Reg8::B)));
+    }
+
+    #[test]
+    fn illegal_opcodes_are_bytes() {
+        let lines = listing(0x0200, &[0xD3, 0xFC, 0x00], 3);
+
+        assert_eq!(lines[0], (0x0200, "DB $D3".to_owned()));
+        assert_eq!(lines[1], (0x0201, "DB $FC".to_owned()));
+        assert_eq!(lines[2], (0x0202, "NOP".to_owned()));
+    }
+
+    #[test]
+    fn wraps_at_end_of_memory() {
+        let mut memory = FlatMemory::with_program(0xFFFE, &[0x00, 0xC3]);
+        memory.0[..2].copy_from_slice(&[0x34, 0x12]);
+
+        let lines = disassemble(&memory, 0xFFFE, 3);
+        assert_eq!(lines[1].2, "JP $1234");
+        assert_eq!(lines[2].0, 0x0002);
+    }
+}
@@ -0,0 +1,243 @@
+//! The SM83 flag register.
+
+use core::fmt::{self, Write};
+use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
+
+/// The flag register of the SM83, `F`.
+///
+/// Only the upper nibble is backed by hardware, the lower nibble always
+/// reads back as zero.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(transparent)]
+pub struct Flags(u8);
+
+impl Flags {
+    /// The zero flag.
+    pub const Z: Self = Self(1 << 7);
+    /// The subtraction flag, used by `DAA`.
+    pub const N: Self = Self(1 << 6);
+    /// The half-carry flag, used by `DAA`.
+    pub const H: Self = Self(1 << 5);
+    /// The carry flag.
+    pub const C: Self = Self(1 << 4);
+
+    /// No flags set.
+    pub const EMPTY: Self = Self(0);
+    /// Every flag set.
+    pub const ALL: Self = Self(0xF0);
+
+    /// Create a flag set from a raw byte, discarding the lower nibble.
+    #[inline]
+    #[must_use]
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    /// Create a flag set from the four flags in `ZNHC` order.
+    #[inline]
+    #[must_use]
+    #[allow(clippy::fn_params_excessive_bools)]
+    pub const fn from_zhnc(z: bool, n: bool, h: bool, c: bool) -> Self {
+        Self((z as u8) << 7 | (n as u8) << 6 | (h as u8) << 5 | (c as u8) << 4)
+    }
+
+    /// Return the raw byte of this flag set.
+    #[inline]
+    #[must_use]
+    pub const fn into_bits(self) -> u8 {
+        self.0
+    }
+
+    /// Check if every flag in `other` is set.
+    #[inline]
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Set every flag in `other`.
+    #[inline]
+    pub const fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    /// Clear every flag in `other`.
+    #[inline]
+    pub const fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// Flip every flag in `other`.
+    #[inline]
+    pub const fn toggle(&mut self, other: Self) {
+        self.0 ^= other.0;
+    }
+
+    /// Set or clear every flag in `other` depending on `value`.
+    #[inline]
+    pub const fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+}
+
+impl fmt::Debug for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Flags")
+            .field("z", &self.contains(Self::Z))
+            .field("n", &self.contains(Self::N))
+            .field("h", &self.contains(Self::H))
+            .field("c", &self.contains(Self::C))
+            .finish()
+    }
+}
+
+/// Writes the flags in `ZNHC` order, with a dash for each clear one, such
+/// as `Z-HC`.
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (flag, letter) in [(Self::Z, 'Z'), (Self::N, 'N'), (Self::H, 'H'), (Self::C, 'C')] {
+            f.write_char(if self.contains(flag) { letter } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Flags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.into_bits())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Flags {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u8::deserialize(deserializer).map(Self::from_bits)
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Flags {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for Flags {
+    type Output = Self;
+
+    #[inline]
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for Flags {
+    #[inline]
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.0 &= rhs.0;
+    }
+}
+
+impl BitXor for Flags {
+    type Output = Self;
+
+    #[inline]
+    fn bitxor(self, rhs: Self) -> Self {
+        Self(self.0 ^ rhs.0)
+    }
+}
+
+impl BitXorAssign for Flags {
+    #[inline]
+    fn bitxor_assign(&mut self, rhs: Self) {
+        self.0 ^= rhs.0;
+    }
+}
+
+impl Not for Flags {
+    type Output = Self;
+
+    #[inline]
+    fn not(self) -> Self {
+        Self(!self.0 & Self::ALL.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn display_dashes_clear_flags() {
+        assert_eq!((Flags::Z | Flags::H | Flags::C).to_string(), "Z-HC");
+        assert_eq!(Flags::N.to_string(), "-N--");
+        assert_eq!(Flags::EMPTY.to_string(), "----");
+    }
+
+    #[test]
+    fn contains_requires_every_bit() {
+        let flags = Flags::Z | Flags::C;
+
+        assert!(flags.contains(Flags::Z));
+        assert!(flags.contains(Flags::Z | Flags::C));
+        assert!(!flags.contains(Flags::Z | Flags::H));
+        assert!(flags.contains(Flags::EMPTY));
+    }
+
+    #[test]
+    fn insert_remove_toggle() {
+        let mut flags = Flags::EMPTY;
+
+        flags.insert(Flags::N | Flags::H);
+        assert_eq!(flags, Flags::N | Flags::H);
+
+        flags.remove(Flags::N);
+        assert_eq!(flags, Flags::H);
+
+        flags.toggle(Flags::H | Flags::C);
+        assert_eq!(flags, Flags::C);
+    }
+
+    #[test]
+    fn set_follows_value() {
+        let mut flags = Flags::ALL;
+
+        flags.set(Flags::Z, false);
+        assert_eq!(flags, Flags::N | Flags::H | Flags::C);
+
+        flags.set(Flags::Z, true);
+        assert_eq!(flags, Flags::ALL);
+    }
+
+    #[test]
+    fn from_zhnc_places_bits() {
+        assert_eq!(Flags::from_zhnc(true, false, true, false).into_bits(), 0b1010_0000);
+        assert_eq!(Flags::from_zhnc(false, true, false, true), Flags::N | Flags::C);
+        assert_eq!(Flags::from_zhnc(true, true, true, true), Flags::ALL);
+    }
+
+    #[test]
+    fn low_nibble_is_never_set() {
+        let mut flags = Flags::from_bits(0xFF);
+        assert_eq!(flags.into_bits(), 0xF0);
+
+        flags.toggle(!Flags::EMPTY);
+        assert_eq!(flags.into_bits(), 0x00);
+    }
+}
@@ -0,0 +1,1098 @@
+//! Decoded SM83 instructions.
+//!
+//! The decoder here is the single source of truth for both the executor and
+//! any external tooling, such as disassemblers and tracers. The encoder is
+//! its inverse, used to assemble programs.
+
+use core::fmt;
+
+use super::{Reg8, Reg16};
+
+/// An 8-bit operand, either a register or the byte at `(HL)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    /// An 8-bit register.
+    Reg(Reg8),
+    /// The byte in memory addressed by `HL`.
+    Hl,
+}
+
+/// A memory operand addressed by a register pair, as used by `LD A, (rr)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Indirect {
+    /// The byte addressed by `BC`.
+    Bc,
+    /// The byte addressed by `DE`.
+    De,
+    /// The byte addressed by `HL`, incrementing `HL` afterwards.
+    HlInc,
+    /// The byte addressed by `HL`, decrementing `HL` afterwards.
+    HlDec,
+}
+
+/// A branch condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Condition {
+    /// The zero flag is clear.
+    NZ,
+    /// The zero flag is set.
+    Z,
+    /// The carry flag is clear.
+    NC,
+    /// The carry flag is set.
+    C,
+}
+
+/// An 8-bit ALU operation on the accumulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AluOp {
+    /// Addition.
+    Add,
+    /// Addition with carry.
+    Adc,
+    /// Subtraction.
+    Sub,
+    /// Subtraction with borrow.
+    Sbc,
+    /// Bitwise AND.
+    And,
+    /// Bitwise XOR.
+    Xor,
+    /// Bitwise OR.
+    Or,
+    /// Comparison, a subtraction that discards its result.
+    Cp,
+}
+
+/// A CB-prefixed shift or rotate operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShiftOp {
+    /// Rotate left.
+    Rlc,
+    /// Rotate right.
+    Rrc,
+    /// Rotate left through the carry.
+    Rl,
+    /// Rotate right through the carry.
+    Rr,
+    /// Shift left arithmetically.
+    Sla,
+    /// Shift right arithmetically.
+    Sra,
+    /// Swap nibbles.
+    Swap,
+    /// Shift right logically.
+    Srl,
+}
+
+/// A decoded SM83 instruction along with its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `NOP`
+    Nop,
+    /// `STOP`
+    Stop,
+    /// `HALT`
+    Halt,
+    /// `DI`
+    Di,
+    /// `EI`
+    Ei,
+
+    /// `LD r, r'`
+    Ld(Operand, Operand),
+    /// `LD r, n`
+    LdImm(Operand, u8),
+    /// `LD (rr), A`
+    StoreIndirect(Indirect),
+    /// `LD A, (rr)`
+    LoadIndirect(Indirect),
+    /// `LDH (n), A`
+    StoreHigh(u8),
+    /// `LDH A, (n)`
+    LoadHigh(u8),
+    /// `LD (C), A`
+    StoreHighC,
+    /// `LD A, (C)`
+    LoadHighC,
+    /// `LD (nn), A`
+    StoreAbsolute(u16),
+    /// `LD A, (nn)`
+    LoadAbsolute(u16),
+
+    /// `LD rr, nn`
+    Ld16(Reg16, u16),
+    /// `LD (nn), SP`
+    StoreSp(u16),
+    /// `LD SP, HL`
+    LdSpHl,
+    /// `LD HL, SP+e`
+    LdHlSp(i8),
+    /// `PUSH rr`
+    Push(Reg16),
+    /// `POP rr`
+    Pop(Reg16),
+
+    /// `INC r`
+    Inc(Operand),
+    /// `DEC r`
+    Dec(Operand),
+    /// `ADD A, r` and the other ALU operations on a register.
+    Alu(AluOp, Operand),
+    /// `ADD A, n` and the other ALU operations on an immediate.
+    AluImm(AluOp, u8),
+    /// `DAA`
+    Daa,
+    /// `CPL`
+    Cpl,
+    /// `SCF`
+    Scf,
+    /// `CCF`
+    Ccf,
+
+    /// `INC rr`
+    Inc16(Reg16),
+    /// `DEC rr`
+    Dec16(Reg16),
+    /// `ADD HL, rr`
+    AddHl(Reg16),
+    /// `ADD SP, e`
+    AddSp(i8),
+
+    /// `RLCA`
+    Rlca,
+    /// `RRCA`
+    Rrca,
+    /// `RLA`
+    Rla,
+    /// `RRA`
+    Rra,
+
+    /// `JR cc, e`, or `JR e` without a condition.
+    Jr(Option<Condition>, i8),
+    /// `JP cc, nn`, or `JP nn` without a condition.
+    Jp(Option<Condition>, u16),
+    /// `JP HL`
+    JpHl,
+    /// `CALL cc, nn`, or `CALL nn` without a condition.
+    Call(Option<Condition>, u16),
+    /// `RET cc`, or `RET` without a condition.
+    Ret(Option<Condition>),
+    /// `RETI`
+    Reti,
+    /// `RST n`
+    Rst(u8),
+
+    /// `RLC r` and the other CB-prefixed shifts.
+    Shift(ShiftOp, Operand),
+    /// `BIT b, r`
+    Bit(u8, Operand),
+    /// `RES b, r`
+    Res(u8, Operand),
+    /// `SET b, r`
+    Set(u8, Operand),
+
+    /// One of the eleven undefined opcodes.
+    Illegal(u8),
+}
+
+/// Static metadata about an opcode, known without executing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstrInfo {
+    /// The length in bytes, including any `CB` prefix.
+    pub bytes: u8,
+    /// The T-cycles taken, or taken by a conditional branch that isn't
+    /// taken.
+    pub cycles: u8,
+    /// The T-cycles taken by a conditional branch that is taken.
+    pub cycles_taken: Option<u8>,
+    /// The instruction with placeholders for its operands: `r` for an 8-bit
+    /// register or `(HL)`, `rr` for a register pair, `n` and `nn` for
+    /// immediates, `e` for a signed offset, `cc` for a condition and `b`
+    /// for a bit number.
+    pub mnemonic: &'static str,
+}
+
+impl InstrInfo {
+    /// Return the metadata of `instruction`, encoded in `bytes` bytes.
+    const fn of(instruction: Instruction, bytes: u8) -> Self {
+        let (cycles, cycles_taken) = instruction.cycles();
+        Self {
+            bytes,
+            cycles,
+            cycles_taken,
+            mnemonic: instruction.template(),
+        }
+    }
+}
+
+/// The metadata of every opcode.
+///
+/// Lengths and mnemonics come from the decoder, and cycle counts from
+/// [`Instruction::cycles`].
+///
+/// The entry for `0xCB` describes the prefix along with the quickest
+/// instruction it starts. The instruction itself is in
+/// [`CB_INSTRUCTION_INFO`].
+pub const INSTRUCTION_INFO: [InstrInfo; 256] = {
+    let mut table = [InstrInfo::of(Instruction::Nop, 1); 256];
+    let mut opcode = 0;
+    while opcode < table.len() {
+        #[allow(clippy::cast_possible_truncation)] // Below 256.
+        let (instruction, len) = decode(&[opcode as u8, 0, 0]);
+        table[opcode] = InstrInfo::of(instruction, len);
+        opcode += 1;
+    }
+
+    table[0xCB].mnemonic = "PREFIX CB";
+    table
+};
+
+/// The metadata of every `CB`-prefixed opcode, by its second byte.
+pub const CB_INSTRUCTION_INFO: [InstrInfo; 256] = {
+    let mut table = [InstrInfo::of(Instruction::Nop, 1); 256];
+    let mut opcode = 0;
+    while opcode < table.len() {
+        #[allow(clippy::cast_possible_truncation)] // Below 256.
+        let instruction = decode_cb(opcode as u8);
+        table[opcode] = InstrInfo::of(instruction, 2);
+        opcode += 1;
+    }
+    table
+};
+
+/// Return the length in bytes of the instruction starting with `opcode`.
+#[must_use]
+pub const fn length(opcode: u8) -> u8 {
+    match opcode {
+        0x01 | 0x11 | 0x21 | 0x31 | 0x08 | 0xC3 | 0xC2 | 0xCA | 0xD2 | 0xDA | 0xCD | 0xC4
+        | 0xCC | 0xD4 | 0xDC | 0xEA | 0xFA => 3,
+        0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0xCB | 0xE0 | 0xF0 | 0xE8 | 0xF8 => 2,
+        op if op & 0xC7 == 0x06 || op & 0xC7 == 0xC6 => 2,
+        _ => 1,
+    }
+}
+
+/// Decode the instruction at the start of `bytes`, returning it along with
+/// its length in bytes.
+///
+/// # Panics
+///
+/// Panics if `bytes` is shorter than the instruction, see [`length`].
+#[must_use]
+pub const fn decode(bytes: &[u8]) -> (Instruction, u8) {
+    use Instruction as I;
+
+    let opcode = bytes[0];
+    let len = length(opcode);
+    assert!(bytes.len() >= len as usize, "instruction is truncated");
+
+    let n = if len > 1 { bytes[1] } else { 0 };
+    let nn = if len > 2 {
+        u16::from_le_bytes([bytes[1], bytes[2]])
+    } else {
+        0
+    };
+    let e = n.cast_signed();
+
+    let instruction = match opcode {
+        0x00 => I::Nop,
+        0x10 => I::Stop,
+        0x76 => I::Halt,
+        0xF3 => I::Di,
+        0xFB => I::Ei,
+
+        0x40..=0x7F => I::Ld(operand(opcode >> 3), operand(opcode)),
+        op if op & 0xC7 == 0x06 => I::LdImm(operand(op >> 3), n),
+        0x02 | 0x12 | 0x22 | 0x32 => I::StoreIndirect(indirect(opcode)),
+        0x0A | 0x1A | 0x2A | 0x3A => I::LoadIndirect(indirect(opcode)),
+        0xE0 => I::StoreHigh(n),
+        0xF0 => I::LoadHigh(n),
+        0xE2 => I::StoreHighC,
+        0xF2 => I::LoadHighC,
+        0xEA => I::StoreAbsolute(nn),
+        0xFA => I::LoadAbsolute(nn),
+
+        op if op & 0xCF == 0x01 => I::Ld16(pair(op), nn),
+        0x08 => I::StoreSp(nn),
+        0xF9 => I::LdSpHl,
+        0xF8 => I::LdHlSp(e),
+        op if op & 0xCF == 0xC5 => I::Push(stack_pair(op)),
+        op if op & 0xCF == 0xC1 => I::Pop(stack_pair(op)),
+
+        op if op & 0xC7 == 0x04 => I::Inc(operand(op >> 3)),
+        op if op & 0xC7 == 0x05 => I::Dec(operand(op >> 3)),
+        0x80..=0xBF => I::Alu(alu_op(opcode >> 3), operand(opcode)),
+        op if op & 0xC7 == 0xC6 => I::AluImm(alu_op(op >> 3), n),
+        0x27 => I::Daa,
+        0x2F => I::Cpl,
+        0x37 => I::Scf,
+        0x3F => I::Ccf,
+
+        op if op & 0xCF == 0x03 => I::Inc16(pair(op)),
+        op if op & 0xCF == 0x0B => I::Dec16(pair(op)),
+        op if op & 0xCF == 0x09 => I::AddHl(pair(op)),
+        0xE8 => I::AddSp(e),
+
+        0x07 => I::Rlca,
+        0x0F => I::Rrca,
+        0x17 => I::Rla,
+        0x1F => I::Rra,
+
+        0x18 => I::Jr(None, e),
+        op if op & 0xE7 == 0x20 => I::Jr(Some(condition(op)), e),
+        0xC3 => I::Jp(None, nn),
+        op if op & 0xE7 == 0xC2 => I::Jp(Some(condition(op)), nn),
+        0xE9 => I::JpHl,
+        0xCD => I::Call(None, nn),
+        op if op & 0xE7 == 0xC4 => I::Call(Some(condition(op)), nn),
+        0xC9 => I::Ret(None),
+        op if op & 0xE7 == 0xC0 => I::Ret(Some(condition(op))),
+        0xD9 => I::Reti,
+        op if op & 0xC7 == 0xC7 => I::Rst(op & 0x38),
+
+        0xCB => decode_cb(n),
+        _ => I::Illegal(opcode),
+    };
+
+    (instruction, len)
+}
+
+/// Decode the second byte of a CB-prefixed instruction.
+const fn decode_cb(opcode: u8) -> Instruction {
+    let target = operand(opcode);
+    let bit = opcode >> 3 & 7;
+
+    match opcode >> 6 {
+        0 => Instruction::Shift(shift_op(bit), target),
+        1 => Instruction::Bit(bit, target),
+        2 => Instruction::Res(bit, target),
+        _ => Instruction::Set(bit, target),
+    }
+}
+
+/// Return the operand selected by the lower three bits of `index`.
+const fn operand(index: u8) -> Operand {
+    match index & 7 {
+        0 => Operand::Reg(Reg8::B),
+        1 => Operand::Reg(Reg8::C),
+        2 => Operand::Reg(Reg8::D),
+        3 => Operand::Reg(Reg8::E),
+        4 => Operand::Reg(Reg8::H),
+        5 => Operand::Reg(Reg8::L),
+        6 => Operand::Hl,
+        _ => Operand::Reg(Reg8::A),
+    }
+}
+
+/// Return the memory operand selected by bits 4-5 of `opcode`.
+const fn indirect(opcode: u8) -> Indirect {
+    match opcode >> 4 & 3 {
+        0 => Indirect::Bc,
+        1 => Indirect::De,
+        2 => Indirect::HlInc,
+        _ => Indirect::HlDec,
+    }
+}
+
+/// Return bits 4-5 of an opcode selecting `ind`, the inverse of [`indirect`].
+const fn indirect_bits(ind: Indirect) -> u8 {
+    match ind {
+        Indirect::Bc => 0x00,
+        Indirect::De => 0x10,
+        Indirect::HlInc => 0x20,
+        Indirect::HlDec => 0x30,
+    }
+}
+
+/// Return the register pair selected by bits 4-5 of `opcode`.
+const fn pair(opcode: u8) -> Reg16 {
+    match opcode >> 4 & 3 {
+        0 => Reg16::BC,
+        1 => Reg16::DE,
+        2 => Reg16::HL,
+        _ => Reg16::SP,
+    }
+}
+
+/// Return the register pair selected by bits 4-5 of a `PUSH` or `POP` opcode.
+const fn stack_pair(opcode: u8) -> Reg16 {
+    match opcode >> 4 & 3 {
+        0 => Reg16::BC,
+        1 => Reg16::DE,
+        2 => Reg16::HL,
+        _ => Reg16::AF,
+    }
+}
+
+/// Return the condition selected by bits 3-4 of `opcode`.
+const fn condition(opcode: u8) -> Condition {
+    match opcode >> 3 & 3 {
+        0 => Condition::NZ,
+        1 => Condition::Z,
+        2 => Condition::NC,
+        _ => Condition::C,
+    }
+}
+
+/// Return the ALU operation selected by the lower three bits of `index`.
+const fn alu_op(index: u8) -> AluOp {
+    match index & 7 {
+        0 => AluOp::Add,
+        1 => AluOp::Adc,
+        2 => AluOp::Sub,
+        3 => AluOp::Sbc,
+        4 => AluOp::And,
+        5 => AluOp::Xor,
+        6 => AluOp::Or,
+        _ => AluOp::Cp,
+    }
+}
+
+/// Return the shift operation selected by the lower three bits of `index`.
+const fn shift_op(index: u8) -> ShiftOp {
+    match index & 7 {
+        0 => ShiftOp::Rlc,
+        1 => ShiftOp::Rrc,
+        2 => ShiftOp::Rl,
+        3 => ShiftOp::Rr,
+        4 => ShiftOp::Sla,
+        5 => ShiftOp::Sra,
+        6 => ShiftOp::Swap,
+        _ => ShiftOp::Srl,
+    }
+}
+
+/// Return the index of `operand` in the lower three bits of an opcode, the
+/// inverse of [`operand`].
+const fn operand_index(operand: Operand) -> u8 {
+    match operand {
+        Operand::Reg(Reg8::B) => 0,
+        Operand::Reg(Reg8::C) => 1,
+        Operand::Reg(Reg8::D) => 2,
+        Operand::Reg(Reg8::E) => 3,
+        Operand::Reg(Reg8::H) => 4,
+        Operand::Reg(Reg8::L) => 5,
+        Operand::Hl => 6,
+        Operand::Reg(Reg8::A) => 7,
+    }
+}
+
+/// Return bits 4-5 of an opcode selecting `reg`, the inverse of [`pair`],
+/// or `None` for `AF`, which only `PUSH` and `POP` take.
+const fn pair_bits(reg: Reg16) -> Option<u8> {
+    match reg {
+        Reg16::BC => Some(0x00),
+        Reg16::DE => Some(0x10),
+        Reg16::HL => Some(0x20),
+        Reg16::SP => Some(0x30),
+        Reg16::AF => None,
+    }
+}
+
+/// Return bits 4-5 of a `PUSH` or `POP` opcode selecting `reg`, the inverse
+/// of [`stack_pair`], or `None` for `SP`, which they can't take.
+const fn stack_pair_bits(reg: Reg16) -> Option<u8> {
+    match reg {
+        Reg16::BC => Some(0x00),
+        Reg16::DE => Some(0x10),
+        Reg16::HL => Some(0x20),
+        Reg16::AF => Some(0x30),
+        Reg16::SP => None,
+    }
+}
+
+/// Return bits 3-4 of an opcode selecting `condition`, the inverse of
+/// [`condition`].
+const fn condition_bits(condition: Condition) -> u8 {
+    match condition {
+        Condition::NZ => 0x00,
+        Condition::Z => 0x08,
+        Condition::NC => 0x10,
+        Condition::C => 0x18,
+    }
+}
+
+impl AluOp {
+    /// Return the mnemonic of this operation.
+    #[must_use]
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Add => "ADD",
+            Self::Adc => "ADC",
+            Self::Sub => "SUB",
+            Self::Sbc => "SBC",
+            Self::And => "AND",
+            Self::Xor => "XOR",
+            Self::Or => "OR",
+            Self::Cp => "CP",
+        }
+    }
+}
+
+impl ShiftOp {
+    /// Return the mnemonic of this operation.
+    #[must_use]
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Rlc => "RLC",
+            Self::Rrc => "RRC",
+            Self::Rl => "RL",
+            Self::Rr => "RR",
+            Self::Sla => "SLA",
+            Self::Sra => "SRA",
+            Self::Swap => "SWAP",
+            Self::Srl => "SRL",
+        }
+    }
+}
+
+impl Instruction {
+    /// Encode this instruction, returning its bytes along with its length,
+    /// the inverse of [`decode`].
+    ///
+    /// Bytes past the length are zero. `STOP` is encoded with the `0x00`
+    /// that usually follows it, and `LD (HL), (HL)` as the `HALT` in its
+    /// place.
+    ///
+    /// Returns `None` if the operands have no encoding: `AF` outside `PUSH`
+    /// and `POP`, `SP` in them, a bit number above 7 or an `RST` target that
+    /// isn't a multiple of 8 up to `0x38`.
+    #[must_use]
+    #[allow(clippy::too_many_lines)]
+    pub const fn encode(self) -> Option<([u8; 3], u8)> {
+        const fn op(opcode: u8) -> ([u8; 3], u8) {
+            ([opcode, 0, 0], 1)
+        }
+        const fn n(opcode: u8, n: u8) -> ([u8; 3], u8) {
+            ([opcode, n, 0], 2)
+        }
+        const fn nn(opcode: u8, nn: u16) -> ([u8; 3], u8) {
+            let [lo, hi] = nn.to_le_bytes();
+            ([opcode, lo, hi], 3)
+        }
+        const fn cb(opcode: u8, bit: u8, target: Operand) -> Option<([u8; 3], u8)> {
+            if bit < 8 {
+                Some(n(0xCB, opcode | bit << 3 | operand_index(target)))
+            } else {
+                None
+            }
+        }
+
+        let encoded = match self {
+            Self::Nop => op(0x00),
+            Self::Stop => n(0x10, 0x00),
+            Self::Halt => op(0x76),
+            Self::Di => op(0xF3),
+            Self::Ei => op(0xFB),
+
+            Self::Ld(dst, src) => op(0x40 | operand_index(dst) << 3 | operand_index(src)),
+            Self::LdImm(dst, value) => n(0x06 | operand_index(dst) << 3, value),
+            Self::StoreIndirect(ind) => op(0x02 | indirect_bits(ind)),
+            Self::LoadIndirect(ind) => op(0x0A | indirect_bits(ind)),
+            Self::StoreHigh(value) => n(0xE0, value),
+            Self::LoadHigh(value) => n(0xF0, value),
+            Self::StoreHighC => op(0xE2),
+            Self::LoadHighC => op(0xF2),
+            Self::StoreAbsolute(addr) => nn(0xEA, addr),
+            Self::LoadAbsolute(addr) => nn(0xFA, addr),
+
+            Self::Ld16(reg, value) => {
+                let Some(bits) = pair_bits(reg) else {
+                    return None;
+                };
+                nn(0x01 | bits, value)
+            }
+            Self::StoreSp(addr) => nn(0x08, addr),
+            Self::LdSpHl => op(0xF9),
+            Self::LdHlSp(e) => n(0xF8, e.cast_unsigned()),
+            Self::Push(reg) => {
+                let Some(bits) = stack_pair_bits(reg) else {
+                    return None;
+                };
+                op(0xC5 | bits)
+            }
+            Self::Pop(reg) => {
+                let Some(bits) = stack_pair_bits(reg) else {
+                    return None;
+                };
+                op(0xC1 | bits)
+            }
+
+            Self::Inc(target) => op(0x04 | operand_index(target) << 3),
+            Self::Dec(target) => op(0x05 | operand_index(target) << 3),
+            Self::Alu(alu, src) => op(0x80 | (alu as u8) << 3 | operand_index(src)),
+            Self::AluImm(alu, value) => n(0xC6 | (alu as u8) << 3, value),
+            Self::Daa => op(0x27),
+            Self::Cpl => op(0x2F),
+            Self::Scf => op(0x37),
+            Self::Ccf => op(0x3F),
+
+            Self::Inc16(reg) => {
+                let Some(bits) = pair_bits(reg) else {
+                    return None;
+                };
+                op(0x03 | bits)
+            }
+            Self::Dec16(reg) => {
+                let Some(bits) = pair_bits(reg) else {
+                    return None;
+                };
+                op(0x0B | bits)
+            }
+            Self::AddHl(reg) => {
+                let Some(bits) = pair_bits(reg) else {
+                    return None;
+                };
+                op(0x09 | bits)
+            }
+            Self::AddSp(e) => n(0xE8, e.cast_unsigned()),
+
+            Self::Rlca => op(0x07),
+            Self::Rrca => op(0x0F),
+            Self::Rla => op(0x17),
+            Self::Rra => op(0x1F),
+
+            Self::Jr(None, e) => n(0x18, e.cast_unsigned()),
+            Self::Jr(Some(cc), e) => n(0x20 | condition_bits(cc), e.cast_unsigned()),
+            Self::Jp(None, addr) => nn(0xC3, addr),
+            Self::Jp(Some(cc), addr) => nn(0xC2 | condition_bits(cc), addr),
+            Self::JpHl => op(0xE9),
+            Self::Call(None, addr) => nn(0xCD, addr),
+            Self::Call(Some(cc), addr) => nn(0xC4 | condition_bits(cc), addr),
+            Self::Ret(None) => op(0xC9),
+            Self::Ret(Some(cc)) => op(0xC0 | condition_bits(cc)),
+            Self::Reti => op(0xD9),
+            Self::Rst(target) if target & !0x38 == 0 => op(0xC7 | target),
+            Self::Rst(_) => return None,
+
+            Self::Shift(shift, target) => return cb(0x00, shift as u8, target),
+            Self::Bit(bit, target) => return cb(0x40, bit, target),
+            Self::Res(bit, target) => return cb(0x80, bit, target),
+            Self::Set(bit, target) => return cb(0xC0, bit, target),
+
+            Self::Illegal(opcode) => op(opcode),
+        };
+        Some(encoded)
+    }
+
+    /// Return the T-cycles this instruction takes, with those of a taken
+    /// branch for conditional instructions.
+    ///
+    /// These are written out by hand rather than taken from the executor,
+    /// which counts the cycles of each memory access and internal delay as
+    /// it runs. A test runs every opcode through the executor to check that
+    /// the two agree.
+    #[must_use]
+    pub const fn cycles(self) -> (u8, Option<u8>) {
+        // Reading or writing `(HL)` takes an extra M-cycle for each access.
+        const fn hl(operand: Operand, extra: u8) -> u8 {
+            if matches!(operand, Operand::Hl) { extra } else { 0 }
+        }
+
+        let cycles = match self {
+            Self::Nop
+            | Self::Halt
+            | Self::Di
+            | Self::Ei
+            | Self::Daa
+            | Self::Cpl
+            | Self::Scf
+            | Self::Ccf
+            | Self::Rlca
+            | Self::Rrca
+            | Self::Rla
+            | Self::Rra
+            | Self::JpHl
+            | Self::Illegal(_) => 4,
+            Self::Stop
+            | Self::StoreIndirect(_)
+            | Self::LoadIndirect(_)
+            | Self::StoreHighC
+            | Self::LoadHighC
+            | Self::LdSpHl
+            | Self::Inc16(_)
+            | Self::Dec16(_)
+            | Self::AddHl(_)
+            | Self::AluImm(..) => 8,
+            Self::Ld(dst, src) => 4 + hl(dst, 4) + hl(src, 4),
+            Self::LdImm(dst, _) => 8 + hl(dst, 4),
+            Self::StoreHigh(_)
+            | Self::LoadHigh(_)
+            | Self::Ld16(..)
+            | Self::LdHlSp(_)
+            | Self::Pop(_)
+            | Self::Jr(None, _) => 12,
+            Self::StoreAbsolute(_)
+            | Self::LoadAbsolute(_)
+            | Self::AddSp(_)
+            | Self::Push(_)
+            | Self::Reti
+            | Self::Rst(_)
+            | Self::Jp(None, _)
+            | Self::Ret(None) => 16,
+            Self::StoreSp(_) => 20,
+            Self::Inc(target) | Self::Dec(target) => 4 + hl(target, 8),
+            Self::Alu(_, src) => 4 + hl(src, 4),
+
+            Self::Jr(Some(_), _) => return (8, Some(12)),
+            Self::Jp(Some(_), _) => return (12, Some(16)),
+            Self::Call(None, _) => 24,
+            Self::Call(Some(_), _) => return (12, Some(24)),
+            Self::Ret(Some(_)) => return (8, Some(20)),
+
+            Self::Bit(_, target) => 8 + hl(target, 4),
+            Self::Shift(_, target) | Self::Res(_, target) | Self::Set(_, target) => {
+                8 + hl(target, 8)
+            }
+        };
+
+        (cycles, None)
+    }
+
+    /// Return this instruction with placeholders for its operands, as
+    /// described by [`InstrInfo::mnemonic`].
+    #[must_use]
+    pub const fn template(self) -> &'static str {
+        match self {
+            Self::Ld(..) => "LD r, r",
+            Self::LdImm(..) => "LD r, n",
+            Self::StoreIndirect(_) => "LD (rr), A",
+            Self::LoadIndirect(_) => "LD A, (rr)",
+            Self::StoreHigh(_) => "LDH (n), A",
+            Self::LoadHigh(_) => "LDH A, (n)",
+            Self::StoreHighC => "LD (C), A",
+            Self::LoadHighC => "LD A, (C)",
+            Self::StoreAbsolute(_) => "LD (nn), A",
+            Self::LoadAbsolute(_) => "LD A, (nn)",
+            Self::Ld16(..) => "LD rr, nn",
+            Self::StoreSp(_) => "LD (nn), SP",
+            Self::LdSpHl => "LD SP, HL",
+            Self::LdHlSp(_) => "LD HL, SP+e",
+            Self::Push(_) => "PUSH rr",
+            Self::Pop(_) => "POP rr",
+            Self::Inc(_) => "INC r",
+            Self::Dec(_) => "DEC r",
+            Self::Alu(op, _) => match op {
+                AluOp::Add => "ADD A, r",
+                AluOp::Adc => "ADC A, r",
+                AluOp::Sub => "SUB A, r",
+                AluOp::Sbc => "SBC A, r",
+                AluOp::And => "AND A, r",
+                AluOp::Xor => "XOR A, r",
+                AluOp::Or => "OR A, r",
+                AluOp::Cp => "CP A, r",
+            },
+            Self::AluImm(op, _) => match op {
+                AluOp::Add => "ADD A, n",
+                AluOp::Adc => "ADC A, n",
+                AluOp::Sub => "SUB A, n",
+                AluOp::Sbc => "SBC A, n",
+                AluOp::And => "AND A, n",
+                AluOp::Xor => "XOR A, n",
+                AluOp::Or => "OR A, n",
+                AluOp::Cp => "CP A, n",
+            },
+            Self::Inc16(_) => "INC rr",
+            Self::Dec16(_) => "DEC rr",
+            Self::AddHl(_) => "ADD HL, rr",
+            Self::AddSp(_) => "ADD SP, e",
+            Self::Jr(None, _) => "JR e",
+            Self::Jr(Some(_), _) => "JR cc, e",
+            Self::Jp(None, _) => "JP nn",
+            Self::Jp(Some(_), _) => "JP cc, nn",
+            Self::JpHl => "JP HL",
+            Self::Call(None, _) => "CALL nn",
+            Self::Call(Some(_), _) => "CALL cc, nn",
+            Self::Ret(Some(_)) => "RET cc",
+            Self::Rst(_) => "RST n",
+            Self::Shift(op, _) => match op {
+                ShiftOp::Rlc => "RLC r",
+                ShiftOp::Rrc => "RRC r",
+                ShiftOp::Rl => "RL r",
+                ShiftOp::Rr => "RR r",
+                ShiftOp::Sla => "SLA r",
+                ShiftOp::Sra => "SRA r",
+                ShiftOp::Swap => "SWAP r",
+                ShiftOp::Srl => "SRL r",
+            },
+            Self::Bit(..) => "BIT b, r",
+            Self::Res(..) => "RES b, r",
+            Self::Set(..) => "SET b, r",
+            Self::Illegal(_) => "DB n",
+            _ => self.mnemonic(),
+        }
+    }
+
+    /// Return the mnemonic of this instruction, without any operands.
+    #[must_use]
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Nop => "NOP",
+            Self::Stop => "STOP",
+            Self::Halt => "HALT",
+            Self::Di => "DI",
+            Self::Ei => "EI",
+            Self::Ld(..)
+            | Self::LdImm(..)
+            | Self::StoreIndirect(_)
+            | Self::LoadIndirect(_)
+            | Self::StoreHighC
+            | Self::LoadHighC
+            | Self::StoreAbsolute(_)
+            | Self::LoadAbsolute(_)
+            | Self::Ld16(..)
+            | Self::StoreSp(_)
+            | Self::LdSpHl
+            | Self::LdHlSp(_) => "LD",
+            Self::StoreHigh(_) | Self::LoadHigh(_) => "LDH",
+            Self::Push(_) => "PUSH",
+            Self::Pop(_) => "POP",
+            Self::Inc(_) | Self::Inc16(_) => "INC",
+            Self::Dec(_) | Self::Dec16(_) => "DEC",
+            Self::Alu(op, _) | Self::AluImm(op, _) => op.mnemonic(),
+            Self::AddHl(_) | Self::AddSp(_) => "ADD",
+            Self::Daa => "DAA",
+            Self::Cpl => "CPL",
+            Self::Scf => "SCF",
+            Self::Ccf => "CCF",
+            Self::Rlca => "RLCA",
+            Self::Rrca => "RRCA",
+            Self::Rla => "RLA",
+            Self::Rra => "RRA",
+            Self::Jr(..) => "JR",
+            Self::Jp(..) | Self::JpHl => "JP",
+            Self::Call(..) => "CALL",
+            Self::Ret(_) => "RET",
+            Self::Reti => "RETI",
+            Self::Rst(_) => "RST",
+            Self::Shift(op, _) => op.mnemonic(),
+            Self::Bit(..) => "BIT",
+            Self::Res(..) => "RES",
+            Self::Set(..) => "SET",
+            Self::Illegal(_) => "DB",
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reg(reg) => write!(f, "{reg}"),
+            Self::Hl => f.write_str("(HL)"),
+        }
+    }
+}
+
+impl fmt::Display for Indirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Bc => "(BC)",
+            Self::De => "(DE)",
+            Self::HlInc => "(HL+)",
+            Self::HlDec => "(HL-)",
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NZ => "NZ",
+            Self::Z => "Z",
+            Self::NC => "NC",
+            Self::C => "C",
+        })
+    }
+}
+
+/// Write a signed offset with an explicit sign, such as `+5` or `-3`.
+fn write_offset(f: &mut fmt::Formatter<'_>, offset: i8) -> fmt::Result {
+    write!(f, "{offset:+}")
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = self.mnemonic();
+
+        match *self {
+            Self::Ld(dst, src) => write!(f, "{mnemonic} {dst}, {src}"),
+            Self::LdImm(dst, n) => write!(f, "{mnemonic} {dst}, ${n:02X}"),
+            Self::StoreIndirect(ind) => write!(f, "{mnemonic} {ind}, A"),
+            Self::LoadIndirect(ind) => write!(f, "{mnemonic} A, {ind}"),
+            Self::StoreHigh(n) => write!(f, "{mnemonic} ($FF{n:02X}), A"),
+            Self::LoadHigh(n) => write!(f, "{mnemonic} A, ($FF{n:02X})"),
+            Self::StoreHighC => write!(f, "{mnemonic} (C), A"),
+            Self::LoadHighC => write!(f, "{mnemonic} A, (C)"),
+            Self::StoreAbsolute(nn) => write!(f, "{mnemonic} (${nn:04X}), A"),
+            Self::LoadAbsolute(nn) => write!(f, "{mnemonic} A, (${nn:04X})"),
+
+            Self::Ld16(reg, nn) => write!(f, "{mnemonic} {reg}, ${nn:04X}"),
+            Self::StoreSp(nn) => write!(f, "{mnemonic} (${nn:04X}), SP"),
+            Self::LdSpHl => write!(f, "{mnemonic} SP, HL"),
+            Self::LdHlSp(e) => {
+                write!(f, "{mnemonic} HL, SP")?;
+                write_offset(f, e)
+            }
+            Self::Push(reg) | Self::Pop(reg) | Self::Inc16(reg) | Self::Dec16(reg) => {
+                write!(f, "{mnemonic} {reg}")
+            }
+
+            Self::Inc(target) | Self::Dec(target) | Self::Shift(_, target) => {
+                write!(f, "{mnemonic} {target}")
+            }
+            Self::Alu(_, src) => write!(f, "{mnemonic} A, {src}"),
+            Self::AluImm(_, n) => write!(f, "{mnemonic} A, ${n:02X}"),
+            Self::AddHl(reg) => write!(f, "{mnemonic} HL, {reg}"),
+            Self::AddSp(e) => {
+                write!(f, "{mnemonic} SP, ")?;
+                write_offset(f, e)
+            }
+
+            Self::Jr(None, e) => {
+                write!(f, "{mnemonic} ")?;
+                write_offset(f, e)
+            }
+            Self::Jr(Some(cc), e) => {
+                write!(f, "{mnemonic} {cc}, ")?;
+                write_offset(f, e)
+            }
+            Self::Jp(None, nn) | Self::Call(None, nn) => write!(f, "{mnemonic} ${nn:04X}"),
+            Self::Jp(Some(cc), nn) | Self::Call(Some(cc), nn) => {
+                write!(f, "{mnemonic} {cc}, ${nn:04X}")
+            }
+            Self::JpHl => write!(f, "{mnemonic} HL"),
+            Self::Ret(Some(cc)) => write!(f, "{mnemonic} {cc}"),
+            Self::Rst(n) => write!(f, "{mnemonic} ${n:02X}"),
+
+            Self::Bit(bit, target) | Self::Res(bit, target) | Self::Set(bit, target) => {
+                write!(f, "{mnemonic} {bit}, {target}")
+            }
+
+            Self::Illegal(opcode) => write!(f, "{mnemonic} ${opcode:02X}"),
+
+            _ => f.write_str(mnemonic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Decode `bytes` and format the result.
+    fn disasm(bytes: &[u8]) -> String {
+        let (instruction, len) = decode(bytes);
+        assert_eq!(usize::from(len), bytes.len());
+        instruction.to_string()
+    }
+
+    #[test]
+    fn display_loads() {
+        assert_eq!(disasm(&[0x41]), "LD B, C");
+        assert_eq!(disasm(&[0x77]), "LD (HL), A");
+        assert_eq!(disasm(&[0x36, 0x42]), "LD (HL), $42");
+        assert_eq!(disasm(&[0x2A]), "LD A, (HL+)");
+        assert_eq!(disasm(&[0x32]), "LD (HL-), A");
+        assert_eq!(disasm(&[0xE0, 0x40]), "LDH ($FF40), A");
+        assert_eq!(disasm(&[0xFA, 0x34, 0x12]), "LD A, ($1234)");
+        assert_eq!(disasm(&[0x31, 0xFE, 0xFF]), "LD SP, $FFFE");
+        assert_eq!(disasm(&[0xF8, 0xFF]), "LD HL, SP-1");
+        assert_eq!(disasm(&[0xF1]), "POP AF");
+    }
+
+    #[test]
+    fn display_arithmetic() {
+        assert_eq!(disasm(&[0x80]), "ADD A, B");
+        assert_eq!(disasm(&[0xFE, 0x90]), "CP A, $90");
+        assert_eq!(disasm(&[0x34]), "INC (HL)");
+        assert_eq!(disasm(&[0x39]), "ADD HL, SP");
+        assert_eq!(disasm(&[0xE8, 0x05]), "ADD SP, +5");
+        assert_eq!(disasm(&[0x27]), "DAA");
+    }
+
+    #[test]
+    fn display_control_flow() {
+        assert_eq!(disasm(&[0xCA, 0x34, 0x12]), "JP Z, $1234");
+        assert_eq!(disasm(&[0xC3, 0x50, 0x01]), "JP $0150");
+        assert_eq!(disasm(&[0x20, 0xFA]), "JR NZ, -6");
+        assert_eq!(disasm(&[0xCD, 0x00, 0x40]), "CALL $4000");
+        assert_eq!(disasm(&[0xD8]), "RET C");
+        assert_eq!(disasm(&[0xC9]), "RET");
+        assert_eq!(disasm(&[0xFF]), "RST $38");
+        assert_eq!(disasm(&[0x10, 0x00]), "STOP");
+    }
+
+    #[test]
+    fn display_cb() {
+        assert_eq!(disasm(&[0xCB, 0x37]), "SWAP A");
+        assert_eq!(disasm(&[0xCB, 0x7C]), "BIT 7, H");
+        assert_eq!(disasm(&[0xCB, 0x86]), "RES 0, (HL)");
+        assert_eq!(disasm(&[0xCB, 0x1E]), "RR (HL)");
+    }
+
+    #[test]
+    fn exactly_eleven_illegal_opcodes() {
+        let illegal: Vec<u8> = (0..=0xFF)
+            .filter(|&opcode| matches!(decode(&[opcode, 0, 0]).0, Instruction::Illegal(_)))
+            .collect();
+
+        assert_eq!(
+            illegal,
+            [
+                0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD
+            ]
+        );
+        assert_eq!(disasm(&[0xDD]), "DB $DD");
+    }
+
+    #[test]
+    fn info_lengths_walk_a_routine() {
+        let routine: [u8; 22] = [
+            0x21, 0x00, 0xC0, // LD HL,$C000
+            0x11, 0x00, 0x80, // LD DE,$8000
+            0x01, 0x10, 0x00, // LD BC,$0010
+            0x1A, // LD A,(DE)
+            0x22, // LD (HL+),A
+            0x13, // INC DE
+            0x0B, // DEC BC
+            0x78, // LD A,B
+            0xB1, // OR C
+            0x20, 0xF8, // JR NZ,-8
+            0xCB, 0x37, // SWAP A
+            0xE0, 0x40, // LDH ($40),A
+            0xC9, // RET
+        ];
+
+        let mut offset = 0;
+        let mut count = 0;
+        while offset < routine.len() {
+            offset += usize::from(INSTRUCTION_INFO[usize::from(routine[offset])].bytes);
+            count += 1;
+        }
+        assert_eq!((offset, count), (22, 13));
+    }
+
+    #[test]
+    fn info_describes_opcodes() {
+        let jr = INSTRUCTION_INFO[0x20];
+        assert_eq!((jr.bytes, jr.cycles, jr.cycles_taken), (2, 8, Some(12)));
+        assert_eq!(jr.mnemonic, "JR cc, e");
+        assert_eq!(INSTRUCTION_INFO[0x34].cycles, 12);
+        assert_eq!(INSTRUCTION_INFO[0xCB].bytes, 2);
+
+        let bit = CB_INSTRUCTION_INFO[0x46];
+        assert_eq!((bit.bytes, bit.cycles, bit.cycles_taken), (2, 12, None));
+        assert_eq!(bit.mnemonic, "BIT b, r");
+    }
+
+    #[test]
+    #[should_panic = "instruction is truncated"]
+    fn truncated_instruction() {
+        let _ = decode(&[0xC3, 0x00]);
+    }
+}
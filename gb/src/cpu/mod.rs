@@ -0,0 +1,1235 @@
+//! The Sharp SM83 processor.
+
+mod alu;
+#[cfg(test)]
+pub(crate) mod asm;
+mod disasm;
+mod flags;
+mod instruction;
+mod registers;
+#[cfg(test)]
+mod single_step;
+
+use core::fmt;
+
+use crate::bus::Bus;
+use crate::interrupt::{IE, IF, Interrupt};
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub use disasm::disassemble;
+pub use flags::Flags;
+pub use instruction::{
+    AluOp, CB_INSTRUCTION_INFO, Condition, INSTRUCTION_INFO, Indirect, InstrInfo, Instruction,
+    Operand, ShiftOp, decode, length,
+};
+pub use registers::{Reg8, Reg16, Registers};
+
+/// The address of the divider register, which is reset by `STOP`.
+const DIV: u16 = 0xFF04;
+/// The address of the CGB speed switch register.
+const KEY1: u16 = 0xFF4D;
+
+/// The register pairs in the order they're saved in a save state.
+const STATE_REGS: [Reg16; 5] = [Reg16::AF, Reg16::BC, Reg16::DE, Reg16::HL, Reg16::SP];
+
+/// The execution state of the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Fetching and executing instructions.
+    Running,
+    /// Idling after `HALT`.
+    Halted,
+    /// Idling after `STOP`, until a joypad line goes low.
+    Stopped,
+    /// Hung by an illegal opcode, until the system is reset.
+    Locked,
+}
+
+/// A machine cycle of the CPU, as recorded by the access log in tests.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BusCycle {
+    /// A read of `value` from `addr`.
+    Read { addr: u16, value: u8 },
+    /// A write of `value` to `addr`.
+    Write { addr: u16, value: u8 },
+    /// A cycle without a memory access.
+    Idle,
+}
+
+/// The Sharp SM83 processor.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Cpu {
+    /// The register file.
+    pub regs: Registers,
+    /// The interrupt master enable flag.
+    pub ime: bool,
+    state: State,
+    cgb: bool,
+    double_speed: bool,
+    /// Whether `EI` ran last, so `IME` is set once the next instruction
+    /// starts.
+    ei_delay: bool,
+    halt_bug: bool,
+    cycles: u8,
+    instruction: Instruction,
+    /// The machine cycles run, when recording.
+    #[cfg(test)]
+    log: Option<alloc::vec::Vec<BusCycle>>,
+}
+
+impl Cpu {
+    /// Create a new CPU in the DMG post-boot state.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            regs: Registers::new_dmg(),
+            ime: false,
+            state: State::Running,
+            cgb: false,
+            double_speed: false,
+            ei_delay: false,
+            halt_bug: false,
+            cycles: 0,
+            instruction: Instruction::Nop,
+            #[cfg(test)]
+            log: None,
+        }
+    }
+
+    /// Create a new CPU in the CGB post-boot state, with speed switching.
+    #[must_use]
+    pub fn new_cgb() -> Self {
+        Self {
+            regs: Registers::new_cgb(),
+            cgb: true,
+            ..Self::new()
+        }
+    }
+
+    /// Check if the CPU is running in CGB double-speed mode.
+    #[must_use]
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Return the clock multiplier of the CPU, 2 in double-speed mode.
+    #[must_use]
+    pub fn speed_multiplier(&self) -> u8 {
+        if self.double_speed { 2 } else { 1 }
+    }
+
+    /// Check if the CPU is idling after `HALT`.
+    #[must_use]
+    pub fn is_halted(&self) -> bool {
+        self.state == State::Halted
+    }
+
+    /// Check if the CPU is idling after `STOP`.
+    #[must_use]
+    pub fn is_stopped(&self) -> bool {
+        self.state == State::Stopped
+    }
+
+    /// Check if the CPU hung on an illegal opcode, for good.
+    #[must_use]
+    pub fn is_locked(&self) -> bool {
+        self.state == State::Locked
+    }
+
+    /// Return the most recently executed instruction.
+    #[must_use]
+    pub fn instruction(&self) -> Instruction {
+        self.instruction
+    }
+
+    /// Return the mnemonic of the most recently executed instruction.
+    #[must_use]
+    pub fn mnemonic(&self) -> &'static str {
+        self.instruction.mnemonic()
+    }
+
+    /// Write the registers and execution state to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for reg in STATE_REGS {
+            state.write_u16(self.regs.read16(reg));
+        }
+        state.write_u16(self.regs.pc);
+
+        state.write_bool(self.ime);
+        state.write_u8(self.state as u8);
+        state.write_bool(self.cgb);
+        state.write_bool(self.double_speed);
+        state.write_bool(self.ei_delay);
+        state.write_bool(self.halt_bug);
+    }
+
+    /// Restore the state written by [`Cpu::save_state`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is truncated or corrupt.
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        for reg in STATE_REGS {
+            self.regs.write16(reg, state.read_u16()?);
+        }
+        self.regs.pc = state.read_u16()?;
+
+        self.ime = state.read_bool()?;
+        self.state = match state.read_u8()? {
+            0 => State::Running,
+            1 => State::Halted,
+            2 => State::Stopped,
+            3 => State::Locked,
+            _ => return Err(StateError::Corrupt),
+        };
+        self.cgb = state.read_bool()?;
+        self.double_speed = state.read_bool()?;
+        self.ei_delay = state.read_bool()?;
+        self.halt_bug = state.read_bool()?;
+        Ok(())
+    }
+
+    /// Fetch, decode and execute a single instruction, or service a pending
+    /// interrupt instead.
+    ///
+    /// Returns the number of T-cycles taken, including the extra cycles of
+    /// taken conditional branches.
+    ///
+    /// An illegal opcode locks the CPU up like on hardware, after which
+    /// every step idles for an M-cycle, see [`Cpu::is_locked`].
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        self.cycles = 0;
+
+        // Not even interrupts get a locked CPU going again.
+        if self.state == State::Locked {
+            return 4;
+        }
+
+        let pending = pending_interrupts(bus);
+        if self.state == State::Halted && pending != 0 {
+            self.state = State::Running;
+        }
+
+        if self.state == State::Stopped && bus.read(IF) & Interrupt::Joypad.bit() != 0 {
+            self.state = State::Running;
+        }
+
+        if self.ime
+            && let Some(kind) = Interrupt::highest(pending)
+        {
+            self.service(bus, kind);
+            return self.cycles;
+        }
+
+        if self.state != State::Running {
+            return 4;
+        }
+
+        // `EI` only takes effect once the following instruction has started.
+        if self.ei_delay {
+            self.ei_delay = false;
+            self.ime = true;
+        }
+
+        let opcode = self.fetch(bus);
+        if self.halt_bug {
+            // The HALT bug fails to increment PC, so this byte is read twice.
+            self.halt_bug = false;
+            self.regs.pc = self.regs.pc.wrapping_sub(1);
+        }
+
+        let mut bytes = [opcode, 0, 0];
+        let len = usize::from(length(opcode));
+        for byte in &mut bytes[1..len] {
+            *byte = self.fetch(bus);
+        }
+
+        let (instruction, _) = decode(&bytes[..len]);
+        self.instruction = instruction;
+        self.execute(bus, instruction);
+        self.cycles
+    }
+
+    /// Jump to the vector of `kind`, taking five M-cycles.
+    fn service<B: Bus>(&mut self, bus: &mut B, kind: Interrupt) {
+        self.ime = false;
+
+        let flags = bus.read(IF);
+        bus.write(IF, flags & !kind.bit());
+
+        self.idle();
+        self.push(bus, self.regs.pc);
+        self.regs.pc = kind.vector();
+        self.idle();
+    }
+
+    /// Execute `STOP`, either switching the CGB clock speed or stopping the
+    /// system until a joypad press.
+    ///
+    /// Entering `STOP` resets the divider as a side effect, which is modeled
+    /// through a write to `DIV`.
+    fn stop<B: Bus>(&mut self, bus: &mut B) {
+        bus.write(DIV, 0);
+
+        let key1 = bus.read(KEY1);
+        if self.cgb && key1 & 1 != 0 {
+            self.double_speed = !self.double_speed;
+            bus.write(KEY1, key1 & !1);
+        } else {
+            self.state = State::Stopped;
+        }
+    }
+
+    /// Read a byte from the bus, taking one M-cycle.
+    fn read<B: Bus>(&mut self, bus: &mut B, addr: u16) -> u8 {
+        self.cycles += 4;
+        let value = bus.read(addr);
+        #[cfg(test)]
+        self.record(BusCycle::Read { addr, value });
+        value
+    }
+
+    /// Write a byte to the bus, taking one M-cycle.
+    fn write<B: Bus>(&mut self, bus: &mut B, addr: u16, value: u8) {
+        self.cycles += 4;
+        bus.write(addr, value);
+        #[cfg(test)]
+        self.record(BusCycle::Write { addr, value });
+    }
+
+    /// Read a byte from the bus while incrementing or decrementing its
+    /// address, taking one M-cycle.
+    fn read_increment<B: Bus>(&mut self, bus: &mut B, addr: u16) -> u8 {
+        self.cycles += 4;
+        let value = bus.read_increment(addr);
+        #[cfg(test)]
+        self.record(BusCycle::Read { addr, value });
+        value
+    }
+
+    /// Spend one M-cycle incrementing or decrementing `addr` without
+    /// accessing the bus.
+    fn increment<B: Bus>(&mut self, bus: &mut B, addr: u16) {
+        bus.increment(addr);
+        self.idle();
+    }
+
+    /// Spend one M-cycle without accessing the bus.
+    fn idle(&mut self) {
+        self.cycles += 4;
+        #[cfg(test)]
+        self.record(BusCycle::Idle);
+    }
+
+    /// Add `cycle` to the access log, if recording.
+    #[cfg(test)]
+    fn record(&mut self, cycle: BusCycle) {
+        if let Some(log) = &mut self.log {
+            log.push(cycle);
+        }
+    }
+
+    /// Read the byte at `PC` and advance past it.
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let addr = self.regs.pc;
+        self.cycles += 4;
+        let value = bus.fetch(addr);
+        #[cfg(test)]
+        self.record(BusCycle::Read { addr, value });
+        self.regs.pc = addr.wrapping_add(1);
+        value
+    }
+
+    /// Push a word onto the stack.
+    fn push<B: Bus>(&mut self, bus: &mut B, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.increment(bus, self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write(bus, self.regs.sp, hi);
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write(bus, self.regs.sp, lo);
+    }
+
+    /// Pop a word off the stack.
+    fn pop<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let lo = self.read_increment(bus, self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        let hi = self.read_increment(bus, self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// Read an 8-bit operand.
+    fn read_operand<B: Bus>(&mut self, bus: &mut B, operand: Operand) -> u8 {
+        match operand {
+            Operand::Reg(reg) => self.regs.read8(reg),
+            Operand::Hl => self.read(bus, self.regs.hl()),
+        }
+    }
+
+    /// Write an 8-bit operand.
+    fn write_operand<B: Bus>(&mut self, bus: &mut B, operand: Operand, value: u8) {
+        match operand {
+            Operand::Reg(reg) => self.regs.write8(reg, value),
+            Operand::Hl => self.write(bus, self.regs.hl(), value),
+        }
+    }
+
+    /// Return the address of a memory operand, applying any `HL` adjustment.
+    const fn indirect(&mut self, indirect: Indirect) -> u16 {
+        match indirect {
+            Indirect::Bc => self.regs.bc(),
+            Indirect::De => self.regs.de(),
+            Indirect::HlInc => {
+                let hl = self.regs.hl();
+                self.regs.set_hl(hl.wrapping_add(1));
+                hl
+            }
+            Indirect::HlDec => {
+                let hl = self.regs.hl();
+                self.regs.set_hl(hl.wrapping_sub(1));
+                hl
+            }
+        }
+    }
+
+    /// Check a branch condition, where `None` always holds.
+    const fn check(&self, condition: Option<Condition>) -> bool {
+        match condition {
+            None => true,
+            Some(Condition::NZ) => !self.regs.f.contains(Flags::Z),
+            Some(Condition::Z) => self.regs.f.contains(Flags::Z),
+            Some(Condition::NC) => !self.regs.f.contains(Flags::C),
+            Some(Condition::C) => self.regs.f.contains(Flags::C),
+        }
+    }
+
+    /// Apply an ALU operation to the accumulator.
+    fn alu(&mut self, op: AluOp, value: u8) {
+        let a = self.regs.a;
+        let carry = self.regs.f.contains(Flags::C);
+
+        let (result, flags) = match op {
+            AluOp::Add => alu::add(a, value, false),
+            AluOp::Adc => alu::add(a, value, carry),
+            AluOp::Sub => alu::sub(a, value, false),
+            AluOp::Sbc => alu::sub(a, value, carry),
+            AluOp::And => alu::and(a, value),
+            AluOp::Xor => alu::xor(a, value),
+            AluOp::Or => alu::or(a, value),
+            AluOp::Cp => (a, alu::sub(a, value, false).1),
+        };
+
+        self.regs.a = result;
+        self.regs.f = flags;
+    }
+
+    /// Add a signed offset to `SP`, as used by `ADD SP, e` and `LD HL, SP+e`.
+    fn add_sp(&mut self, offset: i8) -> u16 {
+        let (result, flags) = alu::add_sp(self.regs.sp, offset);
+        self.regs.f = flags;
+        result
+    }
+
+    /// Add a 16-bit register pair to `HL`.
+    fn add_hl(&mut self, value: u16) {
+        let (result, flags) = alu::add_hl(self.regs.hl(), value, self.regs.f);
+        self.regs.f = flags;
+        self.regs.set_hl(result);
+        self.idle();
+    }
+
+    /// Apply a CB shift operation to `value`.
+    const fn shift(&self, op: ShiftOp, value: u8) -> (u8, Flags) {
+        let carry = self.regs.f.contains(Flags::C);
+
+        match op {
+            ShiftOp::Rlc => alu::rlc(value),
+            ShiftOp::Rrc => alu::rrc(value),
+            ShiftOp::Rl => alu::rl(value, carry),
+            ShiftOp::Rr => alu::rr(value, carry),
+            ShiftOp::Sla => alu::sla(value),
+            ShiftOp::Sra => alu::sra(value),
+            ShiftOp::Swap => alu::swap(value),
+            ShiftOp::Srl => alu::srl(value),
+        }
+    }
+
+    /// Return from a call.
+    fn ret<B: Bus>(&mut self, bus: &mut B) {
+        self.regs.pc = self.pop(bus);
+        self.idle();
+    }
+
+    /// Return the flags left by an accumulator rotate, which unlike their CB
+    /// forms always clear Z.
+    const fn rotate_a_flags(carry: u8) -> Flags {
+        if carry == 1 { Flags::C } else { Flags::EMPTY }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn execute<B: Bus>(&mut self, bus: &mut B, instruction: Instruction) {
+        use Instruction as I;
+
+        match instruction {
+            I::Nop => {}
+            I::Stop => self.stop(bus),
+            I::Halt => {
+                if !self.ime && pending_interrupts(bus) != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.state = State::Halted;
+                }
+            }
+            I::Di => {
+                // This also cancels an `EI` right before it.
+                self.ime = false;
+                self.ei_delay = false;
+            }
+            I::Ei => self.ei_delay = true,
+
+            // 8-bit loads.
+            I::Ld(dst, src) => {
+                let value = self.read_operand(bus, src);
+                self.write_operand(bus, dst, value);
+            }
+            I::LdImm(dst, n) => self.write_operand(bus, dst, n),
+            I::StoreIndirect(ind) => {
+                let addr = self.indirect(ind);
+                self.write(bus, addr, self.regs.a);
+            }
+            I::LoadIndirect(ind @ (Indirect::HlInc | Indirect::HlDec)) => {
+                let addr = self.indirect(ind);
+                self.regs.a = self.read_increment(bus, addr);
+            }
+            I::LoadIndirect(ind) => {
+                let addr = self.indirect(ind);
+                self.regs.a = self.read(bus, addr);
+            }
+            I::StoreHigh(n) => self.write(bus, 0xFF00 | u16::from(n), self.regs.a),
+            I::LoadHigh(n) => self.regs.a = self.read(bus, 0xFF00 | u16::from(n)),
+            I::StoreHighC => self.write(bus, 0xFF00 | u16::from(self.regs.c), self.regs.a),
+            I::LoadHighC => self.regs.a = self.read(bus, 0xFF00 | u16::from(self.regs.c)),
+            I::StoreAbsolute(nn) => self.write(bus, nn, self.regs.a),
+            I::LoadAbsolute(nn) => self.regs.a = self.read(bus, nn),
+
+            // 16-bit loads.
+            I::Ld16(reg, nn) => self.regs.write16(reg, nn),
+            I::StoreSp(nn) => {
+                let [lo, hi] = self.regs.sp.to_le_bytes();
+                self.write(bus, nn, lo);
+                self.write(bus, nn.wrapping_add(1), hi);
+            }
+            I::LdSpHl => {
+                self.regs.sp = self.regs.hl();
+                self.idle();
+            }
+            I::LdHlSp(e) => {
+                let value = self.add_sp(e);
+                self.regs.set_hl(value);
+                self.idle();
+            }
+            I::Push(reg) => self.push(bus, self.regs.read16(reg)),
+            I::Pop(reg) => {
+                let value = self.pop(bus);
+                self.regs.write16(reg, value);
+            }
+
+            // 8-bit arithmetic.
+            I::Inc(target) => {
+                let value = self.read_operand(bus, target);
+                let (result, flags) = alu::inc(value, self.regs.f);
+                self.write_operand(bus, target, result);
+                self.regs.f = flags;
+            }
+            I::Dec(target) => {
+                let value = self.read_operand(bus, target);
+                let (result, flags) = alu::dec(value, self.regs.f);
+                self.write_operand(bus, target, result);
+                self.regs.f = flags;
+            }
+            I::Alu(op, src) => {
+                let value = self.read_operand(bus, src);
+                self.alu(op, value);
+            }
+            I::AluImm(op, n) => self.alu(op, n),
+            I::Daa => (self.regs.a, self.regs.f) = alu::daa(self.regs.a, self.regs.f),
+            I::Cpl => {
+                self.regs.a = !self.regs.a;
+                self.regs.f.insert(Flags::N | Flags::H);
+            }
+            I::Scf => {
+                self.regs.f.remove(Flags::N | Flags::H);
+                self.regs.f.insert(Flags::C);
+            }
+            I::Ccf => {
+                self.regs.f.remove(Flags::N | Flags::H);
+                self.regs.f.toggle(Flags::C);
+            }
+
+            // 16-bit arithmetic.
+            I::Inc16(reg) => {
+                self.increment(bus, self.regs.read16(reg));
+                self.regs.inc16(reg);
+            }
+            I::Dec16(reg) => {
+                self.increment(bus, self.regs.read16(reg));
+                self.regs.dec16(reg);
+            }
+            I::AddHl(reg) => self.add_hl(self.regs.read16(reg)),
+            I::AddSp(e) => {
+                self.regs.sp = self.add_sp(e);
+                self.idle();
+                self.idle();
+            }
+
+            // Accumulator rotates.
+            I::Rlca => {
+                let carry = self.regs.a >> 7;
+                self.regs.a = self.regs.a << 1 | carry;
+                self.regs.f = Self::rotate_a_flags(carry);
+            }
+            I::Rrca => {
+                let carry = self.regs.a & 1;
+                self.regs.a = self.regs.a >> 1 | carry << 7;
+                self.regs.f = Self::rotate_a_flags(carry);
+            }
+            I::Rla => {
+                let carry = self.regs.a >> 7;
+                self.regs.a = self.regs.a << 1 | u8::from(self.regs.f.contains(Flags::C));
+                self.regs.f = Self::rotate_a_flags(carry);
+            }
+            I::Rra => {
+                let carry = self.regs.a & 1;
+                self.regs.a = self.regs.a >> 1 | u8::from(self.regs.f.contains(Flags::C)) << 7;
+                self.regs.f = Self::rotate_a_flags(carry);
+            }
+
+            // Jumps and calls.
+            I::Jr(condition, e) => {
+                if self.check(condition) {
+                    self.regs.pc = self.regs.pc.wrapping_add_signed(i16::from(e));
+                    self.idle();
+                }
+            }
+            I::Jp(condition, nn) => {
+                if self.check(condition) {
+                    self.regs.pc = nn;
+                    self.idle();
+                }
+            }
+            I::JpHl => self.regs.pc = self.regs.hl(),
+            I::Call(condition, nn) => {
+                if self.check(condition) {
+                    self.push(bus, self.regs.pc);
+                    self.regs.pc = nn;
+                }
+            }
+            I::Ret(None) => self.ret(bus),
+            I::Ret(condition) => {
+                self.idle();
+                if self.check(condition) {
+                    self.ret(bus);
+                }
+            }
+            I::Reti => {
+                // Unlike `EI`, this enables interrupts without a delay.
+                self.ret(bus);
+                self.ime = true;
+            }
+            I::Rst(vector) => {
+                self.push(bus, self.regs.pc);
+                self.regs.pc = u16::from(vector);
+            }
+
+            // CB-prefixed bit operations.
+            I::Shift(op, target) => {
+                let value = self.read_operand(bus, target);
+                let (result, flags) = self.shift(op, value);
+                self.write_operand(bus, target, result);
+                self.regs.f = flags;
+            }
+            I::Bit(bit, target) => {
+                let value = self.read_operand(bus, target);
+                self.regs.f.set(Flags::Z, value & 1 << bit == 0);
+                self.regs.f.remove(Flags::N);
+                self.regs.f.insert(Flags::H);
+            }
+            I::Res(bit, target) => {
+                let value = self.read_operand(bus, target);
+                self.write_operand(bus, target, value & !(1 << bit));
+            }
+            I::Set(bit, target) => {
+                let value = self.read_operand(bus, target);
+                self.write_operand(bus, target, value | 1 << bit);
+            }
+
+            I::Illegal(_) => self.state = State::Locked,
+        }
+    }
+}
+
+/// Writes a one-line dump of the registers, flags, `IME` and whether the CPU
+/// is halted, such as
+/// `AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0100 F=Z-HC IME=0 HALT=0`.
+impl fmt::Display for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} F={} IME={} HALT={}",
+            self.regs,
+            self.regs.f,
+            u8::from(self.ime),
+            u8::from(self.is_halted()),
+        )
+    }
+}
+
+/// Return the interrupts that are both requested and enabled.
+///
+/// The CPU sees the interrupt lines directly rather than reading them over
+/// the bus, so this peeks.
+fn pending_interrupts<B: Bus>(bus: &B) -> u8 {
+    bus.peek(IE) & bus.peek(IF) & 0x1F
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::asm::assemble;
+    use super::*;
+    use crate::bus::FlatMemory;
+    use Instruction::{Alu, AluImm, Ld, LdImm, LoadAbsolute};
+    use Operand::{Hl, Reg};
+    use Reg8::{A, B};
+
+    /// Create a CPU and memory with `program` placed at `0x0100`.
+    fn setup(program: &[u8]) -> (Cpu, FlatMemory) {
+        (Cpu::new(), FlatMemory::with_program(0x0100, program))
+    }
+
+    /// Step the CPU until it halts, returning the total T-cycles taken.
+    fn run(cpu: &mut Cpu, memory: &mut FlatMemory) -> u32 {
+        let mut cycles = 0;
+        while !cpu.is_halted() {
+            cycles += u32::from(cpu.step(memory));
+        }
+        cycles
+    }
+
+    #[test]
+    fn loads_advance_pc() {
+        let (mut cpu, mut memory) = setup(&assemble(&[
+            LdImm(Reg(B), 0x42),
+            Ld(Hl, Reg(B)),
+            LoadAbsolute(0xC000),
+        ]));
+        cpu.regs.set_hl(0xC000);
+
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!(cpu.step(&mut memory), 16);
+
+        assert_eq!(cpu.regs.a, 0x42);
+        assert_eq!(cpu.regs.pc, 0x0106);
+        assert_eq!(cpu.mnemonic(), "LD");
+        assert_eq!(cpu.instruction().to_string(), "LD A, ($C000)");
+    }
+
+    #[test]
+    fn arithmetic_sets_flags() {
+        let (mut cpu, mut memory) = setup(&assemble(&[
+            LdImm(Reg(A), 0x0F),
+            AluImm(AluOp::Add, 0x01),
+            Alu(AluOp::Sub, Reg(A)),
+        ]));
+
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.a, 0x10);
+        assert_eq!(cpu.regs.f, Flags::H);
+
+        assert_eq!(cpu.step(&mut memory), 4);
+        assert_eq!(cpu.regs.a, 0x00);
+        assert_eq!(cpu.regs.f, Flags::Z | Flags::N);
+        assert_eq!(cpu.mnemonic(), "SUB");
+    }
+
+    #[test]
+    fn display_dumps_state() {
+        // EI ; HALT
+        let (mut cpu, mut memory) = setup(&[0xFB, 0x76]);
+        assert_eq!(
+            cpu.to_string(),
+            "AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0100 F=Z-HC IME=0 HALT=0"
+        );
+
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert_eq!(
+            cpu.to_string(),
+            "AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0102 F=Z-HC IME=1 HALT=1"
+        );
+    }
+
+    #[test]
+    fn halt_idles() {
+        let (mut cpu, mut memory) = setup(&[0x76]);
+
+        cpu.step(&mut memory);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.step(&mut memory), 4);
+        assert_eq!(cpu.regs.pc, 0x0101);
+    }
+
+    #[test]
+    fn halt_wakes_on_pending_interrupt() {
+        // HALT; INC A
+        let (mut cpu, mut memory) = setup(&[0x76, 0x3C]);
+        cpu.regs.sp = 0xD000;
+        cpu.ime = true;
+        memory.0[usize::from(IE)] = 0x04;
+
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert!(cpu.is_halted());
+
+        memory.request_interrupt(Interrupt::Timer);
+        assert_eq!(cpu.step(&mut memory), 20);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.regs.pc, 0x0050);
+        assert_eq!(memory.0[0xCFFE..0xD000], [0x01, 0x01]);
+    }
+
+    #[test]
+    fn halt_wakes_without_ime() {
+        // HALT; INC A
+        let (mut cpu, mut memory) = setup(&[0x76, 0x3C]);
+        memory.0[usize::from(IE)] = 0x04;
+
+        cpu.step(&mut memory);
+        assert!(cpu.is_halted());
+
+        memory.request_interrupt(Interrupt::Timer);
+        cpu.step(&mut memory);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.regs.a, 0x02);
+        assert_eq!(memory.0[usize::from(IF)], 0x04);
+    }
+
+    #[test]
+    fn interrupts_follow_priority() {
+        let (mut cpu, mut memory) = setup(&[]);
+        cpu.regs.sp = 0xD000;
+        cpu.ime = true;
+        memory.0[usize::from(IE)] = 0x1F;
+        memory.0[usize::from(IF)] = 0x1A;
+
+        assert_eq!(cpu.step(&mut memory), 20);
+        assert_eq!(cpu.regs.pc, 0x0048);
+        assert_eq!(memory.0[usize::from(IF)], 0x18);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn ei_is_delayed_by_one_instruction() {
+        // EI; INC A; INC A
+        let (mut cpu, mut memory) = setup(&[0xFB, 0x3C, 0x3C]);
+        cpu.regs.sp = 0xD000;
+        memory.0[usize::from(IE)] = 0x01;
+        memory.0[usize::from(IF)] = 0x01;
+
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.pc, 0x0102);
+
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.pc, 0x0040);
+        assert_eq!(cpu.regs.a, 0x02);
+    }
+
+    #[test]
+    fn ei_then_nop_services_after_nop() {
+        // EI; NOP; INC A
+        let (mut cpu, mut memory) = setup(&[0xFB, 0x00, 0x3C]);
+        cpu.regs.sp = 0xD000;
+        memory.0[usize::from(IE)] = 0x01;
+        memory.0[usize::from(IF)] = 0x01;
+
+        cpu.step(&mut memory);
+        assert!(!cpu.ime);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.pc, 0x0102);
+
+        assert_eq!(cpu.step(&mut memory), 20);
+        assert_eq!(cpu.regs.pc, 0x0040);
+        assert_eq!(memory.0[0xCFFE..0xD000], [0x02, 0x01]);
+    }
+
+    #[test]
+    fn di_cancels_pending_ei() {
+        // EI; DI; INC A
+        let (mut cpu, mut memory) = setup(&[0xFB, 0xF3, 0x3C]);
+        cpu.regs.sp = 0xD000;
+        memory.0[usize::from(IE)] = 0x01;
+        memory.0[usize::from(IF)] = 0x01;
+
+        for _ in 0..3 {
+            cpu.step(&mut memory);
+        }
+        assert!(!cpu.ime);
+        assert_eq!(cpu.regs.pc, 0x0103);
+        assert_eq!(cpu.regs.a, 0x02);
+        assert_eq!(memory.0[usize::from(IF)], 0x01);
+    }
+
+    #[test]
+    fn reti_enables_interrupts_at_once() {
+        // RETI, returning to INC A
+        let (mut cpu, mut memory) = setup(&[0xD9, 0x3C]);
+        cpu.regs.sp = 0xCFFE;
+        memory.0[0xCFFE..0xD000].copy_from_slice(&[0x01, 0x01]);
+        memory.0[usize::from(IE)] = 0x01;
+        memory.0[usize::from(IF)] = 0x01;
+
+        assert_eq!(cpu.step(&mut memory), 16);
+        assert!(cpu.ime);
+        assert_eq!(cpu.regs.pc, 0x0101);
+
+        // The interrupt is taken before the instruction returned to.
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.pc, 0x0040);
+        assert_eq!(cpu.regs.a, 0x01);
+    }
+
+    #[test]
+    fn ei_before_halt_services_without_halt_bug() {
+        // EI; HALT; INC A
+        let (mut cpu, mut memory) = setup(&[0xFB, 0x76, 0x3C]);
+        cpu.regs.sp = 0xD000;
+        memory.0[usize::from(IE)] = 0x01;
+        memory.0[usize::from(IF)] = 0x01;
+
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert!(cpu.is_halted());
+
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.pc, 0x0040);
+        assert_eq!(memory.0[0xCFFE..0xD000], [0x02, 0x01]);
+    }
+
+    #[test]
+    fn halt_bug_repeats_next_opcode() {
+        // HALT; INC A; HALT
+        let (mut cpu, mut memory) = setup(&[0x76, 0x3C, 0x76]);
+        memory.0[usize::from(IE)] = 0x01;
+        memory.0[usize::from(IF)] = 0x01;
+
+        cpu.step(&mut memory);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.regs.pc, 0x0101);
+
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.pc, 0x0101);
+
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.pc, 0x0102);
+        assert_eq!(cpu.regs.a, 0x03);
+    }
+
+    #[test]
+    fn stop_waits_for_joypad() {
+        // STOP; INC A
+        let (mut cpu, mut memory) = setup(&[0x10, 0x00, 0x3C]);
+        memory.0[usize::from(DIV)] = 0xAB;
+        memory.0[usize::from(KEY1)] = 0x01;
+
+        cpu.step(&mut memory);
+        assert!(cpu.is_stopped());
+        assert!(!cpu.is_double_speed());
+        assert_eq!(cpu.regs.pc, 0x0102);
+        assert_eq!(memory.0[usize::from(DIV)], 0x00);
+
+        cpu.step(&mut memory);
+        assert!(cpu.is_stopped());
+
+        memory.request_interrupt(Interrupt::Joypad);
+        cpu.step(&mut memory);
+        assert!(!cpu.is_stopped());
+        assert_eq!(cpu.regs.a, 0x02);
+    }
+
+    #[test]
+    fn stop_switches_cgb_speed() {
+        // STOP; STOP
+        let mut memory = FlatMemory::with_program(0x0100, &[0x10, 0x00, 0x10, 0x00]);
+        let mut cpu = Cpu::new_cgb();
+        memory.0[usize::from(KEY1)] = 0x01;
+
+        cpu.step(&mut memory);
+        assert!(!cpu.is_stopped());
+        assert!(cpu.is_double_speed());
+        assert_eq!(cpu.speed_multiplier(), 2);
+        assert_eq!(memory.0[usize::from(KEY1)], 0x00);
+
+        // Without arming KEY1 again, STOP really stops.
+        cpu.step(&mut memory);
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.speed_multiplier(), 2);
+    }
+
+    /// Return the fewest and most T-cycles `program` takes to run as a
+    /// single instruction, over flags that take and skip every branch.
+    fn cycle_range(program: &[u8]) -> (u8, u8) {
+        let cycles = [0x00, 0xF0].map(|flags| {
+            let (mut cpu, mut memory) = setup(program);
+            cpu.regs.f = Flags::from_bits(flags);
+            cpu.step(&mut memory)
+        });
+        (cycles[0].min(cycles[1]), cycles[0].max(cycles[1]))
+    }
+
+    #[test]
+    fn instruction_info_matches_executor() {
+        for opcode in (0..=0xFF).filter(|&opcode| opcode != 0xCB) {
+            let info = INSTRUCTION_INFO[usize::from(opcode)];
+            let expected = (info.cycles, info.cycles_taken.unwrap_or(info.cycles));
+            assert_eq!(cycle_range(&[opcode, 0, 0]), expected, "opcode {opcode:#04X}");
+            assert_eq!(info.bytes, length(opcode));
+        }
+
+        for opcode in 0..=0xFF {
+            let info = CB_INSTRUCTION_INFO[usize::from(opcode)];
+            let expected = (info.cycles, info.cycles);
+            assert_eq!(cycle_range(&[0xCB, opcode]), expected, "CB {opcode:#04X}");
+        }
+    }
+
+    #[test]
+    fn illegal_opcode_locks_up() {
+        // DB $DD ; INC A
+        let (mut cpu, mut memory) = setup(&[0xDD, 0x3C]);
+        cpu.ime = true;
+        memory.0[usize::from(IE)] = 0x1F;
+
+        cpu.step(&mut memory);
+        assert!(cpu.is_locked());
+        assert_eq!(cpu.regs.pc, 0x0101);
+
+        // Neither instructions nor interrupts run any more.
+        memory.request_interrupt(Interrupt::VBlank);
+        for _ in 0..4 {
+            assert_eq!(cpu.step(&mut memory), 4);
+        }
+        assert!(cpu.is_locked());
+        assert_eq!(cpu.regs.pc, 0x0101);
+        assert_eq!(cpu.regs.a, 0x01);
+    }
+
+    #[test]
+    fn countdown_loop() {
+        // LD A, $00; LD B, $0A
+        // loop: ADD A, B; DEC B; JR NZ, loop
+        // HALT
+        let (mut cpu, mut memory) = setup(&[0x3E, 0x00, 0x06, 0x0A, 0x80, 0x05, 0x20, 0xFC, 0x76]);
+
+        let cycles = run(&mut cpu, &mut memory);
+
+        assert_eq!(cpu.regs.a, 55);
+        assert_eq!(cpu.regs.b, 0);
+        assert_eq!(cpu.regs.f, Flags::Z | Flags::N);
+        assert_eq!(cpu.regs.pc, 0x0109);
+        // Two loads, ten iterations with nine taken branches, and the halt.
+        assert_eq!(cycles, 8 + 8 + 10 * (4 + 4) + 9 * 12 + 8 + 4);
+    }
+
+    #[test]
+    fn call_and_return() {
+        // LD SP, $D000; CALL $0110; HALT
+        // $0110: PUSH BC; POP DE; INC DE; RET
+        let (mut cpu, mut memory) = setup(&[0x31, 0x00, 0xD0, 0xCD, 0x10, 0x01, 0x76]);
+        memory.0[0x0110..0x0114].copy_from_slice(&[0xC5, 0xD1, 0x13, 0xC9]);
+        cpu.regs.set_bc(0x1234);
+
+        assert_eq!(cpu.step(&mut memory), 12);
+        assert_eq!(cpu.step(&mut memory), 24);
+        assert_eq!(cpu.regs.sp, 0xCFFE);
+        assert_eq!(memory.0[0xCFFE..0xD000], [0x06, 0x01]);
+
+        assert_eq!(cpu.step(&mut memory), 16);
+        assert_eq!(cpu.step(&mut memory), 12);
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!(cpu.step(&mut memory), 16);
+
+        assert_eq!(cpu.regs.de(), 0x1235);
+        assert_eq!(cpu.regs.sp, 0xD000);
+        assert_eq!(cpu.regs.pc, 0x0106);
+    }
+
+    #[test]
+    fn conditional_branch_timing() {
+        // XOR A; JR NZ, +0; JR Z, +0; RET NZ; RET Z
+        let (mut cpu, mut memory) = setup(&[0xAF, 0x20, 0x00, 0x28, 0x00, 0xC0, 0xC8]);
+        cpu.regs.sp = 0xD000;
+        memory.0[0xD000..0xD002].copy_from_slice(&[0x00, 0x02]);
+
+        cpu.step(&mut memory);
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!(cpu.step(&mut memory), 12);
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!(cpu.step(&mut memory), 20);
+        assert_eq!(cpu.regs.pc, 0x0200);
+    }
+
+    #[test]
+    fn accumulator_rotates_clear_zero() {
+        // SCF; RLA; RRCA
+        let (mut cpu, mut memory) = setup(&[0x37, 0x17, 0x0F]);
+        cpu.regs.a = 0x80;
+
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.a, 0x01);
+        assert_eq!(cpu.regs.f, Flags::C);
+
+        cpu.regs.a = 0x00;
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.a, 0x00);
+        assert_eq!(cpu.regs.f, Flags::EMPTY);
+    }
+
+    #[test]
+    fn add_hl_half_carry_from_bit_11() {
+        // ADD HL, BC
+        let (mut cpu, mut memory) = setup(&[0x09]);
+        cpu.regs.f = Flags::Z;
+        cpu.regs.set_hl(0x0FFF);
+        cpu.regs.set_bc(0x0001);
+
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!(cpu.regs.hl(), 0x1000);
+        assert_eq!(cpu.regs.f, Flags::Z | Flags::H);
+    }
+
+    #[test]
+    fn sp_offset_flags_and_timing() {
+        for (offset, result) in [(0x08, 0x0000), (0xFF, 0xFFF7)] {
+            // ADD SP, e; LD HL, SP+e
+            let (mut cpu, mut memory) = setup(&[0xE8, offset, 0xF8, offset]);
+            cpu.regs.f = Flags::Z | Flags::N;
+            cpu.regs.sp = 0xFFF8;
+
+            assert_eq!(cpu.step(&mut memory), 16);
+            assert_eq!(cpu.regs.sp, result);
+            assert_eq!(cpu.regs.f, Flags::H | Flags::C);
+
+            cpu.regs.sp = 0xFFF8;
+            cpu.regs.f = Flags::Z | Flags::N;
+            assert_eq!(cpu.step(&mut memory), 12);
+            assert_eq!(cpu.regs.hl(), result);
+            assert_eq!(cpu.regs.sp, 0xFFF8);
+            assert_eq!(cpu.regs.f, Flags::H | Flags::C);
+        }
+    }
+
+    #[test]
+    fn cb_shift_carry_out() {
+        // RLC B; RRC B; SLA B; SRA B; SRL B
+        let (mut cpu, mut memory) =
+            setup(&[0xCB, 0x00, 0xCB, 0x08, 0xCB, 0x20, 0xCB, 0x28, 0xCB, 0x38]);
+
+        cpu.regs.b = 0x81;
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!((cpu.regs.b, cpu.regs.f), (0x03, Flags::C));
+
+        cpu.step(&mut memory);
+        assert_eq!((cpu.regs.b, cpu.regs.f), (0x81, Flags::C));
+
+        cpu.step(&mut memory);
+        assert_eq!((cpu.regs.b, cpu.regs.f), (0x02, Flags::C));
+
+        cpu.regs.b = 0x81;
+        cpu.step(&mut memory);
+        assert_eq!((cpu.regs.b, cpu.regs.f), (0xC0, Flags::C));
+
+        cpu.regs.b = 0x01;
+        cpu.step(&mut memory);
+        assert_eq!((cpu.regs.b, cpu.regs.f), (0x00, Flags::Z | Flags::C));
+    }
+
+    #[test]
+    fn cb_rotate_through_carry() {
+        // RL C; RR C
+        let (mut cpu, mut memory) = setup(&[0xCB, 0x11, 0xCB, 0x19]);
+        cpu.regs.f = Flags::C;
+        cpu.regs.c = 0x80;
+
+        cpu.step(&mut memory);
+        assert_eq!((cpu.regs.c, cpu.regs.f), (0x01, Flags::C));
+
+        cpu.step(&mut memory);
+        assert_eq!((cpu.regs.c, cpu.regs.f), (0x80, Flags::C));
+    }
+
+    #[test]
+    fn cb_swap_round_trips() {
+        // SWAP A; SWAP A; SWAP (HL)
+        let (mut cpu, mut memory) = setup(&[0xCB, 0x37, 0xCB, 0x37, 0xCB, 0x36]);
+        cpu.regs.f = Flags::ALL;
+        cpu.regs.a = 0x12;
+        cpu.regs.set_hl(0xC000);
+
+        cpu.step(&mut memory);
+        assert_eq!((cpu.regs.a, cpu.regs.f), (0x21, Flags::EMPTY));
+
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.a, 0x12);
+
+        assert_eq!(cpu.step(&mut memory), 16);
+        assert_eq!(memory.0[0xC000], 0x00);
+        assert_eq!(cpu.regs.f, Flags::Z);
+    }
+
+    #[test]
+    fn cb_bit_res_set() {
+        // BIT 7, H; BIT 0, (HL); RES 7, (HL); SET 0, L
+        let (mut cpu, mut memory) = setup(&[0xCB, 0x7C, 0xCB, 0x46, 0xCB, 0xBE, 0xCB, 0xC5]);
+        cpu.regs.f = Flags::C;
+        cpu.regs.set_hl(0xC000);
+        memory.0[0xC000] = 0xFE;
+
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!(cpu.regs.f, Flags::H | Flags::C);
+
+        assert_eq!(cpu.step(&mut memory), 12);
+        assert_eq!(cpu.regs.f, Flags::Z | Flags::H | Flags::C);
+
+        assert_eq!(cpu.step(&mut memory), 16);
+        assert_eq!(memory.0[0xC000], 0x7E);
+
+        assert_eq!(cpu.step(&mut memory), 8);
+        assert_eq!(cpu.regs.l, 0x01);
+        assert_eq!(cpu.mnemonic(), "SET");
+    }
+
+    #[test]
+    fn adc_and_sbc_use_carry() {
+        // SCF; ADC A, $0F; SCF; SBC A, $00
+        let (mut cpu, mut memory) = setup(&[0x37, 0xCE, 0x0F, 0x37, 0xDE, 0x00]);
+        cpu.regs.a = 0x00;
+
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.a, 0x10);
+        assert_eq!(cpu.regs.f, Flags::H);
+
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.regs.a, 0x0F);
+        assert_eq!(cpu.regs.f, Flags::N | Flags::H);
+    }
+}
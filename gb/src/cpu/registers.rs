@@ -0,0 +1,497 @@
+//! The SM83 register file.
+
+use core::fmt;
+
+use super::Flags;
+
+/// An 8-bit register selector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg8 {
+    /// The accumulator.
+    A,
+    /// The `B` register.
+    B,
+    /// The `C` register.
+    C,
+    /// The `D` register.
+    D,
+    /// The `E` register.
+    E,
+    /// The `H` register.
+    H,
+    /// The `L` register.
+    L,
+}
+
+/// A 16-bit register selector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg16 {
+    /// The `BC` register pair.
+    BC,
+    /// The `DE` register pair.
+    DE,
+    /// The `HL` register pair.
+    HL,
+    /// The stack pointer.
+    SP,
+    /// The `AF` register pair.
+    AF,
+}
+
+impl fmt::Display for Reg8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::A => "A",
+            Self::B => "B",
+            Self::C => "C",
+            Self::D => "D",
+            Self::E => "E",
+            Self::H => "H",
+            Self::L => "L",
+        })
+    }
+}
+
+impl fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::BC => "BC",
+            Self::DE => "DE",
+            Self::HL => "HL",
+            Self::SP => "SP",
+            Self::AF => "AF",
+        })
+    }
+}
+
+/// The register file of the SM83.
+///
+/// The 8-bit registers are stored individually, the 16-bit register pairs are
+/// composed on demand through the accessor methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    /// The accumulator.
+    pub a: u8,
+    /// The flag register.
+    pub f: Flags,
+    /// The `B` register.
+    pub b: u8,
+    /// The `C` register.
+    pub c: u8,
+    /// The `D` register.
+    pub d: u8,
+    /// The `E` register.
+    pub e: u8,
+    /// The `H` register.
+    pub h: u8,
+    /// The `L` register.
+    pub l: u8,
+    /// The stack pointer.
+    pub sp: u16,
+    /// The program counter.
+    pub pc: u16,
+}
+
+impl Registers {
+    /// Create a register file cleared as at power-on, before a boot ROM runs.
+    #[must_use]
+    pub const fn new_power_on() -> Self {
+        Self {
+            a: 0,
+            f: Flags::from_bits(0),
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            sp: 0,
+            pc: 0,
+        }
+    }
+
+    /// Create a register file in the state the DMG boot ROM leaves it in.
+    ///
+    /// The values are taken from the "Power Up Sequence" section of Pan Docs.
+    #[must_use]
+    pub const fn new_dmg() -> Self {
+        Self {
+            a: 0x01,
+            f: Flags::from_bits(0xB0),
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xD8,
+            h: 0x01,
+            l: 0x4D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        }
+    }
+
+    /// Create a register file in the state the CGB boot ROM leaves it in.
+    ///
+    /// The values are taken from the "Power Up Sequence" section of Pan Docs
+    /// and assume a CGB-aware cartridge, for which the boot ROM leaves `B`
+    /// zeroed instead of holding the title checksum.
+    #[must_use]
+    pub const fn new_cgb() -> Self {
+        Self {
+            a: 0x11,
+            f: Flags::from_bits(0x80),
+            b: 0x00,
+            c: 0x00,
+            d: 0xFF,
+            e: 0x56,
+            h: 0x00,
+            l: 0x0D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        }
+    }
+
+    /// Return the `AF` register pair.
+    #[inline]
+    #[must_use]
+    pub const fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f.into_bits()])
+    }
+
+    /// Set the `AF` register pair.
+    ///
+    /// The lower nibble of `F` is not backed by hardware and is discarded.
+    #[inline]
+    pub const fn set_af(&mut self, value: u16) {
+        let [a, f] = value.to_be_bytes();
+        self.a = a;
+        self.f = Flags::from_bits(f);
+    }
+
+    /// Pack the register file into 12 bytes: `AF`, `BC`, `DE`, `HL`, `PC`
+    /// and `SP` in that order, each little-endian.
+    ///
+    /// Unlike the serde format, the layout is fixed, for compact and stable
+    /// save formats.
+    #[must_use]
+    pub const fn to_bytes(&self) -> [u8; 12] {
+        let [af_lo, af_hi] = self.af().to_le_bytes();
+        let [bc_lo, bc_hi] = self.bc().to_le_bytes();
+        let [de_lo, de_hi] = self.de().to_le_bytes();
+        let [hl_lo, hl_hi] = self.hl().to_le_bytes();
+        let [pc_lo, pc_hi] = self.pc.to_le_bytes();
+        let [sp_lo, sp_hi] = self.sp.to_le_bytes();
+        [af_lo, af_hi, bc_lo, bc_hi, de_lo, de_hi, hl_lo, hl_hi, pc_lo, pc_hi, sp_lo, sp_hi]
+    }
+
+    /// Unpack a register file packed by [`Registers::to_bytes`].
+    ///
+    /// The lower nibble of `F` is discarded, as by [`Registers::set_af`].
+    #[must_use]
+    pub const fn from_bytes(bytes: [u8; 12]) -> Self {
+        let [af_lo, af_hi, bc_lo, bc_hi, de_lo, de_hi, hl_lo, hl_hi, pc_lo, pc_hi, sp_lo, sp_hi] =
+            bytes;
+        let mut regs = Self::new_power_on();
+        regs.set_af(u16::from_le_bytes([af_lo, af_hi]));
+        regs.set_bc(u16::from_le_bytes([bc_lo, bc_hi]));
+        regs.set_de(u16::from_le_bytes([de_lo, de_hi]));
+        regs.set_hl(u16::from_le_bytes([hl_lo, hl_hi]));
+        regs.pc = u16::from_le_bytes([pc_lo, pc_hi]);
+        regs.sp = u16::from_le_bytes([sp_lo, sp_hi]);
+        regs
+    }
+
+    /// Return the `BC` register pair.
+    #[inline]
+    #[must_use]
+    pub const fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    /// Set the `BC` register pair.
+    #[inline]
+    pub const fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    /// Return the `DE` register pair.
+    #[inline]
+    #[must_use]
+    pub const fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    /// Set the `DE` register pair.
+    #[inline]
+    pub const fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    /// Return the `HL` register pair.
+    #[inline]
+    #[must_use]
+    pub const fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    /// Set the `HL` register pair.
+    #[inline]
+    pub const fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    /// Read the selected 8-bit register.
+    #[inline]
+    #[must_use]
+    pub const fn read8(&self, reg: Reg8) -> u8 {
+        match reg {
+            Reg8::A => self.a,
+            Reg8::B => self.b,
+            Reg8::C => self.c,
+            Reg8::D => self.d,
+            Reg8::E => self.e,
+            Reg8::H => self.h,
+            Reg8::L => self.l,
+        }
+    }
+
+    /// Write the selected 8-bit register.
+    #[inline]
+    pub const fn write8(&mut self, reg: Reg8, value: u8) {
+        match reg {
+            Reg8::A => self.a = value,
+            Reg8::B => self.b = value,
+            Reg8::C => self.c = value,
+            Reg8::D => self.d = value,
+            Reg8::E => self.e = value,
+            Reg8::H => self.h = value,
+            Reg8::L => self.l = value,
+        }
+    }
+
+    /// Read the selected 16-bit register.
+    #[inline]
+    #[must_use]
+    pub const fn read16(&self, reg: Reg16) -> u16 {
+        match reg {
+            Reg16::BC => self.bc(),
+            Reg16::DE => self.de(),
+            Reg16::HL => self.hl(),
+            Reg16::SP => self.sp,
+            Reg16::AF => self.af(),
+        }
+    }
+
+    /// Write the selected 16-bit register.
+    ///
+    /// Writes to `AF` go through [`Registers::set_af`] and lose the lower
+    /// nibble of `F`.
+    #[inline]
+    pub const fn write16(&mut self, reg: Reg16, value: u16) {
+        match reg {
+            Reg16::BC => self.set_bc(value),
+            Reg16::DE => self.set_de(value),
+            Reg16::HL => self.set_hl(value),
+            Reg16::SP => self.sp = value,
+            Reg16::AF => self.set_af(value),
+        }
+    }
+
+    /// Increment the selected 16-bit register, wrapping on overflow.
+    ///
+    /// This mirrors `INC rr` and does not affect the flags.
+    #[inline]
+    pub const fn inc16(&mut self, reg: Reg16) {
+        self.write16(reg, self.read16(reg).wrapping_add(1));
+    }
+
+    /// Decrement the selected 16-bit register, wrapping on underflow.
+    ///
+    /// This mirrors `DEC rr` and does not affect the flags.
+    #[inline]
+    pub const fn dec16(&mut self, reg: Reg16) {
+        self.write16(reg, self.read16(reg).wrapping_sub(1));
+    }
+}
+
+/// Writes the register pairs in the layout of most debuggers, such as
+/// `AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0100`.
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X}",
+            self.af(),
+            self.bc(),
+            self.de(),
+            self.hl(),
+            self.sp,
+            self.pc,
+        )
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new_dmg()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn display_shows_pairs() {
+        let mut regs = Registers::default();
+        regs.set_hl(0xC0DE);
+        assert_eq!(
+            regs.to_string(),
+            "AF=01B0 BC=0013 DE=00D8 HL=C0DE SP=FFFE PC=0100"
+        );
+    }
+
+    #[test]
+    fn default_is_dmg_post_boot() {
+        let regs = Registers::default();
+
+        assert_eq!(regs.af(), 0x01B0);
+        assert_eq!(regs.bc(), 0x0013);
+        assert_eq!(regs.de(), 0x00D8);
+        assert_eq!(regs.hl(), 0x014D);
+        assert_eq!(regs.sp, 0xFFFE);
+        assert_eq!(regs.pc, 0x0100);
+        assert!(regs.f.contains(Flags::Z | Flags::H | Flags::C));
+    }
+
+    #[test]
+    fn dmg_post_boot() {
+        let regs = Registers::new_dmg();
+
+        assert_eq!(regs, Registers::default());
+        assert_eq!(regs.af(), 0x01B0);
+        assert_eq!(regs.bc(), 0x0013);
+        assert_eq!(regs.de(), 0x00D8);
+        assert_eq!(regs.hl(), 0x014D);
+    }
+
+    #[test]
+    fn cgb_post_boot() {
+        let regs = Registers::new_cgb();
+
+        assert_eq!(regs.af(), 0x1180);
+        assert_eq!(regs.bc(), 0x0000);
+        assert_eq!(regs.de(), 0xFF56);
+        assert_eq!(regs.hl(), 0x000D);
+        assert_eq!(regs.sp, 0xFFFE);
+        assert_eq!(regs.pc, 0x0100);
+    }
+
+    #[test]
+    fn read_write8() {
+        let mut regs = Registers::new_dmg();
+
+        regs.write8(Reg8::H, 0xAB);
+        regs.write8(Reg8::L, 0xCD);
+
+        assert_eq!(regs.read8(Reg8::H), 0xAB);
+        assert_eq!(regs.read16(Reg16::HL), 0xABCD);
+    }
+
+    #[test]
+    fn write16_af_masks_flags() {
+        let mut regs = Registers::new_dmg();
+
+        regs.write16(Reg16::AF, 0x42FF);
+        regs.write16(Reg16::SP, 0xC000);
+
+        assert_eq!(regs.read16(Reg16::AF), 0x42F0);
+        assert_eq!(regs.read8(Reg8::A), 0x42);
+        assert_eq!(regs.sp, 0xC000);
+    }
+
+    #[test]
+    fn inc16_wraps() {
+        let mut regs = Registers::new_dmg();
+        regs.set_bc(0xFFFF);
+        regs.sp = 0xFFFF;
+
+        regs.inc16(Reg16::BC);
+        regs.inc16(Reg16::SP);
+
+        assert_eq!(regs.bc(), 0x0000);
+        assert_eq!(regs.sp, 0x0000);
+    }
+
+    #[test]
+    fn dec16_wraps() {
+        let mut regs = Registers::new_dmg();
+        regs.set_de(0x0000);
+        regs.sp = 0x0000;
+
+        regs.dec16(Reg16::DE);
+        regs.dec16(Reg16::SP);
+
+        assert_eq!(regs.de(), 0xFFFF);
+        assert_eq!(regs.sp, 0xFFFF);
+    }
+
+    #[test]
+    fn inc16_af_masks_flags() {
+        let mut regs = Registers::new_dmg();
+        regs.set_af(0x12F0);
+
+        // The carry into the lower nibble of F is discarded entirely.
+        regs.inc16(Reg16::AF);
+        assert_eq!(regs.af(), 0x12F0);
+
+        regs.set_af(0x12FF);
+        regs.dec16(Reg16::AF);
+        assert_eq!(regs.af(), 0x12E0);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let regs = Registers::new_cgb();
+        let bytes = regs.to_bytes();
+
+        assert_eq!(bytes, [0x80, 0x11, 0x00, 0x00, 0x56, 0xFF, 0x0D, 0x00, 0x00, 0x01, 0xFE, 0xFF]);
+        assert_eq!(Registers::from_bytes(bytes), regs);
+    }
+
+    #[test]
+    fn bytes_mask_flag_nibble() {
+        let mut bytes = Registers::new_dmg().to_bytes();
+        bytes[0] = 0xBF;
+        let regs = Registers::from_bytes(bytes);
+
+        assert_eq!(regs.f.into_bits(), 0xB0);
+        assert_eq!(regs.to_bytes()[0] & 0x0F, 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut regs = Registers::new_cgb();
+        regs.set_af(0x12FF);
+
+        let json = serde_json::to_string(&regs).unwrap();
+        let back: Registers = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(back, regs);
+        assert_eq!(back.af(), 0x12F0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_masks_flag_nibble() {
+        let json = serde_json::to_string(&Registers::new_dmg())
+            .unwrap()
+            .replace("\"f\":176", "\"f\":191");
+        let regs: Registers = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(regs.f.into_bits(), 0xB0);
+    }
+}
@@ -0,0 +1,158 @@
+//! A runner for the SM83 single-step tests, in the JSON format of the
+//! `SingleStepTests/sm83` suite.
+//!
+//! Each file holds the cases for one opcode. A case gives the registers and
+//! the memory before and after running one instruction from `pc`, and the
+//! machine cycles it runs: `[addr, value, "r-m"]` for a read, `[addr, value,
+//! "-wm"]` for a write, and either `null` or a `"---"` access for a cycle
+//! without one. The address of such an internal cycle isn't modelled, so only
+//! its place in the sequence is checked.
+//!
+//! The cases in `tests/sm83` cover a handful of opcodes with a few cases
+//! each. Files from the suite can be dropped in beside them and added to
+//! [`CASES`].
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde_json::Value;
+
+use super::*;
+use crate::bus::FlatMemory;
+
+/// The test files, by name.
+const CASES: &[(&str, &str)] = &[
+    ("00", include_str!("../../tests/sm83/00.json")),
+    ("20", include_str!("../../tests/sm83/20.json")),
+    ("34", include_str!("../../tests/sm83/34.json")),
+    ("3c", include_str!("../../tests/sm83/3c.json")),
+    ("c5", include_str!("../../tests/sm83/c5.json")),
+    ("cb 37", include_str!("../../tests/sm83/cb 37.json")),
+    ("cd", include_str!("../../tests/sm83/cd.json")),
+    ("e0", include_str!("../../tests/sm83/e0.json")),
+];
+
+/// Return the field `key` of `state` as a number.
+fn field(state: &Value, key: &str) -> u64 {
+    state[key]
+        .as_u64()
+        .unwrap_or_else(|| panic!("missing field {key:?}"))
+}
+
+/// Return the field `key` of `state` as a byte.
+#[allow(clippy::cast_possible_truncation)]
+fn byte(state: &Value, key: &str) -> u8 {
+    field(state, key) as u8
+}
+
+/// Return the `[addr, value]` pairs of the `ram` field of `state`.
+#[allow(clippy::cast_possible_truncation)]
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"]
+        .as_array()
+        .expect("missing field \"ram\"")
+        .iter()
+        .map(|entry| {
+            let addr = entry[0].as_u64().expect("bad RAM address") as u16;
+            let value = entry[1].as_u64().expect("bad RAM value") as u8;
+            (addr, value)
+        })
+        .collect()
+}
+
+/// Return the registers of `state`.
+#[allow(clippy::cast_possible_truncation)]
+fn registers(state: &Value) -> Registers {
+    Registers {
+        a: byte(state, "a"),
+        f: Flags::from_bits(byte(state, "f")),
+        b: byte(state, "b"),
+        c: byte(state, "c"),
+        d: byte(state, "d"),
+        e: byte(state, "e"),
+        h: byte(state, "h"),
+        l: byte(state, "l"),
+        sp: field(state, "sp") as u16,
+        pc: field(state, "pc") as u16,
+    }
+}
+
+/// Return the machine cycles of a case.
+#[allow(clippy::cast_possible_truncation)]
+fn cycles(case: &Value) -> Vec<BusCycle> {
+    case["cycles"]
+        .as_array()
+        .expect("missing field \"cycles\"")
+        .iter()
+        .map(|cycle| {
+            let kind = cycle[2].as_str().unwrap_or("---");
+            let addr = cycle[0].as_u64().unwrap_or_default() as u16;
+            let value = cycle[1].as_u64().unwrap_or_default() as u8;
+            match kind.as_bytes() {
+                [b'r', ..] => BusCycle::Read { addr, value },
+                [_, b'w', ..] => BusCycle::Write { addr, value },
+                _ => BusCycle::Idle,
+            }
+        })
+        .collect()
+}
+
+/// Run `case`, returning a description of each way it went wrong.
+fn run(case: &Value) -> Vec<String> {
+    let initial = &case["initial"];
+    let expected = &case["final"];
+
+    let mut memory = FlatMemory::with_program(0, &[]);
+    for (addr, value) in ram(initial) {
+        memory.0[usize::from(addr)] = value;
+    }
+    if initial.get("ie").is_some() {
+        memory.0[usize::from(IE)] = byte(initial, "ie");
+    }
+
+    let mut cpu = Cpu::new();
+    cpu.regs = registers(initial);
+    cpu.ime = field(initial, "ime") != 0;
+    cpu.log = Some(Vec::new());
+    let taken = cpu.step(&mut memory);
+
+    let mut errors = Vec::new();
+    let regs = registers(expected);
+    if cpu.regs != regs {
+        errors.push(format!("registers {:?}, expected {regs:?}", cpu.regs));
+    }
+    if cpu.ime != (field(expected, "ime") != 0) {
+        errors.push(format!("IME {}, expected {}", cpu.ime, !cpu.ime));
+    }
+    for (addr, value) in ram(expected) {
+        let actual = memory.0[usize::from(addr)];
+        if actual != value {
+            errors.push(format!("{addr:#06X} is {actual:#04X}, expected {value:#04X}"));
+        }
+    }
+
+    let log = cpu.log.take().unwrap_or_default();
+    let expected = cycles(case);
+    if log != expected {
+        errors.push(format!("cycles {log:?}, expected {expected:?}"));
+    }
+    if usize::from(taken) != expected.len() * 4 {
+        errors.push(format!("took {taken} T-cycles, expected {}", expected.len() * 4));
+    }
+    errors
+}
+
+#[test]
+fn single_step_cases_pass() {
+    let mut failures = Vec::new();
+    for (file, json) in CASES {
+        let cases: Value = serde_json::from_str(json).unwrap();
+        for case in cases.as_array().unwrap() {
+            let name = case["name"].as_str().unwrap_or(file);
+            failures.extend(run(case).into_iter().map(|err| format!("{name}: {err}")));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,342 @@
+//! Breakpoints, watchpoints and stepping over a running system.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+use crate::cpu::{Instruction, disassemble};
+use crate::gameboy::{FRAME_CYCLES, GameBoy};
+
+/// A CPU access to memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// A read, returning `value`.
+    Read {
+        /// The value read.
+        value: u8,
+    },
+    /// A write, changing the memory from `old` to `new`.
+    ///
+    /// Both are peeked, so `new` is what the address reads back as, which
+    /// for some I/O registers differs from the value written.
+    Write {
+        /// The value before the write.
+        old: u8,
+        /// The value after the write.
+        new: u8,
+    },
+}
+
+/// The accesses a watchpoint fires on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchKind {
+    /// Reads only.
+    Read,
+    /// Writes only.
+    Write,
+    /// Both reads and writes.
+    Access,
+}
+
+impl WatchKind {
+    /// Check if this kind fires on `access`.
+    const fn matches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (Self::Access, _)
+                | (Self::Read, Access::Read { .. })
+                | (Self::Write, Access::Write { .. })
+        )
+    }
+}
+
+/// A watchpoint over a range of addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// The addresses watched.
+    pub range: RangeInclusive<u16>,
+    /// The accesses watched.
+    pub kind: WatchKind,
+}
+
+/// The reason execution stopped early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// `PC` reached a breakpoint, before the instruction there ran.
+    Breakpoint(u16),
+    /// An instruction accessed a watched address. It has completed, as
+    /// instructions run as a whole.
+    Watchpoint {
+        /// The address accessed.
+        addr: u16,
+        /// The access made.
+        access: Access,
+    },
+}
+
+/// A system under a debugger.
+///
+/// Watchpoints see every access the CPU makes, including the reads of `IF`
+/// and `IE` it makes to check for interrupts on every step.
+#[derive(Debug)]
+pub struct Debugger {
+    gb: GameBoy,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    /// The breakpoint execution last stopped at, which doesn't fire again
+    /// when resuming from it.
+    paused_at: Option<u16>,
+}
+
+impl Debugger {
+    /// Attach a debugger to `gb`, with no breakpoints or watchpoints.
+    #[must_use]
+    pub const fn new(gb: GameBoy) -> Self {
+        Self {
+            gb,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            paused_at: None,
+        }
+    }
+
+    /// Return the system being debugged.
+    #[must_use]
+    pub const fn gameboy(&self) -> &GameBoy {
+        &self.gb
+    }
+
+    /// Return the system being debugged mutably.
+    pub const fn gameboy_mut(&mut self) -> &mut GameBoy {
+        &mut self.gb
+    }
+
+    /// Detach the debugger, returning the system.
+    #[must_use]
+    pub fn into_inner(self) -> GameBoy {
+        self.gb
+    }
+
+    /// Stop before executing the instruction at `pc`.
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    /// Remove the breakpoint at `pc`, returning whether there was one.
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    /// Return the breakpoints in ascending order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stop after any instruction making a `kind` access within `range`.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { range, kind });
+    }
+
+    /// Return the watchpoints in the order they were added.
+    #[must_use]
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Remove every watchpoint.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Run a single instruction, reporting the first watchpoint it fired.
+    pub fn step_into(&mut self) -> Option<Stop> {
+        self.step().1
+    }
+
+    /// Run a single instruction, running a `CALL` or `RST` through to its
+    /// return.
+    ///
+    /// Breakpoints and watchpoints within the call stop it early. A call that
+    /// never returns runs until one does.
+    pub fn step_over(&mut self) -> Option<Stop> {
+        let cpu = self.gb.cpu();
+        let (pc, sp) = (cpu.regs.pc, cpu.regs.sp);
+
+        let lines = disassemble(self.gb.mmu(), pc, 2);
+        let call = matches!(lines[0].1, Instruction::Call(..) | Instruction::Rst(_));
+        let next = lines[1].0;
+
+        let stop = self.step().1;
+        if !call || stop.is_some() {
+            return stop;
+        }
+
+        // A recursive call can pass through the return address with a deeper
+        // stack, so wait for the stack to unwind as well.
+        while !(self.gb.cpu().regs.pc == next && self.gb.cpu().regs.sp >= sp) {
+            if let Some(stop) = self.breakpoint() {
+                return Some(stop);
+            }
+            if let (_, Some(stop)) = self.step() {
+                return Some(stop);
+            }
+        }
+        None
+    }
+
+    /// Run until the PPU enters vertical blanking, like
+    /// [`GameBoy::run_frame`], or until a breakpoint or watchpoint fires.
+    ///
+    /// Returns `None` once the frame completes, which can be read from
+    /// [`GameBoy::framebuffer`].
+    pub fn run_frame(&mut self) -> Option<Stop> {
+        self.gb.take_frame_completed();
+        let mut cycles = 0;
+        while cycles < FRAME_CYCLES {
+            if let Some(stop) = self.breakpoint() {
+                return Some(stop);
+            }
+
+            let (taken, stop) = self.step();
+            cycles += u32::from(taken) / u32::from(self.gb.cpu().speed_multiplier());
+            if stop.is_some() {
+                return stop;
+            }
+
+            if self.gb.take_frame_completed() {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Check for a breakpoint at `PC`, unless execution is resuming from it.
+    fn breakpoint(&mut self) -> Option<Stop> {
+        let pc = self.gb.cpu().regs.pc;
+        if self.paused_at == Some(pc) || !self.breakpoints.contains(&pc) {
+            return None;
+        }
+
+        self.paused_at = Some(pc);
+        Some(Stop::Breakpoint(pc))
+    }
+
+    /// Run a single step, returning the T-cycles taken and the first
+    /// watchpoint fired.
+    fn step(&mut self) -> (u8, Option<Stop>) {
+        self.paused_at = None;
+
+        let mut stop = None;
+        let watchpoints = &self.watchpoints;
+        let cycles = self.gb.step_inspected(&mut |addr, access| {
+            let fired = watchpoints
+                .iter()
+                .any(|watch| watch.range.contains(&addr) && watch.kind.matches(access));
+            if fired && stop.is_none() {
+                stop = Some(Stop::Watchpoint { addr, access });
+            }
+        });
+
+        (cycles, stop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::bus::Bus;
+    use crate::ppu::LCDC;
+
+    /// Return a debugger over a system running `program` from `0x0100`, with
+    /// `routine` at `0x0200`.
+    fn debugger(program: &[u8], routine: &[u8]) -> Debugger {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        rom[0x0200..0x0200 + routine.len()].copy_from_slice(routine);
+        Debugger::new(GameBoy::from_rom(rom).unwrap())
+    }
+
+    /// Return the program counter.
+    fn pc(debugger: &Debugger) -> u16 {
+        debugger.gameboy().cpu().regs.pc
+    }
+
+    #[test]
+    fn lcdc_write_watchpoint() {
+        // NOP ; LD A,$11 ; LDH ($40),A ; NOP
+        let mut debugger = debugger(&[0x00, 0x3E, 0x11, 0xE0, 0x40, 0x00], &[]);
+        debugger.add_watchpoint(LCDC..=LCDC, WatchKind::Write);
+
+        assert_eq!(
+            debugger.run_frame(),
+            Some(Stop::Watchpoint {
+                addr: LCDC,
+                access: Access::Write { old: 0x91, new: 0x11 },
+            })
+        );
+        assert_eq!(pc(&debugger), 0x0105);
+        assert_eq!(debugger.gameboy_mut().mmu_mut().read(LCDC), 0x11);
+    }
+
+    #[test]
+    fn read_watchpoint_ignores_writes() {
+        // LD ($C000),A ; LD A,($C000)
+        let mut debugger = debugger(&[0xEA, 0x00, 0xC0, 0xFA, 0x00, 0xC0], &[]);
+        debugger.add_watchpoint(0xC000..=0xC0FF, WatchKind::Read);
+
+        assert_eq!(
+            debugger.run_frame(),
+            Some(Stop::Watchpoint {
+                addr: 0xC000,
+                access: Access::Read { value: 0x01 },
+            })
+        );
+        assert_eq!(pc(&debugger), 0x0106);
+    }
+
+    #[test]
+    fn breakpoint_stops_before_and_resumes() {
+        // NOP ; NOP ; JR -2
+        let mut debugger = debugger(&[0x00, 0x00, 0x18, 0xFE], &[]);
+        debugger.add_breakpoint(0x0101);
+
+        assert_eq!(debugger.run_frame(), Some(Stop::Breakpoint(0x0101)));
+        assert_eq!(pc(&debugger), 0x0101);
+
+        // Resuming runs past it into the loop, which spins out the frame.
+        assert_eq!(debugger.run_frame(), None);
+        assert_eq!(pc(&debugger), 0x0102);
+
+        assert!(debugger.remove_breakpoint(0x0101));
+        assert_eq!(debugger.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn step_over_runs_call_to_completion() {
+        // CALL $0200 ; NOP
+        let program = [0xCD, 0x00, 0x02, 0x00];
+        // INC B ; INC B ; RET
+        let routine = [0x04, 0x04, 0xC9];
+
+        let mut debugger = debugger(&program, &routine);
+        let b = debugger.gameboy().cpu().regs.b;
+        assert_eq!(debugger.step_over(), None);
+        assert_eq!(pc(&debugger), 0x0103);
+        assert_eq!(debugger.gameboy().cpu().regs.b, b.wrapping_add(2));
+
+        let mut debugger = self::debugger(&program, &routine);
+        assert_eq!(debugger.step_into(), None);
+        assert_eq!(pc(&debugger), 0x0200);
+    }
+
+    #[test]
+    fn step_over_stops_at_breakpoint_in_call() {
+        let mut debugger = debugger(&[0xCD, 0x00, 0x02], &[0x00, 0x00, 0xC9]);
+        debugger.add_breakpoint(0x0201);
+
+        assert_eq!(debugger.step_over(), Some(Stop::Breakpoint(0x0201)));
+        assert_eq!(pc(&debugger), 0x0201);
+    }
+}
@@ -0,0 +1,1388 @@
+//! The whole system, tying the CPU to the memory map.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+use crate::bus::Bus;
+use crate::cartridge::{Cartridge, CartridgeHeader, CgbSupport, HeaderError, MapperKind};
+use crate::cheat::{Cheat, CheatError};
+use crate::cpu::{Cpu, Registers};
+use crate::debugger::Access;
+use crate::mmu::{CGB_BOOT_SIZE, Mmu};
+use crate::model::Model;
+use crate::ppu::{CompatPalette, DmgPalette};
+use crate::profiler::Profiler;
+use crate::rewind::RewindBuffer;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::test_rom::{TestResult, TestRomWatcher};
+use crate::trace::{CpuState, StepHook};
+
+/// The number of T-cycles in a frame at normal speed.
+pub const FRAME_CYCLES: u32 = 70224;
+
+/// The T-cycles the CPU idles for at a time while stalled by VRAM DMA.
+const STALL_CYCLES: u8 = 4;
+
+/// The size of the DMG boot ROM.
+const DMG_BOOT_SIZE: usize = 0x100;
+
+/// The ® the DMG boot ROM draws after the logo, one bit per pixel.
+const REGISTERED_MARK: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+/// A callback for completed frames.
+type FrameCallback = Box<dyn FnMut(&[u8])>;
+
+/// A callback called with every completed frame.
+struct FrameHook(FrameCallback);
+
+impl fmt::Debug for FrameHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FrameHook")
+    }
+}
+
+/// An error setting up a system with a boot ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootRomError {
+    /// The cartridge header is invalid or unsupported.
+    Header(HeaderError),
+    /// The boot ROM is neither the DMG nor the CGB size.
+    InvalidSize {
+        /// The length of the boot ROM.
+        len: usize,
+    },
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header(err) => write!(f, "invalid cartridge: {err}"),
+            Self::InvalidSize { len } => write!(
+                f,
+                "boot ROM is {len} bytes, expected {DMG_BOOT_SIZE} or {CGB_BOOT_SIZE}"
+            ),
+        }
+    }
+}
+
+impl Error for BootRomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Header(err) => Some(err),
+            Self::InvalidSize { .. } => None,
+        }
+    }
+}
+
+impl From<HeaderError> for BootRomError {
+    fn from(err: HeaderError) -> Self {
+        Self::Header(err)
+    }
+}
+
+/// A Game Boy, with a cartridge inserted.
+///
+/// The CPU only sees the [`Mmu`], which owns the other components and
+/// collects their interrupt requests into `IF` as they're ticked.
+#[derive(Debug)]
+pub struct GameBoy {
+    cpu: Cpu,
+    mmu: Mmu,
+    on_step: Option<StepHook>,
+    on_frame: Option<FrameHook>,
+    /// Whether a frame was completed since it was last taken.
+    frame_completed: bool,
+    rewind: Option<RewindBuffer>,
+    profiler: Option<Profiler>,
+    test_rom: TestRomWatcher,
+    /// The banks, addresses and values forced by `GameShark` codes.
+    game_shark: Vec<(Option<u8>, u16, u8)>,
+}
+
+impl GameBoy {
+    /// Create a system in the post-boot state with `rom` inserted, on the
+    /// hardware its header asks for: a CGB if it declares CGB support, and a
+    /// DMG otherwise, see [`Model::for_cartridge`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cartridge header is invalid or describes an
+    /// unsupported mapper.
+    pub fn from_rom(rom: Vec<u8>) -> Result<Self, HeaderError> {
+        let model = Model::for_cartridge(&CartridgeHeader::parse(&rom)?);
+        Self::new(rom, model)
+    }
+
+    /// Create a `model` in the post-boot state with `rom` inserted.
+    ///
+    /// The registers are left as the boot ROM of `model` leaves them. On
+    /// the models with CGB hardware, a CGB cartridge runs in CGB mode, and
+    /// a DMG one in the compatibility mode the CGB boot ROM leaves it in:
+    /// drawn as on DMG, but in the colors the boot ROM picks for its title,
+    /// see [`CompatPalette`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cartridge header is invalid or describes an
+    /// unsupported mapper.
+    pub fn new(rom: Vec<u8>, model: Model) -> Result<Self, HeaderError> {
+        let palette = CompatPalette::for_rom(&rom);
+        let cartridge = Cartridge::from_bytes(rom)?;
+        let mut mmu = Mmu::with_model(cartridge, model);
+        if model.is_cgb() && !mmu.is_cgb() {
+            mmu.ppu_mut().set_compat_palette(Some(palette));
+        }
+
+        Ok(Self {
+            cpu: post_boot_cpu(model, mmu.is_cgb()),
+            mmu,
+            on_step: None,
+            on_frame: None,
+            frame_completed: false,
+            rewind: None,
+            profiler: None,
+            test_rom: TestRomWatcher::default(),
+            game_shark: Vec::new(),
+        })
+    }
+
+    /// Create a system at power-on with `rom` inserted, about to run `boot`
+    /// from `0x0000`.
+    ///
+    /// A 256-byte boot ROM is taken as the DMG one, and a 2304-byte one as
+    /// the CGB one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cartridge header is invalid, or if `boot` is
+    /// neither size.
+    pub fn with_boot_rom(rom: Vec<u8>, boot: Vec<u8>) -> Result<Self, BootRomError> {
+        let mut cpu = match boot.len() {
+            DMG_BOOT_SIZE => Cpu::new(),
+            CGB_BOOT_SIZE => Cpu::new_cgb(),
+            len => return Err(BootRomError::InvalidSize { len }),
+        };
+        cpu.regs = Registers::new_power_on();
+
+        let cartridge = Cartridge::from_bytes(rom)?;
+        Ok(Self {
+            cpu,
+            mmu: Mmu::with_boot_rom(cartridge, boot),
+            on_step: None,
+            on_frame: None,
+            frame_completed: false,
+            rewind: None,
+            profiler: None,
+            test_rom: TestRomWatcher::default(),
+            game_shark: Vec::new(),
+        })
+    }
+
+    /// Leave the Nintendo logo in VRAM and the background map as the DMG
+    /// boot ROM does, for a system started without one, so the first frame
+    /// shows it in place.
+    ///
+    /// The logo is drawn from the cartridge header, scaled up to tiles 1-24
+    /// with the ® in tile 25. This does nothing if a boot ROM is mapped. In
+    /// CGB mode the background palettes are still white, so the logo only
+    /// shows once the game sets them.
+    #[must_use]
+    pub fn with_fake_logo(mut self) -> Self {
+        if self.mmu.is_boot_rom_mapped() {
+            return self;
+        }
+
+        // Each nibble of the logo is a row of four pixels, doubled in both
+        // directions, so every byte makes half a tile.
+        let mut addr = 0x8010;
+        for offset in 0x0104..0x0134 {
+            let byte = self.mmu.cartridge().read_rom(offset);
+            for nibble in [byte >> 4, byte & 0x0F] {
+                let row = (0..4).fold(0, |row, bit| row | ((nibble >> bit & 1) * 3) << (bit * 2));
+                for _ in 0..2 {
+                    self.mmu.poke(addr, row);
+                    addr += 2;
+                }
+            }
+        }
+        for row in REGISTERED_MARK {
+            self.mmu.poke(addr, row);
+            addr += 2;
+        }
+
+        for (addr, tile) in (0x9904..).zip(1..=12).chain((0x9924..).zip(13..=24)) {
+            self.mmu.poke(addr, tile);
+        }
+        self.mmu.poke(0x9910, 25);
+        self
+    }
+
+    /// Return the hardware model the system was created as.
+    #[must_use]
+    pub const fn model(&self) -> Model {
+        self.mmu.model()
+    }
+
+    /// Return the title of the inserted game, from the cartridge header.
+    #[must_use]
+    pub fn title(&self) -> &str {
+        self.mmu.cartridge().title()
+    }
+
+    /// Return the memory bank controller the cartridge declares.
+    #[must_use]
+    pub const fn mapper(&self) -> MapperKind {
+        self.mmu.cartridge().header().mapper_kind()
+    }
+
+    /// Check if the cartridge declares CGB support, whether enhanced or
+    /// CGB only.
+    #[must_use]
+    pub fn supports_cgb(&self) -> bool {
+        self.mmu.cartridge().header().cgb != CgbSupport::None
+    }
+
+    /// Return the CPU.
+    #[must_use]
+    pub const fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Return the CPU mutably.
+    pub const fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Return the memory map.
+    #[must_use]
+    pub const fn mmu(&self) -> &Mmu {
+        &self.mmu
+    }
+
+    /// Return the memory map mutably.
+    pub const fn mmu_mut(&mut self) -> &mut Mmu {
+        &mut self.mmu
+    }
+
+    /// Read the byte at `addr` without any side effects or DMA conflicts.
+    ///
+    /// This is for tooling like memory viewers, not for the CPU. Banked
+    /// regions read from the banks currently selected.
+    #[must_use]
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.mmu.peek(addr)
+    }
+
+    /// Set the byte at `addr` without any side effects or DMA conflicts.
+    ///
+    /// This is for tooling like memory editors, not for the CPU. See
+    /// [`Mmu::poke`] for the regions that can't be poked.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.mmu.poke(addr, value);
+    }
+
+    /// Return the most recent frame row by row, as 2-bit shades on DMG or
+    /// the indices described by [`crate::ppu::Ppu::framebuffer`] on CGB.
+    #[must_use]
+    pub fn framebuffer(&self) -> &[u8] {
+        self.mmu.ppu().framebuffer()
+    }
+
+    /// Return the most recent frame as RGBA8888, row by row with four bytes
+    /// per pixel, blended with the one before if
+    /// [`GameBoy::set_frame_blending`] is on.
+    #[must_use]
+    pub fn rgba_framebuffer(&self) -> &[u8] {
+        self.mmu.ppu().rgba_framebuffer()
+    }
+
+    /// Return the most recent frame as RGBA8888 as it was drawn, without
+    /// frame blending.
+    #[must_use]
+    pub fn raw_rgba_framebuffer(&self) -> &[u8] {
+        self.mmu.ppu().raw_rgba_framebuffer()
+    }
+
+    /// Draw the DMG shades of [`GameBoy::rgba_framebuffer`] in the colors
+    /// of `palette`.
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.mmu.ppu_mut().set_palette(palette);
+    }
+
+    /// Return the CGB colors a DMG game is drawn in, see
+    /// [`GameBoy::new`].
+    #[must_use]
+    pub fn compat_palette(&self) -> Option<CompatPalette> {
+        self.mmu.ppu().compat_palette()
+    }
+
+    /// Draw a DMG game in the CGB colors of `palette` instead, such as one
+    /// the player picked, or in the [`DmgPalette`] with `None`.
+    pub fn set_compat_palette(&mut self, palette: Option<CompatPalette>) {
+        self.mmu.ppu_mut().set_compat_palette(palette);
+    }
+
+    /// Check if the machine is fast-forwarding, see
+    /// [`GameBoy::set_fast_forward`].
+    #[must_use]
+    pub fn is_fast_forward(&self) -> bool {
+        self.mmu.ppu().is_fast_forward()
+    }
+
+    /// Run without drawing pixels or producing audio samples while `enabled`,
+    /// for skipping ahead headless.
+    ///
+    /// Every component keeps its exact timing, so the machine ends up in the
+    /// same state as running normally. Only the output is skipped: the
+    /// framebuffers keep the last frame drawn, and drawing resumes at the
+    /// start of the first frame after this is disabled.
+    pub fn set_fast_forward(&mut self, enabled: bool) {
+        self.mmu.ppu_mut().set_fast_forward(enabled);
+        self.mmu.apu_mut().set_fast_forward(enabled);
+    }
+
+    /// Return the frames left undrawn after each one drawn, see
+    /// [`GameBoy::set_frame_skip`].
+    #[must_use]
+    pub fn frame_skip(&self) -> u8 {
+        self.mmu.ppu().frame_skip()
+    }
+
+    /// Draw only one frame in every `frames + 1`, for slow hosts.
+    ///
+    /// The skipped frames still run with exact timing and V-blank
+    /// interrupts, and [`GameBoy::run_frame`] still returns after each, but
+    /// the framebuffers keep the last frame drawn and the frame callback
+    /// only runs for drawn frames. Skipping takes effect from the start of
+    /// the next frame.
+    pub fn set_frame_skip(&mut self, frames: u8) {
+        self.mmu.ppu_mut().set_frame_skip(frames);
+    }
+
+    /// Check if frames are blended, see [`GameBoy::set_frame_blending`].
+    #[must_use]
+    pub fn is_frame_blending(&self) -> bool {
+        self.mmu.ppu().is_frame_blending()
+    }
+
+    /// Show the average of the last two frames in
+    /// [`GameBoy::rgba_framebuffer`] while `enabled`, smoothing over the
+    /// flicker games use for transparency the way the DMG's slow LCD does.
+    /// The frames as drawn stay in [`GameBoy::raw_rgba_framebuffer`].
+    pub fn set_frame_blending(&mut self, enabled: bool) {
+        self.mmu.ppu_mut().set_frame_blending(enabled);
+    }
+
+    /// Check if instructions are profiled, see [`GameBoy::set_profiling`].
+    #[must_use]
+    pub const fn is_profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    /// Count the executions and T-cycles of the instruction at each address
+    /// while `enabled`, for [`GameBoy::profile_report`]. Enabling starts the
+    /// counts from zero, and disabling drops them.
+    ///
+    /// Servicing an interrupt counts towards the instruction it interrupted,
+    /// and the cycles spent halted or stopped aren't counted.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if enabled != self.profiler.is_some() {
+            self.profiler = enabled.then(Profiler::new);
+        }
+    }
+
+    /// Return the address, executions and T-cycles of every instruction run
+    /// while profiling, in address order, or nothing if it's off.
+    #[must_use]
+    pub fn profile_report(&self) -> Vec<(u16, u64, u64)> {
+        self.profiler.as_ref().map_or_else(Vec::new, Profiler::report)
+    }
+
+    /// Run one instruction, or service one interrupt, then advance every
+    /// other component by the T-cycles it took.
+    ///
+    /// While VRAM DMA stalls the CPU, this idles for 4 T-cycles instead.
+    ///
+    /// Returns the number of T-cycles taken.
+    pub fn step(&mut self) -> u8 {
+        self.trace();
+        let cycles = if self.mmu.is_cpu_stalled() {
+            STALL_CYCLES
+        } else {
+            let pc = self.profiled_pc();
+            let cycles = self.cpu.step(&mut self.mmu);
+            if let (Some(profiler), Some(pc)) = (&mut self.profiler, pc) {
+                profiler.record(pc, cycles);
+            }
+            cycles
+        };
+        self.mmu.set_double_speed(self.cpu.is_double_speed());
+        self.mmu.tick(cycles);
+        self.finish_step();
+        cycles
+    }
+
+    /// Call `hook` with the CPU state before every instruction, such as to
+    /// write a Gameboy Doctor log.
+    ///
+    /// Without a hook, no state is captured at all.
+    pub fn on_step(&mut self, hook: impl FnMut(&CpuState) + 'static) {
+        self.on_step = Some(StepHook(Box::new(hook)));
+    }
+
+    /// Remove the hook set by [`GameBoy::on_step`].
+    pub fn clear_on_step(&mut self) {
+        self.on_step = None;
+    }
+
+    /// Call `callback` with the framebuffer every time the PPU enters
+    /// vertical blanking, completing a frame.
+    ///
+    /// The callback runs at the end of the step that completed the frame,
+    /// before the next instruction, so it sees exactly the frame drawn.
+    pub fn set_frame_callback(&mut self, callback: impl FnMut(&[u8]) + 'static) {
+        self.on_frame = Some(FrameHook(Box::new(callback)));
+    }
+
+    /// Remove the callback set by [`GameBoy::set_frame_callback`].
+    pub fn clear_frame_callback(&mut self) {
+        self.on_frame = None;
+    }
+
+    /// Parse `code` as a Game Genie or `GameShark` code and apply it.
+    ///
+    /// Game Genie codes patch CPU reads from ROM through
+    /// [`Mmu::add_read_hook`], and only while the ROM holds their compare
+    /// byte, if they have one. `GameShark` codes write their value into RAM
+    /// every time a frame completes, into the bank their type names if
+    /// they're in banked RAM, see [`Cheat::bank`]. Cheats are kept across
+    /// resets.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `code` is in neither format, see [`Cheat`].
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), CheatError> {
+        match code.parse()? {
+            Cheat::GameGenie {
+                addr,
+                value,
+                compare,
+            } => self.mmu.add_read_hook(addr, move |_, byte| {
+                if compare.is_none_or(|compare| compare == byte) {
+                    value
+                } else {
+                    byte
+                }
+            }),
+            cheat @ Cheat::GameShark { addr, value, .. } => {
+                self.game_shark.push((cheat.bank(), addr, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return and clear whether a frame was completed since the last call.
+    pub(crate) const fn take_frame_completed(&mut self) -> bool {
+        let completed = self.frame_completed;
+        self.frame_completed = false;
+        completed
+    }
+
+    /// Hand the results of the last step to the frame callback and the
+    /// test ROM watcher.
+    fn finish_step(&mut self) {
+        if self.mmu.ppu_mut().take_frame_ready() {
+            self.frame_completed = true;
+            for &(bank, addr, value) in &self.game_shark {
+                match bank {
+                    Some(bank) => self.mmu.poke_bank(bank, addr, value),
+                    None => self.mmu.poke(addr, value),
+                }
+            }
+            if let Some(FrameHook(callback)) = &mut self.on_frame
+                && !self.mmu.ppu().is_frame_skipped()
+            {
+                callback(self.mmu.ppu().framebuffer());
+            }
+        }
+        self.watch_test_rom();
+    }
+
+    /// Return the address of the instruction about to run, if it's to be
+    /// profiled.
+    fn profiled_pc(&self) -> Option<u16> {
+        let asleep = self.cpu.is_halted() || self.cpu.is_stopped() || self.cpu.is_locked();
+        (self.profiler.is_some() && !asleep).then_some(self.cpu.regs.pc)
+    }
+
+    /// Pass the CPU state to the step hook, if it's about to run an
+    /// instruction rather than idle.
+    fn trace(&mut self) {
+        #[cfg(feature = "trace")]
+        self.mmu.set_trace_pc(self.cpu.regs.pc);
+        if let Some(StepHook(hook)) = &mut self.on_step
+            && !self.cpu.is_halted()
+            && !self.cpu.is_stopped()
+            && !self.cpu.is_locked()
+            && !self.mmu.is_cpu_stalled()
+        {
+            hook(&CpuState::capture(&self.cpu, &self.mmu));
+        }
+    }
+
+    /// Return the result reported by a Blargg or Mooneye test ROM, once it
+    /// has finished.
+    ///
+    /// Blargg's tests report over the serial port and Mooneye's through the
+    /// registers at an `LD B,B` breakpoint, see [`crate::test_rom`]. The
+    /// first result reported is kept until [`GameBoy::reset`].
+    #[must_use]
+    pub const fn test_result(&self) -> Option<TestResult> {
+        self.test_rom.result()
+    }
+
+    /// Pass the serial output and instruction of the last step on to the
+    /// test ROM watcher.
+    fn watch_test_rom(&mut self) {
+        if let Some(byte) = self.mmu.serial_mut().take_transmitted() {
+            self.test_rom.serial_byte(byte);
+        }
+        self.test_rom.instruction(self.cpu.instruction(), &self.cpu.regs);
+    }
+
+    /// Run one step like [`GameBoy::step`], passing every CPU access to
+    /// `inspect` as it happens.
+    pub(crate) fn step_inspected(&mut self, inspect: &mut dyn FnMut(u16, Access)) -> u8 {
+        self.trace();
+        let pc = self.profiled_pc();
+        let mut bus = InspectedBus {
+            mmu: &mut self.mmu,
+            inspect,
+        };
+        let cycles = if bus.mmu.is_cpu_stalled() {
+            STALL_CYCLES
+        } else {
+            let cycles = self.cpu.step(&mut bus);
+            if let (Some(profiler), Some(pc)) = (&mut self.profiler, pc) {
+                profiler.record(pc, cycles);
+            }
+            cycles
+        };
+        self.mmu.set_double_speed(self.cpu.is_double_speed());
+        self.mmu.tick(cycles);
+        self.finish_step();
+        cycles
+    }
+
+    /// Run until the PPU enters vertical blanking, and return the completed
+    /// frame.
+    ///
+    /// The frame callback is still called, see
+    /// [`GameBoy::set_frame_callback`].
+    ///
+    /// With the LCD off no frame completes, so this stops after a frame's
+    /// worth of cycles instead, at normal speed.
+    ///
+    /// With rewind enabled, the state after the frame is captured when the
+    /// buffer's interval is up.
+    pub fn run_frame(&mut self) -> &[u8] {
+        // Only a frame completed from here on counts.
+        self.frame_completed = false;
+        let mut cycles = 0;
+        while cycles < FRAME_CYCLES {
+            cycles += u32::from(self.step()) / u32::from(self.cpu.speed_multiplier());
+            if self.take_frame_completed() {
+                break;
+            }
+        }
+
+        if self.rewind.as_mut().is_some_and(RewindBuffer::frame_due) {
+            let state = self.save_state();
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(state);
+            }
+        }
+
+        self.framebuffer()
+    }
+
+    /// Run whole instructions until at least `budget` T-cycles have passed,
+    /// and return the T-cycles actually run.
+    ///
+    /// Instructions aren't split, so this may overshoot the budget by up to
+    /// one instruction. Cycles are counted as [`GameBoy::step`] returns
+    /// them, so at double speed they're CPU cycles rather than dots. Frame
+    /// boundaries are ignored, though the frame callback is still called.
+    ///
+    /// Two systems built from the same ROM and given the same calls reach
+    /// the same state, with one exception: an MBC3 clock reads the host
+    /// clock, and is stamped with the time it was created at. Runs that need
+    /// identical save states can give both the same fixed [`RtcSource`], then
+    /// copy one clock over the other through [`Cartridge::rtc_mut`].
+    ///
+    /// [`RtcSource`]: crate::cartridge::RtcSource
+    pub fn run_cycles(&mut self, budget: u64) -> u64 {
+        let mut cycles = 0;
+        while cycles < budget {
+            cycles += u64::from(self.step());
+        }
+        cycles
+    }
+
+    /// Snapshot the whole machine, except the cartridge and boot ROMs.
+    ///
+    /// The state starts with a version header, see [`crate::state`].
+    #[must_use]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        self.cpu.save_state(&mut state);
+        self.mmu.save_state(&mut state);
+        state.finish()
+    }
+
+    /// Restore a snapshot taken by [`GameBoy::save_state`] with the same
+    /// cartridge inserted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is of another version or cartridge, or
+    /// is truncated or corrupt. The machine is left untouched then.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        // Restoring a snapshot of our own keeps a failure from leaving a
+        // half-loaded machine behind.
+        let backup = self.save_state();
+        let loaded = self.read_state(data);
+        if loaded.is_err() {
+            let restored = self.read_state(&backup);
+            debug_assert!(restored.is_ok());
+        }
+        loaded
+    }
+
+    /// Load every component from a save state, in the order it was written.
+    fn read_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data)?;
+        self.cpu.load_state(&mut state)?;
+        self.mmu.load_state(&mut state)?;
+        self.mmu.set_double_speed(self.cpu.is_double_speed());
+        state.finish()
+    }
+
+    /// Capture save states into `buffer` as [`GameBoy::run_frame`] runs, to
+    /// be rewound to with [`GameBoy::rewind`].
+    pub fn enable_rewind(&mut self, buffer: RewindBuffer) {
+        self.rewind = Some(buffer);
+    }
+
+    /// Stop capturing save states, and return the buffer they were kept in.
+    pub fn disable_rewind(&mut self) -> Option<RewindBuffer> {
+        self.rewind.take()
+    }
+
+    /// Return the number of states that can be rewound to.
+    #[must_use]
+    pub fn rewind_depth(&self) -> usize {
+        self.rewind.as_ref().map_or(0, RewindBuffer::depth)
+    }
+
+    /// Restore the most recent captured state, removing it from the rewind
+    /// buffer, and return whether there was one.
+    pub fn rewind(&mut self) -> bool {
+        let Some(state) = self.rewind.as_mut().and_then(RewindBuffer::pop) else {
+            return false;
+        };
+
+        // The state was taken from this machine, so it always loads.
+        let loaded = self.read_state(&state);
+        debug_assert!(loaded.is_ok());
+        true
+    }
+
+    /// Return to the post-boot state, keeping the inserted cartridge.
+    ///
+    /// With a boot ROM, this returns to power-on and runs it again instead.
+    pub fn reset(&mut self) {
+        self.mmu.reset();
+        self.test_rom.clear();
+        self.frame_completed = false;
+        self.cpu = post_boot_cpu(self.mmu.model(), self.mmu.is_cgb());
+        if self.mmu.is_boot_rom_mapped() {
+            self.cpu.regs = Registers::new_power_on();
+        }
+    }
+}
+
+/// Return a CPU in the state the boot ROM of `model` leaves it in, in CGB
+/// mode if `cgb_mode` is set.
+///
+/// Outside CGB mode it runs as a DMG CPU, without the CGB speed switch.
+fn post_boot_cpu(model: Model, cgb_mode: bool) -> Cpu {
+    let mut cpu = if cgb_mode { Cpu::new_cgb() } else { Cpu::new() };
+    cpu.regs = model.registers(cgb_mode);
+    cpu
+}
+
+/// The memory map, reporting every access made through it.
+struct InspectedBus<'a> {
+    mmu: &'a mut Mmu,
+    inspect: &'a mut dyn FnMut(u16, Access),
+}
+
+impl Bus for InspectedBus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.mmu.read(addr);
+        (self.inspect)(addr, Access::Read { value });
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        let old = self.mmu.peek(addr);
+        self.mmu.write(addr, value);
+        let new = self.mmu.peek(addr);
+        (self.inspect)(addr, Access::Write { old, new });
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.mmu.peek(addr)
+    }
+
+    fn fetch(&mut self, addr: u16) -> u8 {
+        let value = self.mmu.fetch(addr);
+        (self.inspect)(addr, Access::Read { value });
+        value
+    }
+
+    fn increment(&mut self, addr: u16) {
+        self.mmu.increment(addr);
+    }
+
+    fn read_increment(&mut self, addr: u16) -> u8 {
+        let value = self.mmu.read_increment(addr);
+        (self.inspect)(addr, Access::Read { value });
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use core::cell::{Cell, RefCell};
+
+    use super::*;
+    use crate::cartridge::{RtcSource, test_rom};
+    use crate::interrupt::{IF, Interrupt};
+    use crate::mmu::{KEY1, SVBK};
+    use crate::ppu::LY;
+    use crate::rewind::RewindLimit;
+    use crate::timer::DIV;
+
+    /// Return a ROM running `program` from `0x0100`, with `JR -2` loops on
+    /// every interrupt vector.
+    fn rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        for vector in (0x40..=0x60).step_by(8) {
+            rom[vector..vector + 2].copy_from_slice(&[0x18, 0xFE]);
+        }
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    /// Return a system running `program` from `0x0100`.
+    fn gameboy(program: &[u8]) -> GameBoy {
+        GameBoy::from_rom(rom(program)).unwrap()
+    }
+
+    #[test]
+    fn fake_logo_fills_vram() {
+        let mut rom = rom(&[0x18, 0xFE]);
+        rom[0x0104] = 0xCE;
+        rom[0x0105] = 0xED;
+        let mut gb = GameBoy::from_rom(rom).unwrap().with_fake_logo();
+
+        // 0xCE scales up to rows 0xF0 and 0xFC, and 0xED to 0xFC and 0xF3.
+        let tile: Vec<u8> = (0x8010..0x8020).step_by(2).map(|addr| gb.peek(addr)).collect();
+        assert_eq!(tile, [0xF0, 0xF0, 0xFC, 0xFC, 0xFC, 0xFC, 0xF3, 0xF3]);
+        assert_eq!(gb.peek(0x8011), 0x00);
+        assert_eq!(gb.peek(0x8190), 0x3C);
+        assert_eq!([gb.peek(0x9904), gb.peek(0x990F), gb.peek(0x9910)], [1, 12, 25]);
+        assert_eq!([gb.peek(0x9924), gb.peek(0x992F)], [13, 24]);
+
+        // The top left pixel of the logo is drawn at (32, 64).
+        gb.run_frame();
+        assert_eq!(gb.framebuffer()[64 * 160 + 32], 3);
+        assert_eq!(gb.framebuffer()[64 * 160 + 31], 0);
+    }
+
+    #[test]
+    fn reports_cartridge_metadata() {
+        let mut rom = rom(&[]);
+        rom[0x0134..0x0134 + 6].copy_from_slice(b"TETRIS");
+        let gb = GameBoy::from_rom(rom.clone()).unwrap();
+        assert_eq!(gb.title(), "TETRIS");
+        assert_eq!(gb.mapper(), MapperKind::None);
+        assert!(!gb.supports_cgb());
+
+        // The CGB title stops short of the manufacturer code.
+        rom[0x0134..0x0143].copy_from_slice(b"POKEMON_SLVAAXE");
+        rom[0x0143] = 0x80;
+        rom[0x0147] = 0x13;
+        rom[0x0149] = 0x03;
+        let gb = GameBoy::from_rom(rom).unwrap();
+        assert_eq!(gb.title(), "POKEMON_SLV");
+        assert_eq!(gb.mapper(), MapperKind::Mbc3);
+        assert!(gb.supports_cgb());
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        assert!(GameBoy::from_rom(vec![0; 0x100]).is_err());
+    }
+
+    #[test]
+    fn game_genie_patches_matching_rom_reads() {
+        // LD A,($0150) ; LD B,A ; LD A,($0151) ; JR -2
+        let mut rom = rom(&[0xFA, 0x50, 0x01, 0x47, 0xFA, 0x51, 0x01, 0x18, 0xFE]);
+        rom[0x0150] = 0x42;
+        let mut gb = GameBoy::from_rom(rom).unwrap();
+        gb.add_cheat("3C1-50F-EA3").unwrap();
+        // The compare byte doesn't match, so this one is ignored.
+        gb.add_cheat("991-51F-EA3").unwrap();
+
+        for _ in 0..3 {
+            gb.step();
+        }
+        assert_eq!(gb.cpu().regs.b, 0x3C);
+        assert_eq!(gb.cpu().regs.a, 0x00);
+        assert_eq!(gb.peek(0x0150), 0x42);
+    }
+
+    #[test]
+    fn game_shark_forces_ram_every_frame() {
+        // LD A,$00 ; LD ($C0A0),A ; JR -7
+        let mut gb = gameboy(&[0x3E, 0x00, 0xEA, 0xA0, 0xC0, 0x18, 0xF9]);
+        gb.add_cheat("01AAA0C0").unwrap();
+        assert_eq!(gb.add_cheat("01AA"), Err(CheatError::InvalidLength { len: 4 }));
+
+        gb.run_frame();
+        assert_eq!(gb.peek(0xC0A0), 0xAA);
+        gb.step();
+        gb.step();
+        assert_eq!(gb.peek(0xC0A0), 0x00);
+    }
+
+    #[test]
+    fn game_shark_writes_the_bank_named() {
+        // LD A,$0A ; LD ($0000),A ; JR -2
+        let mut rom = rom(&[0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x18, 0xFE]);
+        rom[0x0143] = 0x80;
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x03;
+        let mut gb = GameBoy::from_rom(rom).unwrap();
+        gb.add_cheat("03BB00A0").unwrap();
+        gb.add_cheat("92CC10D0").unwrap();
+        // Type 0x01 writes to the bank mapped, bank 0 here.
+        gb.add_cheat("01DD01A0").unwrap();
+        gb.run_frame();
+
+        let banks: Vec<_> = gb.mmu.cartridge().ram_banks().map(|bank| [bank[0], bank[1]]).collect();
+        assert_eq!(banks, [[0x00, 0xDD], [0x00, 0x00], [0x00, 0x00], [0xBB, 0x00]]);
+        // WRAM bank 1 is mapped, so the write went past it.
+        assert_eq!(gb.peek(0xD010), 0x00);
+        gb.mmu.write(SVBK, 0x02);
+        assert_eq!(gb.peek(0xD010), 0xCC);
+    }
+
+    #[test]
+    fn stop_switches_to_double_speed() {
+        // LD A,$01 ; LDH ($4D),A ; STOP ; JR -2
+        let mut boot = vec![0; CGB_BOOT_SIZE];
+        boot[..8].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x18, 0xFE]);
+        let mut gb = GameBoy::with_boot_rom(rom(&[]), boot).unwrap();
+
+        gb.step();
+        gb.step();
+        assert_eq!(gb.peek(KEY1), 0x7F);
+        gb.step();
+        assert!(gb.cpu().is_double_speed());
+        assert_eq!(gb.peek(KEY1), 0xFE);
+
+        // Ten lines' worth of CPU cycles only get the PPU through five.
+        let ly = gb.peek(LY);
+        let mut cycles = 0;
+        while cycles < 10 * 456 {
+            cycles += u32::from(gb.step());
+        }
+        assert_eq!(cycles, 10 * 456);
+        assert_eq!(gb.peek(LY), ly + 5);
+    }
+
+    #[test]
+    fn boot_rom_runs_from_zero() {
+        // LD A,$01 ; LDH ($50),A
+        let mut boot = vec![0; 0x100];
+        boot[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        // JP $00FC
+        boot[..3].copy_from_slice(&[0xC3, 0xFC, 0x00]);
+
+        let mut gb = GameBoy::with_boot_rom(rom(&[0x18, 0xFE]), boot).unwrap();
+        assert_eq!(gb.cpu().regs.pc, 0x0000);
+        assert_eq!(gb.cpu().regs.af(), 0x0000);
+        assert_eq!(gb.mmu_mut().read(0x0000), 0xC3);
+
+        for _ in 0..3 {
+            gb.step();
+        }
+        assert_eq!(gb.cpu().regs.pc, 0x0100);
+        assert_eq!(gb.mmu_mut().read(0x0000), 0x00);
+        assert_eq!(gb.mmu_mut().read(0x0100), 0x18);
+
+        gb.reset();
+        assert_eq!(gb.cpu().regs.pc, 0x0000);
+        assert_eq!(gb.mmu_mut().read(0x0000), 0xC3);
+    }
+
+    #[test]
+    fn rejects_bad_boot_rom_size() {
+        let rom = vec![0; 0x8000];
+
+        assert_eq!(
+            GameBoy::with_boot_rom(rom, vec![0; 0x200]).unwrap_err(),
+            BootRomError::InvalidSize { len: 0x200 }
+        );
+    }
+
+    #[test]
+    fn step_ticks_components() {
+        // LD A,$42 ; LD A,$43
+        let mut gb = gameboy(&[0x3E, 0x42, 0x3E, 0x43]);
+
+        assert_eq!(gb.step(), 8);
+        assert_eq!(gb.cpu().regs.a, 0x42);
+        assert_eq!(gb.cpu().regs.pc, 0x0102);
+
+        let mut cycles = 8;
+        while cycles < 256 {
+            cycles += u16::from(gb.step());
+        }
+        assert_eq!(gb.mmu_mut().read(DIV), 1);
+    }
+
+    #[test]
+    fn run_frame_stops_at_vblank() {
+        // JR -2
+        let mut gb = gameboy(&[0x18, 0xFE]);
+
+        gb.run_frame();
+        assert_eq!(gb.mmu_mut().read(LY), 144);
+
+        gb.mmu_mut().write(IF, 0);
+        gb.run_frame();
+        assert_eq!(gb.mmu_mut().read(LY), 144);
+        assert_eq!(gb.mmu_mut().read(IF), 0xE0 | Interrupt::VBlank.bit());
+    }
+
+    #[test]
+    fn frame_callback_fires_once_per_vblank() {
+        // INC A ; LD (HL),A ; JR -4
+        let mut gb = gameboy(&[0x3C, 0x77, 0x18, 0xFC]);
+        gb.cpu_mut().regs.set_hl(0xC000);
+        let frames = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&frames);
+        gb.set_frame_callback(move |frame| {
+            assert_eq!(frame.len(), 160 * 144);
+            counter.set(counter.get() + 1);
+        });
+
+        // The LCD runs at 59.7 Hz, so one emulated second from power-on,
+        // with the first frame on line 0, holds 59 whole frames.
+        gb.run_cycles(4_194_304);
+        assert_eq!(frames.get(), 59);
+
+        // The callback fires on the step that enters vertical blanking.
+        while frames.get() == 59 {
+            gb.step();
+        }
+        assert_eq!(gb.mmu_mut().read(LY), 144);
+        assert_eq!(frames.get(), 60);
+
+        // `run_frame` is built on the same frames.
+        gb.run_frame();
+        assert_eq!(frames.get(), 61);
+        gb.clear_frame_callback();
+        gb.run_frame();
+        assert_eq!(frames.get(), 61);
+    }
+
+    #[test]
+    fn profile_finds_hot_loop() {
+        // LD B,0 ; loop: DEC B ; JR NZ,loop ; HALT
+        let mut gb = gameboy(&[0x06, 0x00, 0x05, 0x20, 0xFD, 0x76]);
+        assert!(gb.profile_report().is_empty());
+        gb.set_profiling(true);
+        while !gb.cpu().is_halted() {
+            gb.step();
+        }
+        gb.run_cycles(1000);
+
+        let report = gb.profile_report();
+        let &(pc, count, _) = report.iter().max_by_key(|&&(_, count, _)| count).unwrap();
+        assert!((0x0102..=0x0103).contains(&pc));
+        assert_eq!(count, 256);
+        assert_eq!(report[0], (0x0100, 1, 8));
+        assert_eq!(report[2], (0x0103, 256, 255 * 12 + 8));
+        assert_eq!(report.last(), Some(&(0x0105, 1, 4)));
+    }
+
+    #[test]
+    fn frame_skip_draws_one_frame_in_three() {
+        // INC A ; JR -3
+        let mut gb = gameboy(&[0x3C, 0x18, 0xFD]);
+        let drawn = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&drawn);
+        gb.set_frame_callback(move |_| counter.set(counter.get() + 1));
+        gb.set_frame_skip(2);
+
+        let mut vblanks = 0;
+        for _ in 0..9 {
+            gb.run_frame();
+            vblanks += u32::from(gb.peek(IF) & Interrupt::VBlank.bit() != 0);
+            gb.mmu_mut().write(IF, 0);
+        }
+        assert_eq!(drawn.get(), 3);
+        assert_eq!(vblanks, 9);
+        assert_eq!(gb.frame_skip(), 2);
+    }
+
+    #[test]
+    fn fast_forward_matches_normal_execution() {
+        // INC A ; JR -3
+        let machine = || {
+            let mut gb = gameboy(&[0x3C, 0x18, 0xFD]);
+            // Fill tile 0, which the whole background shows, with color 3.
+            for addr in 0x8000..0x8010 {
+                gb.poke(addr, 0xFF);
+            }
+            gb
+        };
+        let mut normal = machine();
+        let mut fast = machine();
+
+        fast.set_fast_forward(true);
+        assert!(fast.is_fast_forward());
+        for _ in 0..3 {
+            normal.run_frame();
+            fast.run_frame();
+        }
+        assert!(fast.framebuffer().iter().all(|&shade| shade == 0));
+        let mut samples = Vec::new();
+        fast.mmu_mut().apu_mut().drain_samples(&mut samples);
+        assert!(samples.is_empty());
+
+        // Drawing resumes with the next whole frame.
+        fast.set_fast_forward(false);
+        normal.run_frame();
+        fast.run_frame();
+        assert!(normal.framebuffer().iter().any(|&shade| shade != 0));
+        assert_eq!(fast.framebuffer(), normal.framebuffer());
+        assert_eq!(fast.rgba_framebuffer(), normal.rgba_framebuffer());
+        assert_eq!(fast.save_state(), normal.save_state());
+    }
+
+    #[test]
+    fn cgb_colors_dmg_games() {
+        let mut rom = rom(&[0x18, 0xFE]);
+        rom[0x0134..0x013A].copy_from_slice(b"TETRIS");
+        rom[0x014B] = 0x01;
+        let mut gb = GameBoy::new(rom.clone(), Model::Cgb).unwrap();
+
+        // The DMG game runs in compatibility mode, not CGB mode.
+        assert!(!gb.mmu().is_cgb());
+        assert_eq!(gb.compat_palette(), Some(CompatPalette::for_rom(&rom)));
+        assert_eq!(gb.cpu().regs.a, 0x11);
+        gb.set_compat_palette(None);
+        gb.reset();
+        assert_eq!(gb.compat_palette(), None);
+        assert_eq!(gb.cpu().regs.hl(), 0x007C);
+
+        rom[0x0143] = 0x80;
+        let gb = GameBoy::new(rom, Model::Cgb).unwrap();
+        assert!(gb.mmu().is_cgb());
+        assert_eq!(gb.compat_palette(), None);
+    }
+
+    #[test]
+    fn model_sets_boot_registers() {
+        let mut rom = rom(&[0x18, 0xFE]);
+        rom[0x0143] = 0x80;
+        let gb = GameBoy::from_rom(rom.clone()).unwrap();
+        assert_eq!(gb.model(), Model::Cgb);
+        assert_eq!(gb.cpu().regs.af(), 0x1180);
+        assert_eq!(gb.cpu().regs.de(), 0xFF56);
+
+        let mut gb = GameBoy::new(rom, Model::Dmg).unwrap();
+        assert!(!gb.mmu().is_cgb());
+        assert_eq!(gb.cpu().regs.af(), 0x01B0);
+        assert_eq!(gb.cpu().regs.hl(), 0x014D);
+        gb.reset();
+        assert_eq!(gb.model(), Model::Dmg);
+        assert_eq!(gb.cpu().regs.af(), 0x01B0);
+    }
+
+    #[test]
+    fn inc_hl_in_oam_corrupts_it_on_dmg() {
+        // LD HL,$FE10 ; INC HL ; JR -2
+        let program = [0x21, 0x10, 0xFE, 0x23, 0x18, 0xFE];
+        let run = |model| {
+            let mut gb = GameBoy::new(rom(&program), model).unwrap();
+            for (addr, value) in (0xFE00..0xFEA0).zip(0..) {
+                gb.poke(addr, value);
+            }
+            gb.step();
+            gb.step();
+            (0xFE00..0xFEA0).map(|addr| gb.peek(addr)).collect::<Vec<_>>()
+        };
+
+        // INC HL runs 12 dots into OAM scan, as the PPU reads row 3, whose
+        // first word mixes with row 2 into a copy of it.
+        let oam = run(Model::Dmg);
+        assert_eq!(oam[24..32], *(16..24).collect::<Vec<_>>());
+        assert_eq!(oam[..24], *(0..24).collect::<Vec<_>>());
+        assert_eq!(oam[32..], *(32..0xA0).collect::<Vec<_>>());
+
+        assert_eq!(run(Model::Cgb), (0..0xA0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn run_frame_with_lcd_off_returns() {
+        // LD A,$00 ; LDH ($40),A ; JR -2
+        let mut gb = gameboy(&[0x3E, 0x00, 0xE0, 0x40, 0x18, 0xFE]);
+
+        assert_eq!(gb.run_frame().len(), 160 * 144);
+        assert!(!gb.mmu().ppu().is_enabled());
+    }
+
+    #[test]
+    fn timer_interrupt_reaches_cpu() {
+        // LD A,$04 ; LDH ($FF),A ; LD A,$05 ; LDH ($07),A ; EI ; HALT ; JR -2
+        let mut gb = gameboy(&[0x3E, 0x04, 0xE0, 0xFF, 0x3E, 0x05, 0xE0, 0x07, 0xFB, 0x76, 0x18, 0xFE]);
+
+        for _ in 0..2000 {
+            gb.step();
+        }
+        assert_eq!(gb.cpu().regs.pc, 0x0050);
+        assert!(!gb.cpu().ime);
+    }
+
+    /// Return a system drawing ever-changing tiles while scrolling, so any
+    /// divergence shows up in the frames.
+    fn scroller() -> GameBoy {
+        let program = [
+            0x21, 0x00, 0x80, // LD HL,$8000
+            0x22, // LD (HL+),A
+            0x3C, // INC A
+            0xE0, 0x43, // LDH ($43),A
+            0xCB, 0xAC, // RES 5,H
+            0x18, 0xF8, // JR -8
+        ];
+        gameboy(&program)
+    }
+
+    #[test]
+    fn state_round_trips_bit_identically() {
+        let mut original = scroller();
+        original.run_frame();
+        for _ in 0..1000 {
+            original.step();
+        }
+
+        let state = original.save_state();
+        let mut restored = scroller();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+
+        for frame in 0..100 {
+            assert_eq!(original.run_frame(), restored.run_frame(), "frame {frame}");
+        }
+        assert_eq!(original.save_state(), restored.save_state());
+    }
+
+    #[test]
+    fn run_cycles_is_deterministic() {
+        let (mut a, mut b) = (scroller(), scroller());
+        for budget in [0, 1, 7, 100, 4_567, 70_224, 123_457] {
+            let cycles = a.run_cycles(budget);
+            assert!(cycles >= budget && cycles < budget + 24, "budget {budget}");
+            assert_eq!(b.run_cycles(budget), cycles);
+        }
+
+        assert_eq!(a.save_state(), b.save_state());
+        assert_eq!(a.run_cycles(0), 0);
+    }
+
+    #[test]
+    fn run_cycles_is_deterministic_with_a_fixed_rtc() {
+        #[derive(Debug)]
+        struct Fixed;
+
+        impl RtcSource for Fixed {
+            fn now(&self) -> u64 {
+                1_000
+            }
+        }
+
+        // loop: LD A,$00 ; LD ($6000),A ; INC A ; LD ($6000),A ; JR loop
+        let program = [0x3E, 0x00, 0xEA, 0x00, 0x60, 0x3C, 0xEA, 0x00, 0x60, 0x18, 0xF5];
+        let mut rom = rom(&program);
+        rom[0x0147] = 0x10;
+        rom[0x0149] = 0x03;
+        let mut a = GameBoy::from_rom(rom.clone()).unwrap();
+        let mut b = GameBoy::from_rom(rom).unwrap();
+
+        // The clocks carry the host time they were created at.
+        b.mmu_mut().cartridge_mut().rtc_mut().unwrap().timestamp -= 1;
+        assert_ne!(a.save_state(), b.save_state());
+
+        a.mmu_mut().cartridge_mut().set_rtc_source(Fixed);
+        b.mmu_mut().cartridge_mut().set_rtc_source(Fixed);
+        let rtc = a.mmu().cartridge().rtc().unwrap().clone();
+        *b.mmu_mut().cartridge_mut().rtc_mut().unwrap() = rtc;
+        assert_eq!(a.run_cycles(4_567), b.run_cycles(4_567));
+        assert_eq!(a.save_state(), b.save_state());
+    }
+
+    #[test]
+    fn bad_state_leaves_machine_untouched() {
+        let mut gb = scroller();
+        gb.run_frame();
+        let state = gb.save_state();
+
+        let mut other = scroller();
+        let before = other.save_state();
+
+        let mut truncated = state.clone();
+        truncated.truncate(state.len() - 1);
+        assert_eq!(other.load_state(&truncated), Err(StateError::Truncated));
+
+        let mut versioned = state.clone();
+        versioned[4] = 0xFF;
+        assert_eq!(other.load_state(&versioned), Err(StateError::UnsupportedVersion(0x00FF)));
+
+        let mut trailing = state;
+        trailing.push(0);
+        assert_eq!(other.load_state(&trailing), Err(StateError::Corrupt));
+
+        assert_eq!(other.save_state(), before);
+    }
+
+    #[test]
+    fn rewind_restores_captured_states() {
+        // INC A ; LD (HL+),A ; JR -4
+        let mut gb = gameboy(&[0x3C, 0x22, 0x18, 0xFC]);
+        gb.cpu_mut().regs.set_hl(0xC000);
+        gb.enable_rewind(RewindBuffer::new(1, RewindLimit::States(100)));
+        assert!(!gb.rewind());
+
+        let mut captured = Vec::new();
+        for _ in 0..60 {
+            gb.run_frame();
+            captured.push(gb.save_state());
+        }
+        assert_eq!(gb.rewind_depth(), 60);
+
+        // The first rewind returns to the state after the last frame.
+        for frame in (30..60).rev() {
+            assert!(gb.rewind());
+            assert_eq!(gb.save_state(), captured[frame]);
+        }
+        assert_eq!(gb.rewind_depth(), 30);
+
+        // Running on captures new states over the rewound ones.
+        gb.run_frame();
+        assert_eq!(gb.rewind_depth(), 31);
+    }
+
+    #[test]
+    fn state_rejects_other_cartridge() {
+        let state = scroller().save_state();
+
+        let mut rom = rom(&[0x00]);
+        rom[0x014D] = 0x12;
+        let mut other = GameBoy::from_rom(rom).unwrap();
+        assert_eq!(other.load_state(&state), Err(StateError::CartridgeMismatch));
+    }
+
+    #[test]
+    fn on_step_traces_each_instruction() {
+        // LD A,$42 ; HALT
+        let mut gb = gameboy(&[0x3E, 0x42, 0x76]);
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&lines);
+        gb.on_step(move |state| sink.borrow_mut().push(state.to_string()));
+
+        for _ in 0..4 {
+            gb.step();
+        }
+        assert_eq!(
+            *lines.borrow(),
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,76,00",
+                "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:76,00,00,00",
+            ]
+        );
+
+        gb.clear_on_step();
+        gb.reset();
+        gb.step();
+        assert_eq!(lines.borrow().len(), 2);
+    }
+
+    #[test]
+    fn peek_keeps_interrupts_pending() {
+        // LD A,$04 ; LDH ($FF),A ; EI ; NOP
+        let mut gb = gameboy(&[0x3E, 0x04, 0xE0, 0xFF, 0xFB, 0x00]);
+        gb.poke(IF, Interrupt::Timer.bit());
+
+        for _ in 0..3 {
+            gb.step();
+            assert_eq!(gb.peek(IF), 0xE0 | Interrupt::Timer.bit());
+        }
+
+        // The interrupt is taken once EI takes effect.
+        gb.step();
+        gb.step();
+        assert_eq!(gb.cpu().regs.pc, 0x0050);
+        assert_eq!(gb.peek(IF), 0xE0);
+    }
+
+    #[test]
+    fn peek_honors_selected_bank() {
+        let mut rom = test_rom(0x01, [0x02, 0x00]);
+        rom[0x0100..0x0105].copy_from_slice(&[0x3E, 0x03, 0xEA, 0x00, 0x20]);
+        let mut gb = GameBoy::from_rom(rom).unwrap();
+        assert_eq!(gb.peek(0x4000), 0x01);
+
+        // LD A,$03 ; LD ($2000),A
+        gb.step();
+        gb.step();
+        assert_eq!(gb.peek(0x4000), 0x03);
+        assert_eq!(gb.peek(0x7FFF), 0x03);
+    }
+
+    #[test]
+    fn reset_keeps_cartridge() {
+        // LD A,$42 ; LD ($C000),A
+        let mut gb = gameboy(&[0x3E, 0x42, 0xEA, 0x00, 0xC0]);
+        gb.step();
+        gb.step();
+        assert_eq!(gb.mmu_mut().read(0xC000), 0x42);
+
+        gb.reset();
+        assert_eq!(gb.cpu().regs.pc, 0x0100);
+        assert_eq!(gb.mmu_mut().read(0xC000), 0x00);
+        assert_eq!(gb.mmu_mut().read(0x0100), 0x3E);
+    }
+}
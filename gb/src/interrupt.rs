@@ -0,0 +1,65 @@
+//! The interrupt sources of the Game Boy.
+
+/// The address of the interrupt flag register, `IF`.
+pub const IF: u16 = 0xFF0F;
+/// The address of the interrupt enable register, `IE`.
+pub const IE: u16 = 0xFFFF;
+
+/// An interrupt source, in descending order of priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interrupt {
+    /// The PPU entered vertical blanking.
+    VBlank,
+    /// One of the enabled LCD status conditions became true.
+    Stat,
+    /// The timer counter overflowed.
+    Timer,
+    /// A serial transfer completed.
+    Serial,
+    /// A joypad line went low.
+    Joypad,
+}
+
+impl Interrupt {
+    /// Every interrupt source, in descending order of priority.
+    pub const ALL: [Self; 5] = [Self::VBlank, Self::Stat, Self::Timer, Self::Serial, Self::Joypad];
+
+    /// Return the bit of this interrupt in `IE` and `IF`.
+    #[inline]
+    #[must_use]
+    pub const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Return the address the CPU jumps to when servicing this interrupt.
+    #[inline]
+    #[must_use]
+    pub const fn vector(self) -> u16 {
+        0x40 + 8 * self as u16
+    }
+
+    /// Return the highest priority interrupt set in `bits`, if any.
+    #[must_use]
+    pub fn highest(bits: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| bits & kind.bit() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors() {
+        let vectors = Interrupt::ALL.map(Interrupt::vector);
+        assert_eq!(vectors, [0x40, 0x48, 0x50, 0x58, 0x60]);
+    }
+
+    #[test]
+    fn highest_follows_priority() {
+        assert_eq!(Interrupt::highest(0x00), None);
+        assert_eq!(Interrupt::highest(0x1F), Some(Interrupt::VBlank));
+        assert_eq!(Interrupt::highest(0x14), Some(Interrupt::Timer));
+        assert_eq!(Interrupt::highest(0xF0), Some(Interrupt::Joypad));
+    }
+}
@@ -0,0 +1,344 @@
+//! The joypad, read through the `P1` register.
+
+use crate::interrupt::Interrupt;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The address of the joypad register.
+pub const P1: u16 = 0xFF00;
+
+/// The `P1` bit that selects the direction row when clear.
+const SELECT_DIRECTIONS: u8 = 0x10;
+/// The `P1` bit that selects the action row when clear.
+const SELECT_ACTIONS: u8 = 0x20;
+
+/// A joypad button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    /// Right on the d-pad.
+    Right,
+    /// Left on the d-pad.
+    Left,
+    /// Up on the d-pad.
+    Up,
+    /// Down on the d-pad.
+    Down,
+    /// The A button.
+    A,
+    /// The B button.
+    B,
+    /// The Select button.
+    Select,
+    /// The Start button.
+    Start,
+}
+
+impl Button {
+    /// Every button, directions first, in the order of their `P1` lines.
+    pub const ALL: [Self; 8] = [
+        Self::Right,
+        Self::Left,
+        Self::Up,
+        Self::Down,
+        Self::A,
+        Self::B,
+        Self::Select,
+        Self::Start,
+    ];
+
+    /// Return the bit of this button in the pressed set, with the directions
+    /// in the lower nibble and the actions in the upper.
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A set of held buttons, for setting the whole joypad at once.
+///
+/// Each button has one bit, the directions in the lower nibble and the
+/// actions in the upper, both in the order of [`Button::ALL`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(transparent)]
+pub struct ButtonState(u8);
+
+impl ButtonState {
+    /// No buttons held.
+    pub const EMPTY: Self = Self(0);
+
+    /// Create a set from a raw byte, one bit per button.
+    #[must_use]
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    /// Return the raw byte of this set.
+    #[must_use]
+    pub const fn into_bits(self) -> u8 {
+        self.0
+    }
+
+    /// Return this set with `button` held as well.
+    #[must_use]
+    pub const fn with(self, button: Button) -> Self {
+        Self(self.0 | button.bit())
+    }
+
+    /// Check if `button` is held.
+    #[must_use]
+    pub const fn contains(self, button: Button) -> bool {
+        self.0 & button.bit() != 0
+    }
+
+    /// Hold `button`.
+    pub const fn insert(&mut self, button: Button) {
+        self.0 |= button.bit();
+    }
+
+    /// Release `button`.
+    pub const fn remove(&mut self, button: Button) {
+        self.0 &= !button.bit();
+    }
+
+    /// Hold or release `button` depending on `held`.
+    pub const fn set(&mut self, button: Button, held: bool) {
+        if held {
+            self.insert(button);
+        } else {
+            self.remove(button);
+        }
+    }
+}
+
+impl FromIterator<Button> for ButtonState {
+    fn from_iter<I: IntoIterator<Item = Button>>(buttons: I) -> Self {
+        buttons.into_iter().fold(Self::EMPTY, Self::with)
+    }
+}
+
+/// The joypad.
+///
+/// The buttons are wired in a matrix of two rows selected through bits 4
+/// and 5 of `P1`, which read back on the lower nibble. Every line is
+/// active-low, so a pressed button in a selected row reads 0.
+#[derive(Debug)]
+pub struct Joypad {
+    /// The pressed buttons, one bit each as given by [`Button::bit`].
+    pressed: u8,
+    /// The row select bits last written to `P1`.
+    select: u8,
+    interrupts: u8,
+}
+
+impl Joypad {
+    /// Create a joypad with no buttons pressed and no row selected.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            pressed: 0,
+            select: SELECT_DIRECTIONS | SELECT_ACTIONS,
+            interrupts: 0,
+        }
+    }
+
+    /// Check if `button` is held.
+    #[must_use]
+    pub const fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & button.bit() != 0
+    }
+
+    /// Press `button`.
+    pub fn press(&mut self, button: Button) {
+        self.update(|joypad| joypad.pressed |= button.bit());
+    }
+
+    /// Release `button`.
+    pub fn release(&mut self, button: Button) {
+        self.update(|joypad| joypad.pressed &= !button.bit());
+    }
+
+    /// Return the held buttons.
+    #[must_use]
+    pub const fn buttons(&self) -> ButtonState {
+        ButtonState(self.pressed)
+    }
+
+    /// Hold exactly the buttons in `state`, releasing every other.
+    ///
+    /// The change is applied at once, so the interrupt is only requested if
+    /// a line falls between the old and the new state. Buttons a real pad
+    /// can't hold together, like Up and Down, are held as given.
+    pub fn set_buttons(&mut self, state: ButtonState) {
+        self.update(|joypad| joypad.pressed = state.0);
+    }
+
+    /// Write the held buttons and row selection to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.pressed);
+        state.write_u8(self.select);
+        state.write_u8(self.interrupts);
+    }
+
+    /// Restore the state written by [`Joypad::save_state`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is truncated.
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.pressed = state.read_u8()?;
+        self.select = state.read_u8()? & (SELECT_DIRECTIONS | SELECT_ACTIONS);
+        self.interrupts = state.read_u8()?;
+        Ok(())
+    }
+
+    /// Return and clear the interrupts requested since the last call, as
+    /// `IF` bits.
+    pub const fn take_interrupts(&mut self) -> u8 {
+        let interrupts = self.interrupts;
+        self.interrupts = 0;
+        interrupts
+    }
+
+    /// Return the lower nibble of `P1`, the active-low button lines.
+    const fn lines(&self) -> u8 {
+        let mut held = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            held |= self.pressed & 0x0F;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            held |= self.pressed >> 4;
+        }
+
+        !held & 0x0F
+    }
+
+    /// Apply `change`, requesting the interrupt if any line fell.
+    fn update(&mut self, change: impl FnOnce(&mut Self)) {
+        let before = self.lines();
+        change(self);
+
+        if before & !self.lines() != 0 {
+            self.interrupts |= Interrupt::Joypad.bit();
+        }
+    }
+
+    /// Read `P1`.
+    #[must_use]
+    pub const fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    /// Set the row select bits of `P1` without requesting an interrupt, for
+    /// tooling.
+    pub const fn poke(&mut self, value: u8) {
+        self.select = value & (SELECT_DIRECTIONS | SELECT_ACTIONS);
+    }
+
+    /// Write `P1`, of which only the row select bits are writable.
+    pub fn write(&mut self, value: u8) {
+        self.update(|joypad| joypad.select = value & (SELECT_DIRECTIONS | SELECT_ACTIONS));
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unselected_rows_read_high() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::A);
+        joypad.press(Button::Down);
+
+        assert_eq!(joypad.read(), 0xFF);
+        assert_eq!(joypad.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn pressing_a_on_action_row() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x10);
+
+        joypad.press(Button::A);
+        assert_eq!(joypad.read(), 0xDE);
+        assert_eq!(joypad.take_interrupts(), Interrupt::Joypad.bit());
+
+        joypad.release(Button::A);
+        assert_eq!(joypad.read(), 0xDF);
+        assert_eq!(joypad.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn rows_are_independent() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Start);
+        joypad.press(Button::Left);
+
+        joypad.write(0x20);
+        assert_eq!(joypad.read(), 0xED);
+        joypad.write(0x10);
+        assert_eq!(joypad.read(), 0xD7);
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xC5);
+    }
+
+    #[test]
+    fn selecting_a_held_row_interrupts() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Up);
+        assert_eq!(joypad.take_interrupts(), 0);
+
+        joypad.write(0x20);
+        assert_eq!(joypad.take_interrupts(), Interrupt::Joypad.bit());
+    }
+
+    #[test]
+    fn set_buttons_changes_pad_at_once() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x10);
+        joypad.press(Button::A);
+        joypad.take_interrupts();
+
+        // B's line falls as A's rises, which still interrupts.
+        joypad.set_buttons([Button::B, Button::Down].into_iter().collect());
+        assert_eq!(joypad.read(), 0xDD);
+        assert_eq!(joypad.take_interrupts(), Interrupt::Joypad.bit());
+        assert!(!joypad.is_pressed(Button::A));
+        assert!(joypad.buttons().contains(Button::Down));
+
+        joypad.set_buttons(ButtonState::EMPTY.with(Button::Down));
+        assert_eq!(joypad.read(), 0xDF);
+        assert_eq!(joypad.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn opposite_directions_read_as_held() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x20);
+
+        let state = ButtonState::EMPTY.with(Button::Up).with(Button::Down);
+        for _ in 0..3 {
+            joypad.set_buttons(state);
+            assert_eq!(joypad.read(), 0xE3);
+        }
+        assert_eq!(joypad.take_interrupts(), Interrupt::Joypad.bit());
+        assert_eq!(joypad.buttons(), state);
+    }
+
+    #[test]
+    fn pressing_a_held_line_does_not_interrupt() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x00);
+        joypad.press(Button::Right);
+        joypad.take_interrupts();
+
+        // A shares the line with Right.
+        joypad.press(Button::A);
+        assert_eq!(joypad.take_interrupts(), 0);
+        assert!(joypad.is_pressed(Button::A));
+    }
+}
@@ -0,0 +1,45 @@
+//! The Game Boy compatibility core of Liam.
+//!
+//! The GBA carries a Sharp SM83 for running original Game Boy software, this
+//! crate models that processor along with the rest of the Game Boy hardware.
+//!
+//! The crate is `no_std`, needing only `alloc`. The default `std` feature adds
+//! loading cartridges from readers and keeps cartridge clocks in step with
+//! the host time. The `wasm` feature exports the machine to JavaScript
+//! through `wasm-bindgen`.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod cheat;
+pub mod cpu;
+pub mod debugger;
+pub mod gameboy;
+pub mod interrupt;
+pub mod joypad;
+pub mod link;
+pub mod mmu;
+pub mod model;
+pub mod pacer;
+pub mod ppu;
+pub mod profiler;
+pub mod region;
+pub mod rewind;
+pub mod scheduler;
+pub mod serial;
+pub mod sgb;
+pub mod state;
+pub mod test_rom;
+pub mod timer;
+pub mod trace;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use gameboy::GameBoy;
+pub use model::Model;
@@ -0,0 +1,179 @@
+//! Two systems linked in-process through their serial ports.
+//!
+//! A [`LocalLink`] runs two [`GameBoy`]s side by side, keeping them within
+//! an instruction of each other, with a [`LinkCable`] between them. The side
+//! on the internal clock drives each transfer, and the bits it clocks out
+//! reach the other side as external clock edges, so either side can be the
+//! master, as in a two-player game of Tetris or a trade.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+use crate::GameBoy;
+use crate::serial::LinkCable;
+
+/// One side of the wire, as the other side's cable sees it.
+#[derive(Debug, Default)]
+struct Side {
+    /// `SB` and the bits shifted, while the side waits on the external
+    /// clock, updated as the partner clocks it.
+    waiting: Option<(u8, u8)>,
+    /// The bits clocked in by the partner, not yet shifted into the port.
+    incoming: VecDeque<bool>,
+}
+
+/// The state shared between the two ends of the cable.
+type Wire = Rc<RefCell<[Side; 2]>>;
+
+/// The end of the cable plugged into one side.
+struct LinkEnd {
+    wire: Wire,
+    /// The side the end is plugged into, 0 or 1.
+    side: usize,
+}
+
+impl LinkCable for LinkEnd {
+    fn exchange(&mut self, bit: bool) -> bool {
+        let mut wire = self.wire.borrow_mut();
+        let partner = &mut wire[1 - self.side];
+        let Some((sb, bits)) = partner.waiting else {
+            return true;
+        };
+
+        partner.incoming.push_back(bit);
+        partner.waiting = (bits < 7).then_some((sb << 1 | u8::from(bit), bits + 1));
+        sb & 0x80 != 0
+    }
+}
+
+/// Two systems connected by a link cable.
+///
+/// Step them together with [`LocalLink::step`] or [`LocalLink::run_cycles`]
+/// rather than on their own, so their serial clocks stay in step. Each
+/// step runs an instruction on the system that's behind.
+#[derive(Debug)]
+pub struct LocalLink {
+    gameboys: [GameBoy; 2],
+    wire: Wire,
+    /// The cycles each system has run.
+    cycles: [u64; 2],
+}
+
+impl LocalLink {
+    /// Connect `first` and `second`, replacing any cables on their serial
+    /// ports.
+    #[must_use]
+    pub fn new(first: GameBoy, second: GameBoy) -> Self {
+        let wire = Wire::default();
+        let mut gameboys = [first, second];
+        for (side, gb) in gameboys.iter_mut().enumerate() {
+            let end = LinkEnd { wire: Rc::clone(&wire), side };
+            gb.mmu_mut().serial_mut().set_cable(Some(Box::new(end)));
+        }
+
+        let mut link = Self { gameboys, wire, cycles: [0; 2] };
+        link.sync();
+        link
+    }
+
+    /// Return the two systems.
+    #[must_use]
+    pub const fn gameboys(&self) -> &[GameBoy; 2] {
+        &self.gameboys
+    }
+
+    /// Return the two systems mutably.
+    pub const fn gameboys_mut(&mut self) -> &mut [GameBoy; 2] {
+        &mut self.gameboys
+    }
+
+    /// Disconnect the systems and return them.
+    #[must_use]
+    pub fn into_inner(self) -> [GameBoy; 2] {
+        let mut gameboys = self.gameboys;
+        for gb in &mut gameboys {
+            gb.mmu_mut().serial_mut().set_cable(None);
+        }
+        gameboys
+    }
+
+    /// Run one step of the system that's behind, and return its cycles.
+    pub fn step(&mut self) -> u8 {
+        let side = usize::from(self.cycles[1] < self.cycles[0]);
+        let cycles = self.gameboys[side].step();
+        self.cycles[side] += u64::from(cycles);
+        self.sync();
+        cycles
+    }
+
+    /// Step until both systems have run at least `budget` cycles more, and
+    /// return the cycles the first ran.
+    pub fn run_cycles(&mut self, budget: u64) -> u64 {
+        let start = self.cycles;
+        while self.cycles.iter().zip(start).any(|(&ran, start)| ran - start < budget) {
+            self.step();
+        }
+        self.cycles[0] - start[0]
+    }
+
+    /// Shift the bits clocked by each partner into the other's port, and
+    /// publish the transfers waiting on the external clock.
+    fn sync(&mut self) {
+        let mut wire = self.wire.borrow_mut();
+        for (side, gb) in wire.iter_mut().zip(&mut self.gameboys) {
+            let serial = gb.mmu_mut().serial_mut();
+            for bit in side.incoming.drain(..) {
+                serial.clock_external(bit);
+            }
+            side.waiting = serial.external_transfer();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::interrupt::{IF, Interrupt};
+    use crate::serial::SB;
+
+    /// Return a system that sends `byte` on the clock selected by `sc`.
+    fn sender(byte: u8, sc: u8) -> GameBoy {
+        // LD A,byte ; LDH (SB),A ; LD A,sc ; LDH (SC),A ; JR -2
+        let program = [0x3E, byte, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE];
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(&program);
+        GameBoy::from_rom(rom).unwrap()
+    }
+
+    #[test]
+    fn exchanges_a_byte() {
+        // Either side can drive the clock.
+        for (sc_first, sc_second) in [(0x81, 0x80), (0x80, 0x81)] {
+            let mut link = LocalLink::new(sender(0x42, sc_first), sender(0x99, sc_second));
+
+            // Eight bits at 8192 Hz, and a little more for the programs.
+            link.run_cycles(8 * 512 + 100);
+            let [first, second] = link.into_inner();
+            assert_eq!(first.peek(SB), 0x99);
+            assert_eq!(second.peek(SB), 0x42);
+            for gb in [&first, &second] {
+                assert_ne!(gb.peek(IF) & Interrupt::Serial.bit(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn master_alone_reads_ones() {
+        let mut link = LocalLink::new(sender(0x42, 0x81), sender(0x99, 0x00));
+        link.run_cycles(8 * 512 + 100);
+
+        let [first, second] = link.gameboys();
+        assert_eq!(first.peek(SB), 0xFF);
+        assert_eq!(second.peek(SB), 0x99);
+        assert_eq!(second.peek(IF) & Interrupt::Serial.bit(), 0);
+    }
+}
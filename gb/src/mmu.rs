@@ -0,0 +1,1628 @@
+//! The memory management unit, which decodes the Game Boy address space.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::apu::{Apu, Channel, NR52};
+use crate::bus::Bus;
+use crate::cartridge::{Cartridge, CgbSupport};
+use crate::interrupt::{IE, IF};
+use crate::joypad::{Joypad, P1};
+use crate::model::Model;
+use crate::ppu::{BCPS, LCDC, Layer, Mode, OCPD, OamCorruption, Ppu, VBK};
+use crate::region::Region;
+use crate::scheduler::{Event, Scheduler};
+use crate::serial::Serial;
+use crate::sgb::Sgb;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::timer::Timer;
+#[cfg(feature = "trace")]
+use crate::trace::{AccessKind, MmuTracer};
+
+/// The address of the OAM DMA source register.
+pub const DMA: u16 = 0xFF46;
+
+/// The address of the CGB speed switch register.
+pub const KEY1: u16 = 0xFF4D;
+
+/// The address of the register that unmaps the boot ROM.
+pub const BOOT: u16 = 0xFF50;
+
+/// The address of the high byte of the CGB VRAM DMA source.
+pub const HDMA1: u16 = 0xFF51;
+/// The address of the low byte of the CGB VRAM DMA source.
+pub const HDMA2: u16 = 0xFF52;
+/// The address of the high byte of the CGB VRAM DMA destination.
+pub const HDMA3: u16 = 0xFF53;
+/// The address of the low byte of the CGB VRAM DMA destination.
+pub const HDMA4: u16 = 0xFF54;
+/// The address of the CGB VRAM DMA length, mode and start register.
+pub const HDMA5: u16 = 0xFF55;
+
+/// The address of the CGB WRAM bank register.
+pub const SVBK: u16 = 0xFF70;
+
+/// The size of the CGB boot ROM, which puts the system in CGB mode.
+pub(crate) const CGB_BOOT_SIZE: usize = 0x900;
+
+/// The number of bytes copied by an OAM DMA transfer.
+const DMA_LEN: u8 = 0xA0;
+
+/// The dots the CPU is stalled for by each 16-byte VRAM DMA block.
+const HDMA_BLOCK_DOTS: u16 = 32;
+
+/// The size of a WRAM bank.
+const WRAM_BANK_SIZE: usize = 0x1000;
+
+/// A hook on reads of an address, called with the address and the byte read
+/// and returning the byte the CPU sees.
+type ReadHook = Box<dyn FnMut(u16, u8) -> u8>;
+
+/// A hook on writes to an address, called with the address and the byte
+/// written and returning the byte to write, or `None` to drop the write.
+type WriteHook = Box<dyn FnMut(u16, u8) -> Option<u8>>;
+
+/// The access hooks, in the order they were added.
+#[derive(Default)]
+struct Hooks {
+    read: Vec<(u16, ReadHook)>,
+    write: Vec<(u16, WriteHook)>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let read: Vec<u16> = self.read.iter().map(|&(addr, _)| addr).collect();
+        let write: Vec<u16> = self.write.iter().map(|&(addr, _)| addr).collect();
+        f.debug_struct("Hooks")
+            .field("read", &read)
+            .field("write", &write)
+            .finish()
+    }
+}
+
+/// What reads of the prohibited region at `0xFEA0-0xFEFF` return, which
+/// differs between hardware revisions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ProhibitedReads {
+    /// `0x00` while the PPU holds OAM in modes 2 and 3, and otherwise the
+    /// upper nibble of the address's low byte twice, such as `0xAA` from
+    /// `0xFEA0-0xFEAF`. The most common behavior, as on the DMG.
+    #[default]
+    Dmg,
+    /// The upper nibble of the address's low byte twice in every mode, as
+    /// on later CGB revisions.
+    Cgb,
+}
+
+impl ProhibitedReads {
+    /// Return what a read of `addr` returns while the PPU is in `mode`.
+    const fn read(self, addr: u16, mode: Mode) -> u8 {
+        let [lo, _] = addr.to_le_bytes();
+        let nibble = lo >> 4;
+        match (self, mode) {
+            (Self::Dmg, Mode::OamScan | Mode::Drawing) => 0x00,
+            _ => nibble << 4 | nibble,
+        }
+    }
+}
+
+/// The memory management unit.
+///
+/// Routes every CPU access to the component backing that address:
+///
+/// | Range           | Target                  |
+/// |-----------------|-------------------------|
+/// | `0x0000-0x7FFF` | Cartridge ROM           |
+/// | `0x8000-0x9FFF` | VRAM, in the PPU        |
+/// | `0xA000-0xBFFF` | Cartridge RAM           |
+/// | `0xC000-0xDFFF` | WRAM                    |
+/// | `0xE000-0xFDFF` | Echo of `0xC000-0xDDFF` |
+/// | `0xFE00-0xFE9F` | OAM, in the PPU         |
+/// | `0xFEA0-0xFEFF` | Prohibited              |
+/// | `0xFF00-0xFF7F` | I/O registers           |
+/// | `0xFF80-0xFFFE` | HRAM                    |
+/// | `0xFFFF`        | `IE`                    |
+///
+/// A boot ROM, if present, is mapped over `0x0000-0x00FF` until `BOOT` is
+/// written with bit 0 set. The 2304-byte CGB boot ROM also covers
+/// `0x0200-0x08FF`, leaving the cartridge header visible in between, and
+/// puts the system in CGB mode.
+///
+/// In CGB mode VRAM is banked through `VBK`, and `0xD000-0xDFFF` through
+/// `SVBK` to one of WRAM banks 1 to 7. `0xC000-0xCFFF` always holds bank 0.
+///
+/// CGB VRAM DMA copies 16-byte blocks from `HDMA1-2` to VRAM at `HDMA3-4`.
+/// Writing `HDMA5` with bit 7 clear copies every block at once, while bit 7
+/// set copies a block each horizontal blank, until `HDMA5` is written with
+/// bit 7 clear. The CPU is stalled while a block is copied.
+///
+/// Cheat engines can hook CPU accesses to single addresses, see
+/// [`Mmu::add_read_hook`] and [`Mmu::add_write_hook`].
+///
+/// What reads of the prohibited region return depends on the hardware
+/// revision and the PPU mode, see [`ProhibitedReads`].
+///
+/// While an OAM DMA transfer runs, the CPU can only reach `0xFF00-0xFFFF`.
+/// Reads from anywhere else return `0xFF` and writes are dropped.
+///
+/// In CGB double-speed mode the CPU, timer and serial port run twice as
+/// fast, while the PPU, OAM DMA and APU keep to the normal clock. `KEY1`
+/// reports the speed in bit 7, and arms a switch through bit 0.
+///
+/// Each tick is split at the events of a [`Scheduler`], so PPU mode
+/// changes, timer reloads, frame sequencer steps and the end of OAM DMA are
+/// handled on their exact cycle.
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Mmu {
+    hooks: Hooks,
+    #[cfg(feature = "trace")]
+    tracer: Option<MmuTracer>,
+    cartridge: Cartridge,
+    cgb: bool,
+    model: Model,
+    prohibited_reads: ProhibitedReads,
+    boot_rom: Option<Box<[u8]>>,
+    /// Whether the boot ROM is still mapped over the cartridge.
+    boot_mapped: bool,
+    ppu: Ppu,
+    timer: Timer,
+    joypad: Joypad,
+    serial: Serial,
+    sgb: Sgb,
+    apu: Apu,
+    wram: Box<[u8]>,
+    io: Box<[u8]>,
+    hram: Box<[u8]>,
+    ie: u8,
+    /// The last value written to `DMA`, the high byte of the source.
+    dma: u8,
+    /// The index of the next byte to copy, while a DMA transfer runs.
+    dma_index: Option<u8>,
+    /// The T-cycles towards the next DMA copy.
+    dma_cycles: u8,
+    /// Whether the CPU runs at double speed, as `KEY1` reports it.
+    double_speed: bool,
+    /// The source of the next VRAM DMA copy.
+    hdma_source: u16,
+    /// The offset into VRAM of the next VRAM DMA copy.
+    hdma_dest: u16,
+    /// The VRAM DMA blocks left minus one, as `HDMA5` reports them.
+    hdma_len: u8,
+    /// Whether an H-blank VRAM DMA transfer is running.
+    hdma_active: bool,
+    /// The dots left that the CPU is stalled for by VRAM DMA.
+    hdma_stall: u16,
+    /// The next event of each component, on the CPU clock.
+    scheduler: Scheduler,
+    /// Whether the CPU cycles run in double-speed mode left half a cycle of
+    /// the normal clock over.
+    half_cycle: bool,
+}
+
+impl Mmu {
+    /// Create a memory map around `cartridge`.
+    #[must_use]
+    pub fn new(cartridge: Cartridge) -> Self {
+        let mut mmu = Self {
+            hooks: Hooks::default(),
+            #[cfg(feature = "trace")]
+            tracer: None,
+            cartridge,
+            cgb: false,
+            model: Model::Dmg,
+            prohibited_reads: ProhibitedReads::Dmg,
+            boot_rom: None,
+            boot_mapped: false,
+            ppu: Ppu::new(),
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            sgb: Sgb::new(),
+            apu: Apu::new(),
+            wram: vec![0; 8 * WRAM_BANK_SIZE].into_boxed_slice(),
+            io: vec![0; 0x80].into_boxed_slice(),
+            hram: vec![0; 0x7F].into_boxed_slice(),
+            ie: 0,
+            dma: 0xFF,
+            dma_index: None,
+            dma_cycles: 0,
+            double_speed: false,
+            hdma_source: 0,
+            hdma_dest: 0,
+            hdma_len: 0x7F,
+            hdma_active: false,
+            hdma_stall: 0,
+            scheduler: Scheduler::new(),
+            half_cycle: false,
+        };
+        mmu.reschedule();
+        mmu
+    }
+
+    /// Create a CGB-mode memory map around `cartridge`.
+    #[must_use]
+    pub fn new_cgb(cartridge: Cartridge) -> Self {
+        let mut mmu = Self {
+            cgb: true,
+            model: Model::Cgb,
+            prohibited_reads: ProhibitedReads::Cgb,
+            ppu: Ppu::new_cgb(),
+            apu: Apu::new_cgb(),
+            ..Self::new(cartridge)
+        };
+        mmu.reschedule();
+        mmu
+    }
+
+    /// Create a memory map around `cartridge` for `model`.
+    ///
+    /// A CGB cartridge runs in CGB mode on the models with CGB hardware.
+    /// Everything else runs as on DMG, with the quirks of `model`.
+    #[must_use]
+    pub fn with_model(cartridge: Cartridge, model: Model) -> Self {
+        let cgb = model.is_cgb() && cartridge.header().cgb != CgbSupport::None;
+        let mut mmu = if cgb { Self::new_cgb(cartridge) } else { Self::new(cartridge) };
+        mmu.model = model;
+        mmu.prohibited_reads = model.prohibited_reads();
+        mmu.ppu.set_stat_write_bug(model.has_stat_write_bug());
+        mmu.ppu.set_oam_bug(model.has_oam_bug());
+        mmu.apu.set_lengths_powered(model.has_powered_lengths());
+        mmu
+    }
+
+    /// Create a memory map around `cartridge`, with `boot_rom` mapped over
+    /// it until it's unmapped through `BOOT`.
+    ///
+    /// A 2304-byte boot ROM is taken as the CGB one, in CGB mode.
+    #[must_use]
+    pub fn with_boot_rom(cartridge: Cartridge, boot_rom: Vec<u8>) -> Self {
+        let mmu = if boot_rom.len() == CGB_BOOT_SIZE {
+            Self::new_cgb(cartridge)
+        } else {
+            Self::new(cartridge)
+        };
+
+        Self {
+            boot_rom: Some(boot_rom.into_boxed_slice()),
+            boot_mapped: true,
+            ..mmu
+        }
+    }
+
+    /// Check if the system is in CGB mode.
+    #[must_use]
+    pub const fn is_cgb(&self) -> bool {
+        self.cgb
+    }
+
+    /// Return the hardware model the memory map was created for.
+    #[must_use]
+    pub const fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Return what reads of the prohibited region return.
+    #[must_use]
+    pub const fn prohibited_reads(&self) -> ProhibitedReads {
+        self.prohibited_reads
+    }
+
+    /// Set what reads of the prohibited region return, to match a hardware
+    /// revision. The DMG behavior is the default, and the CGB one in CGB
+    /// mode.
+    pub const fn set_prohibited_reads(&mut self, reads: ProhibitedReads) {
+        self.prohibited_reads = reads;
+    }
+
+    /// Check if the boot ROM is mapped over the cartridge.
+    #[must_use]
+    pub const fn is_boot_rom_mapped(&self) -> bool {
+        self.boot_mapped
+    }
+
+    /// Return the region of the address space `addr` falls in, such as the
+    /// I/O register behind it, without touching the memory there.
+    #[must_use]
+    pub fn region(&self, addr: u16) -> Region {
+        if self.boot_rom(addr).is_some() { Region::BootRom } else { Region::of(addr) }
+    }
+
+    /// Return the index into WRAM behind `offset` from `0xC000`.
+    ///
+    /// Bank 0 is read as bank 1 through `SVBK`, and outside CGB mode the
+    /// banked half is always bank 1.
+    fn wram_index(&self, offset: usize) -> usize {
+        if offset < WRAM_BANK_SIZE {
+            return offset;
+        }
+
+        let svbk = self.io[usize::from(SVBK - 0xFF00)];
+        let bank = if self.cgb { usize::from(svbk).max(1) } else { 1 };
+        bank * WRAM_BANK_SIZE + offset - WRAM_BANK_SIZE
+    }
+
+    /// Return a byte of the boot ROM, if it's mapped over `addr`.
+    fn boot_rom(&self, addr: u16) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref().filter(|_| self.boot_mapped)?;
+
+        match addr {
+            0x0000..=0x00FF | 0x0200..=0x08FF => boot_rom.get(usize::from(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    /// Return every component but the cartridge and the serial sink to its
+    /// power-on state, mapping the boot ROM again if there is one.
+    pub fn reset(&mut self) {
+        self.boot_mapped = self.boot_rom.is_some();
+        // The colors, hidden layers, fast-forwarding, frame skipping and
+        // blending are the frontend's choice, not machine state.
+        let palette = self.ppu.palette();
+        let compat_palette = self.ppu.compat_palette();
+        let layers = Layer::ALL.map(|layer| (layer, self.ppu.is_layer_enabled(layer)));
+        let fast_forward = self.ppu.is_fast_forward();
+        let frame_skip = self.ppu.frame_skip();
+        let frame_blending = self.ppu.is_frame_blending();
+        self.ppu = if self.cgb { Ppu::new_cgb() } else { Ppu::new() };
+        self.ppu.set_stat_write_bug(self.model.has_stat_write_bug());
+        self.ppu.set_oam_bug(self.model.has_oam_bug());
+        self.ppu.set_palette(palette);
+        self.ppu.set_compat_palette(compat_palette);
+        for (layer, enabled) in layers {
+            self.ppu.set_layer_enabled(layer, enabled);
+        }
+        self.ppu.set_fast_forward(fast_forward);
+        self.ppu.set_frame_skip(frame_skip);
+        self.ppu.set_frame_blending(frame_blending);
+        self.timer = Timer::new();
+        self.joypad = Joypad::new();
+        self.serial.reset();
+        self.sgb = Sgb::new();
+        let channels = Channel::ALL.map(|channel| (channel, self.apu.is_channel_enabled(channel)));
+        self.apu = if self.cgb { Apu::new_cgb() } else { Apu::new() };
+        self.apu.set_lengths_powered(self.model.has_powered_lengths());
+        for (channel, enabled) in channels {
+            self.apu.set_channel_enabled(channel, enabled);
+        }
+        self.apu.set_fast_forward(fast_forward);
+        self.wram.fill(0);
+        self.io.fill(0);
+        self.hram.fill(0);
+        self.ie = 0;
+        self.dma = 0xFF;
+        self.dma_index = None;
+        self.dma_cycles = 0;
+        self.double_speed = false;
+        self.hdma_source = 0;
+        self.hdma_dest = 0;
+        self.hdma_len = 0x7F;
+        self.hdma_active = false;
+        self.hdma_stall = 0;
+        self.half_cycle = false;
+        self.reschedule();
+    }
+
+    /// Write every component on the bus to a save state.
+    ///
+    /// The boot ROM itself is left out, like the cartridge ROM, but whether
+    /// it's still mapped is kept.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.cartridge.save_state(state);
+        state.write_bool(self.boot_mapped);
+        self.ppu.save_state(state);
+        self.timer.save_state(state);
+        self.joypad.save_state(state);
+        self.serial.save_state(state);
+        self.apu.save_state(state);
+
+        state.write_bytes(&self.wram);
+        state.write_bytes(&self.io);
+        state.write_bytes(&self.hram);
+        state.write_u8(self.ie);
+
+        state.write_u8(self.dma);
+        state.write_u8(self.dma_index.unwrap_or(DMA_LEN));
+        state.write_u8(self.dma_cycles);
+
+        state.write_u16(self.hdma_source);
+        state.write_u16(self.hdma_dest);
+        state.write_u8(self.hdma_len);
+        state.write_bool(self.hdma_active);
+        state.write_u16(self.hdma_stall);
+    }
+
+    /// Restore the state written by [`Mmu::save_state`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state was saved with another cartridge, or
+    /// is truncated or corrupt.
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.cartridge.load_state(state)?;
+        self.boot_mapped = state.read_bool()? && self.boot_rom.is_some();
+        self.ppu.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.apu.load_state(state)?;
+
+        state.read_bytes(&mut self.wram)?;
+        state.read_bytes(&mut self.io)?;
+        self.io[usize::from(SVBK - 0xFF00)] &= 0x07;
+        state.read_bytes(&mut self.hram)?;
+        self.ie = state.read_u8()?;
+
+        self.dma = state.read_u8()?;
+        let index = state.read_u8()?;
+        self.dma_index = (index < DMA_LEN).then_some(index);
+        self.dma_cycles = state.read_u8()? & 0x03;
+
+        self.hdma_source = state.read_u16()?;
+        self.hdma_dest = state.read_u16()? & 0x1FFF;
+        self.hdma_len = state.read_u8()? & 0x7F;
+        self.hdma_active = state.read_bool()?;
+        self.hdma_stall = state.read_u16()?;
+        self.half_cycle = false;
+        self.reschedule();
+        Ok(())
+    }
+
+    /// Return the inserted cartridge.
+    #[must_use]
+    pub const fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    /// Return the inserted cartridge mutably.
+    pub const fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    /// Return the PPU.
+    #[must_use]
+    pub const fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    /// Return the PPU mutably.
+    pub const fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    /// Return the timer.
+    #[must_use]
+    pub const fn timer(&self) -> &Timer {
+        &self.timer
+    }
+
+    /// Return the joypad.
+    #[must_use]
+    pub const fn joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    /// Return the joypad mutably.
+    pub const fn joypad_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad
+    }
+
+    /// Return the Super Game Boy packet receiver.
+    #[must_use]
+    pub const fn sgb(&self) -> &Sgb {
+        &self.sgb
+    }
+
+    /// Return the Super Game Boy packet receiver mutably, for taking the
+    /// commands received.
+    pub const fn sgb_mut(&mut self) -> &mut Sgb {
+        &mut self.sgb
+    }
+
+    /// Return the APU.
+    #[must_use]
+    pub const fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// Return the APU mutably.
+    pub const fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    /// Return the serial port.
+    #[must_use]
+    pub const fn serial(&self) -> &Serial {
+        &self.serial
+    }
+
+    /// Return the serial port mutably.
+    pub const fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    /// Check if the CPU runs at double speed, as `KEY1` reports it.
+    #[must_use]
+    pub const fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Set whether the CPU runs at double speed, following a speed switch.
+    ///
+    /// The T-cycles passed to [`Mmu::tick`] are then CPU cycles at twice the
+    /// normal rate.
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        if self.double_speed != double_speed {
+            self.double_speed = double_speed;
+            self.half_cycle = false;
+            self.reschedule();
+        }
+    }
+
+    /// Check if the CPU is stalled by a VRAM DMA transfer, and should idle
+    /// instead of running instructions.
+    #[must_use]
+    pub const fn is_cpu_stalled(&self) -> bool {
+        self.hdma_stall > 0
+    }
+
+    /// Check if an OAM DMA transfer is running.
+    #[must_use]
+    pub const fn is_dma_active(&self) -> bool {
+        self.dma_index.is_some()
+    }
+
+    /// Return the scheduler holding the next event of each component.
+    #[must_use]
+    pub const fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    /// Advance the components on the bus by `cycles` CPU T-cycles.
+    ///
+    /// The cycles are split at each event due within them, which is handled
+    /// on its exact cycle with every component caught up to it.
+    pub fn tick(&mut self, cycles: u8) {
+        let normal = if self.double_speed { cycles / 2 } else { cycles };
+        self.hdma_stall = self.hdma_stall.saturating_sub(u16::from(normal));
+
+        let mut left = cycles;
+        while let Some(until) = self
+            .scheduler
+            .until_next()
+            .and_then(|until| u8::try_from(until).ok())
+            .filter(|&until| until <= left)
+        {
+            self.advance(until);
+            left -= until;
+            while let Some((_, event)) = self.scheduler.pop_due() {
+                self.handle(event);
+            }
+        }
+        self.advance(left);
+
+        let interrupts = self.ppu.take_interrupts()
+            | self.timer.take_interrupts()
+            | self.joypad.take_interrupts()
+            | self.serial.take_interrupts();
+        self.io[usize::from(IF - 0xFF00)] |= interrupts;
+    }
+
+    /// Run `hook` on every CPU read of `addr`, with the address and the byte
+    /// read, returning the byte the CPU sees instead.
+    ///
+    /// Hooks on the same address run in the order they were added, each
+    /// seeing the byte returned by the last. They don't run for DMA, nor for
+    /// [`Bus::peek`], so debuggers still see the memory underneath. Hooks are
+    /// kept across [`Mmu::reset`].
+    pub fn add_read_hook(&mut self, addr: u16, hook: impl FnMut(u16, u8) -> u8 + 'static) {
+        self.hooks.read.push((addr, Box::new(hook)));
+    }
+
+    /// Run `hook` on every CPU write to `addr`, with the address and the byte
+    /// written, returning the byte to write or `None` to drop the write.
+    ///
+    /// Hooks on the same address run in the order they were added, and a
+    /// dropped write skips the hooks after it. Like read hooks, they don't
+    /// run for [`Mmu::poke`].
+    pub fn add_write_hook(
+        &mut self,
+        addr: u16,
+        hook: impl FnMut(u16, u8) -> Option<u8> + 'static,
+    ) {
+        self.hooks.write.push((addr, Box::new(hook)));
+    }
+
+    /// Remove every read and write hook.
+    pub fn clear_hooks(&mut self) {
+        self.hooks.read.clear();
+        self.hooks.write.clear();
+    }
+
+    /// Pass every CPU access to the bus to `tracer`, or stop tracing with
+    /// `None`. The tracer is kept across [`Mmu::reset`].
+    #[cfg(feature = "trace")]
+    pub fn set_tracer(&mut self, tracer: Option<MmuTracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Tell the tracer the address of the instruction about to run.
+    #[cfg(feature = "trace")]
+    pub(crate) const fn set_trace_pc(&mut self, pc: u16) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.set_pc(pc);
+        }
+    }
+
+    /// Pass an access to the tracer, if there is one.
+    #[cfg(feature = "trace")]
+    fn trace(&mut self, kind: AccessKind, addr: u16, value: u8) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(kind, addr, value);
+        }
+    }
+
+    /// Set the byte behind `addr` without any side effects, for tooling.
+    ///
+    /// This bypasses DMA conflicts and the side effects of I/O registers,
+    /// such as `DIV` resetting or `DMA` starting a transfer. The ROM region
+    /// can't be poked, as writes there only drive the cartridge controller,
+    /// and cartridge RAM is written through the selected bank while RAM is
+    /// enabled.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x7FFF | 0xFEA0..=0xFEFF | BOOT => {}
+            0x8000..=0x9FFF
+            | 0xFE00..=0xFE9F
+            | 0xFF40..=0xFF45
+            | 0xFF47..=0xFF4B
+            | VBK
+            | BCPS..=OCPD => {
+                self.ppu.poke(addr, value);
+            }
+            P1 => self.joypad.poke(value),
+            0xFF01..=0xFF02 => self.serial.poke(addr, value),
+            0xFF10..=0xFF3F => self.apu.poke(addr, value),
+            0xFF04..=0xFF07 => self.timer.poke(addr, value),
+            DMA => self.dma = value,
+            HDMA5 if self.cgb => self.hdma_len = value & 0x7F,
+            _ => self.store(addr, value),
+        }
+        self.reschedule();
+    }
+
+    /// Write `value` to `addr` in RAM bank `bank`, rather than the bank
+    /// mapped there, as `GameShark` codes can.
+    ///
+    /// The bank is the cartridge RAM bank at `0xA000-0xBFFF`, or in CGB mode
+    /// the WRAM bank at `0xD000-0xDFFF`, where bank 0 is bank 1 as through
+    /// `SVBK`. Elsewhere this is the same as [`Mmu::poke`].
+    pub fn poke_bank(&mut self, bank: u8, addr: u16, value: u8) {
+        match addr {
+            0xA000..=0xBFFF => self.cartridge.poke_ram_bank(bank, addr, value),
+            0xD000..=0xDFFF if self.cgb => {
+                let bank = usize::from(bank & 0x07).max(1);
+                self.wram[bank * WRAM_BANK_SIZE + usize::from(addr & 0x0FFF)] = value;
+            }
+            _ => self.poke(addr, value),
+        }
+    }
+
+    /// Advance every component by `cycles` CPU T-cycles, up to the next
+    /// event at most.
+    fn advance(&mut self, cycles: u8) {
+        // The timer and serial port run off the CPU clock, while the rest
+        // only sees half the cycles in double-speed mode.
+        let normal = if self.double_speed {
+            let odd = cycles % 2 == 1;
+            let normal = cycles / 2 + u8::from(self.half_cycle && odd);
+            self.half_cycle ^= odd;
+            normal
+        } else {
+            cycles
+        };
+
+        self.tick_dma(normal, true);
+        self.ppu.tick(normal);
+        self.timer.advance(cycles);
+        self.serial.tick(cycles);
+        self.apu.advance(normal);
+        self.scheduler.advance(u64::from(cycles));
+    }
+
+    /// Handle `event`, due on the current cycle, and post the next one.
+    // Events are rare next to ticks, so this is kept out of the tick loop.
+    #[inline(never)]
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::PpuMode => {
+                if self.ppu.take_hblank_started() && self.hdma_active && self.copy_hdma_block() {
+                    self.hdma_active = false;
+                }
+            }
+            Event::TimerOverflow => self.timer.reload(),
+            Event::FrameSequencer => self.apu.step_sequencer(),
+            Event::DmaComplete => self.tick_dma(0, false),
+        }
+        self.schedule(event);
+    }
+
+    /// Replace the pending `event`, if any, with the next one its component
+    /// predicts.
+    // Kept out of `store`, where it's rare next to the writes around it.
+    #[inline(never)]
+    fn post(&mut self, event: Event) {
+        self.scheduler.cancel(event);
+        self.schedule(event);
+    }
+
+    /// Schedule the next `event` its component predicts, if any.
+    fn schedule(&mut self, event: Event) {
+        let cycles = match event {
+            Event::PpuMode => self.ppu.dots_until_mode_change().map(|dots| self.cpu_cycles(dots)),
+            Event::TimerOverflow => self.timer.cycles_until_reload().map(u64::from),
+            Event::FrameSequencer => {
+                self.apu.cycles_until_sequencer().map(|cycles| self.cpu_cycles(cycles))
+            }
+            Event::DmaComplete => self.dma_index.map(|index| {
+                let cycles = 4 * u32::from(DMA_LEN - index) - u32::from(self.dma_cycles);
+                self.cpu_cycles(cycles)
+            }),
+        };
+
+        if let Some(cycles) = cycles {
+            self.scheduler.schedule_in(cycles, event);
+        }
+    }
+
+    /// Post the next event of every component, after changes the scheduled
+    /// ones can't have foreseen.
+    fn reschedule(&mut self) {
+        for event in Event::ALL {
+            self.post(event);
+        }
+    }
+
+    /// Return the CPU T-cycles it takes the normal clock to run `cycles`.
+    fn cpu_cycles(&self, cycles: u32) -> u64 {
+        if self.double_speed {
+            (2 * u64::from(cycles)).saturating_sub(u64::from(self.half_cycle))
+        } else {
+            u64::from(cycles)
+        }
+    }
+
+    /// Copy a byte of an OAM DMA transfer every M-cycle, holding the last
+    /// one for [`Event::DmaComplete`] if it's due at the end and `hold` is
+    /// set.
+    fn tick_dma(&mut self, cycles: u8, hold: bool) {
+        let Some(mut index) = self.dma_index else {
+            return;
+        };
+
+        self.dma_cycles += cycles;
+        while index < DMA_LEN
+            && (self.dma_cycles >= 8 || (self.dma_cycles >= 4 && !(hold && index == DMA_LEN - 1)))
+        {
+            self.dma_cycles -= 4;
+
+            let value = self.load(u16::from_be_bytes([self.dma, index]));
+            self.ppu.write(0xFE00 | u16::from(index), value);
+            index += 1;
+        }
+
+        self.dma_index = (index < DMA_LEN).then_some(index);
+        if self.dma_index.is_none() {
+            // Left over from the last byte, so a save state taken after the
+            // transfer doesn't carry them.
+            self.dma_cycles = 0;
+        }
+    }
+
+    /// Write `HDMA5`, starting or cancelling a VRAM DMA transfer.
+    fn write_hdma5(&mut self, value: u8) {
+        if self.hdma_active && value & 0x80 == 0 {
+            // The remaining length stays readable.
+            self.hdma_active = false;
+            return;
+        }
+
+        self.hdma_len = value & 0x7F;
+        if value & 0x80 != 0 {
+            self.hdma_active = true;
+        } else {
+            while !self.copy_hdma_block() {}
+        }
+    }
+
+    /// Copy a 16-byte VRAM DMA block, stalling the CPU, and return whether
+    /// it was the last one.
+    fn copy_hdma_block(&mut self) -> bool {
+        for _ in 0..0x10 {
+            let value = self.load(self.hdma_source);
+            self.ppu.write(0x8000 | self.hdma_dest, value);
+            self.hdma_source = self.hdma_source.wrapping_add(1);
+            self.hdma_dest = (self.hdma_dest + 1) & 0x1FFF;
+        }
+
+        self.hdma_stall += HDMA_BLOCK_DOTS;
+        self.hdma_len = self.hdma_len.wrapping_sub(1) & 0x7F;
+        self.hdma_len == 0x7F
+    }
+
+    /// Read a byte as seen without any DMA conflicts.
+    fn load(&self, addr: u16) -> u8 {
+        let index = usize::from(addr);
+
+        match addr {
+            0x0000..=0x7FFF => self.boot_rom(addr).unwrap_or_else(|| self.cartridge.read_rom(addr)),
+            0x8000..=0x9FFF
+            | 0xFE00..=0xFE9F
+            | 0xFF40..=0xFF45
+            | 0xFF47..=0xFF4B
+            | VBK
+            | BCPS..=OCPD => {
+                self.ppu.read(addr)
+            }
+            0xA000..=0xBFFF => self.cartridge.read_ram(addr),
+            0xC000..=0xDFFF => self.wram[self.wram_index(index - 0xC000)],
+            0xE000..=0xFDFF => self.wram[self.wram_index(index - 0xE000)],
+            0xFEA0..=0xFEFF => self.prohibited_reads.read(addr, self.ppu.mode()),
+            P1 => self.joypad.read(),
+            0xFF01..=0xFF02 => self.serial.read(addr),
+            0xFF10..=0xFF3F => self.apu.read(addr),
+            0xFF04..=0xFF07 => self.timer.read(addr),
+            DMA => self.dma,
+            KEY1 if self.cgb => 0x7E | u8::from(self.double_speed) << 7 | self.io[index - 0xFF00],
+            SVBK if self.cgb => 0xF8 | self.io[index - 0xFF00],
+            HDMA5 if self.cgb => u8::from(!self.hdma_active) << 7 | self.hdma_len,
+            KEY1 | BOOT | SVBK | HDMA1..=HDMA5 => 0xFF,
+            // The upper three bits are unused and read as set.
+            IF => 0xE0 | self.io[index - 0xFF00],
+            0xFF03..=0xFF7F => self.io[index - 0xFF00],
+            0xFF80..=0xFFFE => self.hram[index - 0xFF80],
+            IE => self.ie,
+        }
+    }
+
+    /// Write a byte as seen without any DMA conflicts.
+    fn store(&mut self, addr: u16, value: u8) {
+        let index = usize::from(addr);
+
+        match addr {
+            0x0000..=0x7FFF => self.cartridge.write_rom(addr, value),
+            0x8000..=0x9FFF
+            | 0xFE00..=0xFE9F
+            | 0xFF40..=0xFF45
+            | 0xFF47..=0xFF4B
+            | VBK
+            | BCPS..=OCPD => {
+                self.ppu.write(addr, value);
+                if addr == LCDC {
+                    self.post(Event::PpuMode);
+                }
+            }
+            0xA000..=0xBFFF => self.cartridge.write_ram(addr, value),
+            0xC000..=0xDFFF => self.wram[self.wram_index(index - 0xC000)] = value,
+            0xE000..=0xFDFF => self.wram[self.wram_index(index - 0xE000)] = value,
+            0xFEA0..=0xFEFF => {}
+            P1 => {
+                self.joypad.write(value);
+                self.sgb.write(value);
+            }
+            0xFF01..=0xFF02 => self.serial.write(addr, value),
+            0xFF10..=0xFF3F => {
+                self.apu.write(addr, value);
+                if addr == NR52 {
+                    self.post(Event::FrameSequencer);
+                }
+            }
+            0xFF04..=0xFF07 => {
+                self.timer.write(addr, value);
+                self.post(Event::TimerOverflow);
+            }
+            DMA => {
+                self.dma = value;
+                self.dma_index = Some(0);
+                self.dma_cycles = 0;
+                self.post(Event::DmaComplete);
+            }
+            KEY1 => self.io[index - 0xFF00] = if self.cgb { value & 1 } else { 0 },
+            IF => self.io[index - 0xFF00] = value & 0x1F,
+            BOOT => self.boot_mapped &= value & 1 == 0,
+            HDMA1 if self.cgb => {
+                self.hdma_source = u16::from(value) << 8 | self.hdma_source & 0x00FF;
+            }
+            HDMA2 if self.cgb => {
+                self.hdma_source = self.hdma_source & 0xFF00 | u16::from(value & 0xF0);
+            }
+            HDMA3 if self.cgb => {
+                self.hdma_dest = u16::from(value & 0x1F) << 8 | self.hdma_dest & 0x00FF;
+            }
+            HDMA4 if self.cgb => {
+                self.hdma_dest = self.hdma_dest & 0x1F00 | u16::from(value & 0xF0);
+            }
+            HDMA5 if self.cgb => self.write_hdma5(value),
+            SVBK => self.io[index - 0xFF00] = if self.cgb { value & 0x07 } else { 0 },
+            0xFF03..=0xFF7F => self.io[index - 0xFF00] = value,
+            0xFF80..=0xFFFE => self.hram[index - 0xFF80] = value,
+            IE => self.ie = value,
+        }
+    }
+}
+
+impl Mmu {
+    /// Read a byte through the read hooks, corrupting OAM as `corruption`
+    /// when `addr` is in `0xFE00-0xFEFF`.
+    fn read_corrupting(&mut self, addr: u16, corruption: OamCorruption) -> u8 {
+        if self.is_dma_active() && addr < 0xFF00 {
+            return 0xFF;
+        }
+
+        if (0xFE00..=0xFEFF).contains(&addr) {
+            self.ppu.corrupt_oam(corruption);
+        }
+        let value = self.load(addr);
+        self.hooks
+            .read
+            .iter_mut()
+            .filter(|(hooked, _)| *hooked == addr)
+            .fold(value, |value, (_, hook)| hook(addr, value))
+    }
+}
+
+impl Bus for Mmu {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.read_corrupting(addr, OamCorruption::Read);
+        #[cfg(feature = "trace")]
+        self.trace(AccessKind::Read, addr, value);
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        #[cfg(feature = "trace")]
+        self.trace(AccessKind::Write, addr, value);
+        if self.is_dma_active() && addr < 0xFF00 {
+            return;
+        }
+
+        if (0xFE00..=0xFEFF).contains(&addr) {
+            self.ppu.corrupt_oam(OamCorruption::Write);
+        }
+
+        let value = self
+            .hooks
+            .write
+            .iter_mut()
+            .filter(|(hooked, _)| *hooked == addr)
+            .try_fold(value, |value, (_, hook)| hook(addr, value));
+        if let Some(value) = value {
+            self.store(addr, value);
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        // Debuggers see through a running DMA transfer.
+        self.load(addr)
+    }
+
+    fn increment(&mut self, addr: u16) {
+        if (0xFE00..=0xFEFF).contains(&addr) {
+            self.ppu.corrupt_oam(OamCorruption::Write);
+        }
+    }
+
+    fn fetch(&mut self, addr: u16) -> u8 {
+        let value = self.read_corrupting(addr, OamCorruption::Read);
+        #[cfg(feature = "trace")]
+        self.trace(AccessKind::Fetch, addr, value);
+        value
+    }
+
+    fn read_increment(&mut self, addr: u16) -> u8 {
+        let value = self.read_corrupting(addr, OamCorruption::ReadIncrement);
+        #[cfg(feature = "trace")]
+        self.trace(AccessKind::Read, addr, value);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::{NR21, NR22, NR23, NR24, WAVE_RAM};
+    use crate::interrupt::Interrupt;
+    use crate::joypad::Button;
+    use crate::ppu::{LY, LYC, STAT};
+    use crate::region::IoReg;
+    use crate::serial::{SB, SC};
+    use crate::sgb::{Command, Mask};
+    use crate::timer::{DIV, TAC, TIMA};
+
+    fn mmu() -> Mmu {
+        let mut rom = vec![0; 0x8000];
+        // ROM with 8 KiB of RAM.
+        rom[0x0147] = 0x08;
+        rom[0x0149] = 0x02;
+        rom[0x0150] = 0x42;
+        rom[0x7FFF] = 0x99;
+        Mmu::new(Cartridge::from_bytes(rom).unwrap())
+    }
+
+    #[test]
+    fn reads_rom() {
+        let mut mmu = mmu();
+
+        assert_eq!(mmu.read(0x0150), 0x42);
+        assert_eq!(mmu.read(0x7FFF), 0x99);
+
+        mmu.write(0x0150, 0x00);
+        assert_eq!(mmu.read(0x0150), 0x42);
+    }
+
+    #[test]
+    fn short_rom_reads_open_bus() {
+        let mut mmu = Mmu::new(Cartridge::from_bytes(vec![0; 0x4000]).unwrap());
+
+        assert_eq!(mmu.read(0x4000), 0xFF);
+    }
+
+    #[test]
+    fn echo_mirrors_wram() {
+        let mut mmu = mmu();
+
+        mmu.write(0xC123, 0xAB);
+        assert_eq!(mmu.read(0xE123), 0xAB);
+
+        mmu.write(0xFDFF, 0xCD);
+        assert_eq!(mmu.read(0xDDFF), 0xCD);
+    }
+
+    #[test]
+    fn if_upper_bits_read_as_set() {
+        let mut mmu = mmu();
+        mmu.write(IF, 0x00);
+        assert_eq!(mmu.read(IF), 0xE0);
+
+        mmu.write(IF, 0xFF);
+        assert_eq!(mmu.read(IF), 0xFF);
+        mmu.write(IF, Interrupt::Timer.bit());
+        assert_eq!(mmu.read(IF), 0xE0 | Interrupt::Timer.bit());
+    }
+
+    #[test]
+    fn dmg_has_no_key1() {
+        let mut mmu = mmu();
+        mmu.write(KEY1, 0x01);
+        assert_eq!(mmu.read(KEY1), 0xFF);
+    }
+
+    #[test]
+    fn svbk_selects_wram_bank() {
+        let mut mmu = Mmu::new_cgb(mmu().cartridge);
+        mmu.write(0xC000, 0x12);
+        mmu.write(0xD000, 0x01);
+
+        mmu.write(SVBK, 0x02);
+        assert_eq!(mmu.read(SVBK), 0xFA);
+        assert_eq!(mmu.read(0xD000), 0x00);
+        mmu.write(0xD000, 0x02);
+        assert_eq!(mmu.read(0xF000), 0x02);
+        assert_eq!(mmu.read(0xC000), 0x12);
+
+        // Bank 0 selects bank 1.
+        mmu.write(SVBK, 0x00);
+        assert_eq!(mmu.read(0xD000), 0x01);
+        assert_eq!(mmu.read(0xC000), 0x12);
+    }
+
+    #[test]
+    fn dmg_has_no_svbk() {
+        let mut mmu = mmu();
+        mmu.write(0xD000, 0x01);
+        mmu.write(SVBK, 0x02);
+
+        assert_eq!(mmu.read(SVBK), 0xFF);
+        assert_eq!(mmu.read(0xD000), 0x01);
+    }
+
+    #[test]
+    fn prohibited_region_reads_depend_on_mode() {
+        let mut mmu = mmu();
+        mmu.write(0xFEA0, 0x12);
+
+        while mmu.ppu().mode() != Mode::Drawing {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.read(0xFEA0), 0x00);
+        assert_eq!(mmu.read(0xFEFF), 0x00);
+        mmu.set_prohibited_reads(ProhibitedReads::Cgb);
+        assert_eq!(mmu.read(0xFEA0), 0xAA);
+
+        mmu.set_prohibited_reads(ProhibitedReads::Dmg);
+        while mmu.ppu().mode() != Mode::HBlank {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.read(0xFEA0), 0xAA);
+        assert_eq!(mmu.read(0xFEBF), 0xBB);
+        assert_eq!(mmu.read(0xFEFF), 0xFF);
+    }
+
+    #[test]
+    fn tick_forwards_ppu_interrupts() {
+        let mut mmu = mmu();
+
+        for _ in 0..144 * 456 / 4 {
+            mmu.tick(4);
+        }
+
+        assert_eq!(mmu.read(IF), 0xE0 | Interrupt::VBlank.bit());
+    }
+
+    #[test]
+    fn tick_forwards_joypad_interrupts() {
+        let mut mmu = mmu();
+        mmu.write(P1, 0x10);
+        mmu.joypad_mut().press(Button::Start);
+        assert_eq!(mmu.read(P1), 0xD7);
+
+        mmu.tick(4);
+        assert_eq!(mmu.read(IF), 0xE0 | Interrupt::Joypad.bit());
+    }
+
+    #[test]
+    fn p1_writes_reach_sgb() {
+        let mut mmu = mmu();
+        // MASK_EN with mode 3, then the stop bit.
+        let mut packet = [0; 16];
+        packet[..2].copy_from_slice(&[0x17 << 3 | 1, 0x03]);
+        mmu.write(P1, 0x00);
+        for i in 0..=128 {
+            let bit = packet.get(i / 8).is_some_and(|byte| byte >> (i % 8) & 1 != 0);
+            mmu.write(P1, 0x30);
+            mmu.write(P1, if bit { 0x10 } else { 0x20 });
+        }
+        mmu.write(P1, 0x30);
+
+        assert_eq!(mmu.sgb().mask(), Mask::Color0);
+        assert_eq!(mmu.sgb_mut().take_packet().unwrap().command, Command::MaskEn);
+    }
+
+    #[test]
+    fn tick_forwards_serial_interrupts() {
+        let mut mmu = mmu();
+        mmu.write(SB, 0x42);
+        mmu.write(SC, 0x81);
+
+        for _ in 0..1024 {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.read(SB), 0xFF);
+        assert_eq!(mmu.read(IF), 0xE0 | Interrupt::Serial.bit());
+    }
+
+    #[test]
+    fn apu_is_mapped_and_ticked() {
+        let mut mmu = mmu();
+        mmu.write(WAVE_RAM, 0x12);
+        mmu.write(NR52, 0x00);
+
+        assert_eq!(mmu.read(NR52), 0x70);
+        assert_eq!(mmu.read(WAVE_RAM), 0x12);
+
+        mmu.tick(8);
+        assert_eq!(mmu.apu_mut().take_samples().len(), 4);
+    }
+
+    #[test]
+    fn tick_forwards_timer_interrupts() {
+        let mut mmu = mmu();
+        mmu.write(TIMA, 0xFF);
+        mmu.write(TAC, 0x05);
+
+        for _ in 0..5 {
+            mmu.tick(4);
+        }
+
+        assert_eq!(mmu.read(IF), 0xE0 | Interrupt::Timer.bit());
+    }
+
+    /// Run a DMA transfer from `source`, checking OAM is blocked until it
+    /// completes after 160 M-cycles.
+    fn run_dma(mmu: &mut Mmu, source: u8) {
+        mmu.write(DMA, source);
+        for _ in 0..159 {
+            mmu.tick(4);
+        }
+
+        assert!(mmu.is_dma_active());
+        assert_eq!(mmu.read(0xFE00), 0xFF);
+
+        mmu.tick(4);
+        assert!(!mmu.is_dma_active());
+    }
+
+    #[test]
+    fn dma_from_wram() {
+        let mut mmu = mmu();
+        for i in 0..0xA0 {
+            mmu.write(0xC100 + i, 0xA0 - u8::try_from(i).unwrap());
+        }
+
+        run_dma(&mut mmu, 0xC1);
+
+        assert_eq!(mmu.read(0xFE00), 0xA0);
+        assert_eq!(mmu.read(0xFE9F), 0x01);
+        assert_eq!(mmu.read(DMA), 0xC1);
+    }
+
+    #[test]
+    fn dma_from_rom_and_vram() {
+        let mut mmu = mmu();
+        mmu.write(0x8010, 0x77);
+
+        run_dma(&mut mmu, 0x01);
+        assert_eq!(mmu.read(0xFE50), 0x42);
+
+        run_dma(&mut mmu, 0x80);
+        assert_eq!(mmu.read(0xFE10), 0x77);
+    }
+
+    #[test]
+    fn dma_blocks_all_but_hram() {
+        let mut mmu = mmu();
+        mmu.write(0xC000, 0x12);
+        mmu.write(0xFF80, 0x34);
+
+        mmu.write(DMA, 0xC0);
+        mmu.tick(4);
+
+        assert_eq!(mmu.read(0xC000), 0xFF);
+        assert_eq!(mmu.read(0x0150), 0xFF);
+        assert_eq!(mmu.read(0xFF80), 0x34);
+
+        mmu.write(0xC000, 0x56);
+        mmu.write(0xFF81, 0x78);
+        assert_eq!(mmu.read(0xFF81), 0x78);
+
+        for _ in 0..159 {
+            mmu.tick(4);
+        }
+        assert_eq!(mmu.read(0xC000), 0x12);
+    }
+
+    /// Tick `mmu` by `cycles` CPU T-cycles, an M-cycle at a time.
+    fn tick_for(mmu: &mut Mmu, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            mmu.tick(4);
+        }
+        mmu.tick(u8::try_from(cycles % 4).unwrap());
+    }
+
+    #[test]
+    fn timer_reload_fires_on_its_cycle() {
+        let mut mmu = mmu();
+        mmu.write(TIMA, 0xFF);
+        mmu.write(TAC, 0x05);
+        // Four M-cycles to the overflow, and one more to the reload.
+        assert_eq!(mmu.scheduler().at(Event::TimerOverflow), Some(20));
+
+        tick_for(&mut mmu, 19);
+        assert_eq!(mmu.read(IF), 0xE0);
+        mmu.tick(1);
+        assert_eq!(mmu.read(IF), 0xE0 | Interrupt::Timer.bit());
+        assert_eq!(mmu.scheduler().at(Event::TimerOverflow), Some(20 + 256 * 16));
+
+        mmu.write(TAC, 0x00);
+        assert_eq!(mmu.scheduler().at(Event::TimerOverflow), None);
+    }
+
+    #[test]
+    fn ppu_mode_changes_fire_on_their_cycle() {
+        let mut mmu = mmu();
+        assert_eq!(mmu.scheduler().at(Event::PpuMode), Some(80));
+        tick_for(&mut mmu, 79);
+        assert_eq!(mmu.read(STAT) & 0x03, 2);
+        mmu.tick(1);
+        assert_eq!(mmu.read(STAT) & 0x03, 3);
+
+        // The PPU keeps to the normal clock in double-speed mode.
+        let mut mmu = Mmu::new_cgb(mmu.cartridge);
+        mmu.set_double_speed(true);
+        assert_eq!(mmu.scheduler().at(Event::PpuMode), Some(160));
+        tick_for(&mut mmu, 159);
+        assert_eq!(mmu.read(STAT) & 0x03, 2);
+        mmu.tick(1);
+        assert_eq!(mmu.read(STAT) & 0x03, 3);
+
+        mmu.write(LCDC, 0x11);
+        assert_eq!(mmu.scheduler().at(Event::PpuMode), None);
+    }
+
+    #[test]
+    fn frame_sequencer_fires_on_its_cycle() {
+        let mut mmu = mmu();
+        for (reg, value) in [(NR21, 0x80 | 0x3E), (NR22, 0xF0), (NR23, 0x00), (NR24, 0xC7)] {
+            mmu.write(reg, value);
+        }
+        assert_eq!(mmu.scheduler().at(Event::FrameSequencer), Some(8192));
+
+        // The length runs out on the third step, 24576 cycles in.
+        tick_for(&mut mmu, 3 * 8192 - 1);
+        assert_eq!(mmu.read(NR52) & 0x02, 0x02);
+        mmu.tick(1);
+        assert_eq!(mmu.read(NR52) & 0x02, 0x00);
+        assert_eq!(mmu.scheduler().at(Event::FrameSequencer), Some(4 * 8192));
+
+        mmu.write(NR52, 0x00);
+        assert_eq!(mmu.scheduler().at(Event::FrameSequencer), None);
+    }
+
+    #[test]
+    fn dma_completion_fires_on_its_cycle() {
+        let mut mmu = mmu();
+        mmu.write(0xC09F, 0x5A);
+        mmu.tick(2);
+        mmu.write(DMA, 0xC0);
+        assert_eq!(mmu.scheduler().at(Event::DmaComplete), Some(2 + 640));
+
+        tick_for(&mut mmu, 639);
+        assert!(mmu.is_dma_active());
+        assert_eq!(mmu.peek(0xFE9F), 0x00);
+        mmu.tick(1);
+        assert!(!mmu.is_dma_active());
+        assert_eq!(mmu.read(0xFE9F), 0x5A);
+        assert_eq!(mmu.scheduler().at(Event::DmaComplete), None);
+    }
+
+    /// Return a CGB memory map with 4 VRAM DMA blocks of data at `0xC000`,
+    /// set up to be copied to `0x8000`.
+    fn hdma_mmu() -> Mmu {
+        let mut mmu = Mmu::new_cgb(mmu().cartridge);
+        for i in 0..0x40 {
+            mmu.write(0xC000 + i, u8::try_from(i).unwrap() + 1);
+        }
+        for (reg, value) in [(HDMA1, 0xC0), (HDMA2, 0x00), (HDMA3, 0x00), (HDMA4, 0x00)] {
+            mmu.write(reg, value);
+        }
+        mmu
+    }
+
+    /// Tick until the PPU is in horizontal blanking, or until it isn't.
+    fn run_until_hblank(mmu: &mut Mmu, hblank: bool) {
+        loop {
+            let mode = mmu.read(STAT) & 0x03;
+            if (mode == 0) == hblank {
+                break;
+            }
+            mmu.tick(4);
+        }
+    }
+
+    #[test]
+    fn hblank_dma_copies_a_block_per_hblank() {
+        let mut mmu = hdma_mmu();
+        mmu.write(HDMA5, 0x83);
+        assert_eq!(mmu.read(HDMA5), 0x03);
+
+        for block in 0..4 {
+            run_until_hblank(&mut mmu, true);
+            let ly = mmu.read(LY);
+
+            let end = 0x8000 + 0x10 * (block + 1);
+            assert_eq!(mmu.read(end - 0x10), u8::try_from(block).unwrap() * 0x10 + 1);
+            assert_eq!(mmu.read(end - 1), u8::try_from(block).unwrap() * 0x10 + 0x10);
+            assert_eq!(mmu.read(end), 0x00);
+
+            run_until_hblank(&mut mmu, false);
+            assert_eq!(mmu.read(LY), ly + 1);
+        }
+        assert_eq!(mmu.read(HDMA5), 0xFF);
+    }
+
+    #[test]
+    fn hblank_dma_copies_as_hblank_starts() {
+        let mut mmu = hdma_mmu();
+        mmu.write(HDMA5, 0x80);
+
+        while mmu.read(STAT) & 0x03 != 0 {
+            assert_eq!(mmu.read(0x8000), 0x00);
+            mmu.tick(1);
+        }
+        assert_eq!(mmu.read(0x8000), 0x01);
+    }
+
+    #[test]
+    fn hblank_dma_can_be_cancelled() {
+        let mut mmu = hdma_mmu();
+        mmu.write(HDMA5, 0x83);
+        run_until_hblank(&mut mmu, true);
+
+        mmu.write(HDMA5, 0x00);
+        assert_eq!(mmu.read(HDMA5), 0x82);
+
+        run_until_hblank(&mut mmu, false);
+        run_until_hblank(&mut mmu, true);
+        assert_eq!(mmu.read(0x8010), 0x00);
+    }
+
+    #[test]
+    fn general_dma_copies_at_once_and_stalls() {
+        let mut mmu = hdma_mmu();
+        mmu.write(HDMA5, 0x01);
+
+        assert_eq!(mmu.read(0x8000), 0x01);
+        assert_eq!(mmu.read(0x801F), 0x20);
+        assert_eq!(mmu.read(0x8020), 0x00);
+        assert_eq!(mmu.read(HDMA5), 0xFF);
+
+        // Two blocks stall the CPU for 64 dots.
+        assert!(mmu.is_cpu_stalled());
+        mmu.tick(60);
+        assert!(mmu.is_cpu_stalled());
+        mmu.tick(4);
+        assert!(!mmu.is_cpu_stalled());
+    }
+
+    #[test]
+    fn boot_rom_unmaps_on_write() {
+        let mut mmu = Mmu::with_boot_rom(mmu().cartridge, vec![0x31; 0x100]);
+        assert!(mmu.is_boot_rom_mapped());
+        assert_eq!(mmu.read(0x0000), 0x31);
+        assert_eq!(mmu.read(0x0150), 0x42);
+
+        mmu.write(BOOT, 0x00);
+        assert_eq!(mmu.read(0x0000), 0x31);
+
+        mmu.write(BOOT, 0x01);
+        assert!(!mmu.is_boot_rom_mapped());
+        assert_eq!(mmu.read(0x0000), 0x00);
+
+        // It can't be mapped back.
+        mmu.write(BOOT, 0x00);
+        assert_eq!(mmu.read(0x0000), 0x00);
+    }
+
+    #[test]
+    fn stat_write_in_vblank_follows_model() {
+        for (model, expected) in [(Model::Dmg, Interrupt::Stat.bit()), (Model::Cgb, 0)] {
+            let mut mmu = Mmu::with_model(mmu().cartridge, model);
+            mmu.write(LYC, 0xFF);
+            while mmu.ppu().mode() != Mode::VBlank {
+                mmu.tick(4);
+            }
+            mmu.write(IF, 0x00);
+
+            // No source is enabled, but on DMG the write acts as if every
+            // one were for a moment.
+            mmu.write(STAT, 0x00);
+            mmu.tick(4);
+            assert_eq!(mmu.read(IF) & Interrupt::Stat.bit(), expected, "{model:?}");
+        }
+    }
+
+    #[test]
+    fn region_names_addresses() {
+        let mut mmu = Mmu::with_boot_rom(mmu().cartridge, vec![0x31; 0x100]);
+        assert_eq!(mmu.region(0x0000), Region::BootRom);
+        assert_eq!(mmu.region(0x0100), Region::RomBank0);
+        mmu.write(BOOT, 0x01);
+        assert_eq!(mmu.region(0x0000), Region::RomBank0);
+
+        assert_eq!(mmu.region(0x4000), Region::RomBankN);
+        assert_eq!(mmu.region(0x9800), Region::Vram);
+        assert_eq!(mmu.region(0xA000), Region::ExtRam);
+        assert_eq!(mmu.region(0xCFFF), Region::WramBank0);
+        assert_eq!(mmu.region(0xD000), Region::WramBankN);
+        assert_eq!(mmu.region(0xE000), Region::EchoRam);
+        assert_eq!(mmu.region(0xFE9F), Region::Oam);
+        assert_eq!(mmu.region(0xFEA0), Region::Prohibited);
+        assert_eq!(mmu.region(STAT), Region::Io(IoReg::Stat));
+        assert_eq!(mmu.region(0xFF3F), Region::Io(IoReg::WaveRam));
+        assert_eq!(mmu.region(0xFF03), Region::Io(IoReg::Unused));
+        assert_eq!(mmu.region(0xFF80), Region::Hram);
+        assert_eq!(mmu.region(IE), Region::InterruptEnable);
+
+        assert_eq!(mmu.region(TIMA).name(), "TIMA");
+        assert_eq!(mmu.region(0xC000).name(), "WRAM bank 0");
+    }
+
+    #[test]
+    fn cgb_boot_rom_skips_header() {
+        let mut mmu = Mmu::with_boot_rom(mmu().cartridge, vec![0x31; 0x900]);
+
+        assert_eq!(mmu.read(0x00FF), 0x31);
+        assert_eq!(mmu.read(0x0150), 0x42);
+        assert_eq!(mmu.read(0x0200), 0x31);
+        assert_eq!(mmu.read(0x08FF), 0x31);
+        assert_eq!(mmu.read(0x0900), 0x00);
+    }
+
+    #[test]
+    fn poke_skips_side_effects() {
+        let mut mmu = mmu();
+        mmu.poke(DIV, 0x12);
+        assert_eq!(mmu.peek(DIV), 0x12);
+
+        mmu.poke(DMA, 0xC0);
+        assert!(!mmu.is_dma_active());
+        assert_eq!(mmu.peek(DMA), 0xC0);
+
+        mmu.poke(0x0150, 0x00);
+        mmu.poke(0xA000, 0x24);
+        assert_eq!(mmu.peek(0x0150), 0x42);
+        assert_eq!(mmu.peek(0xA000), 0x24);
+
+        mmu.poke(0xFF80, 0x34);
+        mmu.poke(0xFE00, 0x56);
+        assert_eq!(mmu.peek(0xFF80), 0x34);
+        assert_eq!(mmu.peek(0xFE00), 0x56);
+    }
+
+    #[test]
+    fn peek_sees_through_dma() {
+        let mut mmu = mmu();
+        mmu.write(0xC000, 0x12);
+        mmu.write(DMA, 0xC0);
+
+        assert_eq!(mmu.read(0xC000), 0xFF);
+        assert_eq!(mmu.peek(0xC000), 0x12);
+    }
+
+    #[test]
+    fn decodes_regions() {
+        let mut mmu = mmu();
+
+        for (addr, value) in [
+            (0x8000, 0x01),
+            (0x9FFF, 0x02),
+            (0xA000, 0x03),
+            (0xBFFF, 0x04),
+            (0xFE00, 0x05),
+            (0xFE9F, 0x06),
+            (0xFF80, 0x07),
+            (0xFFFE, 0x08),
+            (0xFFFF, 0x09),
+        ] {
+            mmu.write(addr, value);
+            assert_eq!(mmu.read(addr), value, "{addr:#06X}");
+        }
+
+        // OAM and HRAM do not alias their neighbours.
+        assert_eq!(mmu.read(0xFEA0), 0x00);
+        assert_eq!(mmu.read(0xFF7F), 0x00);
+    }
+
+    #[test]
+    fn read_hooks_patch_what_the_cpu_sees() {
+        let mut mmu = mmu();
+        mmu.add_read_hook(0x0150, |_, _| 0x3C);
+        mmu.add_read_hook(0x0150, |_, value| value + 1);
+
+        assert_eq!(mmu.read(0x0150), 0x3D);
+        assert_eq!(mmu.read(0x0151), 0x00);
+        // The ROM underneath is untouched.
+        assert_eq!(mmu.peek(0x0150), 0x42);
+
+        mmu.clear_hooks();
+        assert_eq!(mmu.read(0x0150), 0x42);
+    }
+
+    #[test]
+    fn write_hooks_rewrite_or_drop_writes() {
+        let mut mmu = mmu();
+        mmu.add_write_hook(0xC000, |_, value| Some(value | 0x80));
+        mmu.add_write_hook(0xC001, |_, _| None);
+
+        mmu.write(0xC000, 0x01);
+        mmu.write(0xC001, 0x01);
+        assert_eq!(mmu.peek(0xC000), 0x81);
+        assert_eq!(mmu.peek(0xC001), 0x00);
+
+        // Pokes bypass the hooks.
+        mmu.poke(0xC001, 0x02);
+        assert_eq!(mmu.peek(0xC001), 0x02);
+    }
+}
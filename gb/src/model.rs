@@ -0,0 +1,142 @@
+//! The hardware models and the differences between them.
+
+use crate::cartridge::{CartridgeHeader, CgbSupport};
+use crate::cpu::{Flags, Registers};
+use crate::mmu::ProhibitedReads;
+
+/// A Game Boy hardware model.
+///
+/// The models run the same software, but their boot ROMs leave different
+/// values in the registers, and a few hardware quirks differ between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Model {
+    /// The earliest DMG revision, with its own boot ROM.
+    Dmg0,
+    /// The original Game Boy.
+    #[default]
+    Dmg,
+    /// The Game Boy Pocket.
+    Mgb,
+    /// The Super Game Boy.
+    Sgb,
+    /// The Super Game Boy 2.
+    Sgb2,
+    /// The Game Boy Color.
+    Cgb,
+    /// The Game Boy Advance, running Game Boy software on its CGB core.
+    Agb,
+}
+
+impl Model {
+    /// Return the model a cartridge is meant for: the CGB if its header
+    /// declares CGB support, and the DMG otherwise.
+    #[must_use]
+    pub fn for_cartridge(header: &CartridgeHeader) -> Self {
+        if header.cgb == CgbSupport::None { Self::Dmg } else { Self::Cgb }
+    }
+
+    /// Check if the model is built around the CGB hardware, running CGB
+    /// cartridges in CGB mode and the rest in its compatibility mode.
+    #[must_use]
+    pub const fn is_cgb(self) -> bool {
+        matches!(self, Self::Cgb | Self::Agb)
+    }
+
+    /// Create a register file in the state the boot ROM of the model leaves
+    /// it in, for a cartridge run in CGB mode if `cgb_mode` is set.
+    ///
+    /// The values are taken from the "Power Up Sequence" section of Pan
+    /// Docs. Where they depend on the cartridge header, they're those of a
+    /// header whose checksums aren't zero. `cgb_mode` is ignored by the
+    /// models without CGB hardware.
+    #[must_use]
+    pub const fn registers(self, cgb_mode: bool) -> Registers {
+        let mut regs = Registers::new_dmg();
+        match self {
+            Self::Dmg0 => {
+                regs.f = Flags::from_bits(0x00);
+                regs.set_bc(0xFF13);
+                regs.set_de(0x00C1);
+                regs.set_hl(0x8403);
+            }
+            Self::Dmg => {}
+            Self::Mgb => regs.a = 0xFF,
+            Self::Sgb | Self::Sgb2 => {
+                regs.a = if matches!(self, Self::Sgb) { 0x01 } else { 0xFF };
+                regs.f = Flags::from_bits(0x00);
+                regs.set_bc(0x0014);
+                regs.set_de(0x0000);
+                regs.set_hl(0xC060);
+            }
+            Self::Cgb | Self::Agb => {
+                regs = Registers::new_cgb();
+                if !cgb_mode {
+                    regs.set_de(0x0008);
+                    regs.set_hl(0x007C);
+                }
+                // The AGB boot ROM increments `B` on its way out.
+                if matches!(self, Self::Agb) {
+                    regs.b = 0x01;
+                    regs.f = Flags::from_bits(0x00);
+                }
+            }
+        }
+        regs
+    }
+
+    /// Return what reads of the prohibited region return on the model.
+    #[must_use]
+    pub const fn prohibited_reads(self) -> ProhibitedReads {
+        if self.is_cgb() { ProhibitedReads::Cgb } else { ProhibitedReads::Dmg }
+    }
+
+    /// Check if writing `STAT` in H-blank, V-blank or on an `LY` match
+    /// requests a STAT interrupt, as if every source were enabled for a
+    /// moment. Only the models without CGB hardware have the bug.
+    #[must_use]
+    pub const fn has_stat_write_bug(self) -> bool {
+        !self.is_cgb()
+    }
+
+    /// Check if CPU accesses to `0xFE00-0xFEFF` during OAM scan corrupt
+    /// OAM, including the 16-bit increments and decrements of `INC rr`,
+    /// `DEC rr` and the stack. Only the models without CGB hardware have
+    /// the bug.
+    #[must_use]
+    pub const fn has_oam_bug(self) -> bool {
+        !self.is_cgb()
+    }
+
+    /// Check if the APU's length counters stay powered while it's off,
+    /// keeping their counts and still loaded by writes to `NR11`, `NR21`,
+    /// `NR31` and `NR41`. Only the models without CGB hardware keep them.
+    #[must_use]
+    pub const fn has_powered_lengths(self) -> bool {
+        !self.is_cgb()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+
+    #[test]
+    fn cartridge_flag_picks_model() {
+        let mut rom = test_rom(0x00, [0x00, 0x00]);
+        assert_eq!(Model::for_cartridge(&CartridgeHeader::parse(&rom).unwrap()), Model::Dmg);
+
+        rom[0x0143] = 0x80;
+        assert_eq!(Model::for_cartridge(&CartridgeHeader::parse(&rom).unwrap()), Model::Cgb);
+    }
+
+    #[test]
+    fn registers_differ_by_model() {
+        assert_eq!(Model::Dmg.registers(false).af(), 0x01B0);
+        assert_eq!(Model::Mgb.registers(false).af(), 0xFFB0);
+        assert_eq!(Model::Sgb.registers(false).hl(), 0xC060);
+        assert_eq!(Model::Cgb.registers(true).de(), 0xFF56);
+        assert_eq!(Model::Cgb.registers(false).de(), 0x0008);
+        assert_eq!(Model::Agb.registers(true).bc(), 0x0100);
+    }
+}
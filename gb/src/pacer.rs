@@ -0,0 +1,184 @@
+//! Pacing emulation to the host clock and audio queue.
+//!
+//! The Game Boy runs its CPU at [`CPU_CLOCK`] and draws a frame every
+//! [`FRAME_CYCLES`], so the LCD refreshes at about 59.7275 Hz. Hosts rarely
+//! refresh at that rate, and their audio devices drift from their clocks, so
+//! a [`Pacer`] turns the host time passed into the cycles to run, leaning
+//! slightly faster or slower to keep the audio queue filled.
+
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::gameboy::FRAME_CYCLES;
+
+/// The CPU clock at normal speed, in Hz.
+pub const CPU_CLOCK: u32 = 4_194_304;
+
+/// The CPU clock in CGB double-speed mode, in Hz.
+pub const DOUBLE_SPEED_CLOCK: u32 = 2 * CPU_CLOCK;
+
+/// The most the speed is skewed to fill or drain the audio queue, in parts
+/// per million. At 0.5% the change in pitch can't be heard.
+const MAX_SKEW: i64 = 5_000;
+
+/// The most host time run at once. After a longer stall, such as a window
+/// being dragged, the rest is dropped rather than caught up on.
+const MAX_ELAPSED: Duration = Duration::from_millis(100);
+
+/// A parts per million rate of 1.
+const PPM: u128 = 1_000_000;
+
+/// The nanoseconds in a second.
+const NANOS: u64 = 1_000_000_000;
+
+/// Return the time a frame takes, [`FRAME_CYCLES`] at [`CPU_CLOCK`], rounded
+/// up to the nanosecond.
+#[must_use]
+pub const fn frame_duration() -> Duration {
+    let nanos = (FRAME_CYCLES as u64 * NANOS).div_ceil(CPU_CLOCK as u64);
+    Duration::from_nanos(nanos)
+}
+
+/// Turns host time into the cycles to emulate.
+///
+/// Each call to [`Pacer::cycles`] returns the cycles in the time passed,
+/// carrying the fraction of a cycle left over into the next call, so the
+/// emulation never drifts from the host clock. When given an audio target,
+/// it runs up to 0.5% faster while the audio queue is below it and slower
+/// while above, so the queue neither runs dry nor grows.
+///
+/// The cycles are in the units [`crate::GameBoy::run_cycles`] counts, so in
+/// double-speed mode there are twice as many, see [`Pacer::set_double_speed`].
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    /// The stereo frames to keep queued for the audio device, or 0 to
+    /// ignore the audio queue.
+    audio_target: usize,
+    double_speed: bool,
+    /// The fraction of a cycle owed, in cycles times nanoseconds per second
+    /// times parts per million.
+    remainder: u128,
+    /// The host time of the last call to [`Pacer::tick`].
+    #[cfg(feature = "std")]
+    last: Option<Instant>,
+}
+
+impl Pacer {
+    /// Create a pacer keeping about `audio_target` stereo frames queued for
+    /// the audio device, or following the host clock alone with 0.
+    #[must_use]
+    pub const fn new(audio_target: usize) -> Self {
+        Self {
+            audio_target,
+            double_speed: false,
+            remainder: 0,
+            #[cfg(feature = "std")]
+            last: None,
+        }
+    }
+
+    /// Return the CPU clock in Hz, [`DOUBLE_SPEED_CLOCK`] in double-speed
+    /// mode and [`CPU_CLOCK`] otherwise.
+    #[must_use]
+    pub const fn clock(&self) -> u32 {
+        if self.double_speed { DOUBLE_SPEED_CLOCK } else { CPU_CLOCK }
+    }
+
+    /// Count cycles at double speed or not, following
+    /// [`crate::cpu::Cpu::is_double_speed`].
+    pub const fn set_double_speed(&mut self, enabled: bool) {
+        self.double_speed = enabled;
+    }
+
+    /// Return the cycles to run for `elapsed` host time, with `queued`
+    /// stereo frames waiting in the audio queue.
+    ///
+    /// At 60 Hz that's 69,905 or 69,906 cycles a call, a little under the
+    /// [`FRAME_CYCLES`] of a frame. Called every [`frame_duration`] instead,
+    /// it's a frame's worth, with an extra cycle every few hundred frames
+    /// for the rounding of the duration.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn cycles(&mut self, elapsed: Duration, queued: usize) -> u64 {
+        let elapsed = elapsed.min(MAX_ELAPSED).as_nanos();
+        let rate = u128::from(self.clock()) * PPM.saturating_add_signed(self.skew(queued).into());
+        let total = elapsed * rate + self.remainder;
+        self.remainder = total % (u128::from(NANOS) * PPM);
+        // At most 100 ms of cycles, so the count fits easily.
+        (total / (u128::from(NANOS) * PPM)) as u64
+    }
+
+    /// Return the cycles to run for the host time since the last call, with
+    /// `queued` stereo frames waiting in the audio queue.
+    ///
+    /// The first call only starts the clock, and returns 0.
+    #[cfg(feature = "std")]
+    pub fn tick(&mut self, queued: usize) -> u64 {
+        let now = Instant::now();
+        let elapsed = self.last.map_or(Duration::ZERO, |last| now - last);
+        self.last = Some(now);
+        self.cycles(elapsed, queued)
+    }
+
+    /// Return how far to skew the speed for `queued` stereo frames, in parts
+    /// per million.
+    fn skew(&self, queued: usize) -> i64 {
+        if self.audio_target == 0 {
+            return 0;
+        }
+
+        let target = i64::try_from(self.audio_target).unwrap_or(i64::MAX);
+        let queued = i64::try_from(queued).unwrap_or(i64::MAX);
+        let skew = (target.saturating_sub(queued)).saturating_mul(MAX_SKEW) / target;
+        skew.clamp(-MAX_SKEW, MAX_SKEW)
+    }
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_run_frame_cycles() {
+        let mut pacer = Pacer::new(2048);
+        for _ in 0..60 {
+            assert_eq!(pacer.cycles(frame_duration(), 2048), u64::from(FRAME_CYCLES));
+        }
+
+        pacer.set_double_speed(true);
+        assert_eq!(pacer.cycles(frame_duration(), 2048), 2 * u64::from(FRAME_CYCLES));
+    }
+
+    #[test]
+    fn steady_calls_keep_to_the_clock() {
+        let mut pacer = Pacer::default();
+        let mut total = 0;
+        for _ in 0..60 {
+            let cycles = pacer.cycles(Duration::from_nanos(16_666_667), 0);
+            assert!((69_905..=69_906).contains(&cycles));
+            total += cycles;
+        }
+        assert_eq!(total, u64::from(CPU_CLOCK));
+    }
+
+    #[test]
+    fn audio_queue_skews_speed() {
+        let run = |elapsed, queued| Pacer::new(1000).cycles(elapsed, queued);
+        let tenth = Duration::from_millis(100);
+
+        // An empty queue runs 0.5% fast, a full one 0.5% slow.
+        assert_eq!(run(tenth, 1000), 419_430);
+        assert_eq!(run(tenth, 0), 421_527);
+        assert_eq!(run(tenth, 2000), 417_333);
+        assert_eq!(run(tenth, 100_000), 417_333);
+
+        // Long stalls aren't caught up on.
+        assert_eq!(run(Duration::from_secs(5), 1000), 419_430);
+    }
+}
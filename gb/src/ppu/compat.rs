@@ -0,0 +1,357 @@
+//! The palettes the CGB boot ROM colors DMG games in.
+//!
+//! A DMG game run on a CGB is drawn as on DMG, but each of `BGP`, `OBP0`
+//! and `OBP1` picks its shades from a palette of four colors. The boot ROM
+//! chooses the palettes from a table of Nintendo titles, keyed on the sum of
+//! the title bytes and, where that's shared, the fourth letter of the title.
+//! Every other game gets the default palette.
+
+use super::rgb555_to_rgb888;
+
+/// The offset of the title in the ROM.
+const TITLE: usize = 0x0134;
+/// The offset of the new licensee code in the ROM.
+const NEW_LICENSEE: usize = 0x0144;
+/// The offset of the old licensee code in the ROM.
+const OLD_LICENSEE: usize = 0x014B;
+
+/// The index in [`CHECKSUMS`] of the first checksum shared by several
+/// titles.
+const DUPLICATES_START: usize = 65;
+
+/// The title checksums of the games with their own palette, in the order
+/// the boot ROM searches them. From [`DUPLICATES_START`] on, the checksums
+/// are shared by several titles and the fourth letter must match too.
+const CHECKSUMS: [u8; 94] = [
+    0x00,
+    0x88, // ALLEY WAY
+    0x16, // YAKUMAN
+    0x36, // BASEBALL, (Game and Watch 2)
+    0xD1, // TENNIS
+    0xDB, // TETRIS
+    0xF2, // QIX
+    0x3C, // DR.MARIO
+    0x8C, // RADARMISSION
+    0x92, // F1RACE
+    0x3D, // YOSSY NO TAMAGO
+    0x5C,
+    0x58, // X
+    0xC9, // MARIOLAND2
+    0x3E, // YOSSY NO COOKIE
+    0x70, // ZELDA
+    0x1D,
+    0x59,
+    0x69, // TETRIS FLASH
+    0x19, // DONKEY KONG
+    0x35, // MARIO'S PICROSS
+    0xA8,
+    0x14, // POKEMON RED, (GAMEBOYCAMERA G)
+    0xAA, // POKEMON GREEN
+    0x75, // PICROSS 2
+    0x95, // YOSSY NO PANEPON
+    0x99, // KIRAKIRA KIDS
+    0x34, // GAMEBOY GALLERY
+    0x6F, // POCKETCAMERA
+    0x15,
+    0xFF, // BALLOON KID
+    0x97, // KINGOFTHEZOO
+    0x4B, // DMG FOOTBALL
+    0x90, // WORLD CUP
+    0x17, // OTHELLO
+    0x10, // SUPER RC PRO-AM
+    0x39, // DYNABLASTER
+    0xF7, // BOY AND BLOB GB2
+    0xF6, // MEGAMAN
+    0xA2, // STAR WARS-NOA
+    0x49,
+    0x4E, // WAVERACE
+    0x43,
+    0x68, // LOLO2
+    0xE0, // YOSHI'S COOKIE
+    0x8B, // MYSTIC QUEST
+    0xF0,
+    0xCE, // TOPRANKINGTENNIS
+    0x0C, // MANSELL
+    0x29, // MEGAMAN3
+    0xE8, // SPACE INVADERS
+    0xB7, // GAME&WATCH
+    0x86, // DONKEYKONGLAND95
+    0x9A, // ASTEROIDS/MISCMD
+    0x52, // STREET FIGHTER 2
+    0x01, // DEFENDER/JOUST
+    0x9D, // KILLERINSTINCT95
+    0x71, // TETRIS BLAST
+    0x9C, // PINOCCHIO
+    0xBD,
+    0x5D, // BA.TOSHINDEN
+    0x6D, // NETTOU KOF 95
+    0x67,
+    0x3F, // TETRIS PLUS
+    0x6B, // DONKEYKONGLAND 3
+    0xB3,
+    0x46, // SUPER MARIOLAND
+    0x28, // GOLF
+    0xA5, // SOLARSTRIKER
+    0xC6, // GBWARS
+    0xD3, // KAERUNOTAMENI
+    0x27,
+    0x61, // POKEMON BLUE
+    0x18, // DONKEYKONGLAND
+    0x66, // GAMEBOY GALLERY2
+    0x6A, // DONKEYKONGLAND 2
+    0xBF, // KID ICARUS
+    0x0D, // TETRIS2
+    0xF4,
+    0xB3, // MOGURANYA
+    0x46,
+    0x28, // GALAGA&GALAXIAN
+    0xA5, // BT2RAGNAROKWORLD
+    0xC6, // KEN GRIFFEY JR
+    0xD3,
+    0x27, // MAGNETIC SOCCER
+    0x61, // VEGAS STAKES
+    0x18,
+    0x66, // MILLI/CENTI/PEDE
+    0x6A, // MARIO & YOSHI
+    0xBF, // SOCCER
+    0x0D, // POKEBOM
+    0xF4, // G&W GALLERY
+    0xB3, // TETRIS ATTACK
+];
+/// The index into [`COMBINATIONS`] for each of [`CHECKSUMS`].
+const PALETTE_INDEX: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17,
+    46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+/// The palette combinations, as indices into [`COLORS`] of the four
+/// colors of `OBP0`, `OBP1` and `BGP`. A few start partway into a palette,
+/// as they do in the boot ROM.
+const COMBINATIONS: [[u8; 3]; 51] = [
+    [16, 16, 116],
+    [72, 72, 72],
+    [80, 80, 80],
+    [96, 96, 96],
+    [36, 36, 36],
+    [0, 0, 0],
+    [108, 108, 108],
+    [20, 20, 20],
+    [48, 48, 48],
+    [104, 104, 104],
+    [64, 32, 32],
+    [16, 112, 112],
+    [16, 8, 8],
+    [12, 16, 16],
+    [16, 116, 116],
+    [112, 16, 112],
+    [8, 68, 8],
+    [64, 64, 32],
+    [16, 16, 28],
+    [16, 16, 72],
+    [16, 16, 80],
+    [76, 76, 36],
+    [15, 15, 44],
+    [68, 68, 8],
+    [16, 16, 8],
+    [16, 16, 12],
+    [112, 112, 0],
+    [12, 12, 0],
+    [0, 0, 4],
+    [72, 88, 72],
+    [80, 88, 80],
+    [96, 88, 96],
+    [64, 88, 32],
+    [68, 16, 52],
+    [111, 0, 56],
+    [111, 16, 60],
+    [76, 91, 36],
+    [64, 112, 40],
+    [16, 92, 112],
+    [68, 88, 8],
+    [16, 0, 8],
+    [16, 112, 12],
+    [112, 12, 0],
+    [12, 112, 16],
+    [84, 112, 16],
+    [12, 112, 0],
+    [100, 12, 112],
+    [0, 112, 32],
+    [16, 12, 112],
+    [112, 12, 24],
+    [16, 112, 116],
+];
+/// The colors of the palettes, four RGB555 colors each.
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+/// The fourth letter of the title for each checksum from
+/// [`DUPLICATES_START`] on.
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// The colors a CGB draws a DMG game in, one palette each for the
+/// background and window and for either sprite palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompatPalette {
+    /// The RGB555 colors of the shades of `BGP`.
+    pub bg: [u16; 4],
+    /// The RGB555 colors of the shades of `OBP0`.
+    pub obj0: [u16; 4],
+    /// The RGB555 colors of the shades of `OBP1`.
+    pub obj1: [u16; 4],
+}
+
+impl CompatPalette {
+    /// The palette of games missing from the boot ROM's table, with a green
+    /// and blue background and red sprites.
+    pub const DEFAULT: Self = Self::combination(0);
+
+    /// Return the palette the CGB boot ROM picks for the cartridge `rom`.
+    ///
+    /// Only games licensed by Nintendo are looked up, any other gets
+    /// [`CompatPalette::DEFAULT`].
+    #[must_use]
+    pub fn for_rom(rom: &[u8]) -> Self {
+        let Some(header) = rom.get(..=OLD_LICENSEE) else {
+            return Self::DEFAULT;
+        };
+        let nintendo = match header[OLD_LICENSEE] {
+            0x01 => true,
+            0x33 => header[NEW_LICENSEE..NEW_LICENSEE + 2] == *b"01",
+            _ => false,
+        };
+        if !nintendo {
+            return Self::DEFAULT;
+        }
+
+        let title = &header[TITLE..TITLE + 16];
+        let checksum = title.iter().fold(0_u8, |sum, &byte| sum.wrapping_add(byte));
+        let found = CHECKSUMS.iter().enumerate().position(|(i, &entry)| {
+            entry == checksum
+                && (i < DUPLICATES_START || FOURTH_LETTERS[i - DUPLICATES_START] == title[3])
+        });
+        found.map_or(Self::DEFAULT, |i| Self::combination(PALETTE_INDEX[i]))
+    }
+
+    /// Return the palettes of combination `index`.
+    const fn combination(index: u8) -> Self {
+        let [obj0, obj1, bg] = COMBINATIONS[index as usize];
+        Self {
+            bg: colors(bg),
+            obj0: colors(obj0),
+            obj1: colors(obj1),
+        }
+    }
+
+    /// Return the RGBA8888 color of `shade` in palette `palette`, 0 for the
+    /// background and 1 or 2 for the sprite palettes.
+    pub(super) const fn rgba(&self, palette: u8, shade: u8) -> [u8; 4] {
+        let colors = match palette {
+            0 => &self.bg,
+            1 => &self.obj0,
+            _ => &self.obj1,
+        };
+        let [r, g, b] = rgb555_to_rgb888(colors[(shade & 3) as usize]);
+        [r, g, b, 0xFF]
+    }
+}
+
+impl Default for CompatPalette {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Return the four colors from `start` in [`COLORS`].
+const fn colors(start: u8) -> [u16; 4] {
+    let start = start as usize;
+    [COLORS[start], COLORS[start + 1], COLORS[start + 2], COLORS[start + 3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Return a ROM header with `title` and the old licensee `licensee`.
+    fn rom(title: &str, licensee: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x0150];
+        rom[TITLE..TITLE + title.len()].copy_from_slice(title.as_bytes());
+        rom[OLD_LICENSEE] = licensee;
+        rom
+    }
+
+    #[test]
+    fn tetris_is_yellow_and_red() {
+        let tetris = [0x7FFF, 0x03FF, 0x001F, 0x0000];
+        assert_eq!(
+            CompatPalette::for_rom(&rom("TETRIS", 0x01)),
+            CompatPalette {
+                bg: tetris,
+                obj0: tetris,
+                obj1: tetris,
+            }
+        );
+
+        // Other licensees always get the default.
+        assert_eq!(
+            CompatPalette::for_rom(&rom("TETRIS", 0x08)),
+            CompatPalette::DEFAULT
+        );
+        let mut new_licensee = rom("TETRIS", 0x33);
+        new_licensee[NEW_LICENSEE..NEW_LICENSEE + 2].copy_from_slice(b"01");
+        assert_eq!(
+            CompatPalette::for_rom(&new_licensee),
+            CompatPalette::for_rom(&rom("TETRIS", 0x01))
+        );
+    }
+
+    #[test]
+    fn shared_checksums_check_fourth_letter() {
+        // SUPER MARIOLAND shares its checksum, and its palette starts
+        // partway into one.
+        let mario = CompatPalette::for_rom(&rom("SUPER MARIOLAND", 0x01));
+        assert_eq!(mario.obj0, [0x0000, 0x7FFF, 0x421F, 0x1CF2]);
+        assert_eq!(mario.bg, [0x7ED6, 0x4BFF, 0x2175, 0x0000]);
+
+        // The same checksum with another fourth letter isn't in the table.
+        assert_eq!(
+            CompatPalette::for_rom(&rom("SUPDR MARIOLANE", 0x01)),
+            CompatPalette::DEFAULT
+        );
+        assert_eq!(CompatPalette::DEFAULT.bg, [0x7FFF, 0x1BEF, 0x6180, 0x0000]);
+    }
+}
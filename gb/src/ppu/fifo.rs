@@ -0,0 +1,271 @@
+//! The pixel FIFOs and the background fetcher, which draw a line in mode 3.
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The dots taken by the background fetcher to fetch a row of 8 pixels.
+pub(super) const FETCH_DOTS: u8 = 6;
+
+/// The background fetcher, which fetches the tile number, then the low and
+/// high bytes of a row of tile data, taking 2 dots each. The row is then
+/// pushed as soon as the background FIFO is empty.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Fetcher {
+    /// The dots spent on the current row, up to [`FETCH_DOTS`] once it's
+    /// ready to be pushed.
+    pub dots: u8,
+    /// The tile column to fetch next, counted from the start of the line
+    /// or the window.
+    pub x: u8,
+    /// Whether the fetcher has switched to the window for this line.
+    pub window: bool,
+    pub tile: u8,
+    /// The CGB attributes of the tile.
+    pub attrs: u8,
+    /// The row within the tile, after any vertical flip.
+    pub row: u8,
+    pub lo: u8,
+    pub hi: u8,
+}
+
+impl Fetcher {
+    /// Write the fetcher to a save state.
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.dots, self.x, self.tile, self.attrs, self.row, self.lo, self.hi]);
+        state.write_bool(self.window);
+    }
+
+    /// Restore the fetcher written by [`Fetcher::save_state`].
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        let mut bytes = [0; 7];
+        state.read_bytes(&mut bytes)?;
+        let [dots, x, tile, attrs, row, lo, hi] = bytes;
+        if dots > FETCH_DOTS || row > 7 {
+            return Err(StateError::Corrupt);
+        }
+
+        *self = Self {
+            dots,
+            x,
+            window: state.read_bool()?,
+            tile,
+            attrs,
+            row,
+            lo,
+            hi,
+        };
+        Ok(())
+    }
+}
+
+/// The background FIFO, holding the rest of a pushed row as a pair of shift
+/// registers.
+#[derive(Debug, Clone, Default)]
+pub(super) struct BgFifo {
+    lo: u8,
+    hi: u8,
+    /// The CGB attributes of the tile the pixels came from.
+    attrs: u8,
+    len: u8,
+}
+
+impl BgFifo {
+    /// Check if every pixel has been shifted out.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Drop every pixel.
+    pub const fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Fill the FIFO with a row of tile data, leftmost pixel in bit 7.
+    pub const fn push(&mut self, lo: u8, hi: u8, attrs: u8) {
+        *self = Self {
+            lo,
+            hi,
+            attrs,
+            len: 8,
+        };
+    }
+
+    /// Shift out the next pixel, as its color index and CGB attributes.
+    pub const fn pop(&mut self) -> (u8, u8) {
+        let color = (self.hi >> 7) << 1 | self.lo >> 7;
+        self.lo <<= 1;
+        self.hi <<= 1;
+        self.len -= 1;
+        (color, self.attrs)
+    }
+}
+
+/// A pixel in the sprite FIFO.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct ObjPixel {
+    /// The color index, where 0 is transparent.
+    pub color: u8,
+    /// The attributes of the sprite.
+    pub attrs: u8,
+    /// The index of the sprite in OAM.
+    pub index: u8,
+}
+
+/// The sprite FIFO, lined up with the next 8 pixels of the background.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct ObjFifo {
+    pixels: [ObjPixel; 8],
+}
+
+impl ObjFifo {
+    /// Shift out the next pixel, which is transparent if no sprite covers
+    /// it.
+    pub fn pop(&mut self) -> ObjPixel {
+        let pixel = self.pixels[0];
+        self.pixels.rotate_left(1);
+        self.pixels[7] = ObjPixel::default();
+        pixel
+    }
+
+    /// Merge a fetched sprite pixel into `slot`.
+    ///
+    /// An opaque pixel already there is kept, as it came from a sprite
+    /// fetched earlier. With `by_index`, as on CGB, the sprite earlier in
+    /// OAM takes the pixel instead.
+    pub const fn merge(&mut self, slot: usize, pixel: ObjPixel, by_index: bool) {
+        let old = &mut self.pixels[slot];
+        if pixel.color != 0 && (old.color == 0 || by_index && pixel.index < old.index) {
+            *old = pixel;
+        }
+    }
+}
+
+/// The state of mode 3, drawing the current line.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Fifo {
+    pub fetcher: Fetcher,
+    pub bg: BgFifo,
+    pub obj: ObjFifo,
+    /// The next column to draw, up to the width of the LCD.
+    pub lx: u8,
+    /// The dots left of the first fetch of the line, which is thrown away.
+    pub startup: u8,
+    /// The background pixels left to drop before drawing, for fine
+    /// scrolling.
+    pub discard: u8,
+    /// The index in the line's sprites of the sprite being fetched, with the
+    /// dots left until its pixels are merged.
+    pub obj_fetch: Option<(u8, u8)>,
+    /// The line's sprites already fetched, one bit each.
+    pub fetched: u16,
+}
+
+impl Fifo {
+    /// Prepare for drawing a line scrolled horizontally by `scx`.
+    pub fn start_line(&mut self, scx: u8) {
+        *self = Self {
+            startup: FETCH_DOTS,
+            discard: scx & 7,
+            ..Self::default()
+        };
+    }
+
+    /// Write the drawing state to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.fetcher.save_state(state);
+        state.write_bytes(&[self.bg.lo, self.bg.hi, self.bg.attrs, self.bg.len]);
+        for pixel in &self.obj.pixels {
+            state.write_bytes(&[pixel.color, pixel.attrs, pixel.index]);
+        }
+
+        state.write_bytes(&[self.lx, self.startup, self.discard]);
+        let (sprite, dots) = self.obj_fetch.unwrap_or((u8::MAX, 0));
+        state.write_u8(sprite);
+        state.write_u8(dots);
+        state.write_u16(self.fetched);
+    }
+
+    /// Restore the drawing state written by [`Fifo::save_state`], for a
+    /// line with `sprites` sprites and `width` columns.
+    pub fn load_state(
+        &mut self,
+        state: &mut StateReader<'_>,
+        sprites: usize,
+        width: usize,
+    ) -> Result<(), StateError> {
+        self.fetcher.load_state(state)?;
+
+        let mut bg = [0; 4];
+        state.read_bytes(&mut bg)?;
+        let [lo, hi, attrs, len] = bg;
+        if len > 8 {
+            return Err(StateError::Corrupt);
+        }
+        self.bg = BgFifo { lo, hi, attrs, len };
+
+        for pixel in &mut self.obj.pixels {
+            let mut bytes = [0; 3];
+            state.read_bytes(&mut bytes)?;
+            let [color, attrs, index] = bytes;
+            *pixel = ObjPixel {
+                color: color & 0x03,
+                attrs,
+                index,
+            };
+        }
+
+        let mut bytes = [0; 5];
+        state.read_bytes(&mut bytes)?;
+        let [lx, startup, discard, sprite, dots] = bytes;
+        if usize::from(lx) > width || startup > FETCH_DOTS || discard > 7 {
+            return Err(StateError::Corrupt);
+        }
+        (self.lx, self.startup, self.discard) = (lx, startup, discard);
+
+        self.obj_fetch = match sprite {
+            u8::MAX => None,
+            _ if usize::from(sprite) < sprites && dots > 0 => Some((sprite, dots)),
+            _ => return Err(StateError::Corrupt),
+        };
+        self.fetched = state.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bg_fifo_shifts_out_leftmost_pixel_first() {
+        let mut fifo = BgFifo::default();
+        fifo.push(0b1010_0000, 0b1100_0000, 0x03);
+
+        assert_eq!(fifo.pop(), (3, 0x03));
+        assert_eq!(fifo.pop(), (2, 0x03));
+        assert_eq!(fifo.pop(), (1, 0x03));
+        for _ in 0..5 {
+            assert_eq!(fifo.pop().0, 0);
+        }
+        assert!(fifo.is_empty());
+    }
+
+    #[test]
+    fn obj_fifo_keeps_first_opaque_pixel() {
+        let mut fifo = ObjFifo::default();
+        let pixel = |color, index| ObjPixel {
+            color,
+            attrs: 0,
+            index,
+        };
+
+        fifo.merge(0, pixel(0, 0), false);
+        fifo.merge(0, pixel(1, 5), false);
+        fifo.merge(0, pixel(2, 3), false);
+        fifo.merge(1, pixel(1, 5), true);
+        fifo.merge(1, pixel(2, 3), true);
+
+        assert_eq!(fifo.pop(), pixel(1, 5));
+        assert_eq!(fifo.pop(), pixel(2, 3));
+        assert_eq!(fifo.pop(), ObjPixel::default());
+    }
+}
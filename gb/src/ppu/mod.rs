@@ -0,0 +1,2566 @@
+//! The picture processing unit.
+
+mod compat;
+mod fifo;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub use self::compat::CompatPalette;
+use self::fifo::{FETCH_DOTS, Fetcher, Fifo, ObjPixel};
+use crate::interrupt::Interrupt;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The address of the LCD control register.
+pub const LCDC: u16 = 0xFF40;
+/// The address of the LCD status register.
+pub const STAT: u16 = 0xFF41;
+/// The address of the background vertical scroll register.
+pub const SCY: u16 = 0xFF42;
+/// The address of the background horizontal scroll register.
+pub const SCX: u16 = 0xFF43;
+/// The address of the current scanline register.
+pub const LY: u16 = 0xFF44;
+/// The address of the scanline compare register.
+pub const LYC: u16 = 0xFF45;
+/// The address of the background palette register.
+pub const BGP: u16 = 0xFF47;
+/// The address of the first object palette register.
+pub const OBP0: u16 = 0xFF48;
+/// The address of the second object palette register.
+pub const OBP1: u16 = 0xFF49;
+/// The address of the window vertical position register.
+pub const WY: u16 = 0xFF4A;
+/// The address of the window horizontal position register, plus 7.
+pub const WX: u16 = 0xFF4B;
+/// The address of the CGB VRAM bank register.
+pub const VBK: u16 = 0xFF4F;
+/// The address of the CGB background palette index register.
+pub const BCPS: u16 = 0xFF68;
+/// The address of the CGB background palette data register.
+pub const BCPD: u16 = 0xFF69;
+/// The address of the CGB object palette index register.
+pub const OCPS: u16 = 0xFF6A;
+/// The address of the CGB object palette data register.
+pub const OCPD: u16 = 0xFF6B;
+
+/// The width of the LCD in pixels.
+pub const WIDTH: usize = 160;
+/// The height of the LCD in pixels.
+pub const HEIGHT: usize = 144;
+
+/// The number of dots in a scanline.
+const LINE_DOTS: u16 = 456;
+/// The number of dots spent in OAM scan.
+const OAM_SCAN_DOTS: u16 = 80;
+/// The number of scanlines in a frame, including vertical blanking.
+const FRAME_LINES: u8 = 154;
+
+/// The `LCDC` bit that enables the LCD and PPU.
+const LCD_ENABLE: u8 = 0x80;
+/// The `LCDC` bit that selects the `0x9C00` window tile map.
+const WINDOW_MAP: u8 = 0x40;
+/// The `LCDC` bit that enables the window.
+const WINDOW_ENABLE: u8 = 0x20;
+/// The `LCDC` bit that selects the `0x9C00` background tile map.
+const BG_MAP: u8 = 0x08;
+/// The `LCDC` bit that selects unsigned tile data addressing from `0x8000`.
+const TILE_DATA: u8 = 0x10;
+/// The `LCDC` bit that selects 8x16 sprites.
+const OBJ_SIZE: u8 = 0x04;
+/// The `LCDC` bit that enables sprites.
+const OBJ_ENABLE: u8 = 0x02;
+/// The `LCDC` bit that enables the background on DMG.
+const BG_ENABLE: u8 = 0x01;
+
+/// The `STAT` bit that enables the `LY == LYC` interrupt.
+const LYC_INT: u8 = 0x40;
+/// The `STAT` bit that enables the mode 2 interrupt.
+const OAM_INT: u8 = 0x20;
+/// The `STAT` bit that enables the mode 1 interrupt.
+const VBLANK_INT: u8 = 0x10;
+/// The `STAT` bit that enables the mode 0 interrupt.
+const HBLANK_INT: u8 = 0x08;
+/// The `STAT` bit set while `LY == LYC`.
+const COINCIDENCE: u8 = 0x04;
+
+/// The most sprites the OAM scan selects for one scanline.
+const MAX_LINE_SPRITES: usize = 10;
+/// The dots the pixel FIFOs stall for while a sprite is fetched.
+const OBJ_FETCH_DOTS: u8 = 6;
+/// The dots the background fetcher must have spent on a row before a sprite
+/// fetch can take over.
+const OBJ_FETCH_WAIT: u8 = 5;
+
+/// The size of a VRAM bank.
+const VRAM_BANK_SIZE: usize = 0x2000;
+
+/// The sprite attribute bit that draws the background over the sprite, and
+/// the CGB tile attribute bit that draws the tile over sprites.
+const ATTR_BEHIND_BG: u8 = 0x80;
+/// The sprite or CGB tile attribute bit that flips it vertically.
+const ATTR_Y_FLIP: u8 = 0x40;
+/// The sprite or CGB tile attribute bit that flips it horizontally.
+const ATTR_X_FLIP: u8 = 0x20;
+/// The sprite attribute bit that selects `OBP1` on DMG.
+const ATTR_PALETTE: u8 = 0x10;
+/// The sprite or CGB tile attribute bit that fetches tile data from VRAM
+/// bank 1.
+const ATTR_BANK: u8 = 0x08;
+/// The sprite or CGB tile attribute bits that select a color palette.
+const ATTR_CGB_PALETTE: u8 = 0x07;
+
+/// The CGB framebuffer bit that marks a sprite pixel.
+const CGB_OBJ_PIXEL: u8 = 0x20;
+
+/// The shift of the palette a DMG pixel was drawn with, 0 for `BGP` and 1
+/// or 2 for `OBP0` and `OBP1`, which is dropped from the framebuffer.
+const DMG_PALETTE_SHIFT: u8 = 2;
+
+/// The RGBA8888 colors the four DMG shades are drawn in, from lightest to
+/// darkest.
+///
+/// The shades are what `BGP`, `OBP0` and `OBP1` map color indices to, so a
+/// palette only changes how the LCD looks, not what games see.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DmgPalette(pub [[u8; 4]; 4]);
+
+impl DmgPalette {
+    /// Plain gray scale, the default.
+    pub const GRAYSCALE: Self = Self([
+        [0xFF, 0xFF, 0xFF, 0xFF],
+        [0xAA, 0xAA, 0xAA, 0xFF],
+        [0x55, 0x55, 0x55, 0xFF],
+        [0x00, 0x00, 0x00, 0xFF],
+    ]);
+
+    /// The green of the original DMG LCD.
+    pub const DMG: Self = Self([
+        [0x9B, 0xBC, 0x0F, 0xFF],
+        [0x8B, 0xAC, 0x0F, 0xFF],
+        [0x30, 0x62, 0x30, 0xFF],
+        [0x0F, 0x38, 0x0F, 0xFF],
+    ]);
+
+    /// The olive gray of the Game Boy Pocket LCD.
+    pub const POCKET: Self = Self([
+        [0xC4, 0xCF, 0xA1, 0xFF],
+        [0x8B, 0x95, 0x6D, 0xFF],
+        [0x4D, 0x53, 0x3C, 0xFF],
+        [0x1F, 0x1F, 0x1F, 0xFF],
+    ]);
+
+    /// The blue green of the backlit Game Boy Light LCD.
+    pub const LIGHT: Self = Self([
+        [0x00, 0xB5, 0x81, 0xFF],
+        [0x00, 0x9A, 0x71, 0xFF],
+        [0x00, 0x69, 0x4A, 0xFF],
+        [0x00, 0x4F, 0x3B, 0xFF],
+    ]);
+
+    /// Return the color of `shade`, of which only the low 2 bits are used.
+    #[must_use]
+    pub const fn color(self, shade: u8) -> [u8; 4] {
+        self.0[(shade & 3) as usize]
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        Self::GRAYSCALE
+    }
+}
+
+/// Convert a CGB RGB555 color, with red in the lowest bits, to RGB888.
+#[must_use]
+pub const fn rgb555_to_rgb888(color: u16) -> [u8; 3] {
+    // Repeating the top bits in the bottom ones maps 0x1F to 0xFF.
+    #[allow(clippy::cast_possible_truncation)] // Each channel is 5 bits.
+    const fn expand(channel: u16) -> u8 {
+        let channel = (channel & 0x1F) as u8;
+        channel << 3 | channel >> 2
+    }
+
+    [expand(color), expand(color >> 5), expand(color >> 10)]
+}
+
+/// The CGB palette RAM for either backgrounds or objects, of eight palettes
+/// of four RGB555 colors.
+#[derive(Debug, Clone)]
+struct PaletteRam {
+    /// The colors, two little-endian bytes each.
+    data: [u8; 64],
+    /// The index register, with the byte index in bits 0-5 and
+    /// auto-increment in bit 7.
+    spec: u8,
+}
+
+impl PaletteRam {
+    /// Create palette RAM with every color set to `fill`.
+    const fn new(fill: u8) -> Self {
+        Self {
+            data: [fill; 64],
+            spec: 0,
+        }
+    }
+
+    /// Read the index register.
+    const fn read_spec(&self) -> u8 {
+        0x40 | self.spec
+    }
+
+    /// Write the index register.
+    const fn write_spec(&mut self, value: u8) {
+        self.spec = value & 0xBF;
+    }
+
+    /// Read the byte selected by the index register.
+    const fn read_data(&self) -> u8 {
+        self.data[(self.spec & 0x3F) as usize]
+    }
+
+    /// Write the byte selected by the index register, then advance the
+    /// index if auto-increment is set.
+    const fn write_data(&mut self, value: u8) {
+        self.data[(self.spec & 0x3F) as usize] = value;
+        if self.spec & 0x80 != 0 {
+            self.spec = 0x80 | (self.spec + 1) & 0x3F;
+        }
+    }
+
+    /// Return color `color` of palette `palette` as RGB555.
+    fn color(&self, palette: u8, color: u8) -> u16 {
+        let index = usize::from(palette * 8 + color * 2);
+        u16::from_le_bytes([self.data[index], self.data[index + 1]])
+    }
+
+    /// Write the palette RAM to a save state.
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.data);
+        state.write_u8(self.spec);
+    }
+
+    /// Restore the palette RAM written by [`PaletteRam::save_state`].
+    fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        state.read_bytes(&mut self.data)?;
+        self.spec = state.read_u8()? & 0xBF;
+        Ok(())
+    }
+}
+
+/// The frames kept for blending each frame with the last, as an LCD's slow
+/// pixel response does.
+#[derive(Debug, Clone)]
+struct FrameBlend {
+    /// The last frame completed, as drawn, in RGBA8888.
+    previous: Box<[u8]>,
+    /// The average of the last two frames completed, in RGBA8888.
+    output: Box<[u8]>,
+}
+
+impl FrameBlend {
+    /// Start blending from `frame`, as if it were drawn twice.
+    fn new(frame: &[u8]) -> Self {
+        Self {
+            previous: frame.into(),
+            output: frame.into(),
+        }
+    }
+
+    /// Blend the completed `frame` with the last one.
+    fn push(&mut self, frame: &[u8]) {
+        let pixels = self.output.iter_mut().zip(&self.previous).zip(frame);
+        for ((output, &previous), &current) in pixels {
+            *output = previous.midpoint(current);
+        }
+        self.previous.copy_from_slice(frame);
+    }
+}
+
+/// A sprite entry in OAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sprite {
+    /// The vertical position plus 16.
+    y: u8,
+    /// The horizontal position plus 8.
+    x: u8,
+    tile: u8,
+    attrs: u8,
+    /// The index of the sprite in OAM.
+    index: u8,
+}
+
+/// A sprite selected by OAM scan, as reported by
+/// [`Ppu::debug_scanline_sprites`].
+///
+/// The positions are as stored in OAM, offset by 16 vertically and 8
+/// horizontally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpriteInfo {
+    /// The index of the sprite in OAM, from 0 to 39.
+    pub index: u8,
+    /// The horizontal position plus 8.
+    pub x: u8,
+    /// The vertical position plus 16.
+    pub y: u8,
+    /// The tile index, as stored in OAM.
+    pub tile: u8,
+    /// The attribute flags.
+    pub attrs: u8,
+}
+
+impl From<Sprite> for SpriteInfo {
+    fn from(sprite: Sprite) -> Self {
+        Self {
+            index: sprite.index,
+            x: sprite.x,
+            y: sprite.y,
+            tile: sprite.tile,
+            attrs: sprite.attrs,
+        }
+    }
+}
+
+/// A background tile map entry, as reported by [`Ppu::debug_bg_tile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileRef {
+    /// The VRAM address of the tile map entry.
+    pub map_addr: u16,
+    /// The tile index in the map.
+    pub tile: u8,
+    /// The CGB attributes from VRAM bank 1, or 0 on DMG.
+    pub attrs: u8,
+    /// The VRAM address of the tile data, following `LCDC` bit 4.
+    pub data_addr: u16,
+    /// The VRAM bank of the tile data.
+    pub data_bank: u8,
+}
+
+/// The scroll and window positions, as reported by [`Ppu::debug_viewport`]
+/// for overlaying on [`Ppu::render_tilemap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Viewport {
+    /// The column of the background map at the left of the screen, from
+    /// `SCX`. The screen wraps around the edges of the map.
+    pub scroll_x: u8,
+    /// The row of the background map at the top of the screen, from `SCY`.
+    pub scroll_y: u8,
+    /// The screen column of the left of the window plus 7, from `WX`.
+    pub window_x: u8,
+    /// The screen row of the top of the window, from `WY`.
+    pub window_y: u8,
+    /// Whether `LCDC` enables the window.
+    pub window_enabled: bool,
+    /// The tile map of the background, 0 for `0x9800` or 1 for `0x9C00`.
+    pub bg_map: u8,
+    /// The tile map of the window, 0 for `0x9800` or 1 for `0x9C00`.
+    pub window_map: u8,
+}
+
+/// The PPU mode, as reported in the lower bits of `STAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    /// Horizontal blanking, after a line is drawn.
+    HBlank = 0,
+    /// Vertical blanking, after the last visible line.
+    VBlank = 1,
+    /// Searching OAM for the sprites on the line.
+    OamScan = 2,
+    /// Transferring pixels to the LCD.
+    Drawing = 3,
+}
+
+/// An access that corrupts OAM on DMG when made to `0xFE00-0xFEFF` during
+/// OAM scan, see [`Ppu::corrupt_oam`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OamCorruption {
+    /// A write, or a 16-bit increment or decrement like `INC rr`.
+    Write,
+    /// A read.
+    Read,
+    /// A read in the same cycle as an increment or decrement of the address,
+    /// like `POP rr` and `LD A,(HL+)`.
+    ReadIncrement,
+}
+
+/// A layer of the picture, which can be hidden with
+/// [`Ppu::set_layer_enabled`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layer {
+    /// The scrolling background.
+    Background,
+    /// The window drawn over the background.
+    Window,
+    /// The sprites in OAM.
+    Sprites,
+}
+
+impl Layer {
+    /// Every layer, from the bottom up.
+    pub const ALL: [Self; 3] = [Self::Background, Self::Window, Self::Sprites];
+
+    /// Return the bit of the layer in a layer mask.
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The picture processing unit.
+///
+/// Owns VRAM, OAM and the LCD registers, and draws into a framebuffer of
+/// 2-bit DMG shades, where 0 is the lightest.
+///
+/// Lines are drawn a dot at a time through the pixel FIFOs, like the
+/// hardware does. Mode 3 takes 172 dots, plus a dot for each pixel dropped
+/// for fine scrolling, 6 dots to switch to the window and 6 to 11 dots for
+/// each sprite fetched. Registers written during mode 3 take effect on the
+/// pixels drawn after the write.
+///
+/// In CGB mode there's a second VRAM bank, selected through `VBK`. Bank 1
+/// holds an attribute byte for each tile map entry, selecting the tile's
+/// palette, data bank, flips and priority over sprites. The palettes
+/// themselves are RGB555 colors in palette RAM, reached through `BCPS` and
+/// `BCPD` for backgrounds and `OCPS` and `OCPD` for sprites.
+///
+/// Alongside the framebuffer of indices, every frame is rendered to
+/// RGBA8888, through a [`DmgPalette`] on DMG.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Ppu {
+    cgb: bool,
+    /// Whether writing `STAT` requests a STAT interrupt as if every source
+    /// were enabled, as on the models without CGB hardware.
+    stat_write_bug: bool,
+    /// Whether CPU accesses to OAM during OAM scan corrupt it, as on the
+    /// models without CGB hardware.
+    oam_bug: bool,
+    /// Both VRAM banks, of which DMG only uses the first.
+    vram: Box<[u8]>,
+    /// The VRAM bank the CPU sees, from `VBK`.
+    vram_bank: u8,
+    oam: Box<[u8]>,
+    framebuffer: Box<[u8]>,
+    /// The framebuffer as RGBA8888, four bytes per pixel.
+    rgba_framebuffer: Box<[u8]>,
+    /// The blend of the last two frames, while frame blending is on.
+    blend: Option<FrameBlend>,
+    /// The colors of the DMG shades in `rgba_framebuffer`.
+    palette: DmgPalette,
+    /// The CGB colors of the DMG palettes, replacing `palette` when set.
+    compat_palette: Option<CompatPalette>,
+    bg_palettes: PaletteRam,
+    obj_palettes: PaletteRam,
+    lcdc: u8,
+    /// The writable interrupt enable bits of `STAT`.
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    /// The dot within the current scanline.
+    dot: u16,
+    /// Whether `LY` has matched `WY` during this frame.
+    window_triggered: bool,
+    /// The internal window line counter, which only advances on lines where
+    /// the window was drawn.
+    window_line: u8,
+    /// The sprites selected by OAM scan for the current line, in OAM order
+    /// on CGB and in drawing priority order on DMG.
+    line_sprites: Vec<Sprite>,
+    /// The pixel FIFOs and background fetcher, while drawing a line.
+    fifo: Fifo,
+    /// The internal STAT interrupt line, the OR of every enabled condition.
+    stat_line: bool,
+    /// The interrupts requested since they were last taken.
+    interrupts: u8,
+    /// Whether a frame was completed since it was last taken.
+    frame_ready: bool,
+    /// Whether horizontal blanking started since it was last taken.
+    hblank_started: bool,
+    /// Whether this is the first line since the LCD turned on, which spends
+    /// the dots of OAM scan in mode 0 without scanning.
+    first_line: bool,
+    /// The number of frames completed since power-on.
+    frames: u64,
+    /// Whether pixel output is skipped, for fast-forwarding.
+    fast_forward: bool,
+    /// Whether pixels are skipped on the current frame. This follows
+    /// `fast_forward` from the start of each frame, so output only resumes
+    /// on a whole frame.
+    skip_output: bool,
+    /// The frames left undrawn after each one drawn.
+    frame_skip: u8,
+    /// The frames completed since the last one drawn, wrapping past
+    /// `frame_skip`.
+    frame_phase: u8,
+    /// Whether frame skipping leaves the current frame undrawn.
+    skip_frame: bool,
+    /// The layers hidden from the picture, as a mask of [`Layer::bit`]s.
+    hidden_layers: u8,
+}
+
+impl Ppu {
+    /// Create a PPU in the state the DMG boot ROM leaves it in.
+    #[must_use]
+    pub fn new() -> Self {
+        let mut ppu = Self {
+            cgb: false,
+            stat_write_bug: true,
+            oam_bug: true,
+            vram: vec![0; 2 * VRAM_BANK_SIZE].into_boxed_slice(),
+            vram_bank: 0,
+            oam: vec![0; 0xA0].into_boxed_slice(),
+            framebuffer: vec![0; WIDTH * HEIGHT].into_boxed_slice(),
+            rgba_framebuffer: vec![0xFF; WIDTH * HEIGHT * 4].into_boxed_slice(),
+            blend: None,
+            palette: DmgPalette::default(),
+            compat_palette: None,
+            // The CGB boot ROM sets the background palettes to white.
+            bg_palettes: PaletteRam::new(0xFF),
+            obj_palettes: PaletteRam::new(0x00),
+            lcdc: 0x91,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0,
+            wx: 0,
+            mode: Mode::OamScan,
+            dot: 0,
+            window_triggered: false,
+            window_line: 0,
+            line_sprites: Vec::with_capacity(MAX_LINE_SPRITES),
+            fifo: Fifo::default(),
+            stat_line: false,
+            interrupts: 0,
+            frame_ready: false,
+            hblank_started: false,
+            first_line: false,
+            frames: 0,
+            fast_forward: false,
+            skip_output: false,
+            frame_skip: 0,
+            frame_phase: 0,
+            skip_frame: false,
+            hidden_layers: 0,
+        };
+
+        ppu.start_line();
+        ppu
+    }
+
+    /// Create a PPU in CGB mode, in the state the CGB boot ROM leaves it in.
+    #[must_use]
+    pub fn new_cgb() -> Self {
+        Self {
+            cgb: true,
+            stat_write_bug: false,
+            oam_bug: false,
+            ..Self::new()
+        }
+    }
+
+    /// Check if the PPU is in CGB mode.
+    #[must_use]
+    pub const fn is_cgb(&self) -> bool {
+        self.cgb
+    }
+
+    /// Set whether writing `STAT` in H-blank, V-blank or on an `LY` match
+    /// requests a STAT interrupt, see [`crate::Model::has_stat_write_bug`].
+    /// A PPU created by [`Ppu::new`] has the bug, and one created by
+    /// [`Ppu::new_cgb`] doesn't.
+    pub const fn set_stat_write_bug(&mut self, enabled: bool) {
+        self.stat_write_bug = enabled;
+    }
+
+    /// Set whether [`Ppu::corrupt_oam`] corrupts OAM, see
+    /// [`crate::Model::has_oam_bug`]. A PPU created by [`Ppu::new`] has the
+    /// bug, and one created by [`Ppu::new_cgb`] doesn't.
+    pub const fn set_oam_bug(&mut self, enabled: bool) {
+        self.oam_bug = enabled;
+    }
+
+    /// Corrupt OAM as the DMG does when the CPU puts an address in
+    /// `0xFE00-0xFEFF` on the bus during OAM scan, clashing with the PPU
+    /// reading the row of 8 bytes it has reached.
+    ///
+    /// The patterns are those of the "OAM Corruption Bug" section of Pan
+    /// Docs, applied to 16-bit words: the first word of the row is mixed
+    /// with words of the row before, and the rest is copied from it. The
+    /// first row is never corrupted. Outside OAM scan, or without the bug,
+    /// nothing happens.
+    pub fn corrupt_oam(&mut self, corruption: OamCorruption) {
+        if !self.oam_bug || !self.is_enabled() || self.mode != Mode::OamScan {
+            return;
+        }
+
+        let row = usize::from(self.dot / 4);
+        if corruption == OamCorruption::ReadIncrement && (4..19).contains(&row) {
+            let a = self.oam_word(row - 2, 0);
+            let b = self.oam_word(row - 1, 0);
+            let c = self.oam_word(row, 0);
+            let d = self.oam_word(row - 1, 2);
+            self.set_oam_word(row - 1, (b & (a | c | d)) | (a & c & d));
+            self.oam.copy_within((row - 1) * 8..row * 8, row * 8);
+            self.oam.copy_within((row - 1) * 8..row * 8, (row - 2) * 8);
+        }
+        if row == 0 {
+            return;
+        }
+
+        let a = self.oam_word(row, 0);
+        let b = self.oam_word(row - 1, 0);
+        let c = self.oam_word(row - 1, 2);
+        let first = match corruption {
+            OamCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamCorruption::Read | OamCorruption::ReadIncrement => b | (a & c),
+        };
+        self.set_oam_word(row, first);
+        self.oam.copy_within((row - 1) * 8 + 2..row * 8, row * 8 + 2);
+    }
+
+    /// Return word `word` of OAM row `row`.
+    fn oam_word(&self, row: usize, word: usize) -> u16 {
+        let index = row * 8 + word * 2;
+        u16::from_le_bytes([self.oam[index], self.oam[index + 1]])
+    }
+
+    /// Set the first word of OAM row `row`.
+    fn set_oam_word(&mut self, row: usize, value: u16) {
+        self.oam[row * 8..row * 8 + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Return the framebuffer, row by row.
+    ///
+    /// On DMG each pixel is a 2-bit shade. In CGB mode each pixel is instead
+    /// a color index in bits 0-1 and a palette in bits 2-4, with bit 5 set
+    /// for sprite palettes.
+    #[must_use]
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    /// Return the framebuffer as RGBA8888, row by row with four bytes per
+    /// pixel.
+    ///
+    /// On DMG the shades are drawn in the colors set by
+    /// [`Ppu::set_palette`], and in CGB mode the colors are looked up in
+    /// palette RAM as each line is drawn.
+    ///
+    /// With frame blending on, this is the average of the last two frames
+    /// completed instead, see [`Ppu::set_frame_blending`].
+    #[must_use]
+    pub fn rgba_framebuffer(&self) -> &[u8] {
+        self.blend.as_ref().map_or(&self.rgba_framebuffer, |blend| &blend.output)
+    }
+
+    /// Return the framebuffer as RGBA8888 as it's drawn, without frame
+    /// blending.
+    #[must_use]
+    pub fn raw_rgba_framebuffer(&self) -> &[u8] {
+        &self.rgba_framebuffer
+    }
+
+    /// Check if frames are blended, see [`Ppu::set_frame_blending`].
+    #[must_use]
+    pub const fn is_frame_blending(&self) -> bool {
+        self.blend.is_some()
+    }
+
+    /// Blend each frame with the last in [`Ppu::rgba_framebuffer`] while
+    /// `enabled`, like the slow pixel response of the DMG's LCD.
+    ///
+    /// Games flicker sprites or whole layers on alternate frames to fake
+    /// transparency and extra shades, which the LCD smoothed over. Each
+    /// color channel is averaged, so the blend is of the colors the shades
+    /// are drawn in. The blend changes once per frame, as each one
+    /// completes, starting from the frame already drawn.
+    pub fn set_frame_blending(&mut self, enabled: bool) {
+        if enabled != self.blend.is_some() {
+            self.blend = enabled.then(|| FrameBlend::new(&self.rgba_framebuffer));
+        }
+    }
+
+    /// Return the colors the DMG shades are drawn in.
+    #[must_use]
+    pub const fn palette(&self) -> DmgPalette {
+        self.palette
+    }
+
+    /// Draw the DMG shades in the colors of `palette`, which has no effect
+    /// in CGB mode. The frame already drawn is recolored as well.
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.palette = palette;
+        self.recolor();
+    }
+
+    /// Return the CGB colors the DMG palettes are drawn in, if set.
+    #[must_use]
+    pub const fn compat_palette(&self) -> Option<CompatPalette> {
+        self.compat_palette
+    }
+
+    /// Draw the shades of `BGP`, `OBP0` and `OBP1` in separate colors from
+    /// `palette`, the way a CGB draws DMG games, or in the [`DmgPalette`]
+    /// again with `None`. This has no effect in CGB mode.
+    ///
+    /// The framebuffer doesn't keep which palette a shade came from, so the
+    /// colors change from the next pixel drawn rather than for the whole
+    /// frame.
+    pub const fn set_compat_palette(&mut self, palette: Option<CompatPalette>) {
+        self.compat_palette = palette;
+    }
+
+    /// Check if `layer` is drawn, see [`Ppu::set_layer_enabled`].
+    #[must_use]
+    pub const fn is_layer_enabled(&self, layer: Layer) -> bool {
+        self.hidden_layers & layer.bit() == 0
+    }
+
+    /// Show or hide `layer` in the picture, for debugging.
+    ///
+    /// This only changes the pixels drawn: `LCDC` reads back as the game
+    /// wrote it, and the timing of mode 3 still follows it. A hidden
+    /// background or window draws color 0 in its place, like a background
+    /// turned off through `LCDC` on DMG, and hidden sprites let the
+    /// background show through.
+    pub const fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        if enabled {
+            self.hidden_layers &= !layer.bit();
+        } else {
+            self.hidden_layers |= layer.bit();
+        }
+    }
+
+    /// Check if pixel output is skipped, see [`Ppu::set_fast_forward`].
+    #[must_use]
+    pub const fn is_fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Skip drawing pixels into the framebuffers while `enabled`, keeping
+    /// the timing of the modes, `STAT` and interrupts exact.
+    ///
+    /// Output stops straight away, but only resumes at the start of the next
+    /// frame, so no frame mixes in lines left from before.
+    pub const fn set_fast_forward(&mut self, enabled: bool) {
+        self.fast_forward = enabled;
+        self.skip_output |= enabled;
+    }
+
+    /// Return the frames left undrawn after each one drawn, see
+    /// [`Ppu::set_frame_skip`].
+    #[must_use]
+    pub const fn frame_skip(&self) -> u8 {
+        self.frame_skip
+    }
+
+    /// Draw only one frame in every `frames + 1`, leaving the framebuffers
+    /// with the last frame drawn in between. Timing, interrupts and
+    /// [`Ppu::take_frame_ready`] are kept for every frame.
+    ///
+    /// Frames are skipped whole, from the start of the next frame, so no
+    /// frame mixes in lines left from before.
+    pub const fn set_frame_skip(&mut self, frames: u8) {
+        self.frame_skip = frames;
+        self.frame_phase = 0;
+    }
+
+    /// Check if frame skipping leaves the current frame undrawn, see
+    /// [`Ppu::set_frame_skip`]. In V-blank, this is the frame just
+    /// completed.
+    #[must_use]
+    pub const fn is_frame_skipped(&self) -> bool {
+        self.skip_frame
+    }
+
+    /// Return the sprites OAM scan selects for line `ly` from the current
+    /// OAM, at most ten, in priority order.
+    ///
+    /// On DMG the sprite with the smaller X comes first, then the earlier in
+    /// OAM, while on CGB only OAM order counts. Rendering isn't affected.
+    #[must_use]
+    pub fn debug_scanline_sprites(&self, ly: u8) -> Vec<SpriteInfo> {
+        let mut sprites = Vec::with_capacity(MAX_LINE_SPRITES);
+        self.select_sprites(ly, &mut sprites);
+        sprites.into_iter().map(SpriteInfo::from).collect()
+    }
+
+    /// Return the entry at column `map_x` and row `map_y` of the background
+    /// tile map selected by `LCDC` bit 3, wrapping both to 32.
+    #[must_use]
+    pub fn debug_bg_tile(&self, map_x: u8, map_y: u8) -> TileRef {
+        let map = if self.lcdc & BG_MAP == 0 { 0x9800 } else { 0x9C00 };
+        let map_addr = map + u16::from(map_y % 32) * 32 + u16::from(map_x % 32);
+        let tile = self.vram(0, map_addr);
+        let attrs = if self.cgb { self.vram(1, map_addr) } else { 0 };
+
+        TileRef {
+            map_addr,
+            tile,
+            attrs,
+            data_addr: self.tile_addr(tile),
+            data_bank: self.tile_bank(attrs),
+        }
+    }
+
+    /// Return the scroll and window positions.
+    #[must_use]
+    pub fn debug_viewport(&self) -> Viewport {
+        Viewport {
+            scroll_x: self.scx,
+            scroll_y: self.scy,
+            window_x: self.wx,
+            window_y: self.wy,
+            window_enabled: self.lcdc & WINDOW_ENABLE != 0,
+            bg_map: u8::from(self.lcdc & BG_MAP != 0),
+            window_map: u8::from(self.lcdc & WINDOW_MAP != 0),
+        }
+    }
+
+    /// Draw the 384 tiles of VRAM bank 0 as a 128×192 RGBA8888 image, 16
+    /// tiles to a row, in the colors of `BGP` or CGB background palette 0.
+    ///
+    /// This only reads VRAM, so it works in any mode.
+    #[must_use]
+    pub fn render_tile_data(&self) -> Vec<u8> {
+        self.render_tile_bank(0)
+    }
+
+    /// Draw the 384 tiles of VRAM bank `bank` like
+    /// [`Ppu::render_tile_data`]. Bank 1 only holds tiles in CGB mode.
+    #[must_use]
+    pub fn render_tile_bank(&self, bank: u8) -> Vec<u8> {
+        let mut image = Vec::with_capacity(128 * 192 * 4);
+        for y in 0..192 {
+            for tile in y / 8 * 16..y / 8 * 16 + 16 {
+                let addr = 0x8000 + tile * 16 + y % 8 * 2;
+                for bit in (0..8).rev() {
+                    let color = self.tile_color(bank & 1, addr, bit);
+                    image.extend_from_slice(&self.rgba(self.bg_pixel(color, 0)));
+                }
+            }
+        }
+        image
+    }
+
+    /// Draw tile map `map`, 0 for `0x9800` or 1 for `0x9C00`, as a 256×256
+    /// RGBA8888 image, as the background would show it.
+    ///
+    /// Tiles are addressed as `LCDC` bit 4 selects, and drawn in the colors
+    /// of `BGP`. In CGB mode the attributes in bank 1 pick each tile's bank,
+    /// palette and flips. This only reads VRAM, so it works in any mode, see
+    /// [`Ppu::debug_viewport`] for where the screen and window sit.
+    #[must_use]
+    pub fn render_tilemap(&self, map: u8) -> Vec<u8> {
+        let base = if map & 1 == 0 { 0x9800 } else { 0x9C00 };
+        let mut image = Vec::with_capacity(256 * 256 * 4);
+        for y in 0..256 {
+            for map_addr in base + y / 8 * 32..base + y / 8 * 32 + 32 {
+                let tile = self.vram(0, map_addr);
+                let attrs = if self.cgb { self.vram(1, map_addr) } else { 0 };
+                let row = if attrs & ATTR_Y_FLIP == 0 { y % 8 } else { 7 - y % 8 };
+                let addr = self.tile_addr(tile) + row * 2;
+                for x in 0..8 {
+                    let bit = if attrs & ATTR_X_FLIP == 0 { 7 - x } else { x };
+                    let color = self.tile_color(self.tile_bank(attrs), addr, bit);
+                    image.extend_from_slice(&self.rgba(self.bg_pixel(color, attrs)));
+                }
+            }
+        }
+        image
+    }
+
+    /// Return the framebuffer pixel of background `color`, in `BGP` on DMG
+    /// and in the palette of `attrs` in CGB mode.
+    const fn bg_pixel(&self, color: u8, attrs: u8) -> u8 {
+        if self.cgb { (attrs & ATTR_CGB_PALETTE) << 2 | color } else { self.bgp >> (color * 2) & 3 }
+    }
+
+    /// Check if the LCD is enabled.
+    #[must_use]
+    pub const fn is_enabled(&self) -> bool {
+        self.lcdc & LCD_ENABLE != 0
+    }
+
+    /// Return the current mode of the mode machine.
+    ///
+    /// With the LCD off this is [`Mode::HBlank`], as `STAT` reads.
+    #[must_use]
+    pub const fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Return the dot within the current scanline, from 0 to 455.
+    ///
+    /// A dot is one T-cycle at normal speed. The dot stays at 0 while the
+    /// LCD is off.
+    #[must_use]
+    pub const fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// Return the number of frames completed since power-on, counting each
+    /// entry into vertical blanking.
+    ///
+    /// Frames don't advance while the LCD is off.
+    #[must_use]
+    pub const fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Return and clear the interrupts requested since the last call, as
+    /// `IF` bits.
+    pub const fn take_interrupts(&mut self) -> u8 {
+        let interrupts = self.interrupts;
+        self.interrupts = 0;
+        interrupts
+    }
+
+    /// Return and clear whether the PPU entered vertical blanking, completing
+    /// a frame, since the last call.
+    pub const fn take_frame_ready(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
+
+    /// Return and clear whether the PPU entered horizontal blanking after
+    /// drawing a line, since the last call.
+    pub const fn take_hblank_started(&mut self) -> bool {
+        let started = self.hblank_started;
+        self.hblank_started = false;
+        started
+    }
+
+    /// Write the memories, registers and mode machine to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.vram);
+        state.write_u8(self.vram_bank);
+        state.write_bytes(&self.oam);
+        state.write_bytes(&self.framebuffer);
+        state.write_bytes(&self.rgba_framebuffer);
+        self.bg_palettes.save_state(state);
+        self.obj_palettes.save_state(state);
+
+        let registers = [
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0,
+            self.obp1, self.wy, self.wx,
+        ];
+        state.write_bytes(&registers);
+
+        state.write_u8(self.mode as u8);
+        state.write_u16(self.dot);
+        state.write_bool(self.window_triggered);
+        state.write_u8(self.window_line);
+
+        // The OAM scan result is kept until the line is drawn, when OAM may
+        // already have changed.
+        #[allow(clippy::cast_possible_truncation)] // At most 10 sprites.
+        state.write_u8(self.line_sprites.len() as u8);
+        for sprite in &self.line_sprites {
+            state.write_bytes(&[sprite.y, sprite.x, sprite.tile, sprite.attrs, sprite.index]);
+        }
+        self.fifo.save_state(state);
+
+        state.write_bool(self.stat_line);
+        state.write_u8(self.interrupts);
+        state.write_bool(self.frame_ready);
+        state.write_bool(self.hblank_started);
+        state.write_bool(self.first_line);
+        state.write_u64(self.frames);
+    }
+
+    /// Restore the state written by [`Ppu::save_state`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is truncated or corrupt.
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        state.read_bytes(&mut self.vram)?;
+        self.vram_bank = state.read_u8()? & 1;
+        state.read_bytes(&mut self.oam)?;
+        state.read_bytes(&mut self.framebuffer)?;
+        state.read_bytes(&mut self.rgba_framebuffer)?;
+        // The state may have been saved with other DMG colors.
+        self.recolor();
+        self.bg_palettes.load_state(state)?;
+        self.obj_palettes.load_state(state)?;
+
+        let mut registers = [0; 11];
+        state.read_bytes(&mut registers)?;
+        let [lcdc, stat, scy, scx, ly, lyc, bgp, obp0, obp1, wy, wx] = registers;
+        (self.lcdc, self.stat, self.scy, self.scx) = (lcdc, stat & 0x78, scy, scx);
+        (self.ly, self.lyc, self.bgp, self.obp0) = (ly, lyc, bgp, obp0);
+        (self.obp1, self.wy, self.wx) = (obp1, wy, wx);
+
+        self.mode = match state.read_u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            _ => return Err(StateError::Corrupt),
+        };
+        self.dot = state.read_u16()?;
+        self.window_triggered = state.read_bool()?;
+        self.window_line = state.read_u8()?;
+        if self.ly >= FRAME_LINES || self.dot >= LINE_DOTS {
+            return Err(StateError::Corrupt);
+        }
+
+        let count = usize::from(state.read_u8()?);
+        if count > MAX_LINE_SPRITES {
+            return Err(StateError::Corrupt);
+        }
+        self.line_sprites.clear();
+        for _ in 0..count {
+            let mut bytes = [0; 5];
+            state.read_bytes(&mut bytes)?;
+            let [y, x, tile, attrs, index] = bytes;
+            // The sprites are kept past their line, but while drawing they
+            // must cover it.
+            if self.mode == Mode::Drawing && usize::from(self.ly) + 16 < usize::from(y) {
+                return Err(StateError::Corrupt);
+            }
+            self.line_sprites.push(Sprite {
+                y,
+                x,
+                tile,
+                attrs,
+                index,
+            });
+        }
+        self.fifo.load_state(state, count, WIDTH)?;
+
+        self.stat_line = state.read_bool()?;
+        self.interrupts = state.read_u8()?;
+        self.frame_ready = state.read_bool()?;
+        self.hblank_started = state.read_bool()?;
+        self.first_line = state.read_bool()?;
+        if self.first_line && (self.ly != 0 || self.mode != Mode::HBlank) {
+            return Err(StateError::Corrupt);
+        }
+        self.frames = state.read_u64()?;
+        Ok(())
+    }
+
+    /// Advance the PPU by `cycles` dots.
+    pub fn tick(&mut self, cycles: u8) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.step_dot();
+        }
+    }
+
+    /// Return the dots until the mode or line next changes, or `None` with
+    /// the LCD off.
+    ///
+    /// In mode 3 this is the earliest the line could end, a pixel a dot,
+    /// since the fetches for sprites and the window only lengthen it.
+    pub(crate) fn dots_until_mode_change(&self) -> Option<u32> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let dots = match self.mode {
+            Mode::OamScan => usize::from(OAM_SCAN_DOTS - self.dot),
+            Mode::HBlank if self.first_line => usize::from(OAM_SCAN_DOTS - self.dot),
+            Mode::Drawing => WIDTH - usize::from(self.fifo.lx),
+            Mode::HBlank | Mode::VBlank => usize::from(LINE_DOTS - self.dot),
+        };
+        u32::try_from(dots).ok()
+    }
+
+    /// Advance the mode machine by one dot.
+    fn step_dot(&mut self) {
+        self.dot += 1;
+
+        match self.mode {
+            Mode::OamScan if self.dot == OAM_SCAN_DOTS => {
+                self.scan_oam();
+                self.fifo.start_line(self.scx);
+                self.mode = Mode::Drawing;
+            }
+            Mode::HBlank if self.first_line && self.dot == OAM_SCAN_DOTS => {
+                self.first_line = false;
+                self.line_sprites.clear();
+                self.fifo.start_line(self.scx);
+                self.mode = Mode::Drawing;
+            }
+            Mode::Drawing => {
+                if !self.draw_dot() {
+                    return;
+                }
+
+                if self.fifo.fetcher.window {
+                    self.window_line += 1;
+                }
+                self.mode = Mode::HBlank;
+                self.hblank_started = true;
+            }
+            _ if self.dot == LINE_DOTS => {
+                self.dot = 0;
+                self.ly += 1;
+
+                if self.ly == FRAME_LINES {
+                    self.ly = 0;
+                }
+
+                self.mode = match self.ly {
+                    0 => Mode::OamScan,
+                    line if usize::from(line) == HEIGHT => {
+                        self.interrupts |= Interrupt::VBlank.bit();
+                        self.frame_ready = true;
+                        if !self.skip_output && let Some(blend) = &mut self.blend {
+                            blend.push(&self.rgba_framebuffer);
+                        }
+                        self.frames += 1;
+                        self.frame_phase = if self.frame_phase < self.frame_skip {
+                            self.frame_phase + 1
+                        } else {
+                            0
+                        };
+                        Mode::VBlank
+                    }
+                    _ if self.mode == Mode::VBlank => Mode::VBlank,
+                    _ => Mode::OamScan,
+                };
+
+                if self.mode == Mode::OamScan {
+                    self.start_line();
+                }
+            }
+            _ => return,
+        }
+
+        self.update_stat_line();
+    }
+
+    /// Re-evaluate the STAT interrupt line, requesting the interrupt on a
+    /// rising edge.
+    ///
+    /// As the line is shared, a new condition becoming true while another
+    /// enabled condition already holds the line high requests nothing.
+    const fn update_stat_line(&mut self) {
+        let conditions = match self.mode {
+            // The mode 0 of the first line after the LCD turns on isn't an
+            // H-blank, and doesn't raise the interrupt.
+            Mode::HBlank if self.first_line => 0,
+            Mode::HBlank => HBLANK_INT,
+            Mode::VBlank => VBLANK_INT,
+            Mode::OamScan => OAM_INT,
+            Mode::Drawing => 0,
+        };
+
+        let coincidence = if self.ly == self.lyc { LYC_INT } else { 0 };
+        let line = self.is_enabled() && self.stat & (conditions | coincidence) != 0;
+
+        if line && !self.stat_line {
+            self.interrupts |= Interrupt::Stat.bit();
+        }
+        self.stat_line = line;
+    }
+
+    /// Prepare for drawing the current scanline, at the start of OAM scan.
+    const fn start_line(&mut self) {
+        if self.ly == 0 {
+            self.window_triggered = false;
+            self.window_line = 0;
+            self.skip_frame = self.frame_phase != 0;
+            self.skip_output = self.fast_forward || self.skip_frame;
+        }
+
+        // Once triggered the window stays active for the rest of the frame,
+        // even if `WY` is changed afterwards.
+        self.window_triggered |= self.ly == self.wy;
+    }
+
+    /// Return the height of sprites in pixels, following `LCDC` bit 2.
+    const fn sprite_height(&self) -> u8 {
+        if self.lcdc & OBJ_SIZE == 0 { 8 } else { 16 }
+    }
+
+    /// Select the first ten sprites in OAM order that overlap the current
+    /// line.
+    fn scan_oam(&mut self) {
+        let mut sprites = core::mem::take(&mut self.line_sprites);
+        self.select_sprites(self.ly, &mut sprites);
+        self.line_sprites = sprites;
+    }
+
+    /// Fill `selected` with the sprites OAM scan selects for line `ly`, in
+    /// OAM order on CGB and in drawing priority order on DMG.
+    fn select_sprites(&self, ly: u8, selected: &mut Vec<Sprite>) {
+        let height = self.sprite_height();
+        let line = ly + 16;
+
+        selected.clear();
+        let sprites = self.oam.chunks_exact(4).zip(0..).map(|(entry, index)| Sprite {
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attrs: entry[3],
+            index,
+        });
+
+        // Sprites off the sides of the screen still count towards the limit.
+        let visible = sprites.filter(|sprite| line >= sprite.y && line < sprite.y + height);
+        selected.extend(visible.take(MAX_LINE_SPRITES));
+
+        // On DMG the sprite with the smaller X wins, then the earlier in OAM,
+        // so a stable sort puts the winner first. On CGB only OAM order counts.
+        if !self.cgb {
+            selected.sort_by_key(|sprite| sprite.x);
+        }
+    }
+
+    /// Advance mode 3 by one dot, and return whether the line is finished.
+    fn draw_dot(&mut self) -> bool {
+        if self.fifo.startup > 0 {
+            self.fifo.startup -= 1;
+            return false;
+        }
+
+        // Both the fetcher and the FIFOs wait for a sprite fetch.
+        if let Some((sprite, dots)) = self.fifo.obj_fetch {
+            if dots > 1 {
+                self.fifo.obj_fetch = Some((sprite, dots - 1));
+                return false;
+            }
+            self.fifo.obj_fetch = None;
+            self.fetch_sprite(sprite);
+        }
+
+        self.step_fetcher();
+        self.shift_pixel()
+    }
+
+    /// Advance the background fetcher by one dot, pushing the fetched row
+    /// once the background FIFO is empty.
+    fn step_fetcher(&mut self) {
+        let fetcher = &mut self.fifo.fetcher;
+        if fetcher.dots == FETCH_DOTS {
+            if self.fifo.bg.is_empty() {
+                let (mut lo, mut hi) = (fetcher.lo, fetcher.hi);
+                if fetcher.attrs & ATTR_X_FLIP != 0 {
+                    (lo, hi) = (lo.reverse_bits(), hi.reverse_bits());
+                }
+                self.fifo.bg.push(lo, hi, fetcher.attrs);
+                fetcher.dots = 0;
+                fetcher.x = fetcher.x.wrapping_add(1);
+            }
+            return;
+        }
+
+        fetcher.dots += 1;
+        match fetcher.dots {
+            2 => self.fetch_tile(),
+            4 => self.fifo.fetcher.lo = self.fetch_tile_data(0),
+            FETCH_DOTS => self.fifo.fetcher.hi = self.fetch_tile_data(1),
+            _ => {}
+        }
+    }
+
+    /// Fetch the number and CGB attributes of the next background or window
+    /// tile.
+    fn fetch_tile(&mut self) {
+        let fetcher = &self.fifo.fetcher;
+        let (map, column, y) = if fetcher.window {
+            let map = if self.lcdc & WINDOW_MAP == 0 { 0x9800 } else { 0x9C00 };
+            (map, fetcher.x, self.window_line)
+        } else {
+            let map = if self.lcdc & BG_MAP == 0 { 0x9800 } else { 0x9C00 };
+            (map, (self.scx / 8).wrapping_add(fetcher.x), self.scy.wrapping_add(self.ly))
+        };
+
+        let entry = map + u16::from(y / 8) * 32 + u16::from(column % 32);
+        let tile = self.vram(0, entry);
+        let attrs = if self.cgb { self.vram(1, entry) } else { 0 };
+
+        let fetcher = &mut self.fifo.fetcher;
+        fetcher.tile = tile;
+        fetcher.attrs = attrs;
+        fetcher.row = if attrs & ATTR_Y_FLIP == 0 { y % 8 } else { 7 - y % 8 };
+    }
+
+    /// Fetch byte `offset` of the tile data row of the fetched tile.
+    fn fetch_tile_data(&self, offset: u16) -> u8 {
+        let fetcher = &self.fifo.fetcher;
+        let addr = self.tile_addr(fetcher.tile) + u16::from(fetcher.row) * 2 + offset;
+        self.vram(self.tile_bank(fetcher.attrs), addr)
+    }
+
+    /// Output the next pixel, unless pixels are being dropped or the window
+    /// or a sprite has to be fetched first, and return whether the line is
+    /// finished.
+    fn shift_pixel(&mut self) -> bool {
+        if self.fifo.bg.is_empty() {
+            return false;
+        }
+
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            self.fifo.bg.pop();
+            return false;
+        }
+
+        if !self.fifo.fetcher.window && self.window_reached() {
+            // The fetcher starts over on the window in this same dot. With
+            // `WX` below 7 the window's first pixels are off the screen.
+            self.fifo.bg.clear();
+            self.fifo.fetcher = Fetcher {
+                window: true,
+                ..Fetcher::default()
+            };
+            self.fifo.discard = 7_u8.saturating_sub(self.wx);
+            self.step_fetcher();
+            return false;
+        }
+
+        if self.lcdc & OBJ_ENABLE != 0
+            && let Some(sprite) = self.next_sprite()
+        {
+            // The sprite fetch waits for the background fetcher to get
+            // through most of its row.
+            if self.fifo.fetcher.dots >= OBJ_FETCH_WAIT {
+                self.fifo.obj_fetch = Some((sprite, OBJ_FETCH_DOTS));
+            }
+            return false;
+        }
+
+        let (color, attrs) = self.fifo.bg.pop();
+        let obj = self.fifo.obj.pop();
+        if !self.skip_output {
+            let pixel = self.mix(color, attrs, obj);
+            let index = usize::from(self.ly) * WIDTH + usize::from(self.fifo.lx);
+            self.framebuffer[index] = if self.cgb { pixel } else { pixel & 3 };
+            let rgba = self.rgba(pixel);
+            self.rgba_framebuffer[index * 4..index * 4 + 4].copy_from_slice(&rgba);
+        }
+
+        self.fifo.lx += 1;
+        usize::from(self.fifo.lx) == WIDTH
+    }
+
+    /// Check if the window starts at the next column.
+    fn window_reached(&self) -> bool {
+        // The window starts at `WX - 7`, so compare with `x + 7` to keep the
+        // arithmetic unsigned. On DMG clearing `LCDC` bit 0 hides the window
+        // along with the background.
+        self.lcdc & WINDOW_ENABLE != 0
+            && self.window_triggered
+            && (self.cgb || self.lcdc & BG_ENABLE != 0)
+            && u16::from(self.fifo.lx) + 7 >= u16::from(self.wx)
+    }
+
+    /// Return the index in the line's sprites of the next sprite to fetch
+    /// at the current column, if any.
+    fn next_sprite(&self) -> Option<u8> {
+        // Sprites partly off the left edge are all fetched at column 0.
+        let x = u16::from(self.fifo.lx) + 8;
+        (0..)
+            .zip(&self.line_sprites)
+            .find(|&(i, sprite)| self.fifo.fetched & 1 << i == 0 && u16::from(sprite.x) <= x)
+            .map(|(i, _)| i)
+    }
+
+    /// Fetch a row of the sprite at `index` in the line's sprites, merging
+    /// its pixels into the sprite FIFO.
+    fn fetch_sprite(&mut self, index: u8) {
+        self.fifo.fetched |= 1 << index;
+        let sprite = self.line_sprites[usize::from(index)];
+
+        let height = self.sprite_height();
+        let mut row = (self.ly + 16 - sprite.y) & (height - 1);
+        if sprite.attrs & ATTR_Y_FLIP != 0 {
+            row = height - 1 - row;
+        }
+
+        // 8x16 sprites ignore the lowest bit of the tile index.
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let addr = 0x8000 + u16::from(tile) * 16 + u16::from(row) * 2;
+        let bank = self.tile_bank(sprite.attrs);
+        let x = u16::from(self.fifo.lx) + 8;
+
+        for column in 0..8 {
+            // Pixels left of the current column are off the screen.
+            let Some(slot) = (u16::from(sprite.x) + u16::from(column)).checked_sub(x) else {
+                continue;
+            };
+
+            let bit = if sprite.attrs & ATTR_X_FLIP == 0 { 7 - column } else { column };
+            let pixel = ObjPixel {
+                color: self.tile_color(bank, addr, bit),
+                attrs: sprite.attrs,
+                index: sprite.index,
+            };
+            self.fifo.obj.merge(usize::from(slot), pixel, self.cgb);
+        }
+    }
+
+    /// Return the framebuffer pixel for background color `color` with CGB
+    /// tile attributes `attrs`, under sprite pixel `obj`.
+    ///
+    /// A DMG pixel also holds its palette at [`DMG_PALETTE_SHIFT`].
+    fn mix(&self, color: u8, attrs: u8, obj: ObjPixel) -> u8 {
+        // The window has taken over the rest of the line once the fetcher
+        // switched to it.
+        let layer = if self.fifo.fetcher.window { Layer::Window } else { Layer::Background };
+        // On CGB the background can't be turned off, `LCDC` bit 0 only drops
+        // its priority over sprites.
+        let hidden = !self.cgb && self.lcdc & BG_ENABLE == 0 || !self.is_layer_enabled(layer);
+        let color = if hidden { 0 } else { color };
+        let bg = self.bg_pixel(color, attrs);
+
+        if obj.color == 0 || self.lcdc & OBJ_ENABLE == 0 || !self.is_layer_enabled(Layer::Sprites) {
+            return bg;
+        }
+
+        // The winning sprite may still be hidden behind the background,
+        // without falling through to the next sprite. On CGB, clearing
+        // `LCDC` bit 0 puts every sprite on top instead.
+        let behind = obj.attrs & ATTR_BEHIND_BG != 0 || attrs & ATTR_BEHIND_BG != 0;
+        let master = !self.cgb || self.lcdc & BG_ENABLE != 0;
+        if behind && master && color != 0 {
+            return bg;
+        }
+
+        if self.cgb {
+            return CGB_OBJ_PIXEL | (obj.attrs & ATTR_CGB_PALETTE) << 2 | obj.color;
+        }
+        let (palette, obp) = if obj.attrs & ATTR_PALETTE == 0 {
+            (1, self.obp0)
+        } else {
+            (2, self.obp1)
+        };
+        palette << DMG_PALETTE_SHIFT | obp >> (obj.color * 2) & 3
+    }
+
+    /// Return the RGBA8888 color of framebuffer `pixel`.
+    fn rgba(&self, pixel: u8) -> [u8; 4] {
+        if !self.cgb {
+            return self.compat_palette.map_or_else(
+                || self.palette.color(pixel),
+                |compat| compat.rgba(pixel >> DMG_PALETTE_SHIFT, pixel),
+            );
+        }
+
+        let palettes = if pixel & CGB_OBJ_PIXEL == 0 {
+            &self.bg_palettes
+        } else {
+            &self.obj_palettes
+        };
+        let [r, g, b] = rgb555_to_rgb888(palettes.color(pixel >> 2 & 0x07, pixel & 0x03));
+        [r, g, b, 0xFF]
+    }
+
+    /// Redraw the DMG RGBA8888 framebuffer from its shades, after the
+    /// palette changed.
+    fn recolor(&mut self) {
+        // CGB colors come from palette RAM as each line is drawn, and
+        // compatibility colors from the palette of each shade, so neither can
+        // be rebuilt from the framebuffer alone.
+        if !self.cgb && self.compat_palette.is_none() {
+            let pixels = self.rgba_framebuffer.chunks_exact_mut(4).zip(&self.framebuffer);
+            for (rgba, &shade) in pixels {
+                rgba.copy_from_slice(&self.palette.color(shade));
+            }
+        }
+
+        // The last frame was drawn in the old colors, so blending starts
+        // over.
+        if let Some(blend) = &mut self.blend {
+            *blend = FrameBlend::new(&self.rgba_framebuffer);
+        }
+    }
+
+    /// Return the VRAM bank of the tile data selected by `attrs`, which is
+    /// always bank 0 on DMG.
+    fn tile_bank(&self, attrs: u8) -> u8 {
+        u8::from(self.cgb && attrs & ATTR_BANK != 0)
+    }
+
+    /// Return the color index at `bit` of the tile row at `addr` in `bank`.
+    fn tile_color(&self, bank: u8, addr: u16, bit: u8) -> u8 {
+        let lo = self.vram(bank, addr) >> bit & 1;
+        let hi = self.vram(bank, addr + 1) >> bit & 1;
+        hi << 1 | lo
+    }
+
+    /// Return the address of background or window `tile`, following the
+    /// addressing mode selected by `LCDC` bit 4.
+    const fn tile_addr(&self, tile: u8) -> u16 {
+        if self.lcdc & TILE_DATA != 0 {
+            0x8000 + tile as u16 * 16
+        } else {
+            // Signed addressing treats the index as an offset from 0x9000.
+            0x9000_u16.wrapping_add_signed(tile.cast_signed() as i16 * 16)
+        }
+    }
+
+    /// Return the index into VRAM of `addr` in `bank`.
+    fn vram_index(bank: u8, addr: u16) -> usize {
+        usize::from(bank) * VRAM_BANK_SIZE + usize::from(addr - 0x8000)
+    }
+
+    /// Read a byte of VRAM by its CPU address in `bank`.
+    fn vram(&self, bank: u8, addr: u16) -> u8 {
+        self.vram[Self::vram_index(bank, addr)]
+    }
+
+    /// Read a byte of VRAM, OAM or the LCD registers.
+    #[must_use]
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.vram(self.vram_bank, addr),
+            0xFE00..=0xFE9F => self.oam[usize::from(addr - 0xFE00)],
+            LCDC => self.lcdc,
+            STAT => {
+                let coincidence = if self.ly == self.lyc && self.is_enabled() {
+                    COINCIDENCE
+                } else {
+                    0
+                };
+                0x80 | self.stat | coincidence | self.mode as u8
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            VBK if self.cgb => 0xFE | self.vram_bank,
+            BCPS if self.cgb => self.bg_palettes.read_spec(),
+            BCPD if self.cgb => self.bg_palettes.read_data(),
+            OCPS if self.cgb => self.obj_palettes.read_spec(),
+            OCPD if self.cgb => self.obj_palettes.read_data(),
+            _ => 0xFF,
+        }
+    }
+
+    /// Write a byte of VRAM, OAM or the LCD registers.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.vram[Self::vram_index(self.vram_bank, addr)] = value,
+            0xFE00..=0xFE9F => self.oam[usize::from(addr - 0xFE00)] = value,
+            LCDC => {
+                self.write_lcdc(value);
+                self.update_stat_line();
+            }
+            STAT => {
+                // The sources are all enabled for a moment, so H-blank,
+                // V-blank and an LY match raise the line.
+                let held = matches!(self.mode, Mode::HBlank | Mode::VBlank) || self.ly == self.lyc;
+                if self.stat_write_bug && self.is_enabled() && held && !self.stat_line {
+                    self.interrupts |= Interrupt::Stat.bit();
+                }
+                self.stat = value & 0x78;
+                self.update_stat_line();
+            }
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            LYC => {
+                self.lyc = value;
+                self.update_stat_line();
+            }
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            WX => self.wx = value,
+            VBK if self.cgb => self.vram_bank = value & 1,
+            BCPS if self.cgb => self.bg_palettes.write_spec(value),
+            BCPD if self.cgb => self.bg_palettes.write_data(value),
+            OCPS if self.cgb => self.obj_palettes.write_spec(value),
+            OCPD if self.cgb => self.obj_palettes.write_data(value),
+            // LY is read-only.
+            _ => {}
+        }
+    }
+
+    /// Set a byte of VRAM, OAM or the LCD registers without any side
+    /// effects, for tooling.
+    ///
+    /// Unlike [`Ppu::write`], this can set `LY`, and setting `LCDC` or `STAT`
+    /// leaves the mode machine and the STAT interrupt line as they are.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            LCDC => self.lcdc = value,
+            STAT => self.stat = value & 0x78,
+            LY => self.ly = value % FRAME_LINES,
+            LYC => self.lyc = value,
+            _ => self.write(addr, value),
+        }
+    }
+
+    /// Write `LCDC`, stopping the mode machine when the LCD turns off and
+    /// restarting it at the top of a frame when it turns back on.
+    ///
+    /// The first line after the LCD turns on stays in mode 0 through what
+    /// would be OAM scan, leaving OAM accessible and drawing no sprites. The
+    /// LCD doesn't show the frame that follows, so it isn't drawn, and the
+    /// framebuffer keeps the last picture instead of flickering.
+    const fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.is_enabled();
+        self.lcdc = value;
+
+        if was_enabled && !self.is_enabled() {
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::HBlank;
+            self.first_line = false;
+            self.update_stat_line();
+        } else if !was_enabled && self.is_enabled() {
+            self.mode = Mode::HBlank;
+            self.first_line = true;
+            self.start_line();
+            self.skip_output = true;
+            // LY is compared against LYC again at once.
+            self.update_stat_line();
+        }
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the PPU until it finishes drawing line `ly`.
+    fn run_to_line_end(ppu: &mut Ppu, ly: u8) {
+        while !(ppu.ly == ly && ppu.mode == Mode::HBlank) {
+            ppu.tick(4);
+        }
+    }
+
+    /// Fill tile `index` at `base` with a single color index.
+    fn fill_tile(ppu: &mut Ppu, base: u16, index: u8, color: u8) {
+        let [lo, hi] = [0xFF * (color & 1), 0xFF * (color >> 1)];
+        for row in 0..8 {
+            let addr = base + u16::from(index) * 16 + row * 2;
+            ppu.write(addr, lo);
+            ppu.write(addr + 1, hi);
+        }
+    }
+
+    #[test]
+    fn mode_timing() {
+        let mut ppu = Ppu::new();
+        let mut modes = Vec::new();
+        let mut dots = 0u32;
+
+        while modes.len() < 4 {
+            let mode = ppu.read(STAT) & 3;
+            if modes.last() != Some(&mode) {
+                modes.push(mode);
+            }
+            ppu.tick(1);
+            dots += 1;
+        }
+
+        assert_eq!(modes, [2, 3, 0, 2]);
+        assert_eq!(dots, 457);
+        assert_eq!(ppu.read(LY), 1);
+    }
+
+    #[test]
+    fn mode_and_dot_follow_frame_structure() {
+        let mut ppu = Ppu::new();
+        // The dots spent in each mode on each line.
+        let mut lines = [[0u32; 4]; 154];
+
+        for _ in 0..154 * 456 {
+            assert!(ppu.dot() < 456);
+            assert_eq!(ppu.mode() as u8, ppu.read(STAT) & 3);
+            if ppu.mode() == Mode::Drawing {
+                assert!(ppu.dot() >= 80);
+            }
+            lines[usize::from(ppu.read(LY))][ppu.mode() as usize] += 1;
+            ppu.tick(1);
+        }
+
+        for line in &lines[..144] {
+            let [hblank, vblank, oam_scan, drawing] = *line;
+            assert_eq!((oam_scan, vblank), (80, 0));
+            assert!(drawing >= 172);
+            assert_eq!(drawing + hblank, 376);
+        }
+        let vblank: u32 = lines[144..].iter().map(|line| line[Mode::VBlank as usize]).sum();
+        assert_eq!(vblank, 4560);
+        assert_eq!(lines[144..].iter().flatten().sum::<u32>(), 4560);
+
+        assert_eq!(ppu.frame_count(), 1);
+        assert_eq!((ppu.mode(), ppu.dot()), (Mode::OamScan, 0));
+    }
+
+    #[test]
+    fn mode_changes_come_as_predicted() {
+        let mut ppu = Ppu::new();
+        // Scrolling and a sprite both lengthen mode 3 past its prediction.
+        ppu.write(SCX, 5);
+        ppu.write(LCDC, 0x93);
+        ppu.write(0xFE00, 20);
+        ppu.write(0xFE01, 40);
+
+        while ppu.frame_count() < 2 {
+            let before = (ppu.mode(), ppu.ly);
+            let dots = ppu.dots_until_mode_change().unwrap();
+            for _ in 1..dots {
+                ppu.tick(1);
+                assert_eq!((ppu.mode(), ppu.ly), before);
+            }
+            ppu.tick(1);
+            if before.0 != Mode::Drawing {
+                assert_ne!((ppu.mode(), ppu.ly), before);
+            }
+        }
+
+        ppu.write(LCDC, 0x13);
+        assert_eq!(ppu.dots_until_mode_change(), None);
+    }
+
+    #[test]
+    fn frame_timing() {
+        let mut ppu = Ppu::new();
+
+        // 143 full lines plus one line's worth of dots enters VBlank.
+        for _ in 0..144 * 456 / 4 {
+            ppu.tick(4);
+        }
+        assert_eq!(ppu.read(LY), 144);
+        assert_eq!(ppu.read(STAT) & 3, 1);
+
+        for _ in 0..10 * 456 / 4 {
+            ppu.tick(4);
+        }
+        assert_eq!(ppu.read(LY), 0);
+        assert_eq!(ppu.read(STAT) & 3, 2);
+    }
+
+    #[test]
+    fn lcd_off_resets_ly() {
+        let mut ppu = Ppu::new();
+        for _ in 0..1000 {
+            ppu.tick(4);
+        }
+
+        ppu.write(LCDC, 0x11);
+        ppu.tick(255);
+
+        assert_eq!(ppu.read(LY), 0);
+        assert_eq!(ppu.read(STAT) & 3, 0);
+    }
+
+    #[test]
+    fn lcd_on_restarts_frame_without_oam_scan() {
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0b1110_0100);
+        ppu.write(0x9800, 1);
+        fill_tile(&mut ppu, 0x8000, 1, 3);
+        run_to_line_end(&mut ppu, 0);
+        assert_eq!(ppu.framebuffer()[0], 3);
+
+        ppu.write(LCDC, 0x11);
+        ppu.write(0x9800, 0);
+        ppu.write(LYC, 0);
+        ppu.write(STAT, LYC_INT);
+        ppu.take_interrupts();
+        ppu.write(LCDC, 0x91);
+        assert_eq!(ppu.read(LY), 0);
+        assert_eq!(ppu.read(STAT) & 7, 0x04);
+        assert_eq!(ppu.take_interrupts(), Interrupt::Stat.bit());
+
+        // Mode 0 takes the place of OAM scan on the first line.
+        for _ in 0..OAM_SCAN_DOTS - 1 {
+            ppu.tick(1);
+            assert_eq!(ppu.read(STAT) & 3, 0);
+        }
+        ppu.tick(1);
+        assert_eq!(ppu.read(STAT) & 3, 3);
+
+        // The first frame isn't drawn, and the last picture stays.
+        run_to_line_end(&mut ppu, 143);
+        assert_eq!(ppu.framebuffer()[0], 3);
+        run_to_line_end(&mut ppu, 0);
+        assert_eq!(ppu.read(STAT) & 3, 0);
+        assert_eq!(ppu.framebuffer()[0], 0);
+    }
+
+    #[test]
+    fn unsigned_tile_data() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x91);
+        ppu.write(BGP, 0b1110_0100);
+        fill_tile(&mut ppu, 0x8000, 1, 2);
+        ppu.write(0x9801, 1);
+
+        run_to_line_end(&mut ppu, 0);
+
+        assert_eq!(ppu.framebuffer()[..16], [0, 0, 0, 0, 0, 0, 0, 0, 2, 2, 2, 2, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn signed_tile_data() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x81);
+        ppu.write(BGP, 0b1110_0100);
+        // Tile 0 lives at 0x9000 and tile 0xFF at 0x8FF0.
+        fill_tile(&mut ppu, 0x9000, 0, 1);
+        fill_tile(&mut ppu, 0x8800, 0x7F, 3);
+        ppu.write(0x9801, 0xFF);
+
+        run_to_line_end(&mut ppu, 0);
+
+        assert_eq!(ppu.framebuffer()[0], 1);
+        assert_eq!(ppu.framebuffer()[8], 3);
+    }
+
+    #[test]
+    fn scroll_and_palette() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x99);
+        ppu.write(BGP, 0b0001_1011);
+        ppu.write(SCX, 4);
+        ppu.write(SCY, 8);
+        fill_tile(&mut ppu, 0x8000, 1, 3);
+        // Tile (1, 1) of the 0x9C00 map.
+        ppu.write(0x9C21, 1);
+
+        run_to_line_end(&mut ppu, 0);
+
+        let line = &ppu.framebuffer()[..12];
+        assert_eq!(line, [3, 3, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn window_covers_background() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0xF1);
+        ppu.write(BGP, 0b1110_0100);
+        ppu.write(WY, 0);
+        ppu.write(WX, 7 + 100);
+        fill_tile(&mut ppu, 0x8000, 1, 2);
+        fill_tile(&mut ppu, 0x8000, 2, 1);
+        // The window map at 0x9C00 starts at its own origin.
+        ppu.write(0x9C00, 1);
+        ppu.write(0x9800 + 12, 2);
+
+        run_to_line_end(&mut ppu, 0);
+
+        let line = &ppu.framebuffer()[..WIDTH];
+        assert_eq!(line[96..100], [1, 1, 1, 1]);
+        assert_eq!(line[100..108], [2; 8]);
+        assert_eq!(line[108..116], [0; 8]);
+    }
+
+    #[test]
+    fn window_line_counter_skips_hidden_lines() {
+        let mut ppu = Ppu::new();
+        // The background reads the empty 0x9C00 map.
+        ppu.write(LCDC, 0xB9);
+        ppu.write(BGP, 0b1110_0100);
+        ppu.write(WX, 7);
+
+        // Window row 2 uses tile 1, row 3 onward uses tile 2.
+        fill_tile(&mut ppu, 0x8000, 1, 1);
+        fill_tile(&mut ppu, 0x8000, 2, 3);
+        ppu.write(0x9800 + 32 * 2, 1);
+        for row in 3..32 {
+            ppu.write(0x9800 + 32 * row, 2);
+        }
+
+        // Draw 16 window lines, then hide the window for the next 16.
+        run_to_line_end(&mut ppu, 15);
+        ppu.write(LCDC, 0x99);
+        run_to_line_end(&mut ppu, 31);
+        ppu.write(LCDC, 0xB9);
+        run_to_line_end(&mut ppu, 32);
+
+        // Line 32 continues from window line 16, in window tile row 2.
+        assert_eq!(ppu.framebuffer()[32 * WIDTH], 1);
+        assert_eq!(ppu.framebuffer()[31 * WIDTH], 0);
+    }
+
+    /// Write sprite `index` to OAM.
+    fn write_sprite(ppu: &mut Ppu, index: u16, [y, x, tile, attrs]: [u8; 4]) {
+        for (offset, value) in (0..).zip([y, x, tile, attrs]) {
+            ppu.write(0xFE00 + index * 4 + offset, value);
+        }
+    }
+
+    /// Create a PPU with sprites enabled and a blank background.
+    fn sprite_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x93);
+        ppu.write(BGP, 0b1110_0100);
+        ppu.write(OBP0, 0b1110_0100);
+        ppu.write(OBP1, 0b0101_0100);
+        ppu
+    }
+
+    #[test]
+    fn ten_sprites_per_line() {
+        let mut ppu = sprite_ppu();
+        fill_tile(&mut ppu, 0x8000, 1, 3);
+
+        for index in 0..11 {
+            let x = 8 + 12 * u8::try_from(index).unwrap();
+            write_sprite(&mut ppu, index, [16, x, 1, 0]);
+        }
+
+        run_to_line_end(&mut ppu, 0);
+
+        let line = &ppu.framebuffer()[..WIDTH];
+        assert_eq!(line[108..116], [3; 8]);
+        assert_eq!(line[120..128], [0; 8]);
+    }
+
+    #[test]
+    fn debug_scanline_sprites_reports_ten_in_priority_order() {
+        let mut ppu = sprite_ppu();
+        // Twelve sprites on line 4, with X descending through OAM.
+        for index in 0..12 {
+            let x = 100 - 5 * u8::try_from(index).unwrap();
+            write_sprite(&mut ppu, index, [16, x, 0x40 + u8::try_from(index).unwrap(), 0]);
+        }
+        // Off this line, so it doesn't count towards the limit.
+        write_sprite(&mut ppu, 12, [30, 8, 0, 0]);
+        // Sharing the X of sprite 2, so only OAM order tells them apart.
+        write_sprite(&mut ppu, 2, [13, 95, 0x42, ATTR_X_FLIP]);
+
+        let state = |ppu: &Ppu| {
+            let mut state = StateWriter::new();
+            ppu.save_state(&mut state);
+            state.finish()
+        };
+        let before = state(&ppu);
+        let sprites = ppu.debug_scanline_sprites(4);
+        assert_eq!(
+            sprites.iter().map(|sprite| sprite.index).collect::<Vec<_>>(),
+            [9, 8, 7, 6, 5, 4, 3, 1, 2, 0]
+        );
+        assert_eq!(
+            sprites[8],
+            SpriteInfo { index: 2, x: 95, y: 13, tile: 0x42, attrs: ATTR_X_FLIP }
+        );
+        assert_eq!(state(&ppu), before);
+
+        let mut cgb = Ppu::new_cgb();
+        cgb.oam.copy_from_slice(&ppu.oam);
+        cgb.lcdc = ppu.lcdc;
+        let order: Vec<_> = cgb.debug_scanline_sprites(4).iter().map(|s| s.index).collect();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn debug_bg_tile_follows_lcdc() {
+        let mut ppu = Ppu::new();
+        ppu.write(0x9800 + 2 * 32 + 3, 0x05);
+        ppu.write(0x9C00 + 31, 0x80);
+
+        let tile = ppu.debug_bg_tile(35, 2);
+        assert_eq!(
+            tile,
+            TileRef { map_addr: 0x9843, tile: 0x05, attrs: 0, data_addr: 0x8050, data_bank: 0 }
+        );
+
+        ppu.write(LCDC, 0x89);
+        let tile = ppu.debug_bg_tile(31, 0);
+        assert_eq!((tile.map_addr, tile.tile, tile.data_addr), (0x9C1F, 0x80, 0x8800));
+    }
+
+    #[test]
+    fn tile_data_image_places_tiles() {
+        let mut ppu = Ppu::new();
+        fill_tile(&mut ppu, 0x8000, 17, 3);
+        // Tile 18 has a single dark pixel at its top right.
+        ppu.write(0x8000 + 18 * 16, 0x01);
+        ppu.write(0x8000 + 18 * 16 + 1, 0x01);
+
+        let image = ppu.render_tile_data();
+        assert_eq!(image.len(), 128 * 192 * 4);
+        let pixel = |x: usize, y: usize| &image[(y * 128 + x) * 4..][..4];
+        // Tile 17 is the second on the second row of tiles.
+        assert_eq!(pixel(8, 8), DmgPalette::GRAYSCALE.color(3));
+        assert_eq!(pixel(15, 15), DmgPalette::GRAYSCALE.color(3));
+        assert_eq!(pixel(7, 8), DmgPalette::GRAYSCALE.color(0));
+        assert_eq!(pixel(16 + 7, 8), DmgPalette::GRAYSCALE.color(3));
+        assert_eq!(pixel(16 + 6, 8), DmgPalette::GRAYSCALE.color(0));
+    }
+
+    #[test]
+    fn tilemap_image_follows_map_and_attributes() {
+        let mut ppu = Ppu::new_cgb();
+        fill_tile(&mut ppu, 0x8000, 1, 1);
+        ppu.write(0x9C00 + 32 + 2, 0x01);
+        // Tile 1 in bank 1 is color 3, and palette 2 holds pure red there.
+        ppu.write(VBK, 1);
+        fill_tile(&mut ppu, 0x8000, 1, 3);
+        ppu.write(0x9C00 + 32 + 2, ATTR_BANK | 2);
+        ppu.write(BCPS, 0x80 | (2 * 8 + 3 * 2));
+        ppu.write(BCPD, 0x1F);
+        ppu.write(BCPD, 0x00);
+
+        let image = ppu.render_tilemap(1);
+        assert_eq!(image.len(), 256 * 256 * 4);
+        assert_eq!(image[(8 * 256 + 16) * 4..][..4], [0xFF, 0, 0, 0xFF]);
+        assert_ne!(ppu.render_tilemap(0)[(8 * 256 + 16) * 4..][..4], [0xFF, 0, 0, 0xFF]);
+
+        ppu.write(SCX, 0x12);
+        ppu.write(WX, 0x30);
+        let viewport = ppu.debug_viewport();
+        assert_eq!((viewport.scroll_x, viewport.window_x, viewport.bg_map), (0x12, 0x30, 0));
+    }
+
+    #[test]
+    fn smaller_x_wins() {
+        let mut ppu = sprite_ppu();
+        fill_tile(&mut ppu, 0x8000, 1, 3);
+
+        // The later sprite in OAM is further left, so it wins the overlap.
+        write_sprite(&mut ppu, 0, [16, 8 + 4, 1, ATTR_PALETTE]);
+        write_sprite(&mut ppu, 1, [16, 8, 1, 0]);
+        // With equal X, the earlier sprite in OAM wins.
+        write_sprite(&mut ppu, 2, [16, 8 + 40, 1, ATTR_PALETTE]);
+        write_sprite(&mut ppu, 3, [16, 8 + 40, 1, 0]);
+
+        run_to_line_end(&mut ppu, 0);
+
+        let line = &ppu.framebuffer()[..WIDTH];
+        assert_eq!(line[..12], [3, 3, 3, 3, 3, 3, 3, 3, 1, 1, 1, 1]);
+        assert_eq!(line[40..48], [1; 8]);
+    }
+
+    #[test]
+    fn transparent_pixels_show_lower_priority_sprites() {
+        let mut ppu = sprite_ppu();
+        // Left half transparent, right half color 3.
+        for row in 0..8 {
+            ppu.write(0x8010 + row * 2, 0x0F);
+            ppu.write(0x8011 + row * 2, 0x0F);
+        }
+        fill_tile(&mut ppu, 0x8000, 2, 1);
+
+        write_sprite(&mut ppu, 0, [16, 8, 1, 0]);
+        write_sprite(&mut ppu, 1, [16, 8, 2, 0]);
+
+        run_to_line_end(&mut ppu, 0);
+
+        assert_eq!(ppu.framebuffer()[..8], [1, 1, 1, 1, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn sprite_flips_and_tall_sprites() {
+        let mut ppu = sprite_ppu();
+        ppu.write(LCDC, 0x97);
+        // Tile 2 has a single pixel in its top left, tile 3 in its bottom right.
+        ppu.write(0x8020, 0x80);
+        ppu.write(0x803E, 0x01);
+
+        write_sprite(&mut ppu, 0, [16, 8, 3, 0]);
+        write_sprite(&mut ppu, 1, [16, 16, 2, ATTR_X_FLIP | ATTR_Y_FLIP]);
+
+        run_to_line_end(&mut ppu, 0);
+        assert_eq!(ppu.framebuffer()[..16], [1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+        run_to_line_end(&mut ppu, 15);
+        let line = &ppu.framebuffer()[15 * WIDTH..16 * WIDTH];
+        assert_eq!(line[..16], [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn behind_background_priority() {
+        let mut ppu = sprite_ppu();
+        fill_tile(&mut ppu, 0x8000, 1, 3);
+        fill_tile(&mut ppu, 0x8000, 2, 1);
+        // Background color 1 in the first tile, color 0 in the second.
+        ppu.write(0x9800, 2);
+
+        write_sprite(&mut ppu, 0, [16, 12, 1, ATTR_BEHIND_BG]);
+
+        run_to_line_end(&mut ppu, 0);
+
+        assert_eq!(ppu.framebuffer()[..12], [1, 1, 1, 1, 1, 1, 1, 1, 3, 3, 3, 3]);
+    }
+
+    /// Return the dots that the next line spends in mode 3.
+    fn mode_3_dots(ppu: &mut Ppu) -> u32 {
+        while ppu.mode != Mode::Drawing {
+            ppu.tick(1);
+        }
+
+        let mut dots = 0;
+        while ppu.mode == Mode::Drawing {
+            ppu.tick(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn fine_scroll_lengthens_mode_3() {
+        for (scx, dots) in [(0, 172), (1, 173), (5, 177), (7, 179), (8, 172), (13, 177)] {
+            let mut ppu = Ppu::new();
+            ppu.write(SCX, scx);
+            assert_eq!(mode_3_dots(&mut ppu), dots, "SCX {scx}");
+        }
+    }
+
+    #[test]
+    fn sprites_lengthen_mode_3() {
+        // The X positions of the sprites on the line and SCX, with the
+        // length of mode 3.
+        let layouts: [(&[u8], u8, u32); 10] = [
+            (&[8], 0, 172 + 11),
+            (&[0], 0, 172 + 11),
+            (&[11], 0, 172 + 8),
+            (&[13], 0, 172 + 6),
+            (&[8], 3, 172 + 3 + 8),
+            (&[8, 8], 0, 172 + 11 + 6),
+            (&[8, 12], 0, 172 + 11 + 6),
+            (&[8, 16], 0, 172 + 11 + 11),
+            (&[8; 10], 0, 172 + 11 + 9 * 6),
+            (&[168], 0, 172),
+        ];
+
+        for (xs, scx, dots) in layouts {
+            let mut ppu = sprite_ppu();
+            ppu.write(SCX, scx);
+            for (index, &x) in (0..).zip(xs) {
+                write_sprite(&mut ppu, index, [16, x, 0, 0]);
+            }
+            assert_eq!(mode_3_dots(&mut ppu), dots, "sprites at {xs:?}, SCX {scx}");
+        }
+    }
+
+    #[test]
+    fn disabled_sprites_cost_nothing() {
+        let mut ppu = sprite_ppu();
+        ppu.write(LCDC, 0x91);
+        write_sprite(&mut ppu, 0, [16, 8, 0, 0]);
+
+        assert_eq!(mode_3_dots(&mut ppu), 172);
+    }
+
+    #[test]
+    fn window_lengthens_mode_3() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0xB1);
+        ppu.write(WX, 7 + 80);
+        assert_eq!(mode_3_dots(&mut ppu), 172 + 6);
+
+        // The window never starts with `WX` past the right edge.
+        ppu.write(WX, 7 + 160);
+        assert_eq!(mode_3_dots(&mut ppu), 172);
+    }
+
+    #[test]
+    fn scx_written_mid_line_moves_later_tiles() {
+        let mut ppu = Ppu::new();
+        fill_tile(&mut ppu, 0x8000, 1, 3);
+        ppu.write(LCDC, 0x91);
+        ppu.write(0x9802, 1);
+
+        // Change SCX right after the first pixel is drawn, before the second
+        // tile is fetched.
+        while ppu.mode != Mode::Drawing {
+            ppu.tick(1);
+        }
+        ppu.tick(13);
+        ppu.write(SCX, 8);
+        run_to_line_end(&mut ppu, 0);
+
+        let line = &ppu.framebuffer()[..24];
+        assert_eq!(line[..8], [0; 8]);
+        assert_eq!(line[8..16], [3; 8]);
+        assert_eq!(line[16..], [0; 8]);
+    }
+
+    /// Tick the PPU by `dots`, returning the interrupts requested.
+    fn tick_interrupts(ppu: &mut Ppu, dots: u32) -> u8 {
+        let mut interrupts = 0;
+        for _ in 0..dots {
+            ppu.tick(1);
+            interrupts |= ppu.take_interrupts();
+        }
+        interrupts
+    }
+
+    #[test]
+    fn vblank_interrupt() {
+        let mut ppu = Ppu::new();
+
+        assert_eq!(tick_interrupts(&mut ppu, 144 * 456 - 1), 0);
+        assert!(!ppu.take_frame_ready());
+        assert_eq!(tick_interrupts(&mut ppu, 1), Interrupt::VBlank.bit());
+        assert!(ppu.take_frame_ready());
+        assert!(!ppu.take_frame_ready());
+        assert_eq!(tick_interrupts(&mut ppu, 10 * 456), 0);
+    }
+
+    #[test]
+    fn stat_mode_interrupts() {
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 0xFF);
+        ppu.write(STAT, HBLANK_INT);
+
+        assert_eq!(tick_interrupts(&mut ppu, 251), 0);
+        assert_eq!(tick_interrupts(&mut ppu, 1), Interrupt::Stat.bit());
+
+        // Mode 2 starts right after the mode 0 condition drops the line.
+        ppu.write(STAT, OAM_INT);
+        assert_eq!(tick_interrupts(&mut ppu, 204), Interrupt::Stat.bit());
+    }
+
+    #[test]
+    fn stat_blocking() {
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 0xFF);
+        ppu.write(STAT, HBLANK_INT | OAM_INT);
+        ppu.take_interrupts();
+        tick_interrupts(&mut ppu, 80);
+
+        // HBlank raises the line, then mode 2 keeps it high with no new edge.
+        assert_eq!(tick_interrupts(&mut ppu, 172), Interrupt::Stat.bit());
+        assert_eq!(tick_interrupts(&mut ppu, 204), 0);
+    }
+
+    #[test]
+    fn stat_write_bug() {
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 0xFF);
+        ppu.take_interrupts();
+        tick_interrupts(&mut ppu, 80 + 172);
+
+        // Writing STAT in H-blank requests an interrupt with no source on.
+        ppu.write(STAT, 0);
+        assert_eq!(ppu.take_interrupts(), Interrupt::Stat.bit());
+
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 0xFF);
+        ppu.set_stat_write_bug(false);
+        tick_interrupts(&mut ppu, 80 + 172);
+        ppu.write(STAT, 0);
+        assert_eq!(ppu.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn oam_corruption_patterns() {
+        let corrupt = |corruption, dots| {
+            let mut ppu = Ppu::new();
+            for (byte, value) in ppu.oam.iter_mut().zip(0..) {
+                *byte = value;
+            }
+            ppu.oam[40..42].copy_from_slice(&0x1234_u16.to_le_bytes());
+            ppu.oam[32..34].copy_from_slice(&0x00FF_u16.to_le_bytes());
+            ppu.oam[36..38].copy_from_slice(&0x0F0F_u16.to_le_bytes());
+            tick_interrupts(&mut ppu, dots);
+            ppu.corrupt_oam(corruption);
+            ppu.oam
+        };
+
+        // Row 5 mixes its first word with row 4, then copies the rest.
+        let oam = corrupt(OamCorruption::Write, 20);
+        assert_eq!(oam[40..42], 0x023F_u16.to_le_bytes());
+        assert_eq!(oam[42..48], oam[34..40]);
+        let oam = corrupt(OamCorruption::Read, 20);
+        assert_eq!(oam[40..42], 0x02FF_u16.to_le_bytes());
+        assert_eq!(oam[42..48], oam[34..40]);
+
+        // Row 4 is mixed with rows 3 and 5 and copied over both first.
+        let oam = corrupt(OamCorruption::ReadIncrement, 20);
+        assert_eq!(oam[24..32], oam[32..40]);
+        assert_eq!(oam[32..34], 0x003F_u16.to_le_bytes());
+        assert_eq!(oam[40..42], 0x003F_u16.to_le_bytes());
+        assert_eq!(oam[42..48], oam[34..40]);
+
+        // The first row and the other modes are left alone.
+        let clean = corrupt(OamCorruption::Write, 0);
+        assert_eq!(corrupt(OamCorruption::Write, 2), clean);
+        assert_eq!(corrupt(OamCorruption::Write, 100), clean);
+    }
+
+    #[test]
+    fn lyc_write_updates_coincidence() {
+        let mut ppu = Ppu::new();
+        ppu.write(LYC, 0xFF);
+        ppu.write(STAT, LYC_INT);
+        tick_interrupts(&mut ppu, 456 * 3 + 100);
+
+        assert_eq!(ppu.read(STAT) & COINCIDENCE, 0);
+        ppu.write(LYC, 3);
+        assert_ne!(ppu.read(STAT) & COINCIDENCE, 0);
+        assert_eq!(ppu.take_interrupts(), Interrupt::Stat.bit());
+
+        ppu.write(LYC, 4);
+        assert_eq!(ppu.read(STAT) & COINCIDENCE, 0);
+        assert_eq!(tick_interrupts(&mut ppu, 356), Interrupt::Stat.bit());
+    }
+
+    #[test]
+    fn background_disable_draws_color_0() {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, 0x90);
+        ppu.write(BGP, 0b1110_0111);
+        fill_tile(&mut ppu, 0x8000, 0, 3);
+
+        run_to_line_end(&mut ppu, 0);
+
+        assert!(ppu.framebuffer()[..WIDTH].iter().all(|&shade| shade == 3));
+    }
+
+    #[test]
+    fn hidden_layers_draw_as_absent() {
+        let mut ppu = sprite_ppu();
+        ppu.write(LCDC, 0xF3);
+        ppu.write(WX, 7 + 80);
+        // The background draws tile 0, the window tile 2 and the sprite
+        // tile 1.
+        fill_tile(&mut ppu, 0x8000, 0, 1);
+        fill_tile(&mut ppu, 0x8000, 1, 3);
+        fill_tile(&mut ppu, 0x8000, 2, 2);
+        ppu.write(0x9C00, 2);
+        write_sprite(&mut ppu, 0, [16, 8, 1, 0]);
+
+        ppu.set_layer_enabled(Layer::Sprites, false);
+        assert!(!ppu.is_layer_enabled(Layer::Sprites));
+        run_to_line_end(&mut ppu, 0);
+        let line = &ppu.framebuffer()[..WIDTH];
+        assert_eq!(line[..8], [1; 8]);
+        assert_eq!(line[80..88], [2; 8]);
+        assert_eq!(ppu.read(LCDC), 0xF3);
+
+        ppu.set_layer_enabled(Layer::Sprites, true);
+        ppu.set_layer_enabled(Layer::Background, false);
+        ppu.set_layer_enabled(Layer::Window, false);
+        run_to_line_end(&mut ppu, 1);
+        let line = &ppu.framebuffer()[WIDTH..2 * WIDTH];
+        assert_eq!(line[..8], [3; 8]);
+        assert_eq!(line[8..], [0; WIDTH - 8]);
+    }
+
+    #[test]
+    fn compat_palette_colors_each_palette() {
+        let mut ppu = sprite_ppu();
+        let palette = CompatPalette {
+            bg: [0x7FFF, 0x0000, 0x0000, 0x0000],
+            obj0: [0x0000, 0x001F, 0x0000, 0x0000],
+            obj1: [0x0000, 0x03E0, 0x0000, 0x0000],
+        };
+        ppu.set_compat_palette(Some(palette));
+        // OBP1 maps color 3 to shade 1, as does OBP0 for color 1.
+        fill_tile(&mut ppu, 0x8000, 1, 1);
+        fill_tile(&mut ppu, 0x8000, 2, 3);
+        write_sprite(&mut ppu, 0, [16, 8, 1, 0]);
+        write_sprite(&mut ppu, 1, [16, 16, 2, ATTR_PALETTE]);
+
+        run_to_line_end(&mut ppu, 0);
+
+        assert_eq!(ppu.framebuffer()[..24], [[1; 8], [1; 8], [0; 8]].concat());
+        let rgba = |x: usize| &ppu.rgba_framebuffer()[x * 4..x * 4 + 4];
+        assert_eq!(rgba(0), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(rgba(8), [0x00, 0xFF, 0x00, 0xFF]);
+        assert_eq!(rgba(16), [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn vbk_selects_vram_bank() {
+        let mut ppu = Ppu::new_cgb();
+        ppu.write(0x8000, 0x11);
+        assert_eq!(ppu.read(VBK), 0xFE);
+
+        ppu.write(VBK, 0xFF);
+        assert_eq!(ppu.read(VBK), 0xFF);
+        assert_eq!(ppu.read(0x8000), 0x00);
+        ppu.write(0x8000, 0x22);
+
+        ppu.write(VBK, 0x00);
+        assert_eq!(ppu.read(0x8000), 0x11);
+    }
+
+    #[test]
+    fn dmg_has_no_vbk() {
+        let mut ppu = Ppu::new();
+        ppu.write(0x8000, 0x11);
+        ppu.write(VBK, 0x01);
+
+        assert_eq!(ppu.read(VBK), 0xFF);
+        assert_eq!(ppu.read(0x8000), 0x11);
+    }
+
+    #[test]
+    fn palette_data_auto_increments() {
+        let mut ppu = Ppu::new_cgb();
+        ppu.write(BCPS, 0x80 | 0x3E);
+        ppu.write(BCPD, 0x12);
+        ppu.write(BCPD, 0x34);
+        assert_eq!(ppu.read(BCPS), 0xC0);
+
+        ppu.write(BCPS, 0x3E);
+        assert_eq!(ppu.read(BCPD), 0x12);
+        ppu.write(BCPD, 0x56);
+        assert_eq!(ppu.read(BCPS), 0x7E);
+        assert_eq!(ppu.read(BCPD), 0x56);
+    }
+
+    #[test]
+    fn rendering_uses_palette_ram() {
+        let mut ppu = Ppu::new_cgb();
+        fill_tile(&mut ppu, 0x8000, 1, 2);
+        ppu.write(0x9801, 1);
+        write_attrs(&mut ppu, 1, 0x03);
+
+        // Background palette 3, color 2 is pure red.
+        ppu.write(BCPS, 0x80 | 0x1C);
+        ppu.write(BCPD, 0x1F);
+        ppu.write(BCPD, 0x00);
+        // Sprite palette 0, color 3 is pure blue.
+        ppu.write(OCPS, 0x80 | 0x06);
+        ppu.write(OCPD, 0x00);
+        ppu.write(OCPD, 0x7C);
+        fill_tile(&mut ppu, 0x8000, 2, 3);
+        for (i, byte) in [16, 8 + 16, 2, 0].into_iter().enumerate() {
+            ppu.write(0xFE00 + u16::try_from(i).unwrap(), byte);
+        }
+        ppu.write(LCDC, 0x91 | OBJ_ENABLE);
+
+        run_to_line_end(&mut ppu, 0);
+
+        let line = &ppu.rgba_framebuffer()[..24 * 4];
+        assert_eq!(line[..4], [0xFF; 4]);
+        assert_eq!(line[8 * 4..8 * 4 + 4], [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(line[16 * 4..16 * 4 + 4], [0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn dmg_palette_colors_shades_after_bgp() {
+        let mut ppu = Ppu::new();
+        ppu.set_palette(DmgPalette::DMG);
+        // Color index 0 becomes shade 2 through `BGP`.
+        ppu.write(BGP, 0b1110_0110);
+
+        run_to_line_end(&mut ppu, 0);
+
+        assert_eq!(ppu.framebuffer()[0], 2);
+        assert_eq!(ppu.rgba_framebuffer()[..4], DmgPalette::DMG.color(2));
+
+        // Switching palettes recolors the frame already drawn.
+        ppu.set_palette(DmgPalette::LIGHT);
+        assert_eq!(ppu.rgba_framebuffer()[..4], DmgPalette::LIGHT.color(2));
+    }
+
+    #[test]
+    fn frame_blending_averages_flicker() {
+        let mut ppu = Ppu::new();
+        ppu.set_palette(DmgPalette::DMG);
+        ppu.set_frame_blending(true);
+        assert!(ppu.is_frame_blending());
+
+        // Color index 0 flickers between the lightest and darkest shades.
+        for bgp in [0x00, 0xFF, 0x00, 0xFF] {
+            ppu.write(BGP, bgp);
+            for _ in 0..154 * 456 / 4 {
+                ppu.tick(4);
+            }
+        }
+        assert_eq!(ppu.raw_rgba_framebuffer()[..4], DmgPalette::DMG.color(3));
+        assert_eq!(ppu.rgba_framebuffer()[..4], [0x55, 0x7A, 0x0F, 0xFF]);
+        let last = ppu.rgba_framebuffer().len() - 4;
+        assert_eq!(ppu.rgba_framebuffer()[last..], [0x55, 0x7A, 0x0F, 0xFF]);
+
+        ppu.set_frame_blending(false);
+        assert_eq!(ppu.rgba_framebuffer(), ppu.raw_rgba_framebuffer());
+    }
+
+    #[test]
+    fn converts_rgb555_to_rgb888() {
+        assert_eq!(rgb555_to_rgb888(0x0000), [0x00; 3]);
+        assert_eq!(rgb555_to_rgb888(0x7FFF), [0xFF; 3]);
+        assert_eq!(rgb555_to_rgb888(0x03E0), [0x00, 0xFF, 0x00]);
+        assert_eq!(rgb555_to_rgb888(0x0010), [0x84, 0x00, 0x00]);
+    }
+
+    /// Write the CGB attributes of background map entry `index`.
+    fn write_attrs(ppu: &mut Ppu, index: u16, attrs: u8) {
+        ppu.write(VBK, 1);
+        ppu.write(0x9800 + index, attrs);
+        ppu.write(VBK, 0);
+    }
+
+    #[test]
+    fn bank_1_attributes_select_palette() {
+        let mut ppu = Ppu::new_cgb();
+        fill_tile(&mut ppu, 0x8000, 1, 2);
+        ppu.write(0x9800, 1);
+        ppu.write(0x9801, 1);
+        write_attrs(&mut ppu, 1, 0x03);
+
+        run_to_line_end(&mut ppu, 0);
+
+        let line = &ppu.framebuffer()[..16];
+        assert_eq!(line[..8], [2; 8]);
+        assert_eq!(line[8..], [3 << 2 | 2; 8]);
+    }
+
+    #[test]
+    fn bank_1_attributes_select_tile_bank_and_flip() {
+        let mut ppu = Ppu::new_cgb();
+        // Tile 1 in bank 1 has a single pixel in its top left.
+        ppu.write(VBK, 1);
+        ppu.write(0x8010, 0x80);
+        ppu.write(0x8011, 0x80);
+        ppu.write(VBK, 0);
+        ppu.write(0x9800, 1);
+        ppu.write(0x9801, 1);
+        write_attrs(&mut ppu, 0, ATTR_BANK);
+        write_attrs(&mut ppu, 1, ATTR_BANK | ATTR_X_FLIP);
+
+        run_to_line_end(&mut ppu, 0);
+
+        assert_eq!(ppu.framebuffer()[..16], [3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn cgb_tile_priority_covers_sprites() {
+        let mut ppu = Ppu::new_cgb();
+        ppu.write(LCDC, 0x93);
+        fill_tile(&mut ppu, 0x8000, 1, 1);
+        fill_tile(&mut ppu, 0x8000, 2, 3);
+        ppu.write(0x9800, 1);
+        write_attrs(&mut ppu, 0, ATTR_BEHIND_BG);
+        write_sprite(&mut ppu, 0, [16, 12, 2, 0x05]);
+
+        run_to_line_end(&mut ppu, 0);
+        let sprite = CGB_OBJ_PIXEL | 5 << 2 | 3;
+        assert_eq!(ppu.framebuffer()[..8], [1; 8]);
+        assert_eq!(ppu.framebuffer()[8..12], [sprite; 4]);
+
+        // Clearing LCDC bit 0 puts sprites on top regardless.
+        ppu.write(LCDC, 0x92);
+        run_to_line_end(&mut ppu, 1);
+        assert_eq!(ppu.framebuffer()[WIDTH + 4], sprite);
+    }
+}
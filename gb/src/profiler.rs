@@ -0,0 +1,74 @@
+//! Counting where the CPU spends its time.
+//!
+//! A [`Profiler`] counts the executions and T-cycles of the instruction at
+//! each address, for a frontend to build a view of the hot spots from. It
+//! costs nothing unless enabled through [`GameBoy::set_profiling`].
+//!
+//! [`GameBoy::set_profiling`]: crate::GameBoy::set_profiling
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The executions and T-cycles of the instructions at every address.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    /// The executions and T-cycles at each address.
+    counts: Box<[(u64, u64)]>,
+}
+
+impl Profiler {
+    /// Create a profiler with nothing counted.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            counts: vec![(0, 0); 0x10000].into_boxed_slice(),
+        }
+    }
+
+    /// Count an execution of the instruction at `pc`, taking `cycles`.
+    pub fn record(&mut self, pc: u16, cycles: u8) {
+        let (count, total) = &mut self.counts[usize::from(pc)];
+        *count += 1;
+        *total += u64::from(cycles);
+    }
+
+    /// Return the address, executions and T-cycles of every address an
+    /// instruction was counted at, in address order.
+    #[must_use]
+    pub fn report(&self) -> Vec<(u16, u64, u64)> {
+        (0..=u16::MAX)
+            .zip(&self.counts)
+            .filter(|&(_, &(count, _))| count != 0)
+            .map(|(pc, &(count, cycles))| (pc, count, cycles))
+            .collect()
+    }
+
+    /// Forget everything counted so far.
+    pub fn clear(&mut self) {
+        self.counts.fill((0, 0));
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_sums_by_address() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x0150, 12);
+        profiler.record(0x0100, 4);
+        profiler.record(0x0150, 8);
+        assert_eq!(profiler.report(), [(0x0100, 1, 4), (0x0150, 2, 20)]);
+
+        profiler.clear();
+        assert!(profiler.report().is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+//! Naming the regions of the address space and the I/O registers.
+
+use crate::apu::{
+    NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24, NR30, NR31, NR32, NR33, NR34, NR41,
+    NR42, NR43, NR44, NR50, NR51, NR52,
+};
+use crate::interrupt::IF;
+use crate::joypad::P1;
+use crate::mmu::{BOOT, DMA, HDMA1, HDMA2, HDMA3, HDMA4, HDMA5, KEY1, SVBK};
+use crate::ppu::{
+    BCPD, BCPS, BGP, LCDC, LY, LYC, OBP0, OBP1, OCPD, OCPS, SCX, SCY, STAT, VBK, WX, WY,
+};
+use crate::serial::{SB, SC};
+use crate::timer::{DIV, TAC, TIMA, TMA};
+
+/// The region of the address space an address falls in, as returned by
+/// [`Mmu::region`](crate::mmu::Mmu::region).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Region {
+    /// The boot ROM, while it's mapped over the cartridge.
+    BootRom,
+    /// The fixed cartridge ROM bank at `0x0000-0x3FFF`.
+    RomBank0,
+    /// The switchable cartridge ROM bank at `0x4000-0x7FFF`.
+    RomBankN,
+    /// Video RAM at `0x8000-0x9FFF`.
+    Vram,
+    /// Cartridge RAM, or whatever the mapper puts there, at `0xA000-0xBFFF`.
+    ExtRam,
+    /// The fixed WRAM bank at `0xC000-0xCFFF`.
+    WramBank0,
+    /// The WRAM bank at `0xD000-0xDFFF`, switchable on the CGB.
+    WramBankN,
+    /// The echo of `0xC000-0xDDFF` at `0xE000-0xFDFF`.
+    EchoRam,
+    /// Object attribute memory at `0xFE00-0xFE9F`.
+    Oam,
+    /// The prohibited region at `0xFEA0-0xFEFF`.
+    Prohibited,
+    /// An I/O register in `0xFF00-0xFF7F`.
+    Io(IoReg),
+    /// High RAM at `0xFF80-0xFFFE`.
+    Hram,
+    /// The interrupt enable register at `0xFFFF`.
+    InterruptEnable,
+}
+
+impl Region {
+    /// Return the region of `addr`, taking the boot ROM as unmapped.
+    #[must_use]
+    pub const fn of(addr: u16) -> Self {
+        match addr {
+            0x0000..=0x3FFF => Self::RomBank0,
+            0x4000..=0x7FFF => Self::RomBankN,
+            0x8000..=0x9FFF => Self::Vram,
+            0xA000..=0xBFFF => Self::ExtRam,
+            0xC000..=0xCFFF => Self::WramBank0,
+            0xD000..=0xDFFF => Self::WramBankN,
+            0xE000..=0xFDFF => Self::EchoRam,
+            0xFE00..=0xFE9F => Self::Oam,
+            0xFEA0..=0xFEFF => Self::Prohibited,
+            0xFF00..=0xFF7F => Self::Io(IoReg::of(addr)),
+            0xFF80..=0xFFFE => Self::Hram,
+            0xFFFF => Self::InterruptEnable,
+        }
+    }
+
+    /// Return a human-readable name for the region, the register's for
+    /// [`Region::Io`].
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::BootRom => "Boot ROM",
+            Self::RomBank0 => "ROM bank 0",
+            Self::RomBankN => "ROM bank N",
+            Self::Vram => "VRAM",
+            Self::ExtRam => "External RAM",
+            Self::WramBank0 => "WRAM bank 0",
+            Self::WramBankN => "WRAM bank N",
+            Self::EchoRam => "Echo RAM",
+            Self::Oam => "OAM",
+            Self::Prohibited => "Prohibited",
+            Self::Io(reg) => reg.name(),
+            Self::Hram => "HRAM",
+            Self::InterruptEnable => "IE",
+        }
+    }
+}
+
+/// An I/O register in `0xFF00-0xFF7F`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IoReg {
+    /// The joypad register.
+    P1,
+    /// The serial transfer data.
+    Sb,
+    /// The serial transfer control.
+    Sc,
+    /// The divider.
+    Div,
+    /// The timer counter.
+    Tima,
+    /// The timer modulo.
+    Tma,
+    /// The timer control.
+    Tac,
+    /// The interrupt flags.
+    If,
+    /// The channel 1 sweep.
+    Nr10,
+    /// The channel 1 duty and length.
+    Nr11,
+    /// The channel 1 envelope.
+    Nr12,
+    /// The low byte of the channel 1 period.
+    Nr13,
+    /// The channel 1 control and period high bits.
+    Nr14,
+    /// The channel 2 duty and length.
+    Nr21,
+    /// The channel 2 envelope.
+    Nr22,
+    /// The low byte of the channel 2 period.
+    Nr23,
+    /// The channel 2 control and period high bits.
+    Nr24,
+    /// The channel 3 DAC enable.
+    Nr30,
+    /// The channel 3 length.
+    Nr31,
+    /// The channel 3 output level.
+    Nr32,
+    /// The low byte of the channel 3 period.
+    Nr33,
+    /// The channel 3 control and period high bits.
+    Nr34,
+    /// The channel 4 length.
+    Nr41,
+    /// The channel 4 envelope.
+    Nr42,
+    /// The channel 4 frequency and randomness.
+    Nr43,
+    /// The channel 4 control.
+    Nr44,
+    /// The master volume and VIN panning.
+    Nr50,
+    /// The sound panning.
+    Nr51,
+    /// The sound on/off and channel status.
+    Nr52,
+    /// The 16 bytes of wave RAM at `0xFF30-0xFF3F`.
+    WaveRam,
+    /// The LCD control.
+    Lcdc,
+    /// The LCD status.
+    Stat,
+    /// The background Y scroll.
+    Scy,
+    /// The background X scroll.
+    Scx,
+    /// The current line.
+    Ly,
+    /// The line compare.
+    Lyc,
+    /// The OAM DMA source.
+    Dma,
+    /// The background palette.
+    Bgp,
+    /// The object palette 0.
+    Obp0,
+    /// The object palette 1.
+    Obp1,
+    /// The window Y position.
+    Wy,
+    /// The window X position plus 7.
+    Wx,
+    /// The CGB speed switch.
+    Key1,
+    /// The CGB VRAM bank.
+    Vbk,
+    /// The boot ROM unmap register.
+    Boot,
+    /// The high byte of the VRAM DMA source.
+    Hdma1,
+    /// The low byte of the VRAM DMA source.
+    Hdma2,
+    /// The high byte of the VRAM DMA destination.
+    Hdma3,
+    /// The low byte of the VRAM DMA destination.
+    Hdma4,
+    /// The VRAM DMA length, mode and start.
+    Hdma5,
+    /// The CGB background palette index.
+    Bcps,
+    /// The CGB background palette data.
+    Bcpd,
+    /// The CGB object palette index.
+    Ocps,
+    /// The CGB object palette data.
+    Ocpd,
+    /// The CGB WRAM bank.
+    Svbk,
+    /// An address with no register behind it.
+    Unused,
+}
+
+impl IoReg {
+    /// Return the register at `addr`, or [`IoReg::Unused`] if there's none.
+    #[must_use]
+    pub const fn of(addr: u16) -> Self {
+        match addr {
+            P1 => Self::P1,
+            SB => Self::Sb,
+            SC => Self::Sc,
+            DIV => Self::Div,
+            TIMA => Self::Tima,
+            TMA => Self::Tma,
+            TAC => Self::Tac,
+            IF => Self::If,
+            NR10 => Self::Nr10,
+            NR11 => Self::Nr11,
+            NR12 => Self::Nr12,
+            NR13 => Self::Nr13,
+            NR14 => Self::Nr14,
+            NR21 => Self::Nr21,
+            NR22 => Self::Nr22,
+            NR23 => Self::Nr23,
+            NR24 => Self::Nr24,
+            NR30 => Self::Nr30,
+            NR31 => Self::Nr31,
+            NR32 => Self::Nr32,
+            NR33 => Self::Nr33,
+            NR34 => Self::Nr34,
+            NR41 => Self::Nr41,
+            NR42 => Self::Nr42,
+            NR43 => Self::Nr43,
+            NR44 => Self::Nr44,
+            NR50 => Self::Nr50,
+            NR51 => Self::Nr51,
+            NR52 => Self::Nr52,
+            0xFF30..=0xFF3F => Self::WaveRam,
+            LCDC => Self::Lcdc,
+            STAT => Self::Stat,
+            SCY => Self::Scy,
+            SCX => Self::Scx,
+            LY => Self::Ly,
+            LYC => Self::Lyc,
+            DMA => Self::Dma,
+            BGP => Self::Bgp,
+            OBP0 => Self::Obp0,
+            OBP1 => Self::Obp1,
+            WY => Self::Wy,
+            WX => Self::Wx,
+            KEY1 => Self::Key1,
+            VBK => Self::Vbk,
+            BOOT => Self::Boot,
+            HDMA1 => Self::Hdma1,
+            HDMA2 => Self::Hdma2,
+            HDMA3 => Self::Hdma3,
+            HDMA4 => Self::Hdma4,
+            HDMA5 => Self::Hdma5,
+            BCPS => Self::Bcps,
+            BCPD => Self::Bcpd,
+            OCPS => Self::Ocps,
+            OCPD => Self::Ocpd,
+            SVBK => Self::Svbk,
+            _ => Self::Unused,
+        }
+    }
+
+    /// Return the register's name, as in Pan Docs.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::P1 => "P1",
+            Self::Sb => "SB",
+            Self::Sc => "SC",
+            Self::Div => "DIV",
+            Self::Tima => "TIMA",
+            Self::Tma => "TMA",
+            Self::Tac => "TAC",
+            Self::If => "IF",
+            Self::Nr10 => "NR10",
+            Self::Nr11 => "NR11",
+            Self::Nr12 => "NR12",
+            Self::Nr13 => "NR13",
+            Self::Nr14 => "NR14",
+            Self::Nr21 => "NR21",
+            Self::Nr22 => "NR22",
+            Self::Nr23 => "NR23",
+            Self::Nr24 => "NR24",
+            Self::Nr30 => "NR30",
+            Self::Nr31 => "NR31",
+            Self::Nr32 => "NR32",
+            Self::Nr33 => "NR33",
+            Self::Nr34 => "NR34",
+            Self::Nr41 => "NR41",
+            Self::Nr42 => "NR42",
+            Self::Nr43 => "NR43",
+            Self::Nr44 => "NR44",
+            Self::Nr50 => "NR50",
+            Self::Nr51 => "NR51",
+            Self::Nr52 => "NR52",
+            Self::WaveRam => "Wave RAM",
+            Self::Lcdc => "LCDC",
+            Self::Stat => "STAT",
+            Self::Scy => "SCY",
+            Self::Scx => "SCX",
+            Self::Ly => "LY",
+            Self::Lyc => "LYC",
+            Self::Dma => "DMA",
+            Self::Bgp => "BGP",
+            Self::Obp0 => "OBP0",
+            Self::Obp1 => "OBP1",
+            Self::Wy => "WY",
+            Self::Wx => "WX",
+            Self::Key1 => "KEY1",
+            Self::Vbk => "VBK",
+            Self::Boot => "BOOT",
+            Self::Hdma1 => "HDMA1",
+            Self::Hdma2 => "HDMA2",
+            Self::Hdma3 => "HDMA3",
+            Self::Hdma4 => "HDMA4",
+            Self::Hdma5 => "HDMA5",
+            Self::Bcps => "BCPS",
+            Self::Bcpd => "BCPD",
+            Self::Ocps => "OCPS",
+            Self::Ocpd => "OCPD",
+            Self::Svbk => "SVBK",
+            Self::Unused => "Unused",
+        }
+    }
+}
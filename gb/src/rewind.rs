@@ -0,0 +1,296 @@
+//! Rewinding through recent save states.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// How much history a [`RewindBuffer`] keeps before dropping the oldest
+/// states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RewindLimit {
+    /// Keep at most this many states.
+    States(usize),
+    /// Keep at most about this many bytes of compressed states, though the
+    /// most recent state is always kept.
+    Bytes(usize),
+}
+
+/// A ring buffer of save states, captured every few frames.
+///
+/// Only the most recent state is kept whole. Each older one is stored as
+/// the difference to the state after it, with unchanged runs of bytes
+/// compressed away, which keeps both memory use and the cost of a capture
+/// low enough to run every frame.
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    /// The frames between captures.
+    interval: u32,
+    limit: RewindLimit,
+    /// The frames since the last capture.
+    frames: u32,
+    /// The most recent state.
+    latest: Option<Vec<u8>>,
+    /// The compressed differences between each older state and the next
+    /// one, oldest first.
+    deltas: VecDeque<Vec<u8>>,
+    /// The total length of `deltas`.
+    delta_bytes: usize,
+}
+
+impl RewindBuffer {
+    /// Create an empty buffer capturing a state every `interval` frames, of
+    /// at least 1, and keeping up to `limit` of them.
+    #[must_use]
+    pub fn new(interval: u32, limit: RewindLimit) -> Self {
+        Self {
+            interval: interval.max(1),
+            limit,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    /// Return the number of states that can be rewound to.
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.deltas.len() + usize::from(self.latest.is_some())
+    }
+
+    /// Return the bytes taken by the stored states.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.delta_bytes + self.latest.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Drop every stored state.
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    /// Count a completed frame, and return whether a state should be
+    /// captured after it.
+    pub fn frame_due(&mut self) -> bool {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return false;
+        }
+
+        self.frames = 0;
+        true
+    }
+
+    /// Store `state` as the most recent one, dropping the oldest states
+    /// beyond the limit.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(latest) = self.latest.take() {
+            let delta = encode_delta(&latest, &state);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(state);
+
+        while self.over_limit()
+            && let Some(delta) = self.deltas.pop_front()
+        {
+            self.delta_bytes -= delta.len();
+        }
+    }
+
+    /// Remove and return the most recent state.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.delta_bytes -= delta.len();
+            self.latest = Some(apply_delta(&latest, &delta));
+        }
+
+        self.frames = 0;
+        Some(latest)
+    }
+
+    /// Check if more states are stored than the limit allows.
+    fn over_limit(&self) -> bool {
+        match self.limit {
+            RewindLimit::States(states) => self.depth() > states.max(1),
+            RewindLimit::Bytes(bytes) => self.size() > bytes,
+        }
+    }
+}
+
+/// Encode the difference from `new` back to `old`.
+///
+/// The delta is the length of `old`, then the exclusive or of the two, with the
+/// shorter one padded with zeroes. That is stored as pairs of a count of
+/// zeroes and a run of other bytes, as most of a state is unchanged from
+/// one frame to the next.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, old.len());
+
+    let len = old.len().max(new.len());
+    let byte = |state: &[u8], index| state.get(index).copied().unwrap_or(0);
+    let mut diff = (0..len).map(|index| byte(old, index) ^ byte(new, index)).peekable();
+
+    while diff.peek().is_some() {
+        let mut same = 0;
+        while diff.next_if_eq(&0).is_some() {
+            same += 1;
+        }
+
+        let mut changed = Vec::new();
+        while let Some(byte) = diff.next_if(|&byte| byte != 0) {
+            changed.push(byte);
+        }
+
+        write_varint(&mut delta, same);
+        write_varint(&mut delta, changed.len());
+        delta.extend(changed);
+    }
+
+    delta
+}
+
+/// Rebuild the old state from `new` and the `delta` encoded back to it by
+/// [`encode_delta`].
+fn apply_delta(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut delta = delta.iter().copied();
+    let old_len = read_varint(&mut delta).unwrap_or(0);
+
+    let mut old = new.to_vec();
+    old.resize(old_len.max(new.len()), 0);
+    let mut index = 0;
+
+    while let Some(same) = read_varint(&mut delta) {
+        index += same;
+        let changed = read_varint(&mut delta).unwrap_or(0);
+        for (byte, diff) in old[index..].iter_mut().zip(delta.by_ref().take(changed)) {
+            *byte ^= diff;
+        }
+        index += changed;
+    }
+
+    old.truncate(old_len);
+    old
+}
+
+/// Append `value` as a little-endian base-128 varint.
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        // The low 7 bits, with the continuation bit set.
+        #[allow(clippy::cast_possible_truncation)]
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)] // Below 0x80.
+    out.push(value as u8);
+}
+
+/// Read a varint written by [`write_varint`].
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = bytes.next()?;
+        value |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    /// Return a state of 1000 bytes, of which `changed` differ from the
+    /// first one.
+    fn state(changed: usize) -> Vec<u8> {
+        let mut state = vec![0x55; 1000];
+        for byte in &mut state[500..500 + changed] {
+            *byte = 0xAA;
+        }
+        state
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        let old: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut new = old.clone();
+        new[0] = 1;
+        new[300..420].fill(0);
+        new[999] = 7;
+
+        let delta = encode_delta(&old, &new);
+        assert!(delta.len() < 140);
+        assert_eq!(apply_delta(&new, &delta), old);
+    }
+
+    #[test]
+    fn pops_most_recent_first() {
+        let mut buffer = RewindBuffer::new(1, RewindLimit::States(10));
+        for changed in 0..5 {
+            buffer.push(state(changed));
+        }
+        assert_eq!(buffer.depth(), 5);
+
+        for changed in (0..5).rev() {
+            assert_eq!(buffer.pop(), Some(state(changed)));
+        }
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.depth(), 0);
+    }
+
+    #[test]
+    fn drops_oldest_over_state_limit() {
+        let mut buffer = RewindBuffer::new(1, RewindLimit::States(3));
+        for changed in 0..5 {
+            buffer.push(state(changed));
+        }
+
+        assert_eq!(buffer.depth(), 3);
+        assert_eq!(buffer.pop(), Some(state(4)));
+        assert_eq!(buffer.pop(), Some(state(3)));
+        assert_eq!(buffer.pop(), Some(state(2)));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn drops_oldest_over_byte_limit() {
+        let mut buffer = RewindBuffer::new(1, RewindLimit::Bytes(1100));
+        for changed in 0..50 {
+            buffer.push(state(changed));
+        }
+
+        assert!(buffer.size() <= 1100);
+        assert!(buffer.depth() > 1);
+        assert_eq!(buffer.pop(), Some(state(49)));
+    }
+
+    #[test]
+    fn captures_every_interval() {
+        let mut buffer = RewindBuffer::new(3, RewindLimit::States(10));
+        let due: Vec<_> = (0..7).map(|_| buffer.frame_due()).collect();
+
+        assert_eq!(due, [false, false, true, false, false, true, false]);
+    }
+
+    #[test]
+    fn states_may_change_size() {
+        let mut buffer = RewindBuffer::new(1, RewindLimit::States(10));
+        buffer.push(vec![1, 2, 3]);
+        buffer.push(state(1));
+        buffer.push(vec![4, 5]);
+
+        assert_eq!(buffer.pop(), Some(vec![4, 5]));
+        assert_eq!(buffer.pop(), Some(state(1)));
+        assert_eq!(buffer.pop(), Some(vec![1, 2, 3]));
+    }
+}
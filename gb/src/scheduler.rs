@@ -0,0 +1,207 @@
+//! A queue of future hardware events, keyed on the global cycle count.
+//!
+//! The [`Mmu`](crate::mmu::Mmu) posts the next event of each component to a
+//! [`Scheduler`], such as the PPU's next mode change or the timer's next
+//! reload, and splits every tick at the events due within it, so each one is
+//! handled on its exact cycle. Events due on the same cycle fire in the order
+//! they were scheduled.
+
+use alloc::collections::BinaryHeap;
+use core::cmp::{Ordering, Reverse};
+
+/// An event a component posts for a future cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Event {
+    /// The PPU moves on to its next mode or line. In mode 3 this is only the
+    /// earliest the line could end.
+    PpuMode,
+    /// `TIMA` reloads from `TMA`, an M-cycle after overflowing.
+    TimerOverflow,
+    /// The APU's frame sequencer steps.
+    FrameSequencer,
+    /// An OAM DMA transfer copies its last byte.
+    DmaComplete,
+}
+
+impl Event {
+    /// Every event.
+    pub const ALL: [Self; 4] =
+        [Self::PpuMode, Self::TimerOverflow, Self::FrameSequencer, Self::DmaComplete];
+}
+
+/// An event in the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    /// The global cycle the event fires on.
+    at: u64,
+    /// The order the event was scheduled in, breaking ties between events
+    /// due on the same cycle.
+    seq: u64,
+    event: Event,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A priority queue of [`Event`]s and the global cycle count.
+#[derive(Debug, Clone, Default)]
+pub struct Scheduler {
+    /// The current global cycle.
+    now: u64,
+    /// The events scheduled so far, for ordering those due together.
+    scheduled: u64,
+    queue: BinaryHeap<Reverse<Entry>>,
+}
+
+impl Scheduler {
+    /// Create an empty scheduler at cycle 0.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the current global cycle.
+    #[must_use]
+    pub const fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Return the number of events waiting to fire.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Check if no events are waiting to fire.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Schedule `event` to fire on the global cycle `at`.
+    ///
+    /// An event scheduled in the past fires on the next call to
+    /// [`Scheduler::pop_due`].
+    pub fn schedule(&mut self, at: u64, event: Event) {
+        let seq = self.scheduled;
+        self.scheduled += 1;
+        self.queue.push(Reverse(Entry { at, seq, event }));
+    }
+
+    /// Schedule `event` to fire `cycles` after the current cycle.
+    pub fn schedule_in(&mut self, cycles: u64, event: Event) {
+        self.schedule(self.now + cycles, event);
+    }
+
+    /// Remove every pending occurrence of `event`, as when a register write
+    /// moves the next timer overflow.
+    pub fn cancel(&mut self, event: Event) {
+        self.queue.retain(|Reverse(entry)| entry.event != event);
+    }
+
+    /// Return the cycle the next event fires on, if any.
+    #[must_use]
+    pub fn next_at(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse(entry)| entry.at)
+    }
+
+    /// Return the cycle the next `event` fires on, if it's scheduled.
+    #[must_use]
+    pub fn at(&self, event: Event) -> Option<u64> {
+        self.queue
+            .iter()
+            .filter(|Reverse(entry)| entry.event == event)
+            .map(|Reverse(entry)| entry.at)
+            .min()
+    }
+
+    /// Return the cycles until the next event, zero if it's already due.
+    #[must_use]
+    pub fn until_next(&self) -> Option<u64> {
+        self.next_at().map(|at| at.saturating_sub(self.now))
+    }
+
+    /// Advance the global cycle count by `cycles`.
+    pub const fn advance(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    /// Advance to the next event, if any, and remove it, returning the
+    /// cycle it fired on with the event.
+    ///
+    /// The cycle count never runs backwards, so an event scheduled in the
+    /// past fires on the current cycle.
+    pub fn run_to_next(&mut self) -> Option<(u64, Event)> {
+        let Reverse(entry) = self.queue.pop()?;
+        self.now = self.now.max(entry.at);
+        Some((self.now, entry.event))
+    }
+
+    /// Remove and return the next event due by the current cycle, with the
+    /// cycle it was scheduled for.
+    pub fn pop_due(&mut self) -> Option<(u64, Event)> {
+        if self.next_at()? > self.now {
+            return None;
+        }
+        self.queue.pop().map(|Reverse(entry)| (entry.at, entry.event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn events_fire_on_their_cycle() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(456, Event::PpuMode);
+        scheduler.schedule_in(80, Event::PpuMode);
+        scheduler.schedule(8192, Event::FrameSequencer);
+        scheduler.schedule(80, Event::TimerOverflow);
+        assert_eq!(scheduler.until_next(), Some(80));
+        assert_eq!(scheduler.at(Event::PpuMode), Some(80));
+        assert_eq!(scheduler.at(Event::DmaComplete), None);
+
+        scheduler.advance(79);
+        assert_eq!(scheduler.pop_due(), None);
+        scheduler.advance(1);
+        // Events due together fire in the order they were scheduled.
+        assert_eq!(scheduler.pop_due(), Some((80, Event::PpuMode)));
+        assert_eq!(scheduler.pop_due(), Some((80, Event::TimerOverflow)));
+        assert_eq!(scheduler.pop_due(), None);
+
+        assert_eq!(scheduler.run_to_next(), Some((456, Event::PpuMode)));
+        assert_eq!(scheduler.now(), 456);
+        assert_eq!(scheduler.run_to_next(), Some((8192, Event::FrameSequencer)));
+        assert_eq!(scheduler.run_to_next(), None);
+        assert_eq!(scheduler.now(), 8192);
+    }
+
+    #[test]
+    fn cancel_and_past_events() {
+        let mut scheduler = Scheduler::new();
+        scheduler.advance(1000);
+        scheduler.schedule_in(16, Event::TimerOverflow);
+        scheduler.schedule_in(640, Event::DmaComplete);
+        scheduler.cancel(Event::TimerOverflow);
+        scheduler.schedule(900, Event::TimerOverflow);
+        assert_eq!(scheduler.len(), 2);
+
+        // Late events fire at once, without turning the clock back.
+        assert_eq!(scheduler.pop_due(), Some((900, Event::TimerOverflow)));
+        let fired: Vec<_> = core::iter::from_fn(|| scheduler.run_to_next()).collect();
+        assert_eq!(fired, [(1640, Event::DmaComplete)]);
+        assert!(scheduler.is_empty());
+    }
+}
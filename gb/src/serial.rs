@@ -0,0 +1,355 @@
+//! The serial port, and the link cable that can be attached to it.
+
+use alloc::boxed::Box;
+use core::fmt;
+
+use crate::interrupt::Interrupt;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The address of the serial transfer data register.
+pub const SB: u16 = 0xFF01;
+/// The address of the serial transfer control register.
+pub const SC: u16 = 0xFF02;
+
+/// The `SC` bit that starts a transfer, and stays set while it runs.
+const TRANSFER_START: u8 = 0x80;
+/// The `SC` bit that selects the internal clock.
+const INTERNAL_CLOCK: u8 = 0x01;
+
+/// The T-cycles per bit with the internal 8192 Hz clock.
+const BIT_CYCLES: u16 = 512;
+
+/// A sink for the bytes shifted out of the serial port.
+type ByteSink = Box<dyn FnMut(u8)>;
+
+/// A link cable, carrying bits between a serial port and its partner.
+///
+/// Only the side on the internal clock drives the transfer. For each bit of
+/// its clock the port calls [`LinkCable::exchange`], which delivers the bit
+/// to the partner and returns the one the partner shifts out. A partner on
+/// the external clock is clocked through [`Serial::clock_external`] instead,
+/// see [`crate::link::LocalLink`].
+pub trait LinkCable {
+    /// Send `bit` to the partner as the side driving the clock, returning
+    /// the bit shifted in from it. With no partner listening, the line is
+    /// pulled up and reads 1.
+    fn exchange(&mut self, bit: bool) -> bool;
+}
+
+/// The serial port.
+///
+/// A transfer with the internal clock shifts `SB` out over eight bits of
+/// serial clock, shifting in the bits from the [`LinkCable`], or a 1 for
+/// every bit with no cable connected. Transfers on the external clock wait
+/// for the partner to clock them with [`Serial::clock_external`].
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    /// The byte being shifted out, as it was when the transfer started.
+    outgoing: u8,
+    /// The bits shifted so far in the running transfer.
+    bits: u8,
+    /// The T-cycles towards the next bit.
+    cycles: u16,
+    sink: Option<ByteSink>,
+    cable: Option<Box<dyn LinkCable>>,
+    interrupts: u8,
+    /// The byte shifted out since it was last taken, if any.
+    transmitted: Option<u8>,
+}
+
+impl Serial {
+    /// Create an idle serial port.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            outgoing: 0,
+            bits: 0,
+            cycles: 0,
+            sink: None,
+            cable: None,
+            interrupts: 0,
+            transmitted: None,
+        }
+    }
+
+    /// Reset the registers and abort any transfer, keeping the sink and the
+    /// cable.
+    pub const fn reset(&mut self) {
+        self.sb = 0;
+        self.sc = 0;
+        self.outgoing = 0;
+        self.bits = 0;
+        self.cycles = 0;
+        self.interrupts = 0;
+        self.transmitted = None;
+    }
+
+    /// Write the registers and transfer progress to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.sb);
+        state.write_u8(self.sc);
+        state.write_u8(self.outgoing);
+        state.write_u8(self.bits);
+        state.write_u16(self.cycles);
+        state.write_u8(self.interrupts);
+        state.write_bool(self.transmitted.is_some());
+        state.write_u8(self.transmitted.unwrap_or(0));
+    }
+
+    /// Restore the state written by [`Serial::save_state`], keeping the sink.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is truncated or corrupt.
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.sb = state.read_u8()?;
+        self.sc = state.read_u8()? & (TRANSFER_START | INTERNAL_CLOCK);
+        self.outgoing = state.read_u8()?;
+        self.bits = state.read_u8()?;
+        self.cycles = state.read_u16()?;
+        self.interrupts = state.read_u8()?;
+        let transmitted = state.read_bool()?;
+        let byte = state.read_u8()?;
+        self.transmitted = transmitted.then_some(byte);
+
+        if self.bits >= 8 || self.cycles >= BIT_CYCLES {
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
+    }
+
+    /// Call `sink` with every byte once it's been shifted out.
+    pub fn on_byte_transmitted(&mut self, sink: impl FnMut(u8) + 'static) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    /// Attach `cable` to the port, or disconnect it with `None`.
+    pub fn set_cable(&mut self, cable: Option<Box<dyn LinkCable>>) {
+        self.cable = cable;
+    }
+
+    /// Check if a transfer on the internal clock is running.
+    #[must_use]
+    pub const fn is_transferring(&self) -> bool {
+        self.sc & (TRANSFER_START | INTERNAL_CLOCK) == TRANSFER_START | INTERNAL_CLOCK
+    }
+
+    /// Return `SB` and the bits shifted so far, while a transfer on the
+    /// external clock waits for its partner.
+    #[must_use]
+    pub const fn external_transfer(&self) -> Option<(u8, u8)> {
+        if self.sc & (TRANSFER_START | INTERNAL_CLOCK) == TRANSFER_START {
+            Some((self.sb, self.bits))
+        } else {
+            None
+        }
+    }
+
+    /// Clock a transfer on the external clock by one bit, shifting `bit` in
+    /// from the partner, and return the bit shifted out to it. Without such
+    /// a transfer running, this returns `None`.
+    pub fn clock_external(&mut self, bit: bool) -> Option<bool> {
+        self.external_transfer()?;
+        let out = self.sb & 0x80 != 0;
+        self.shift(bit);
+        Some(out)
+    }
+
+    /// Return and clear the interrupts requested since the last call, as
+    /// `IF` bits.
+    pub const fn take_interrupts(&mut self) -> u8 {
+        let interrupts = self.interrupts;
+        self.interrupts = 0;
+        interrupts
+    }
+
+    /// Return and clear the byte shifted out since the last call, if any.
+    pub const fn take_transmitted(&mut self) -> Option<u8> {
+        self.transmitted.take()
+    }
+
+    /// Advance the serial clock by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u8) {
+        if !self.is_transferring() {
+            return;
+        }
+
+        self.cycles += u16::from(cycles);
+        while self.cycles >= BIT_CYCLES && self.is_transferring() {
+            self.cycles -= BIT_CYCLES;
+            let out = self.sb & 0x80 != 0;
+            let bit = self.cable.as_mut().is_none_or(|cable| cable.exchange(out));
+            self.shift(bit);
+        }
+    }
+
+    /// Shift one bit out of `SB`, and `bit` in from the partner.
+    fn shift(&mut self, bit: bool) {
+        self.sb = self.sb << 1 | u8::from(bit);
+        self.bits += 1;
+        if self.bits < 8 {
+            return;
+        }
+
+        self.sc &= !TRANSFER_START;
+        self.interrupts |= Interrupt::Serial.bit();
+        self.transmitted = Some(self.outgoing);
+        if let Some(sink) = &mut self.sink {
+            sink(self.outgoing);
+        }
+    }
+
+    /// Read a serial register.
+    #[must_use]
+    pub const fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB => self.sb,
+            SC => 0x7E | self.sc,
+            _ => 0xFF,
+        }
+    }
+
+    /// Set a serial register without any side effects, for tooling.
+    ///
+    /// Poking `SC` changes its bits without starting or restarting a
+    /// transfer.
+    pub const fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            SB => self.sb = value,
+            SC => self.sc = value & (TRANSFER_START | INTERNAL_CLOCK),
+            _ => {}
+        }
+    }
+
+    /// Write a serial register.
+    pub const fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            SB => self.sb = value,
+            SC => {
+                self.sc = value & (TRANSFER_START | INTERNAL_CLOCK);
+                self.outgoing = self.sb;
+                self.bits = 0;
+                self.cycles = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Serial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Serial")
+            .field("sb", &self.sb)
+            .field("sc", &self.sc)
+            .field("bits", &self.bits)
+            .field("cycles", &self.cycles)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    use super::*;
+
+    /// Return a serial port capturing its output into the returned buffer.
+    fn capture() -> (Serial, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut serial = Serial::new();
+
+        let sink = Rc::clone(&output);
+        serial.on_byte_transmitted(move |byte| sink.borrow_mut().push(byte));
+        (serial, output)
+    }
+
+    /// Send `byte` with the internal clock and run the transfer to the end.
+    fn send(serial: &mut Serial, byte: u8) {
+        serial.write(SB, byte);
+        serial.write(SC, 0x81);
+        for _ in 0..8 * BIT_CYCLES / 4 {
+            serial.tick(4);
+        }
+    }
+
+    #[test]
+    fn transmits_with_internal_clock() {
+        let (mut serial, output) = capture();
+
+        serial.write(SB, b'H');
+        serial.write(SC, 0x81);
+        assert!(serial.is_transferring());
+        assert_eq!(serial.read(SC), 0xFF);
+
+        for _ in 0..8 * BIT_CYCLES / 4 - 1 {
+            serial.tick(4);
+        }
+        assert!(output.borrow().is_empty());
+        assert_eq!(serial.take_interrupts(), 0);
+
+        serial.tick(4);
+        assert_eq!(*output.borrow(), b"H");
+        assert_eq!(serial.take_interrupts(), Interrupt::Serial.bit());
+        assert_eq!(serial.read(SC), 0x7F);
+    }
+
+    #[test]
+    fn receives_ones_without_cable() {
+        let mut serial = Serial::new();
+
+        send(&mut serial, 0x12);
+        assert_eq!(serial.read(SB), 0xFF);
+    }
+
+    #[test]
+    fn captures_blargg_output() {
+        let (mut serial, output) = capture();
+
+        for &byte in b"Passed\n" {
+            send(&mut serial, byte);
+        }
+        assert_eq!(*output.borrow(), b"Passed\n");
+    }
+
+    #[test]
+    fn external_clock_waits() {
+        let (mut serial, output) = capture();
+        serial.write(SB, 0x12);
+        serial.write(SC, 0x80);
+
+        for _ in 0..0x1000 {
+            serial.tick(4);
+        }
+        assert_eq!(serial.read(SB), 0x12);
+        assert_eq!(serial.read(SC), 0xFE);
+        assert!(output.borrow().is_empty());
+    }
+
+    #[test]
+    fn partner_clocks_external_transfer() {
+        let (mut serial, output) = capture();
+        assert_eq!(serial.clock_external(true), None);
+
+        serial.write(SB, 0xA5);
+        serial.write(SC, 0x80);
+        assert_eq!(serial.external_transfer(), Some((0xA5, 0)));
+        let out: Vec<_> = (0..8).map(|bit| serial.clock_external(bit % 2 == 0)).collect();
+        assert_eq!(out, [true, false, true, false, false, true, false, true].map(Some));
+
+        assert_eq!(serial.read(SB), 0xAA);
+        assert_eq!(serial.external_transfer(), None);
+        assert_eq!(serial.take_interrupts(), Interrupt::Serial.bit());
+        assert_eq!(*output.borrow(), [0xA5]);
+    }
+}
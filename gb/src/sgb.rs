@@ -0,0 +1,388 @@
+//! The command packets of the Super Game Boy.
+//!
+//! A game talks to the SNES side of the Super Game Boy by pulsing the row
+//! select lines of `P1`. Pulling both low starts a packet, then each bit is
+//! sent by pulling `P14` low for a 0 or `P15` low for a 1, with both lines
+//! high in between. A packet is 16 bytes, least significant bit first,
+//! followed by a 0 stop bit. The first byte of a command holds its code in
+//! the upper 5 bits and its length in packets, 1 to 7, in the lower 3.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// The number of bytes in a packet.
+pub const PACKET_SIZE: usize = 16;
+
+/// The number of data bits in a packet, before the stop bit.
+const PACKET_BITS: u8 = 128;
+
+/// The `P1` row select bits, which carry the packets.
+const LINES: u8 = 0x30;
+/// The lines pulled low by a reset pulse, which starts a packet.
+const RESET: u8 = 0x00;
+/// The lines pulled low for a 0 bit, only `P14`.
+const ZERO: u8 = 0x20;
+/// The lines pulled low for a 1 bit, only `P15`.
+const ONE: u8 = 0x10;
+
+/// The most commands kept for the frontend, after which the oldest are
+/// dropped.
+const MAX_COMMANDS: usize = 64;
+
+/// A Super Game Boy command, from the code in its first byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    /// Set the colors of palettes 0 and 1.
+    Pal01,
+    /// Set the colors of palettes 2 and 3.
+    Pal23,
+    /// Set the colors of palettes 0 and 3.
+    Pal03,
+    /// Set the colors of palettes 1 and 2.
+    Pal12,
+    /// Apply palettes to blocks of the screen.
+    AttrBlk,
+    /// Apply palettes to lines of the screen.
+    AttrLin,
+    /// Apply palettes either side of a dividing line.
+    AttrDiv,
+    /// Apply palettes to single characters of the screen.
+    AttrChr,
+    /// Play a sound effect on the SNES.
+    Sound,
+    /// Transfer sound data from VRAM.
+    SouTrn,
+    /// Select palettes from those transferred with `PAL_TRN`.
+    PalSet,
+    /// Transfer system palettes from VRAM.
+    PalTrn,
+    /// Enable or disable attraction mode.
+    AtrcEn,
+    /// Enable or disable test speed mode.
+    TestEn,
+    /// Enable or disable the SNES menu icons.
+    IconEn,
+    /// Write bytes to SNES memory.
+    DataSnd,
+    /// Transfer data to SNES memory from VRAM.
+    DataTrn,
+    /// Request multiplayer mode.
+    MltReq,
+    /// Jump to a SNES routine.
+    Jump,
+    /// Transfer border tiles from VRAM.
+    ChrTrn,
+    /// Transfer the border tile map and palettes from VRAM.
+    PctTrn,
+    /// Transfer attribute files from VRAM.
+    AttrTrn,
+    /// Select an attribute file from those transferred with `ATTR_TRN`.
+    AttrSet,
+    /// Freeze or blank the screen.
+    MaskEn,
+    /// Transfer sprite data from VRAM.
+    ObjTrn,
+    /// A code no command uses.
+    Unknown(u8),
+}
+
+impl Command {
+    /// The commands in order of their codes.
+    const ALL: [Self; 25] = [
+        Self::Pal01,
+        Self::Pal23,
+        Self::Pal03,
+        Self::Pal12,
+        Self::AttrBlk,
+        Self::AttrLin,
+        Self::AttrDiv,
+        Self::AttrChr,
+        Self::Sound,
+        Self::SouTrn,
+        Self::PalSet,
+        Self::PalTrn,
+        Self::AtrcEn,
+        Self::TestEn,
+        Self::IconEn,
+        Self::DataSnd,
+        Self::DataTrn,
+        Self::MltReq,
+        Self::Jump,
+        Self::ChrTrn,
+        Self::PctTrn,
+        Self::AttrTrn,
+        Self::AttrSet,
+        Self::MaskEn,
+        Self::ObjTrn,
+    ];
+
+    /// Return the command with the 5-bit `code`.
+    #[must_use]
+    pub fn from_code(code: u8) -> Self {
+        Self::ALL
+            .get(usize::from(code))
+            .copied()
+            .unwrap_or(Self::Unknown(code))
+    }
+}
+
+/// A command received in full, with every packet it was sent in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Packet {
+    /// The command, from the first byte.
+    pub command: Command,
+    /// The bytes of every packet, the first byte included.
+    pub data: Box<[u8]>,
+}
+
+/// How the screen is masked, as set by `MASK_EN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Mask {
+    /// The screen shows the Game Boy picture.
+    #[default]
+    Cancel,
+    /// The screen keeps the picture it showed when masked.
+    Freeze,
+    /// The screen is black.
+    Black,
+    /// The screen is filled with color 0.
+    Color0,
+}
+
+/// The packet receiver of a Super Game Boy.
+///
+/// It follows writes to `P1`, reassembling packets into commands. Each
+/// command is queued for the frontend, which takes them with
+/// [`Sgb::take_packet`]. The palette and mask commands are decoded as well,
+/// see [`Sgb::palettes`] and [`Sgb::mask`].
+///
+/// Packets are received from any cartridge, whether or not its header asks
+/// for Super Game Boy functions. Nothing is drawn from them yet.
+#[derive(Debug, Clone)]
+pub struct Sgb {
+    /// The row select lines last written to `P1`.
+    lines: u8,
+    /// Whether a packet is being received, after a reset pulse.
+    receiving: bool,
+    /// The packet being received.
+    packet: [u8; PACKET_SIZE],
+    /// The number of bits of `packet` received.
+    bits: u8,
+    /// The packets received of the current command.
+    data: Vec<u8>,
+    /// The packets left of the current command.
+    remaining: u8,
+    /// The commands received and not yet taken.
+    packets: VecDeque<Packet>,
+    /// The four palettes, as RGB555 colors.
+    palettes: [[u16; 4]; 4],
+    mask: Mask,
+}
+
+impl Sgb {
+    /// Create a receiver waiting for a packet, with black palettes.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            lines: LINES,
+            receiving: false,
+            packet: [0; PACKET_SIZE],
+            bits: 0,
+            data: Vec::new(),
+            remaining: 0,
+            packets: VecDeque::new(),
+            palettes: [[0; 4]; 4],
+            mask: Mask::Cancel,
+        }
+    }
+
+    /// Return the four palettes, as set by the `PAL01`, `PAL23`, `PAL03` and
+    /// `PAL12` commands, in RGB555.
+    #[must_use]
+    pub const fn palettes(&self) -> [[u16; 4]; 4] {
+        self.palettes
+    }
+
+    /// Return how the screen is masked, as set by `MASK_EN`.
+    #[must_use]
+    pub const fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    /// Return and remove the oldest command received, if any.
+    pub fn take_packet(&mut self) -> Option<Packet> {
+        self.packets.pop_front()
+    }
+
+    /// Follow a write of `value` to `P1`.
+    pub fn write(&mut self, value: u8) {
+        let lines = value & LINES;
+        if lines == self.lines {
+            return;
+        }
+        self.lines = lines;
+
+        match lines {
+            RESET => {
+                self.receiving = true;
+                self.packet = [0; PACKET_SIZE];
+                self.bits = 0;
+            }
+            ZERO | ONE if self.receiving => self.receive(lines == ONE),
+            _ => {}
+        }
+    }
+
+    /// Receive the next bit of the packet.
+    fn receive(&mut self, bit: bool) {
+        if self.bits < PACKET_BITS {
+            if bit {
+                self.packet[usize::from(self.bits / 8)] |= 1 << (self.bits % 8);
+            }
+            self.bits += 1;
+            return;
+        }
+
+        // A packet without its 0 stop bit is dropped.
+        self.receiving = false;
+        if !bit {
+            self.finish_packet();
+        }
+    }
+
+    /// Add the packet received to the current command, finishing the
+    /// command with its last packet.
+    fn finish_packet(&mut self) {
+        if self.remaining == 0 {
+            let len = self.packet[0] & 0x07;
+            if len == 0 {
+                return;
+            }
+            self.data.clear();
+            self.remaining = len;
+        }
+
+        self.data.extend_from_slice(&self.packet);
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return;
+        }
+
+        let packet = Packet {
+            command: Command::from_code(self.data[0] >> 3),
+            data: self.data.as_slice().into(),
+        };
+        self.apply(&packet);
+        if self.packets.len() == MAX_COMMANDS {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(packet);
+    }
+
+    /// Decode the effects of `packet` kept here.
+    fn apply(&mut self, packet: &Packet) {
+        let data = &packet.data;
+        let pair = match packet.command {
+            Command::Pal01 => [0, 1],
+            Command::Pal23 => [2, 3],
+            Command::Pal03 => [0, 3],
+            Command::Pal12 => [1, 2],
+            Command::MaskEn => {
+                self.mask = match data[1] & 0x03 {
+                    0 => Mask::Cancel,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                };
+                return;
+            }
+            _ => return,
+        };
+
+        let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x7FFF;
+        // Color 0 is shared by every palette.
+        for palette in &mut self.palettes {
+            palette[0] = color(0);
+        }
+        for (n, palette) in pair.into_iter().enumerate() {
+            for i in 1..4 {
+                self.palettes[palette][i] = color(n * 3 + i);
+            }
+        }
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send `packet` as `P1` writes, with the stop bit `stop`.
+    fn send(sgb: &mut Sgb, packet: [u8; PACKET_SIZE], stop: bool) {
+        sgb.write(RESET);
+        sgb.write(LINES);
+        let bits = (0..PACKET_BITS).map(|i| packet[usize::from(i / 8)] >> (i % 8) & 1 != 0);
+        for bit in bits.chain([stop]) {
+            sgb.write(if bit { ONE } else { ZERO });
+            sgb.write(LINES);
+        }
+    }
+
+    #[test]
+    fn pal01_sets_palettes() {
+        let mut sgb = Sgb::new();
+        let colors: [u16; 7] = [0x7FFF, 0x001F, 0x03E0, 0x7C00, 0x1234, 0x5678, 0x0000];
+        let mut packet = [0; PACKET_SIZE];
+        // PAL01, in 1 packet.
+        packet[0] = 0x01;
+        for (i, color) in colors.iter().enumerate() {
+            packet[1 + i * 2..3 + i * 2].copy_from_slice(&color.to_le_bytes());
+        }
+
+        // A packet missing its stop bit is dropped.
+        send(&mut sgb, packet, true);
+        assert_eq!(sgb.take_packet(), None);
+
+        send(&mut sgb, packet, false);
+        let received = sgb.take_packet().unwrap();
+        assert_eq!(received.command, Command::Pal01);
+        assert_eq!(*received.data, packet);
+        assert_eq!(sgb.take_packet(), None);
+
+        let palettes = sgb.palettes();
+        assert_eq!(palettes[0], [0x7FFF, 0x001F, 0x03E0, 0x7C00]);
+        assert_eq!(palettes[1], [0x7FFF, 0x1234, 0x5678, 0x0000]);
+        assert_eq!(palettes[2], [0x7FFF, 0, 0, 0]);
+    }
+
+    #[test]
+    fn commands_span_packets() {
+        let mut sgb = Sgb::new();
+        let mut first = [0x11; PACKET_SIZE];
+        // ATTR_BLK, in 2 packets.
+        first[0] = 0x04 << 3 | 2;
+        send(&mut sgb, first, false);
+        assert_eq!(sgb.take_packet(), None);
+        send(&mut sgb, [0x22; PACKET_SIZE], false);
+
+        let received = sgb.take_packet().unwrap();
+        assert_eq!(received.command, Command::AttrBlk);
+        assert_eq!(received.data.len(), 2 * PACKET_SIZE);
+        assert_eq!(received.data[..PACKET_SIZE], first);
+        assert_eq!(received.data[PACKET_SIZE..], [0x22; PACKET_SIZE]);
+
+        let mut mask = [0; PACKET_SIZE];
+        mask[0] = 0x17 << 3 | 1;
+        mask[1] = 2;
+        send(&mut sgb, mask, false);
+        assert_eq!(sgb.take_packet().unwrap().command, Command::MaskEn);
+        assert_eq!(sgb.mask(), Mask::Black);
+        assert_eq!(Command::from_code(0x1F), Command::Unknown(0x1F));
+    }
+}
@@ -0,0 +1,272 @@
+//! Save states, snapshotting the whole machine.
+//!
+//! A state is a small header followed by every component in a fixed order,
+//! each written as little-endian integers and raw bytes. There are no field
+//! tags, so any change to the layout must bump [`VERSION`].
+
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+/// The bytes every save state starts with.
+pub const MAGIC: [u8; 4] = *b"LIAM";
+/// The version of the save state layout.
+pub const VERSION: u16 = 12;
+
+/// An error encountered while loading a save state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with [`MAGIC`].
+    InvalidMagic,
+    /// The state was saved with an incompatible layout.
+    UnsupportedVersion(u16),
+    /// The state was saved with a different cartridge inserted.
+    CartridgeMismatch,
+    /// The data ended before the state was complete.
+    Truncated,
+    /// A value in the state is out of range, or there is trailing data.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => f.write_str("not a save state"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "save state version {version} is not supported, expected {VERSION}")
+            }
+            Self::CartridgeMismatch => f.write_str("save state is for a different cartridge"),
+            Self::Truncated => f.write_str("save state is truncated"),
+            Self::Corrupt => f.write_str("save state is corrupt"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// A writer appending the fields of a save state.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Create a writer with the header already written.
+    #[must_use]
+    pub fn new() -> Self {
+        let mut writer = Self::default();
+        writer.write_bytes(&MAGIC);
+        writer.write_u16(VERSION);
+        writer
+    }
+
+    /// Return the written state.
+    #[must_use]
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Write a byte.
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    /// Write a little-endian 16-bit value.
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    /// Write a little-endian 32-bit value.
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    /// Write a little-endian 64-bit value.
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    /// Write a flag as a byte.
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value.into());
+    }
+
+    /// Write raw bytes, whose length the reader must already know.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+}
+
+/// A reader taking the fields of a save state in the order they were written.
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Create a reader over `data`, checking its header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` isn't a save state of this layout version.
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        let mut reader = Self { data };
+
+        let mut magic = [0; 4];
+        reader
+            .read_bytes(&mut magic)
+            .map_err(|_| StateError::InvalidMagic)?;
+        if magic != MAGIC {
+            return Err(StateError::InvalidMagic);
+        }
+
+        let version = reader.read_u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        Ok(reader)
+    }
+
+    /// Check the whole state was read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is data left over.
+    pub const fn finish(self) -> Result<(), StateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Corrupt)
+        }
+    }
+
+    /// Read bytes to fill `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is too short.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), StateError> {
+        let (bytes, rest) = self
+            .data
+            .split_at_checked(buf.len())
+            .ok_or(StateError::Truncated)?;
+
+        buf.copy_from_slice(bytes);
+        self.data = rest;
+        Ok(())
+    }
+
+    /// Read a fixed-size array of bytes.
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut bytes = [0; N];
+        self.read_bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Read a byte.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is too short.
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        self.read_array().map(u8::from_le_bytes)
+    }
+
+    /// Read a little-endian 16-bit value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is too short.
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        self.read_array().map(u16::from_le_bytes)
+    }
+
+    /// Read a little-endian 32-bit value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is too short.
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    /// Read a little-endian 64-bit value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is too short.
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    /// Read a flag written as a byte.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is too short or the byte is neither 0
+    /// nor 1.
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_fields() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789A_BCDE);
+        writer.write_u64(u64::MAX);
+        writer.write_bool(true);
+        writer.write_bytes(&[1, 2, 3]);
+        let state = writer.finish();
+
+        let mut reader = StateReader::new(&state).unwrap();
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u32(), Ok(0x789A_BCDE));
+        assert_eq!(reader.read_u64(), Ok(u64::MAX));
+        assert_eq!(reader.read_bool(), Ok(true));
+
+        let mut bytes = [0; 3];
+        reader.read_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2, 3]);
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert_eq!(StateReader::new(b"LI").unwrap_err(), StateError::InvalidMagic);
+        assert_eq!(StateReader::new(b"MAIL\x01\x00").unwrap_err(), StateError::InvalidMagic);
+
+        let mut data = MAGIC.to_vec();
+        data.extend((VERSION + 1).to_le_bytes());
+        assert_eq!(
+            StateReader::new(&data).unwrap_err(),
+            StateError::UnsupportedVersion(VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(2);
+        let state = writer.finish();
+
+        let mut reader = StateReader::new(&state).unwrap();
+        assert_eq!(reader.read_bool(), Err(StateError::Corrupt));
+        assert_eq!(reader.read_u8(), Err(StateError::Truncated));
+
+        let reader = StateReader::new(&state).unwrap();
+        assert_eq!(reader.finish(), Err(StateError::Corrupt));
+    }
+}
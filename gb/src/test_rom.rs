@@ -0,0 +1,126 @@
+//! Detection of the results reported by Blargg's and Mooneye's test ROMs.
+//!
+//! Blargg's tests print their result over the serial port, ending in
+//! `Passed` or `Failed`. Mooneye's tests run `LD B,B` once done, with the
+//! Fibonacci numbers 3, 5, 8, 13, 21 and 34 in `B` through `L` on success,
+//! or `0x42` in each of them on failure.
+
+use crate::cpu::{Instruction, Operand, Reg8, Registers};
+
+/// The breakpoint instruction Mooneye's tests finish on.
+const BREAKPOINT: Instruction = Instruction::Ld(Operand::Reg(Reg8::B), Operand::Reg(Reg8::B));
+
+/// `B`, `C`, `D`, `E`, `H` and `L` after a passed Mooneye test.
+const MOONEYE_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/// `B`, `C`, `D`, `E`, `H` and `L` after a failed Mooneye test.
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+
+/// The outcome reported by a test ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TestResult {
+    /// Every test passed.
+    Passed,
+    /// At least one test failed.
+    Failed,
+}
+
+/// Watches the serial output and the CPU for a test ROM reporting its
+/// result.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TestRomWatcher {
+    /// The last bytes sent over the serial port, oldest first.
+    serial: [u8; 6],
+    result: Option<TestResult>,
+}
+
+impl TestRomWatcher {
+    /// Return the first result reported, if any.
+    pub const fn result(&self) -> Option<TestResult> {
+        self.result
+    }
+
+    /// Forget the reported result and serial output.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Look for Blargg's result in the serial output, ending in `byte`.
+    pub fn serial_byte(&mut self, byte: u8) {
+        self.serial.rotate_left(1);
+        self.serial[5] = byte;
+
+        match &self.serial {
+            b"Passed" => self.report(TestResult::Passed),
+            b"Failed" => self.report(TestResult::Failed),
+            _ => {}
+        }
+    }
+
+    /// Look for Mooneye's result after the CPU ran `instruction`.
+    pub fn instruction(&mut self, instruction: Instruction, regs: &Registers) {
+        if instruction != BREAKPOINT {
+            return;
+        }
+
+        match [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l] {
+            MOONEYE_PASSED => self.report(TestResult::Passed),
+            MOONEYE_FAILED => self.report(TestResult::Failed),
+            _ => {}
+        }
+    }
+
+    /// Keep `result` unless one was already reported.
+    fn report(&mut self, result: TestResult) {
+        self.result.get_or_insert(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(watcher: &mut TestRomWatcher, text: &[u8]) {
+        for &byte in text {
+            watcher.serial_byte(byte);
+        }
+    }
+
+    #[test]
+    fn finds_blargg_result_in_serial_output() {
+        let mut watcher = TestRomWatcher::default();
+        send(&mut watcher, b"cpu_instrs\n\n01:ok  02:ok\n\n");
+        assert_eq!(watcher.result(), None);
+
+        send(&mut watcher, b"Failed 1 tests.\n");
+        assert_eq!(watcher.result(), Some(TestResult::Failed));
+
+        // The first result sticks.
+        send(&mut watcher, b"Passed");
+        assert_eq!(watcher.result(), Some(TestResult::Failed));
+    }
+
+    #[test]
+    fn finds_mooneye_result_on_breakpoint() {
+        let mut watcher = TestRomWatcher::default();
+        let mut regs = Registers::new_dmg();
+        [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l] = MOONEYE_PASSED;
+
+        watcher.instruction(Instruction::Nop, &regs);
+        assert_eq!(watcher.result(), None);
+
+        watcher.instruction(BREAKPOINT, &regs);
+        assert_eq!(watcher.result(), Some(TestResult::Passed));
+    }
+
+    #[test]
+    fn breakpoint_without_signature_is_ignored() {
+        let mut watcher = TestRomWatcher::default();
+        watcher.instruction(BREAKPOINT, &Registers::new_dmg());
+        assert_eq!(watcher.result(), None);
+
+        let mut regs = Registers::new_dmg();
+        [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l] = MOONEYE_FAILED;
+        watcher.instruction(BREAKPOINT, &regs);
+        assert_eq!(watcher.result(), Some(TestResult::Failed));
+    }
+}
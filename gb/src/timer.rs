@@ -0,0 +1,420 @@
+//! The timer, clocked from the system counter.
+
+use crate::interrupt::Interrupt;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The address of the divider register, the upper byte of the system counter.
+pub const DIV: u16 = 0xFF04;
+/// The address of the timer counter register.
+pub const TIMA: u16 = 0xFF05;
+/// The address of the timer modulo register.
+pub const TMA: u16 = 0xFF06;
+/// The address of the timer control register.
+pub const TAC: u16 = 0xFF07;
+
+/// The `TAC` bit that enables `TIMA` increments.
+const TAC_ENABLE: u8 = 0x04;
+
+/// The timer.
+///
+/// `TIMA` increments on a falling edge of the system counter bit selected by
+/// `TAC`, AND-ed with the enable bit. Since the edge detector sees the
+/// combined signal, resetting `DIV` or changing `TAC` can also increment it.
+///
+/// An overflow leaves `TIMA` at zero for one M-cycle before it's reloaded
+/// from `TMA` and the interrupt is requested.
+#[derive(Debug, Default)]
+pub struct Timer {
+    /// The 16-bit system counter, incremented every T-cycle.
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// `TIMA` overflowed in the last M-cycle and is waiting to be reloaded.
+    overflowed: bool,
+    /// `TIMA` was reloaded this M-cycle, so it ignores writes and follows
+    /// writes to `TMA` instead.
+    reloading: bool,
+    /// The T-cycles towards the next M-cycle.
+    cycles: u8,
+    interrupts: u8,
+}
+
+impl Timer {
+    /// Create a timer with a cleared system counter.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return and clear the interrupts requested since the last call, as
+    /// `IF` bits.
+    pub const fn take_interrupts(&mut self) -> u8 {
+        let interrupts = self.interrupts;
+        self.interrupts = 0;
+        interrupts
+    }
+
+    /// Write the registers and internal counter to a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_u8(self.tima);
+        state.write_u8(self.tma);
+        state.write_u8(self.tac);
+        state.write_bool(self.overflowed);
+        state.write_bool(self.reloading);
+        state.write_u8(self.cycles);
+        state.write_u8(self.interrupts);
+    }
+
+    /// Restore the state written by [`Timer::save_state`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state is truncated or corrupt.
+    pub fn load_state(&mut self, state: &mut StateReader<'_>) -> Result<(), StateError> {
+        self.counter = state.read_u16()?;
+        self.tima = state.read_u8()?;
+        self.tma = state.read_u8()?;
+        self.tac = state.read_u8()? & 0x07;
+        self.overflowed = state.read_bool()?;
+        self.reloading = state.read_bool()?;
+        self.cycles = state.read_u8()? & 0x03;
+        self.interrupts = state.read_u8()?;
+        Ok(())
+    }
+
+    /// Advance the timer by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles;
+        while self.cycles >= 4 {
+            self.cycles -= 4;
+            self.step();
+        }
+    }
+
+    /// Advance the timer by `cycles` T-cycles like [`Timer::tick`], but hold
+    /// a reload due on the last M-cycle for [`Timer::reload`].
+    pub(crate) fn advance(&mut self, cycles: u8) {
+        self.cycles += cycles;
+        while self.cycles >= 8 || (self.cycles >= 4 && !self.overflowed) {
+            self.cycles -= 4;
+            self.step();
+        }
+    }
+
+    /// Run the M-cycle [`Timer::advance`] held, reloading `TIMA`.
+    pub(crate) fn reload(&mut self) {
+        self.tick(0);
+    }
+
+    /// Return the T-cycles until the M-cycle that next reloads `TIMA`, or
+    /// `None` if the timer is stopped with no overflow pending.
+    pub(crate) fn cycles_until_reload(&self) -> Option<u32> {
+        let steps = if self.overflowed {
+            1
+        } else if self.tac & TAC_ENABLE != 0 {
+            // `TIMA` increments each time the counter passes a multiple of
+            // twice the selected bit, and reloads the M-cycle after the
+            // increment that wraps it.
+            let period = 2 << self.bit();
+            let first = (period - u32::from(self.counter) % period).div_ceil(4);
+            first + u32::from(0xFF - self.tima) * period / 4 + 1
+        } else {
+            return None;
+        };
+
+        Some((4 * steps).saturating_sub(u32::from(self.cycles)))
+    }
+
+    /// Advance the timer by one M-cycle.
+    fn step(&mut self) {
+        self.reloading = false;
+        if self.overflowed {
+            self.overflowed = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            self.interrupts |= Interrupt::Timer.bit();
+        }
+
+        let before = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_edge(before);
+    }
+
+    /// Return the counter bit selected by `TAC`.
+    const fn bit(&self) -> u32 {
+        match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        }
+    }
+
+    /// Return the counter bit selected by `TAC`, AND-ed with the enable bit.
+    const fn signal(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && self.counter & (1 << self.bit()) != 0
+    }
+
+    /// Increment `TIMA` if the timer signal fell from `before`.
+    const fn detect_edge(&mut self, before: bool) {
+        if !before || self.signal() {
+            return;
+        }
+
+        let (tima, overflowed) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflowed |= overflowed;
+    }
+
+    /// Read a timer register.
+    #[must_use]
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV => self.counter.to_be_bytes()[0],
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    /// Set a timer register without any side effects, for tooling.
+    ///
+    /// Poking `DIV` sets the upper byte of the system counter instead of
+    /// resetting it.
+    pub const fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            DIV => self.counter = (value as u16) << 8 | self.counter & 0xFF,
+            TIMA => self.tima = value,
+            TMA => self.tma = value,
+            TAC => self.tac = value & 0x07,
+            _ => {}
+        }
+    }
+
+    /// Write a timer register.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            DIV => {
+                let before = self.signal();
+                self.counter = 0;
+                self.detect_edge(before);
+            }
+            // The reload wins over a write in the same M-cycle, but a write
+            // during the delay cancels it.
+            TIMA if !self.reloading => {
+                self.tima = value;
+                self.overflowed = false;
+            }
+            TMA => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            }
+            TAC => {
+                let before = self.signal();
+                self.tac = value & 0x07;
+                self.detect_edge(before);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the timer for `n` M-cycles.
+    fn run(timer: &mut Timer, n: usize) {
+        for _ in 0..n {
+            timer.tick(4);
+        }
+    }
+
+    /// Return how many M-cycles it takes `TIMA` to first increment after a
+    /// `DIV` reset, with `TAC` set to `tac`.
+    fn period(tac: u8) -> usize {
+        let mut timer = Timer::new();
+        timer.write(TAC, tac);
+        timer.write(DIV, 0);
+
+        let mut cycles = 0;
+        while timer.read(TIMA) == 0 {
+            run(&mut timer, 1);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn div_counts_every_64_m_cycles() {
+        let mut timer = Timer::new();
+
+        run(&mut timer, 63);
+        assert_eq!(timer.read(DIV), 0);
+        run(&mut timer, 1);
+        assert_eq!(timer.read(DIV), 1);
+
+        timer.write(DIV, 0x55);
+        assert_eq!(timer.read(DIV), 0);
+    }
+
+    #[test]
+    fn tac_selects_the_rate() {
+        // tim00, tim01, tim10 and tim11.
+        assert_eq!(period(0x04), 256);
+        assert_eq!(period(0x05), 4);
+        assert_eq!(period(0x06), 16);
+        assert_eq!(period(0x07), 64);
+    }
+
+    #[test]
+    fn disabled_timer_holds() {
+        let mut timer = Timer::new();
+        timer.write(TAC, 0x01);
+
+        run(&mut timer, 1000);
+        assert_eq!(timer.read(TIMA), 0);
+        assert_eq!(timer.read(TAC), 0xF9);
+    }
+
+    #[test]
+    fn div_write_causes_falling_edge() {
+        // div_write: resetting DIV with the selected bit set increments TIMA.
+        let mut timer = Timer::new();
+        timer.write(TAC, 0x05);
+
+        run(&mut timer, 2);
+        assert_eq!(timer.read(TIMA), 0);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+
+        // With the bit clear there's no edge.
+        run(&mut timer, 1);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+    }
+
+    #[test]
+    fn disabling_causes_falling_edge() {
+        // rapid_toggle: turning the timer off with the bit set increments TIMA.
+        let mut timer = Timer::new();
+        timer.write(TAC, 0x05);
+
+        run(&mut timer, 2);
+        timer.write(TAC, 0x01);
+        assert_eq!(timer.read(TIMA), 1);
+    }
+
+    /// Return a timer whose `TIMA` overflowed on the last M-cycle.
+    fn overflowed() -> Timer {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0xFE);
+        timer.write(TIMA, 0xFF);
+        timer.write(TAC, 0x05);
+        timer.write(DIV, 0);
+
+        run(&mut timer, 4);
+        assert!(timer.overflowed);
+        timer
+    }
+
+    #[test]
+    fn overflow_reloads_a_cycle_later() {
+        // tima_reload: TIMA reads zero for an M-cycle before the reload.
+        let mut timer = overflowed();
+        assert_eq!(timer.read(TIMA), 0x00);
+        assert_eq!(timer.take_interrupts(), 0);
+
+        run(&mut timer, 1);
+        assert_eq!(timer.read(TIMA), 0xFE);
+        assert_eq!(timer.take_interrupts(), Interrupt::Timer.bit());
+    }
+
+    #[test]
+    fn tima_write_during_delay_cancels_reload() {
+        // tima_write_reloading: a write in the delay aborts the reload.
+        let mut timer = overflowed();
+        timer.write(TIMA, 0x10);
+
+        run(&mut timer, 1);
+        assert_eq!(timer.read(TIMA), 0x10);
+        assert_eq!(timer.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn tima_write_during_reload_is_ignored() {
+        let mut timer = overflowed();
+        run(&mut timer, 1);
+
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.read(TIMA), 0xFE);
+    }
+
+    #[test]
+    fn tma_write_during_reload_reaches_tima() {
+        // tma_write_reloading: TIMA follows TMA during the reload cycle.
+        let mut timer = overflowed();
+        run(&mut timer, 1);
+
+        timer.write(TMA, 0x20);
+        assert_eq!(timer.read(TIMA), 0x20);
+
+        run(&mut timer, 1);
+        timer.write(TMA, 0x30);
+        assert_eq!(timer.read(TIMA), 0x20);
+    }
+
+    #[test]
+    fn reload_is_predicted_to_the_cycle() {
+        let cases = [
+            (0x04, 0x0000, 0xFF, 0),
+            (0x05, 0x1235, 0xF0, 3),
+            (0x06, 0xFFF8, 0x80, 1),
+            (0x07, 0x00C2, 0x00, 2),
+        ];
+        for (tac, counter, tima, cycles) in cases {
+            let mut timer = Timer::new();
+            timer.write(TAC, tac);
+            timer.counter = counter;
+            timer.tima = tima;
+            timer.tick(cycles);
+
+            let until = timer.cycles_until_reload().unwrap();
+            let mut elapsed = 0;
+            while timer.take_interrupts() == 0 {
+                timer.tick(1);
+                elapsed += 1;
+            }
+            assert_eq!(elapsed, until, "TAC {tac:#04X}");
+        }
+
+        let mut timer = overflowed();
+        assert_eq!(timer.cycles_until_reload(), Some(4));
+        timer.write(TAC, 0x00);
+        assert_eq!(timer.cycles_until_reload(), Some(4));
+        run(&mut timer, 1);
+        assert_eq!(timer.cycles_until_reload(), None);
+    }
+
+    #[test]
+    fn advance_holds_the_reload_for_last() {
+        let mut timer = overflowed();
+        timer.advance(4);
+        assert_eq!(timer.read(TIMA), 0x00);
+
+        timer.reload();
+        assert_eq!(timer.read(TIMA), 0xFE);
+        assert_eq!(timer.take_interrupts(), Interrupt::Timer.bit());
+
+        // A held reload runs first if the timer moves on without it.
+        let mut timer = overflowed();
+        timer.advance(4);
+        timer.advance(4);
+        assert_eq!(timer.read(TIMA), 0xFE);
+    }
+}
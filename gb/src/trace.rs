@@ -0,0 +1,225 @@
+//! CPU tracing in the Gameboy Doctor log format, and with the `trace`
+//! feature, logging of every bus access the CPU makes.
+
+use alloc::boxed::Box;
+use core::fmt;
+#[cfg(feature = "trace")]
+use core::ops::RangeInclusive;
+
+use crate::bus::Bus;
+use crate::cpu::{Cpu, Registers};
+
+/// The CPU state before an instruction, as logged by Gameboy Doctor.
+///
+/// Formats as a log line, such as:
+///
+/// ```text
+/// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    /// The register file.
+    pub regs: Registers,
+    /// The four bytes from `PC` onwards, peeked without side effects.
+    pub pcmem: [u8; 4],
+}
+
+impl CpuState {
+    /// Capture the state of `cpu` about to run from `bus`.
+    #[must_use]
+    pub fn capture(cpu: &Cpu, bus: &impl Bus) -> Self {
+        let pc = cpu.regs.pc;
+        let pcmem = [0, 1, 2, 3].map(|offset| bus.peek(pc.wrapping_add(offset)));
+
+        Self {
+            regs: cpu.regs.clone(),
+            pcmem,
+        }
+    }
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Registers { a, f: flags, b, c, d, e, h, l, sp, pc } = &self.regs;
+        let [m0, m1, m2, m3] = self.pcmem;
+
+        write!(f, "A:{a:02X} F:{:02X} B:{b:02X} C:{c:02X} ", flags.into_bits())?;
+        write!(f, "D:{d:02X} E:{e:02X} H:{h:02X} L:{l:02X} ")?;
+        write!(f, "SP:{sp:04X} PC:{pc:04X} PCMEM:{m0:02X},{m1:02X},{m2:02X},{m3:02X}")
+    }
+}
+
+/// A hook called with the CPU state before every instruction.
+pub(crate) struct StepHook(pub Box<dyn FnMut(&CpuState)>);
+
+impl fmt::Debug for StepHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StepHook")
+    }
+}
+
+/// The kind of a bus access recorded by an [`MmuTracer`].
+#[cfg(feature = "trace")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    /// A read of an opcode or operand byte of an instruction.
+    Fetch,
+    /// A data read.
+    Read,
+    /// A data write.
+    Write,
+}
+
+/// A bus access recorded by an [`MmuTracer`].
+#[cfg(feature = "trace")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BusAccess {
+    /// The address of the instruction making the access.
+    pub pc: u16,
+    /// Whether the access fetched, read or wrote.
+    pub kind: AccessKind,
+    /// The address accessed.
+    pub addr: u16,
+    /// The byte the CPU read, or the byte it wrote.
+    pub value: u8,
+}
+
+/// Passes every CPU access to the bus in an address range to a sink, once
+/// set with [`crate::mmu::Mmu::set_tracer`].
+///
+/// Only the CPU's accesses are recorded, not DMA or [`Bus::peek`]. Reads
+/// are recorded with the byte the CPU saw, after any read hooks.
+#[cfg(feature = "trace")]
+pub struct MmuTracer {
+    sink: Box<dyn FnMut(BusAccess)>,
+    range: RangeInclusive<u16>,
+    /// The address of the instruction being run.
+    pc: u16,
+}
+
+#[cfg(feature = "trace")]
+impl MmuTracer {
+    /// Create a tracer passing every access to `sink`.
+    pub fn new(sink: impl FnMut(BusAccess) + 'static) -> Self {
+        Self {
+            sink: Box::new(sink),
+            range: 0x0000..=0xFFFF,
+            pc: 0,
+        }
+    }
+
+    /// Only record the accesses to `range`.
+    #[must_use]
+    pub fn with_range(self, range: RangeInclusive<u16>) -> Self {
+        Self { range, ..self }
+    }
+
+    /// Return the addresses whose accesses are recorded.
+    #[must_use]
+    pub const fn range(&self) -> &RangeInclusive<u16> {
+        &self.range
+    }
+
+    /// Set the address of the instruction about to run.
+    pub(crate) const fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Pass an access to the sink, if it's in range.
+    pub(crate) fn record(&mut self, kind: AccessKind, addr: u16, value: u8) {
+        if self.range.contains(&addr) {
+            (self.sink)(BusAccess { pc: self.pc, kind, addr, value });
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+impl fmt::Debug for MmuTracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmuTracer")
+            .field("range", &self.range)
+            .field("pc", &self.pc)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::bus::FlatMemory;
+
+    #[test]
+    fn formats_doctor_line() {
+        let memory = FlatMemory::with_program(0x0100, &[0x00, 0xC3, 0x13, 0x02]);
+        let state = CpuState::capture(&Cpu::new(), &memory);
+
+        assert_eq!(
+            state.to_string(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+        );
+    }
+
+    #[test]
+    fn pcmem_wraps() {
+        let mut memory = FlatMemory::with_program(0xFFFE, &[0x12, 0x34]);
+        memory.0[0] = 0x56;
+        let mut cpu = Cpu::new();
+        cpu.regs.pc = 0xFFFE;
+
+        assert_eq!(CpuState::capture(&cpu, &memory).pcmem, [0x12, 0x34, 0x56, 0x00]);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn tracer_records_accesses() {
+        use alloc::rc::Rc;
+        use alloc::vec::Vec;
+        use core::cell::RefCell;
+
+        use crate::GameBoy;
+        use crate::cartridge::test_rom;
+
+        // LD A,$42 ; LD ($C000),A ; LD A,($FF80) ; JR -2
+        let mut rom = test_rom(0x00, [0x00, 0x00]);
+        let program = [0x3E, 0x42, 0xEA, 0x00, 0xC0, 0xF0, 0x80, 0x18, 0xFE];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(&program);
+        let mut gb = GameBoy::from_rom(rom).unwrap();
+        gb.poke(0xFF80, 0x99);
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&log);
+        let tracer = MmuTracer::new(move |access| sink.borrow_mut().push(access));
+        gb.mmu_mut().set_tracer(Some(tracer));
+        for _ in 0..3 {
+            gb.step();
+        }
+
+        let access = |pc, kind, addr, value| BusAccess { pc, kind, addr, value };
+        let (fetch, read, write) = (AccessKind::Fetch, AccessKind::Read, AccessKind::Write);
+        assert_eq!(
+            *log.borrow(),
+            [
+                access(0x0100, fetch, 0x0100, 0x3E),
+                access(0x0100, fetch, 0x0101, 0x42),
+                access(0x0102, fetch, 0x0102, 0xEA),
+                access(0x0102, fetch, 0x0103, 0x00),
+                access(0x0102, fetch, 0x0104, 0xC0),
+                access(0x0102, write, 0xC000, 0x42),
+                access(0x0105, fetch, 0x0105, 0xF0),
+                access(0x0105, fetch, 0x0106, 0x80),
+                access(0x0105, read, 0xFF80, 0x99),
+            ]
+        );
+
+        // Filtered to WRAM, only the store is recorded.
+        log.borrow_mut().clear();
+        let sink = Rc::clone(&log);
+        let tracer = MmuTracer::new(move |access| sink.borrow_mut().push(access));
+        gb.mmu_mut().set_tracer(Some(tracer.with_range(0xC000..=0xDFFF)));
+        gb.cpu_mut().regs.pc = 0x0102;
+        gb.step();
+        assert_eq!(*log.borrow(), [access(0x0102, write, 0xC000, 0x99)]);
+    }
+}
@@ -0,0 +1,167 @@
+//! Bindings for driving a [`GameBoy`] from JavaScript, behind the `wasm`
+//! feature.
+//!
+//! The machine is exported to JavaScript as `GameBoy`. A frontend creates one
+//! from the bytes of a ROM, then each animation frame runs a frame, copies
+//! the RGBA framebuffer into the `ImageData` of a canvas and queues the audio
+//! drained since the last frame. See `examples/wasm` for a render loop.
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use js_sys::{Float32Array, Uint8ClampedArray};
+use wasm_bindgen::prelude::*;
+
+use crate::GameBoy;
+use crate::joypad::Button;
+use crate::ppu::{HEIGHT, WIDTH};
+
+/// A [`GameBoy`] exported to JavaScript.
+#[wasm_bindgen(js_name = GameBoy)]
+#[derive(Debug)]
+pub struct WasmGameBoy {
+    gb: GameBoy,
+    /// The samples drained by the last [`WasmGameBoy::drain_audio`], kept to
+    /// reuse the allocation.
+    audio: Vec<f32>,
+}
+
+#[wasm_bindgen(js_class = GameBoy)]
+impl WasmGameBoy {
+    /// Create a machine around the ROM in `rom`, which is a `Uint8Array` on
+    /// the JavaScript side.
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8]) -> Result<Self, JsError> {
+        let gb = GameBoy::from_rom(rom.to_vec()).map_err(|err| JsError::new(&err.to_string()))?;
+        Ok(Self {
+            gb,
+            audio: Vec::new(),
+        })
+    }
+
+    /// Return the width of the framebuffer in pixels.
+    #[must_use]
+    pub fn width() -> usize {
+        WIDTH
+    }
+
+    /// Return the height of the framebuffer in pixels.
+    #[must_use]
+    pub fn height() -> usize {
+        HEIGHT
+    }
+
+    /// Run until the next frame is complete.
+    #[wasm_bindgen(js_name = runFrame)]
+    pub fn run_frame(&mut self) {
+        self.gb.run_frame();
+    }
+
+    /// Copy the RGBA framebuffer into `out`, such as the `data` of an
+    /// `ImageData`, which must be `width() * height() * 4` bytes long.
+    ///
+    /// The bytes are copied straight out of the machine, so nothing is
+    /// allocated per frame.
+    #[wasm_bindgen(js_name = copyFramebuffer)]
+    pub fn copy_framebuffer(&self, out: &Uint8ClampedArray) -> Result<(), JsError> {
+        let framebuffer = self.gb.rgba_framebuffer();
+        if out.length() as usize != framebuffer.len() {
+            return Err(JsError::new("framebuffer copy needs width * height * 4 bytes"));
+        }
+
+        out.copy_from(framebuffer);
+        Ok(())
+    }
+
+    /// Set the rate of the samples returned by [`WasmGameBoy::drain_audio`]
+    /// to `hz`, usually the `sampleRate` of an `AudioContext`.
+    #[wasm_bindgen(js_name = setSampleRate)]
+    pub fn set_sample_rate(&mut self, hz: u32) {
+        self.gb.mmu_mut().apu_mut().set_sample_rate(hz);
+    }
+
+    /// Return the audio produced since the last call, as interleaved left
+    /// and right samples from -1.0 to 1.0.
+    #[wasm_bindgen(js_name = drainAudio)]
+    pub fn drain_audio(&mut self) -> Float32Array {
+        self.drain_samples();
+        Float32Array::from(self.audio.as_slice())
+    }
+
+    /// Press or release the button at `index`, in the order right, left, up,
+    /// down, A, B, select and start. Other indices are ignored.
+    #[wasm_bindgen(js_name = setButton)]
+    pub fn set_button(&mut self, index: u8, pressed: bool) {
+        let Some(&button) = Button::ALL.get(usize::from(index)) else {
+            return;
+        };
+
+        let joypad = self.gb.mmu_mut().joypad_mut();
+        if pressed {
+            joypad.press(button);
+        } else {
+            joypad.release(button);
+        }
+    }
+
+    /// Return the machine to its power-on state, keeping the cartridge.
+    pub fn reset(&mut self) {
+        self.gb.reset();
+    }
+}
+
+impl WasmGameBoy {
+    /// Drain the resampled audio into the reused buffer.
+    fn drain_samples(&mut self) {
+        self.audio.clear();
+        self.gb.mmu_mut().apu_mut().drain_samples(&mut self.audio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::joypad::P1;
+
+    fn machine() -> WasmGameBoy {
+        let mut rom = vec![0; 0x8000];
+        // JR -2
+        rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+        WasmGameBoy::new(&rom).unwrap()
+    }
+
+    #[test]
+    fn set_button_follows_p1_order() {
+        let mut machine = machine();
+        machine.gb.poke(P1, 0x20);
+
+        machine.set_button(0, true);
+        assert_eq!(machine.gb.peek(P1) & 0x0F, 0x0E);
+        machine.set_button(3, true);
+        assert_eq!(machine.gb.peek(P1) & 0x0F, 0x06);
+        machine.set_button(0, false);
+        assert_eq!(machine.gb.peek(P1) & 0x0F, 0x07);
+
+        // Out of range indices are ignored.
+        machine.set_button(8, true);
+        assert_eq!(machine.gb.peek(P1) & 0x0F, 0x07);
+    }
+
+    #[test]
+    fn audio_buffer_is_reused() {
+        let mut machine = machine();
+        machine.set_sample_rate(48_000);
+        machine.run_frame();
+        machine.drain_samples();
+
+        let samples = machine.audio.len();
+        assert!(samples > 0 && samples.is_multiple_of(2));
+        let capacity = machine.audio.capacity();
+
+        machine.run_frame();
+        machine.drain_samples();
+        assert_eq!(machine.audio.capacity(), capacity);
+    }
+}
@@ -0,0 +1,117 @@
+//! Runs small test ROMs to completion, reading back their results the way
+//! a CI harness would.
+//!
+//! No Blargg or Mooneye ROM is bundled yet, so these are hand-assembled
+//! stand-ins that end the way those suites do. They check the result
+//! watchers behind [`GameBoy::test_result`], not the instruction set, which
+//! the unit tests and the `SingleStepTests` cases in `tests/sm83` cover.
+
+use liam_gb::GameBoy;
+use liam_gb::test_rom::TestResult;
+
+/// The most frames a test ROM may take before giving up.
+const FRAME_LIMIT: usize = 600;
+
+/// A stand-in for a Blargg test, which checks a few instructions and prints
+/// `Passed` or `Failed` over the serial port as `cpu_instrs` does.
+fn blargg_style_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    let mut place = |addr: usize, code: &[u8]| rom[addr..addr + code.len()].copy_from_slice(code);
+
+    // NOP ; JP $0150
+    place(0x0100, &[0x00, 0xC3, 0x50, 0x01]);
+    place(0x0150, &[
+        0x31, 0xFF, 0xDF, // LD SP,$DFFF
+        0x3E, 0x0F, // LD A,$0F
+        0xC6, 0x01, // ADD A,$01
+        0xFE, 0x10, // CP $10
+        0xC2, 0x70, 0x01, // JP NZ,fail
+        0x06, 0x03, // LD B,$03
+        0x3E, 0x05, // LD A,$05
+        0x90, // SUB B
+        0xFE, 0x02, // CP $02
+        0xC2, 0x70, 0x01, // JP NZ,fail
+        0xAF, // XOR A
+        0xC2, 0x70, 0x01, // JP NZ,fail
+        0x21, 0x00, 0x02, // LD HL,passed
+        0xC3, 0x80, 0x01, // JP print
+    ]);
+    // fail: LD HL,failed ; JP print
+    place(0x0170, &[0x21, 0x10, 0x02, 0xC3, 0x80, 0x01]);
+    place(0x0180, &[
+        0x2A, // print: LD A,(HL+)
+        0xB7, // OR A
+        0x28, 0x0E, // JR Z,done
+        0xE0, 0x01, // LDH ($01),A
+        0x3E, 0x81, // LD A,$81
+        0xE0, 0x02, // LDH ($02),A
+        0xF0, 0x02, // wait: LDH A,($02)
+        0xE6, 0x80, // AND $80
+        0x20, 0xFA, // JR NZ,wait
+        0x18, 0xEE, // JR print
+        0x18, 0xFE, // done: JR done
+    ]);
+    place(0x0200, b"Passed\n\0");
+    place(0x0210, b"Failed\n\0");
+    rom
+}
+
+/// A stand-in for a Mooneye test, which loads the Fibonacci numbers and
+/// stops on the `LD B,B` breakpoint.
+fn mooneye_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x010F].copy_from_slice(&[
+        0x06, 3, // LD B,3
+        0x0E, 5, // LD C,5
+        0x16, 8, // LD D,8
+        0x1E, 13, // LD E,13
+        0x26, 21, // LD H,21
+        0x2E, 34, // LD L,34
+        0x40, // LD B,B
+        0x18, 0xFE, // JR -2
+    ]);
+    rom
+}
+
+/// Run `rom` until it reports a result, or the frame limit runs out.
+fn run(rom: Vec<u8>) -> Option<TestResult> {
+    let mut gb = GameBoy::from_rom(rom).unwrap();
+    for _ in 0..FRAME_LIMIT {
+        gb.run_frame();
+        if let Some(result) = gb.test_result() {
+            return Some(result);
+        }
+    }
+    None
+}
+
+#[test]
+fn blargg_style_passes() {
+    assert_eq!(run(blargg_style_rom()), Some(TestResult::Passed));
+}
+
+#[test]
+fn blargg_style_failure_is_reported() {
+    let mut rom = blargg_style_rom();
+    // Expect $11 from $0F + $01.
+    rom[0x0158] = 0x11;
+    assert_eq!(run(rom), Some(TestResult::Failed));
+}
+
+#[test]
+fn mooneye_signature_passes() {
+    assert_eq!(run(mooneye_rom()), Some(TestResult::Passed));
+}
+
+#[test]
+fn silent_rom_reports_nothing() {
+    let mut rom = vec![0; 0x8000];
+    // JR -2
+    rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+
+    let mut gb = GameBoy::from_rom(rom).unwrap();
+    for _ in 0..10 {
+        gb.run_frame();
+    }
+    assert_eq!(gb.test_result(), None);
+}